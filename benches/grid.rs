@@ -0,0 +1,75 @@
+use std::collections::HashMap;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use bugworld_proto::asm::*;
+use bugworld_proto::mapgen::*;
+use bugworld_proto::sim::*;
+use bugworld_proto::world::*;
+
+const FORAGER: &str = "
+    start:   PickUpFood home look
+    look:    Flip 3 turn walk
+    turn:    Turn Left walk
+    walk:    Move start turn
+    home:    Sense Here Home drop wander
+    wander:  Mark 0 step
+    step:    Flip 2 left forward
+    left:    Turn Right forward
+    forward: Move home home
+    drop:    DropFood start start
+";
+
+// A 512x512 map with a few thousand ants on each side.
+fn big_world() -> World {
+    MapGenerator::new(512, 512)
+        .with_anthill_radius(30)
+        .with_food_clusters(64, 5)
+        .generate(7)
+}
+
+fn grid(c: &mut Criterion) {
+    let world = big_world();
+    let grid = world.grid();
+    let (width, height) = (grid.width() as i32, grid.height() as i32);
+
+    c.bench_function("cell_at sweep 512x512", |b| {
+        b.iter(|| {
+            let mut food = 0;
+            for y in 0..height {
+                for x in 0..width {
+                    food += grid.cell_at(Position { x, y }).unwrap().food();
+                }
+            }
+            black_box(food)
+        })
+    });
+
+    c.bench_function("iter_cells 512x512", |b| {
+        b.iter(|| black_box(grid.iter_cells().map(|(_, cell)| cell.food()).sum::<u32>()))
+    });
+
+    c.bench_function("food_on_ground 512x512", |b| {
+        b.iter(|| black_box(world.food_on_ground()))
+    });
+}
+
+fn steps(c: &mut Criterion) {
+    let world = big_world();
+    let program = parse_program(FORAGER).unwrap();
+    let programs = HashMap::from([(Color::Red, program.clone()), (Color::Black, program)]);
+
+    c.bench_function("10 steps 512x512", |b| {
+        b.iter_batched(
+            || Simulator::headless(world.clone(), programs.clone(), 1).unwrap(),
+            |mut simulator| {
+                simulator.run_for(10);
+                simulator
+            },
+            criterion::BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, grid, steps);
+criterion_main!(benches);
@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+use bugworld_proto::asm::*;
+use bugworld_proto::scent::*;
+use bugworld_proto::sim::*;
+use bugworld_proto::world::*;
+
+const FORAGER: &str = "
+    start:   PickUpFood home look
+    look:    Flip 3 turn walk
+    turn:    Turn Left walk
+    walk:    Move start turn
+    home:    Sense Here Home drop wander
+    wander:  Mark 0 step
+    step:    Flip 2 left forward
+    left:    Turn Right forward
+    forward: Move home home
+    drop:    DropFood start start
+";
+
+const ANTS: usize = 10_000;
+
+// A torus with `ANTS` ants, half of each color, on every other cell of the
+// left and right thirds, and food scattered over the middle.
+fn crowded_world() -> World {
+    let (width, height) = (300, 100);
+    let mut grid = Grid::new_torus(width, height);
+    for y in 0..height as i32 {
+        for x in (width as i32 / 3..2 * width as i32 / 3).step_by(4) {
+            for _ in 0..3 {
+                let cell = grid.cell_at_mut(Position { x, y }).unwrap();
+                cell.try_drop_food().unwrap();
+            }
+        }
+    }
+    let mut world = World::new(grid);
+    let per_color = ANTS / 2;
+    for i in 0..per_color {
+        let (x, y) = ((i % 50) as i32 * 2, (i / 50) as i32);
+        world.add_ant(Color::Red, Position { x, y }).unwrap();
+        let x = width as i32 - 1 - x;
+        world.add_ant(Color::Black, Position { x, y }).unwrap();
+    }
+    world
+}
+
+fn throughput(c: &mut Criterion) {
+    let world = crowded_world();
+    let program = parse_program(FORAGER).unwrap();
+    let programs = HashMap::from([(Color::Red, program.clone()), (Color::Black, program)]);
+
+    let mut group = c.benchmark_group("10k ants");
+    group
+        .sample_size(10)
+        .measurement_time(Duration::from_secs(60));
+    for steps in [100, 10_000] {
+        group.throughput(Throughput::Elements((ANTS * steps) as u64));
+        group.bench_function(format!("{} steps", steps), |b| {
+            b.iter_batched(
+                || Simulator::headless(world.clone(), programs.clone(), 1).unwrap(),
+                |mut simulator| {
+                    simulator.run_for(steps as u32);
+                    simulator
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+// Scent spreading over the same world, on its own.
+fn scent(c: &mut Criterion) {
+    let world = crowded_world();
+    let grid = world.grid();
+    let mut field = ScentField::new(grid);
+    let rule = ScentRule::default();
+    let mut group = c.benchmark_group("scent");
+    group.throughput(Throughput::Elements((grid.width() * grid.height()) as u64));
+    group.bench_function("update", |b| b.iter(|| field.update(grid, &rule)));
+    group.finish();
+}
+
+criterion_group!(benches, throughput, scent);
+criterion_main!(benches);
@@ -1,6 +1,16 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::data::*;
+use crate::rng::*;
+use crate::rules::*;
+use crate::scent::*;
 use crate::world::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum TurnDirection {
     Left,
     Right,
@@ -8,19 +18,109 @@ pub enum TurnDirection {
 
 impl TurnDirection {
     pub fn apply_to(self, direction: Direction) -> Direction {
-        let d = match self {
-            TurnDirection::Left => -1,
-            TurnDirection::Right => 1,
+        match self {
+            TurnDirection::Left => direction.rotated(-1),
+            TurnDirection::Right => direction.rotated(1),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SwarmCensus {
+    pub friends: usize,
+    pub foes: usize,
+    pub score: u32,
+    pub foe_score: u32,
+}
+
+impl SwarmCensus {
+    pub fn of(world: &World, color: Color) -> Self {
+        SwarmCensus {
+            friends: world.swarm_size(color),
+            foes: world.foes(color).map(|foe| world.swarm_size(foe)).sum(),
+            score: world.food_in_anthill(color),
+            foe_score: world
+                .foes(color)
+                .map(|foe| world.food_in_anthill(foe))
+                .max()
+                .unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum SwarmCondition {
+    SizeBelow(usize),
+    Outnumbered,
+    ScoreAhead,
+}
+
+impl SwarmCondition {
+    pub fn holds(self, census: SwarmCensus) -> bool {
+        match self {
+            SwarmCondition::SizeBelow(n) => census.friends < n,
+            SwarmCondition::Outnumbered => census.friends < census.foes,
+            SwarmCondition::ScoreAhead => census.score > census.foe_score,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum SenseCondition {
+    Friend,
+    Foe,
+    Food,
+    Wall,
+    Home,
+    FoeHome,
+    Marker(Marker),
+    FoeMarker,
+}
+
+impl SenseCondition {
+    pub fn holds(self, ant: &AntMut, sense_dir: SenseDirection, rules: &RuleSet) -> bool {
+        let Some(cell) = ant.sensed_cell(sense_dir) else {
+            return self == SenseCondition::Wall;
         };
-        let direction: u32 = direction.into();
-        let direction = direction as i32;
-        let direction = (direction + d + 6) % 6;
-        let direction = direction as u32;
-        Direction::try_from(direction).unwrap()
+        match self {
+            SenseCondition::Friend => cell
+                .ant()
+                .is_some_and(|id| ant.other(id).color() == ant.color()),
+            SenseCondition::Foe => cell
+                .ant()
+                .is_some_and(|id| ant.other(id).color() != ant.color()),
+            SenseCondition::Food => cell.has_food(),
+            SenseCondition::Wall => *cell == Cell::Wall,
+            SenseCondition::Home => cell.anthill() == Some(ant.color()),
+            SenseCondition::FoeHome => cell.anthill().is_some_and(|home| home != ant.color()),
+            SenseCondition::Marker(marker) => cell.has_marker(ant.color(), marker),
+            SenseCondition::FoeMarker => match rules.foe_markers {
+                MarkerVisibility::Aggregated => cell.has_foreign_marker(ant.color()),
+                MarkerVisibility::Private => false,
+            },
+        }
     }
 }
 
+pub struct EvalContext<'a> {
+    pub rules: &'a RuleSet,
+    pub census: SwarmCensus,
+    pub rng: &'a mut Rng,
+    // Only there when the rules have a `ScentRule`.
+    pub scent: Option<&'a ScentField>,
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
 pub enum Instr {
     Turn {
         direction: TurnDirection,
@@ -39,59 +139,2089 @@ pub enum Instr {
         success_instr: InstrIdx,
         fail_instr: InstrIdx,
     },
+    // Fails when the ant has no food or nowhere to put it, see
+    // `FoodOverflow`.
     DropFood {
+        success_instr: InstrIdx,
+        fail_instr: InstrIdx,
+    },
+    Mark {
+        marker: Marker,
+        next_instr: InstrIdx,
+    },
+    Unmark {
+        marker: Marker,
         next_instr: InstrIdx,
     },
+    Sense {
+        sense_dir: SenseDirection,
+        condition: SenseCondition,
+        success_instr: InstrIdx,
+        fail_instr: InstrIdx,
+    },
+    SenseSwarm {
+        condition: SwarmCondition,
+        success_instr: InstrIdx,
+        fail_instr: InstrIdx,
+    },
+    Flip {
+        n: u32,
+        success_instr: InstrIdx,
+        fail_instr: InstrIdx,
+    },
+    // Succeeds when the scent is stronger at the sensed cell than where the
+    // ant stands, and never without a `ScentRule`.
+    SenseScent {
+        sense_dir: SenseDirection,
+        scent: Scent,
+        success_instr: InstrIdx,
+        fail_instr: InstrIdx,
+    },
+    // Fails when the ant is not hungry or has nothing to eat, and always
+    // without a `Metabolism`.
+    Eat {
+        success_instr: InstrIdx,
+        fail_instr: InstrIdx,
+    },
+    Set {
+        register: Register,
+        value: u8,
+        next_instr: InstrIdx,
+    },
+    // Wraps around past 255.
+    Add {
+        register: Register,
+        value: u8,
+        next_instr: InstrIdx,
+    },
+    // Stops at zero.
+    Dec {
+        register: Register,
+        next_instr: InstrIdx,
+    },
+    JumpIfZero {
+        register: Register,
+        success_instr: InstrIdx,
+        fail_instr: InstrIdx,
+    },
+    // Jumps to `target`, to come back to `next_instr` on `Return`, see
+    // `CallStack` for what happens when the stack is full.
+    Call {
+        target: InstrIdx,
+        next_instr: InstrIdx,
+    },
+    // Returning with nothing on the stack starts the program over.
+    Return,
+}
+
+// The instruction without its operands. The discriminants are the opcodes
+// every binary format uses; they are part of the public interface, so
+// existing ones never change and new instructions take the next free one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[repr(u8)]
+pub enum InstrKind {
+    Turn = 0,
+    Move = 1,
+    Direction = 2,
+    PickUpFood = 3,
+    DropFood = 4,
+    Mark = 5,
+    Unmark = 6,
+    Sense = 7,
+    SenseSwarm = 8,
+    Flip = 9,
+    Eat = 10,
+    Set = 11,
+    Add = 12,
+    Dec = 13,
+    JumpIfZero = 14,
+    Call = 15,
+    Return = 16,
+    SenseScent = 17,
+}
+
+impl InstrKind {
+    pub const COUNT: usize = 18;
+
+    // In opcode order.
+    pub const ALL: [InstrKind; InstrKind::COUNT] = [
+        InstrKind::Turn,
+        InstrKind::Move,
+        InstrKind::Direction,
+        InstrKind::PickUpFood,
+        InstrKind::DropFood,
+        InstrKind::Mark,
+        InstrKind::Unmark,
+        InstrKind::Sense,
+        InstrKind::SenseSwarm,
+        InstrKind::Flip,
+        InstrKind::Eat,
+        InstrKind::Set,
+        InstrKind::Add,
+        InstrKind::Dec,
+        InstrKind::JumpIfZero,
+        InstrKind::Call,
+        InstrKind::Return,
+        InstrKind::SenseScent,
+    ];
+
+    pub fn opcode(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_opcode(opcode: u8) -> Option<InstrKind> {
+        InstrKind::ALL.get(usize::from(opcode)).copied()
+    }
+
+    // Moves the ant or the food; the rest only look around, mark or
+    // compute.
+    pub fn is_physical(self) -> bool {
+        matches!(
+            self,
+            InstrKind::Move | InstrKind::PickUpFood | InstrKind::DropFood | InstrKind::Turn
+        )
+    }
+
+    // The mnemonic in asm source.
+    pub fn name(self) -> &'static str {
+        match self {
+            InstrKind::Turn => "Turn",
+            InstrKind::Move => "Move",
+            InstrKind::Direction => "Direction",
+            InstrKind::PickUpFood => "PickUpFood",
+            InstrKind::DropFood => "DropFood",
+            InstrKind::Mark => "Mark",
+            InstrKind::Unmark => "Unmark",
+            InstrKind::Sense => "Sense",
+            InstrKind::SenseSwarm => "SenseSwarm",
+            InstrKind::Flip => "Flip",
+            InstrKind::Eat => "Eat",
+            InstrKind::Set => "Set",
+            InstrKind::Add => "Add",
+            InstrKind::Dec => "Dec",
+            InstrKind::JumpIfZero => "JumpIfZero",
+            InstrKind::Call => "Call",
+            InstrKind::Return => "Return",
+            InstrKind::SenseScent => "SenseScent",
+        }
+    }
 }
 
 impl Instr {
-    pub fn eval(self, ant: &mut AntMut) -> InstrIdx {
+    pub fn kind(self) -> InstrKind {
+        match self {
+            Instr::Turn { .. } => InstrKind::Turn,
+            Instr::Move { .. } => InstrKind::Move,
+            Instr::Direction { .. } => InstrKind::Direction,
+            Instr::PickUpFood { .. } => InstrKind::PickUpFood,
+            Instr::DropFood { .. } => InstrKind::DropFood,
+            Instr::Mark { .. } => InstrKind::Mark,
+            Instr::Unmark { .. } => InstrKind::Unmark,
+            Instr::Sense { .. } => InstrKind::Sense,
+            Instr::SenseSwarm { .. } => InstrKind::SenseSwarm,
+            Instr::Flip { .. } => InstrKind::Flip,
+            Instr::Eat { .. } => InstrKind::Eat,
+            Instr::Set { .. } => InstrKind::Set,
+            Instr::Add { .. } => InstrKind::Add,
+            Instr::Dec { .. } => InstrKind::Dec,
+            Instr::JumpIfZero { .. } => InstrKind::JumpIfZero,
+            Instr::Call { .. } => InstrKind::Call,
+            Instr::Return => InstrKind::Return,
+            Instr::SenseScent { .. } => InstrKind::SenseScent,
+        }
+    }
+
+    pub fn map_targets(self, mut f: impl FnMut(InstrIdx) -> InstrIdx) -> Instr {
+        let mut instr = self;
+        match &mut instr {
+            Instr::Turn { next_instr, .. }
+            | Instr::Mark { next_instr, .. }
+            | Instr::Unmark { next_instr, .. }
+            | Instr::Set { next_instr, .. }
+            | Instr::Add { next_instr, .. }
+            | Instr::Dec { next_instr, .. } => {
+                *next_instr = f(*next_instr);
+            }
+            Instr::Move {
+                success_instr,
+                fail_instr,
+            }
+            | Instr::Direction {
+                success_instr,
+                fail_instr,
+                ..
+            }
+            | Instr::PickUpFood {
+                success_instr,
+                fail_instr,
+            }
+            | Instr::DropFood {
+                success_instr,
+                fail_instr,
+            }
+            | Instr::Sense {
+                success_instr,
+                fail_instr,
+                ..
+            }
+            | Instr::SenseSwarm {
+                success_instr,
+                fail_instr,
+                ..
+            }
+            | Instr::Flip {
+                success_instr,
+                fail_instr,
+                ..
+            }
+            | Instr::SenseScent {
+                success_instr,
+                fail_instr,
+                ..
+            }
+            | Instr::Eat {
+                success_instr,
+                fail_instr,
+            }
+            | Instr::JumpIfZero {
+                success_instr,
+                fail_instr,
+                ..
+            } => {
+                *success_instr = f(*success_instr);
+                *fail_instr = f(*fail_instr);
+            }
+            Instr::Call { target, next_instr } => {
+                *target = f(*target);
+                *next_instr = f(*next_instr);
+            }
+            Instr::Return => {}
+        }
+        instr
+    }
+
+    pub fn targets(self) -> Vec<InstrIdx> {
+        let mut targets = Vec::new();
+        self.map_targets(|target| {
+            targets.push(target);
+            target
+        });
+        targets
+    }
+
+    pub fn eval(self, ant: &mut AntMut, ctx: &mut EvalContext) -> InstrIdx {
+        self.execute(ant, ctx).0
+    }
+
+    // Like `eval`, but also reports the action that took effect, if any.
+    pub fn execute(self, ant: &mut AntMut, ctx: &mut EvalContext) -> (InstrIdx, Option<Action>) {
+        let rules = ctx.rules;
+        self.command(ant, ctx)
+            .resolve(|action| action.apply(ant, rules).is_ok())
+    }
+
+    // Evaluates everything the ant decides alone: branches, registers and
+    // the call stack. What it wants done to the world comes back as an
+    // action, for `World::apply` to take or refuse.
+    pub fn command(self, ant: &mut AntMut, ctx: &mut EvalContext) -> Command {
         match self {
             Instr::Turn {
                 direction,
                 next_instr,
             } => {
                 let new_direction = direction.apply_to(ant.direction());
-                ant.rotate(new_direction);
-                next_instr
+                Command::Act {
+                    action: Action::Turn(new_direction),
+                    success_instr: next_instr,
+                    fail_instr: next_instr,
+                }
             }
             Instr::Move {
                 success_instr,
                 fail_instr,
-            } => {
-                if ant.move_forward().is_ok() {
-                    success_instr
-                } else {
-                    fail_instr
-                }
-            }
+            } => Command::Act {
+                action: Action::Move,
+                success_instr,
+                fail_instr,
+            },
             Instr::Direction {
                 direction,
                 success_instr,
                 fail_instr,
             } => {
                 if ant.direction() == direction {
-                    success_instr
+                    Command::Jump(success_instr)
                 } else {
-                    fail_instr
+                    Command::Jump(fail_instr)
                 }
             }
             Instr::PickUpFood {
                 success_instr,
                 fail_instr,
+            } => Command::Act {
+                action: Action::PickUpFood,
+                success_instr,
+                fail_instr,
+            },
+            Instr::DropFood {
+                success_instr,
+                fail_instr,
+            } => Command::Act {
+                action: Action::DropFood,
+                success_instr,
+                fail_instr,
+            },
+            Instr::Mark { marker, next_instr } => Command::Act {
+                action: Action::Mark(marker),
+                success_instr: next_instr,
+                fail_instr: next_instr,
+            },
+            Instr::Unmark { marker, next_instr } => Command::Act {
+                action: Action::Unmark(marker),
+                success_instr: next_instr,
+                fail_instr: next_instr,
+            },
+            Instr::Sense {
+                sense_dir,
+                condition,
+                success_instr,
+                fail_instr,
+            } => {
+                if condition.holds(ant, sense_dir, ctx.rules) {
+                    Command::Jump(success_instr)
+                } else {
+                    Command::Jump(fail_instr)
+                }
+            }
+            Instr::SenseSwarm {
+                condition,
+                success_instr,
+                fail_instr,
             } => {
-                if ant.pickup_food().is_ok() {
-                    success_instr
+                if ctx.rules.swarm_sensing && condition.holds(ctx.census) {
+                    Command::Jump(success_instr)
                 } else {
-                    fail_instr
+                    Command::Jump(fail_instr)
                 }
             }
-            Instr::DropFood { next_instr } => {
-                let _ = ant.drop_food();
-                next_instr
+            Instr::Flip {
+                n,
+                success_instr,
+                fail_instr,
+            } => {
+                if n > 0 && ctx.rng.below(n) == 0 {
+                    Command::Jump(success_instr)
+                } else {
+                    Command::Jump(fail_instr)
+                }
+            }
+            Instr::SenseScent {
+                sense_dir,
+                scent,
+                success_instr,
+                fail_instr,
+            } => {
+                let stronger = ctx.scent.is_some_and(|field| {
+                    let strength = |position| field.strength(scent, ant.color(), position);
+                    strength(ant.sensed_position(sense_dir)) > strength(ant.position())
+                });
+                if stronger {
+                    Command::Jump(success_instr)
+                } else {
+                    Command::Jump(fail_instr)
+                }
             }
+            Instr::Eat {
+                success_instr,
+                fail_instr,
+            } => Command::Act {
+                action: Action::Eat,
+                success_instr,
+                fail_instr,
+            },
+            Instr::Set {
+                register,
+                value,
+                next_instr,
+            } => {
+                let _ = ant.set_register(register, value);
+                Command::Jump(next_instr)
+            }
+            Instr::Add {
+                register,
+                value,
+                next_instr,
+            } => {
+                let _ = ant.set_register(register, ant.register(register).wrapping_add(value));
+                Command::Jump(next_instr)
+            }
+            Instr::Dec {
+                register,
+                next_instr,
+            } => {
+                let _ = ant.set_register(register, ant.register(register).saturating_sub(1));
+                Command::Jump(next_instr)
+            }
+            Instr::JumpIfZero {
+                register,
+                success_instr,
+                fail_instr,
+            } => {
+                if ant.register(register) == 0 {
+                    Command::Jump(success_instr)
+                } else {
+                    Command::Jump(fail_instr)
+                }
+            }
+            Instr::Call { target, next_instr } => {
+                ant.call_stack_mut().push(next_instr);
+                Command::Jump(target)
+            }
+            Instr::Return => Command::Jump(ant.call_stack_mut().pop().unwrap_or(0)),
         }
     }
 }
 
 pub type Program = Vec<Instr>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AsmErrorKind {
+    UnknownInstruction(String),
+    UnknownLabel(String),
+    DuplicateLabel(String),
+    InvalidLabel(String),
+    InvalidOperand(String),
+    MissingOperand,
+    UnexpectedOperand(String),
+}
+
+impl fmt::Display for AsmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AsmErrorKind::UnknownInstruction(name) => write!(f, "unknown instruction `{}`", name),
+            AsmErrorKind::UnknownLabel(label) => write!(f, "unknown label `{}`", label),
+            AsmErrorKind::DuplicateLabel(label) => write!(f, "label `{}` is defined twice", label),
+            AsmErrorKind::InvalidLabel(label) => write!(f, "invalid label `{}`", label),
+            AsmErrorKind::InvalidOperand(operand) => write!(f, "invalid operand `{}`", operand),
+            AsmErrorKind::MissingOperand => write!(f, "missing operand"),
+            AsmErrorKind::UnexpectedOperand(operand) => {
+                write!(f, "unexpected operand `{}`", operand)
+            }
+        }
+    }
+}
+
+// `line` is 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub line: usize,
+    pub kind: AsmErrorKind,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+struct Operands<'a> {
+    line: usize,
+    tokens: std::slice::Iter<'a, &'a str>,
+    labels: &'a HashMap<&'a str, InstrIdx>,
+}
+
+impl<'a> Operands<'a> {
+    fn error(&self, kind: AsmErrorKind) -> AsmError {
+        AsmError {
+            line: self.line,
+            kind,
+        }
+    }
+
+    fn next(&mut self) -> Result<&'a str, AsmError> {
+        match self.tokens.next() {
+            Some(token) => Ok(token),
+            None => Err(self.error(AsmErrorKind::MissingOperand)),
+        }
+    }
+
+    fn number<T: FromStr>(&mut self) -> Result<T, AsmError> {
+        let token = self.next()?;
+        token
+            .parse()
+            .map_err(|_| self.error(AsmErrorKind::InvalidOperand(token.to_string())))
+    }
+
+    fn target(&mut self) -> Result<InstrIdx, AsmError> {
+        let token = self.next()?;
+        if token.starts_with(|c: char| c.is_ascii_digit()) {
+            return token
+                .parse()
+                .map_err(|_| self.error(AsmErrorKind::InvalidOperand(token.to_string())));
+        }
+        match self.labels.get(token) {
+            Some(&idx) => Ok(idx),
+            None => Err(self.error(AsmErrorKind::UnknownLabel(token.to_string()))),
+        }
+    }
+
+    // `r0` to `r3`.
+    fn register(&mut self) -> Result<Register, AsmError> {
+        let token = self.next()?;
+        token
+            .strip_prefix(['r', 'R'])
+            .and_then(|n| n.parse().ok())
+            .filter(|&register: &Register| usize::from(register) < REGISTERS)
+            .ok_or_else(|| self.error(AsmErrorKind::InvalidOperand(token.to_string())))
+    }
+
+    fn optional_target(&mut self) -> Result<Option<InstrIdx>, AsmError> {
+        if self.tokens.as_slice().is_empty() {
+            return Ok(None);
+        }
+        self.target().map(Some)
+    }
+
+    fn keyword<T: Copy>(&mut self, options: &[(&str, T)]) -> Result<T, AsmError> {
+        let token = self.next()?;
+        options
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(token))
+            .map(|&(_, value)| value)
+            .ok_or_else(|| self.error(AsmErrorKind::InvalidOperand(token.to_string())))
+    }
+
+    fn finish(mut self) -> Result<(), AsmError> {
+        match self.tokens.next() {
+            Some(token) => Err(self.error(AsmErrorKind::UnexpectedOperand(token.to_string()))),
+            None => Ok(()),
+        }
+    }
+}
+
+pub(crate) const DIRECTIONS: [(&str, Direction); 6] = [
+    ("Right", Direction::Right),
+    ("DownRight", Direction::DownRight),
+    ("DownLeft", Direction::DownLeft),
+    ("Left", Direction::Left),
+    ("UpLeft", Direction::UpLeft),
+    ("UpRight", Direction::UpRight),
+];
+
+pub(crate) const TURN_DIRECTIONS: [(&str, TurnDirection); 2] = [
+    ("Left", TurnDirection::Left),
+    ("Right", TurnDirection::Right),
+];
+
+pub(crate) const SENSE_DIRECTIONS: [(&str, SenseDirection); 4] = [
+    ("Here", SenseDirection::Here),
+    ("Ahead", SenseDirection::Ahead),
+    ("LeftAhead", SenseDirection::LeftAhead),
+    ("RightAhead", SenseDirection::RightAhead),
+];
+
+pub(crate) const SCENTS: [(&str, Scent); 2] = [("Food", Scent::Food), ("Home", Scent::Home)];
+
+// All but `Marker`, which takes an operand.
+pub(crate) const SENSE_CONDITIONS: [(&str, SenseCondition); 7] = [
+    ("Friend", SenseCondition::Friend),
+    ("Foe", SenseCondition::Foe),
+    ("Food", SenseCondition::Food),
+    ("Wall", SenseCondition::Wall),
+    ("Home", SenseCondition::Home),
+    ("FoeHome", SenseCondition::FoeHome),
+    ("FoeMarker", SenseCondition::FoeMarker),
+];
+
+fn parse_sense_condition(operands: &mut Operands) -> Result<SenseCondition, AsmError> {
+    let token = operands.next()?;
+    if let Some(&(_, condition)) = SENSE_CONDITIONS
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(token))
+    {
+        return Ok(condition);
+    }
+    if token.eq_ignore_ascii_case("Marker") {
+        return Ok(SenseCondition::Marker(operands.number()?));
+    }
+    Err(operands.error(AsmErrorKind::InvalidOperand(token.to_string())))
+}
+
+fn parse_swarm_condition(operands: &mut Operands) -> Result<SwarmCondition, AsmError> {
+    let token = operands.next()?;
+    if token.eq_ignore_ascii_case("SizeBelow") {
+        Ok(SwarmCondition::SizeBelow(operands.number()?))
+    } else if token.eq_ignore_ascii_case("Outnumbered") {
+        Ok(SwarmCondition::Outnumbered)
+    } else if token.eq_ignore_ascii_case("ScoreAhead") {
+        Ok(SwarmCondition::ScoreAhead)
+    } else {
+        Err(operands.error(AsmErrorKind::InvalidOperand(token.to_string())))
+    }
+}
+
+fn parse_instr(mnemonic: &str, operands: &mut Operands) -> Result<Instr, AsmError> {
+    let instr = match mnemonic.to_ascii_lowercase().as_str() {
+        "turn" => Instr::Turn {
+            direction: operands.keyword(&TURN_DIRECTIONS)?,
+            next_instr: operands.target()?,
+        },
+        "move" => Instr::Move {
+            success_instr: operands.target()?,
+            fail_instr: operands.target()?,
+        },
+        "direction" => Instr::Direction {
+            direction: operands.keyword(&DIRECTIONS)?,
+            success_instr: operands.target()?,
+            fail_instr: operands.target()?,
+        },
+        "pickupfood" => Instr::PickUpFood {
+            success_instr: operands.target()?,
+            fail_instr: operands.target()?,
+        },
+        // The fail branch may be left out: classic programs only ever go on
+        // to the next instruction.
+        "dropfood" => {
+            let success_instr = operands.target()?;
+            Instr::DropFood {
+                success_instr,
+                fail_instr: operands.optional_target()?.unwrap_or(success_instr),
+            }
+        }
+        "mark" => Instr::Mark {
+            marker: operands.number()?,
+            next_instr: operands.target()?,
+        },
+        "unmark" => Instr::Unmark {
+            marker: operands.number()?,
+            next_instr: operands.target()?,
+        },
+        "sense" => Instr::Sense {
+            sense_dir: operands.keyword(&SENSE_DIRECTIONS)?,
+            condition: parse_sense_condition(operands)?,
+            success_instr: operands.target()?,
+            fail_instr: operands.target()?,
+        },
+        "senseswarm" => Instr::SenseSwarm {
+            condition: parse_swarm_condition(operands)?,
+            success_instr: operands.target()?,
+            fail_instr: operands.target()?,
+        },
+        "flip" => Instr::Flip {
+            n: operands.number()?,
+            success_instr: operands.target()?,
+            fail_instr: operands.target()?,
+        },
+        "sensescent" => Instr::SenseScent {
+            sense_dir: operands.keyword(&SENSE_DIRECTIONS)?,
+            scent: operands.keyword(&SCENTS)?,
+            success_instr: operands.target()?,
+            fail_instr: operands.target()?,
+        },
+        "eat" => Instr::Eat {
+            success_instr: operands.target()?,
+            fail_instr: operands.target()?,
+        },
+        "set" => Instr::Set {
+            register: operands.register()?,
+            value: operands.number()?,
+            next_instr: operands.target()?,
+        },
+        "add" => Instr::Add {
+            register: operands.register()?,
+            value: operands.number()?,
+            next_instr: operands.target()?,
+        },
+        "dec" => Instr::Dec {
+            register: operands.register()?,
+            next_instr: operands.target()?,
+        },
+        "jumpifzero" => Instr::JumpIfZero {
+            register: operands.register()?,
+            success_instr: operands.target()?,
+            fail_instr: operands.target()?,
+        },
+        "call" => Instr::Call {
+            target: operands.target()?,
+            next_instr: operands.target()?,
+        },
+        "return" => Instr::Return,
+        _ => {
+            return Err(operands.error(AsmErrorKind::UnknownInstruction(mnemonic.to_string())));
+        }
+    };
+    Ok(instr)
+}
+
+pub(crate) fn is_label(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Parses line-oriented assembly: one instruction per line, `;` starts a
+// comment, and `name:` labels the next instruction. Branch targets are
+// either labels or absolute instruction indices:
+//
+//   start:  Sense Ahead Food found start
+//   found:  Move pickup start
+//   pickup: PickUpFood start start
+pub fn parse_program(source: &str) -> Result<Program, AsmError> {
+    let mut labels = HashMap::new();
+    let mut lines = Vec::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_no = line_idx + 1;
+        let mut rest = line.split(';').next().unwrap();
+        while let Some((label, tail)) = rest.split_once(':') {
+            let label = label.trim();
+            let error = |kind| AsmError {
+                line: line_no,
+                kind,
+            };
+            if !is_label(label) {
+                return Err(error(AsmErrorKind::InvalidLabel(label.to_string())));
+            }
+            if labels.insert(label, lines.len()).is_some() {
+                return Err(error(AsmErrorKind::DuplicateLabel(label.to_string())));
+            }
+            rest = tail;
+        }
+        let tokens: Vec<&str> = rest.split_whitespace().collect();
+        if !tokens.is_empty() {
+            lines.push((line_no, tokens));
+        }
+    }
+
+    lines
+        .iter()
+        .map(|(line, tokens)| {
+            let mut operands = Operands {
+                line: *line,
+                tokens: tokens[1..].iter(),
+                labels: &labels,
+            };
+            let instr = parse_instr(tokens[0], &mut operands)?;
+            operands.finish()?;
+            Ok(instr)
+        })
+        .collect()
+}
+
+fn keyword<T: PartialEq>(options: &[(&'static str, T)], value: T) -> &'static str {
+    options.iter().find(|(_, v)| *v == value).unwrap().0
+}
+
+impl Instr {
+    fn write(self, f: &mut fmt::Formatter, target: &dyn Fn(InstrIdx) -> String) -> fmt::Result {
+        match self {
+            Instr::Turn {
+                direction,
+                next_instr,
+            } => write!(
+                f,
+                "Turn {} {}",
+                keyword(&TURN_DIRECTIONS, direction),
+                target(next_instr)
+            ),
+            Instr::Move {
+                success_instr,
+                fail_instr,
+            } => write!(f, "Move {} {}", target(success_instr), target(fail_instr)),
+            Instr::Direction {
+                direction,
+                success_instr,
+                fail_instr,
+            } => write!(
+                f,
+                "Direction {} {} {}",
+                keyword(&DIRECTIONS, direction),
+                target(success_instr),
+                target(fail_instr)
+            ),
+            Instr::PickUpFood {
+                success_instr,
+                fail_instr,
+            } => write!(
+                f,
+                "PickUpFood {} {}",
+                target(success_instr),
+                target(fail_instr)
+            ),
+            Instr::DropFood {
+                success_instr,
+                fail_instr,
+            } if success_instr == fail_instr => write!(f, "DropFood {}", target(success_instr)),
+            Instr::DropFood {
+                success_instr,
+                fail_instr,
+            } => write!(
+                f,
+                "DropFood {} {}",
+                target(success_instr),
+                target(fail_instr)
+            ),
+            Instr::Mark { marker, next_instr } => {
+                write!(f, "Mark {} {}", marker, target(next_instr))
+            }
+            Instr::Unmark { marker, next_instr } => {
+                write!(f, "Unmark {} {}", marker, target(next_instr))
+            }
+            Instr::Sense {
+                sense_dir,
+                condition,
+                success_instr,
+                fail_instr,
+            } => write!(
+                f,
+                "Sense {} {} {} {}",
+                keyword(&SENSE_DIRECTIONS, sense_dir),
+                condition,
+                target(success_instr),
+                target(fail_instr)
+            ),
+            Instr::SenseSwarm {
+                condition,
+                success_instr,
+                fail_instr,
+            } => write!(
+                f,
+                "SenseSwarm {} {} {}",
+                condition,
+                target(success_instr),
+                target(fail_instr)
+            ),
+            Instr::Flip {
+                n,
+                success_instr,
+                fail_instr,
+            } => write!(
+                f,
+                "Flip {} {} {}",
+                n,
+                target(success_instr),
+                target(fail_instr)
+            ),
+            Instr::SenseScent {
+                sense_dir,
+                scent,
+                success_instr,
+                fail_instr,
+            } => write!(
+                f,
+                "SenseScent {} {} {} {}",
+                keyword(&SENSE_DIRECTIONS, sense_dir),
+                keyword(&SCENTS, scent),
+                target(success_instr),
+                target(fail_instr)
+            ),
+            Instr::Eat {
+                success_instr,
+                fail_instr,
+            } => write!(f, "Eat {} {}", target(success_instr), target(fail_instr)),
+            Instr::Set {
+                register,
+                value,
+                next_instr,
+            } => write!(f, "Set r{} {} {}", register, value, target(next_instr)),
+            Instr::Add {
+                register,
+                value,
+                next_instr,
+            } => write!(f, "Add r{} {} {}", register, value, target(next_instr)),
+            Instr::Dec {
+                register,
+                next_instr,
+            } => write!(f, "Dec r{} {}", register, target(next_instr)),
+            Instr::JumpIfZero {
+                register,
+                success_instr,
+                fail_instr,
+            } => write!(
+                f,
+                "JumpIfZero r{} {} {}",
+                register,
+                target(success_instr),
+                target(fail_instr)
+            ),
+            Instr::Call {
+                target: call,
+                next_instr,
+            } => {
+                write!(f, "Call {} {}", target(call), target(next_instr))
+            }
+            Instr::Return => write!(f, "Return"),
+        }
+    }
+}
+
+impl fmt::Display for SenseCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SenseCondition::Friend => write!(f, "Friend"),
+            SenseCondition::Foe => write!(f, "Foe"),
+            SenseCondition::Food => write!(f, "Food"),
+            SenseCondition::Wall => write!(f, "Wall"),
+            SenseCondition::Home => write!(f, "Home"),
+            SenseCondition::FoeHome => write!(f, "FoeHome"),
+            SenseCondition::Marker(marker) => write!(f, "Marker {}", marker),
+            SenseCondition::FoeMarker => write!(f, "FoeMarker"),
+        }
+    }
+}
+
+impl fmt::Display for SwarmCondition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SwarmCondition::SizeBelow(n) => write!(f, "SizeBelow {}", n),
+            SwarmCondition::Outnumbered => write!(f, "Outnumbered"),
+            SwarmCondition::ScoreAhead => write!(f, "ScoreAhead"),
+        }
+    }
+}
+
+// Assembly syntax with absolute instruction indices as targets.
+impl fmt::Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.write(f, &|target| target.to_string())
+    }
+}
+
+struct Labelled<'a> {
+    instr: Instr,
+    labels: &'a BTreeSet<InstrIdx>,
+}
+
+impl fmt::Display for Labelled<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.instr.write(f, &|target| {
+            if self.labels.contains(&target) {
+                format!("L{}", target)
+            } else {
+                target.to_string()
+            }
+        })
+    }
+}
+
+// Emits assembly that `parse_program` reads back into the same program.
+// Every jump target gets a synthesized `L<index>` label; targets outside
+// the program stay numeric.
+pub fn disassemble(program: &Program) -> String {
+    let labels: BTreeSet<InstrIdx> = program
+        .iter()
+        .flat_map(|instr| instr.targets())
+        .filter(|&target| target < program.len())
+        .collect();
+    let width = labels
+        .iter()
+        .last()
+        .map_or(0, |&last| format!("L{}: ", last).len());
+
+    let mut out = String::new();
+    for (idx, &instr) in program.iter().enumerate() {
+        let label = if labels.contains(&idx) {
+            format!("L{}:", idx)
+        } else {
+            String::new()
+        };
+        let line = format!(
+            "{:<width$}{}",
+            label,
+            Labelled {
+                instr,
+                labels: &labels
+            },
+            width = width
+        );
+        out.push_str(&line);
+        out.push('\n');
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgramError {
+    Empty,
+    TargetOutOfBounds { instr: InstrIdx, target: InstrIdx },
+    Unreachable { instr: InstrIdx },
+    SelfLoop { instr: InstrIdx },
+}
+
+impl fmt::Display for ProgramError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProgramError::Empty => write!(f, "the program is empty"),
+            ProgramError::TargetOutOfBounds { instr, target } => write!(
+                f,
+                "instruction {} jumps to {}, past the end of the program",
+                instr, target
+            ),
+            ProgramError::Unreachable { instr } => {
+                write!(f, "instruction {} is unreachable", instr)
+            }
+            ProgramError::SelfLoop { instr } => {
+                write!(f, "instruction {} only leads back to itself", instr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProgramError {}
+
+// The errors `validate` found, as one line.
+pub(crate) fn describe_errors(errors: &[ProgramError]) -> String {
+    let errors: Vec<_> = errors.iter().map(ProgramError::to_string).collect();
+    errors.join("; ")
+}
+
+impl ProgramError {
+    // Fatal errors would make the interpreter panic; the rest are lints.
+    pub fn is_fatal(self) -> bool {
+        matches!(
+            self,
+            ProgramError::Empty | ProgramError::TargetOutOfBounds { .. }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct ValidateOptions {
+    // Flag instructions whose every branch leads back to themselves: an ant
+    // reaching one is stuck there forever.
+    pub self_loops: bool,
+}
+
+pub fn validate(program: &Program) -> Result<(), Vec<ProgramError>> {
+    validate_with(program, ValidateOptions::default())
+}
+
+pub fn validate_with(program: &Program, options: ValidateOptions) -> Result<(), Vec<ProgramError>> {
+    if program.is_empty() {
+        return Err(vec![ProgramError::Empty]);
+    }
+    let mut errors = Vec::new();
+    for (idx, instr) in program.iter().enumerate() {
+        let mut targets = instr.targets();
+        targets.dedup();
+        for target in targets {
+            if target >= program.len() {
+                errors.push(ProgramError::TargetOutOfBounds { instr: idx, target });
+            }
+        }
+    }
+
+    let mut reachable = vec![false; program.len()];
+    let mut stack = vec![0];
+    while let Some(idx) = stack.pop() {
+        if idx >= program.len() || reachable[idx] {
+            continue;
+        }
+        reachable[idx] = true;
+        stack.extend(program[idx].targets());
+    }
+    errors.extend(
+        (0..program.len())
+            .filter(|&idx| !reachable[idx])
+            .map(|instr| ProgramError::Unreachable { instr }),
+    );
+
+    if options.self_loops {
+        errors.extend(
+            program
+                .iter()
+                .enumerate()
+                .filter(|(idx, instr)| {
+                    let targets = instr.targets();
+                    !targets.is_empty() && targets.iter().all(|target| target == idx)
+                })
+                .map(|(instr, _)| ProgramError::SelfLoop { instr }),
+        );
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    mod sense {
+        use super::*;
+
+        fn sense(
+            world: &mut World,
+            id: AntId,
+            sense_dir: SenseDirection,
+            condition: SenseCondition,
+        ) -> bool {
+            condition.holds(&world.ant_mut(id).unwrap(), sense_dir, &RuleSet::classic())
+        }
+
+        #[test]
+        fn friend_and_foe() {
+            let pos = Position { x: 5, y: 5 };
+            let mut world = World::new(Grid::new(10, 10));
+            let id = world.add_ant(Color::Red, pos).unwrap();
+            world
+                .add_ant(Color::Red, pos.translate(Direction::Right))
+                .unwrap();
+            world
+                .add_ant(Color::Black, pos.translate(Direction::DownRight))
+                .unwrap();
+
+            use SenseCondition::*;
+            use SenseDirection::*;
+            assert!(sense(&mut world, id, Here, Friend));
+            assert!(sense(&mut world, id, Ahead, Friend));
+            assert!(!sense(&mut world, id, Ahead, Foe));
+            assert!(sense(&mut world, id, RightAhead, Foe));
+            assert!(!sense(&mut world, id, RightAhead, Friend));
+            assert!(!sense(&mut world, id, LeftAhead, Friend));
+            assert!(!sense(&mut world, id, LeftAhead, Foe));
+        }
+
+        #[test]
+        fn food_and_wall() {
+            let pos = Position { x: 5, y: 5 };
+            let mut grid = Grid::new(10, 10);
+            *grid.cell_at_mut(pos.translate(Direction::Right)).unwrap() = Cell::Wall;
+            *grid.cell_at_mut(pos).unwrap() = Cell::FreeCell {
+                ant_id: None,
+                food: 1,
+                markers: Markers::default(),
+                anthill: None,
+            };
+            let mut world = World::new(grid);
+            let id = world.add_ant(Color::Red, pos).unwrap();
+
+            use SenseCondition::*;
+            use SenseDirection::*;
+            assert!(sense(&mut world, id, Here, Food));
+            assert!(!sense(&mut world, id, Here, Wall));
+            assert!(sense(&mut world, id, Ahead, Wall));
+            assert!(!sense(&mut world, id, Ahead, Food));
+        }
+
+        #[test]
+        fn markers() {
+            let pos = Position { x: 5, y: 5 };
+            let mut world = World::new(Grid::new(10, 10));
+            let red = world.add_ant(Color::Red, pos).unwrap();
+            let black = world
+                .add_ant(Color::Black, pos.translate(Direction::Right))
+                .unwrap();
+            world.ant_mut(red).unwrap().set_marker(1).unwrap();
+            world.ant_mut(black).unwrap().set_marker(4).unwrap();
+
+            use SenseCondition::*;
+            use SenseDirection::*;
+            assert!(sense(&mut world, red, Here, Marker(1)));
+            assert!(!sense(&mut world, red, Here, Marker(2)));
+            assert!(!sense(&mut world, red, Here, FoeMarker));
+            assert!(!sense(&mut world, red, Ahead, Marker(4)));
+            assert!(sense(&mut world, red, Ahead, FoeMarker));
+            assert!(sense(&mut world, black, Here, Marker(4)));
+
+            let private = RuleSet {
+                foe_markers: MarkerVisibility::Private,
+                ..RuleSet::classic()
+            };
+            assert!(!FoeMarker.holds(&world.ant_mut(red).unwrap(), Ahead, &private));
+            assert!(Marker(1).holds(&world.ant_mut(red).unwrap(), Here, &private));
+        }
+
+        #[test]
+        fn home_and_foe_home() {
+            let pos = Position { x: 5, y: 5 };
+            let mut grid = Grid::new(10, 10);
+            *grid.cell_at_mut(pos).unwrap() = Cell::new_anthill(Color::Red);
+            *grid.cell_at_mut(pos.translate(Direction::Right)).unwrap() =
+                Cell::new_anthill(Color::Black);
+            let mut world = World::new(grid);
+            let id = world.add_ant(Color::Red, pos).unwrap();
+
+            use SenseCondition::*;
+            use SenseDirection::*;
+            assert!(sense(&mut world, id, Here, Home));
+            assert!(!sense(&mut world, id, Here, FoeHome));
+            assert!(sense(&mut world, id, Ahead, FoeHome));
+            assert!(!sense(&mut world, id, Ahead, Home));
+            assert!(!sense(&mut world, id, LeftAhead, Home));
+        }
+
+        #[test]
+        fn out_of_bounds_is_wall() {
+            let mut world = World::new(Grid::new(10, 10));
+            let id = world.add_ant(Color::Red, Position { x: 9, y: 0 }).unwrap();
+
+            assert!(sense(
+                &mut world,
+                id,
+                SenseDirection::Ahead,
+                SenseCondition::Wall
+            ));
+            assert!(!sense(
+                &mut world,
+                id,
+                SenseDirection::Ahead,
+                SenseCondition::Food
+            ));
+        }
+
+        #[test]
+        fn commands_leave_the_world_to_apply() {
+            let mut world = World::new(Grid::new(4, 1));
+            let id = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+            let rules = RuleSet::classic();
+            let mut rng = Rng::new(0);
+            let mut ctx = EvalContext {
+                rules: &rules,
+                census: SwarmCensus::default(),
+                rng: &mut rng,
+                scent: None,
+            };
+            let instr = Instr::Move {
+                success_instr: 1,
+                fail_instr: 2,
+            };
+            let command = instr.command(&mut world.ant_mut(id).unwrap(), &mut ctx);
+            assert_eq!(
+                command,
+                Command::Act {
+                    action: Action::Move,
+                    success_instr: 1,
+                    fail_instr: 2,
+                }
+            );
+            assert_eq!(world.ant(id).unwrap().position(), Position { x: 0, y: 0 });
+            let resolved = command.resolve(|action| world.apply(id, action, &rules).is_ok());
+            assert_eq!(resolved, (1, Some(Action::Move)));
+            assert_eq!(world.ant(id).unwrap().position(), Position { x: 1, y: 0 });
+
+            let error = world.apply(id + 1, Action::Move, &rules).unwrap_err();
+            assert_eq!(error.error, WorldError::NoSuchAnt);
+        }
+
+        #[test]
+        fn eval_branches() {
+            let mut world = World::new(Grid::new(10, 10));
+            let id = world.add_ant(Color::Red, Position { x: 9, y: 0 }).unwrap();
+            let rules = RuleSet::classic();
+            let mut rng = Rng::new(0);
+            let mut ctx = EvalContext {
+                rules: &rules,
+                census: SwarmCensus::of(&world, Color::Red),
+                rng: &mut rng,
+                scent: None,
+            };
+            let instr = |condition| Instr::Sense {
+                sense_dir: SenseDirection::Ahead,
+                condition,
+                success_instr: 1,
+                fail_instr: 2,
+            };
+
+            let mut ant = world.ant_mut(id).unwrap();
+            assert_eq!(instr(SenseCondition::Wall).eval(&mut ant, &mut ctx), 1);
+            assert_eq!(instr(SenseCondition::Food).eval(&mut ant, &mut ctx), 2);
+        }
+    }
+
+    mod sense_swarm {
+        use super::*;
+
+        fn world_with_swarms(red: i32, black: i32) -> World {
+            let mut world = World::new(Grid::new(10, 10));
+            for x in 0..red {
+                world.add_ant(Color::Red, Position { x, y: 0 }).unwrap();
+            }
+            for x in 0..black {
+                world.add_ant(Color::Black, Position { x, y: 1 }).unwrap();
+            }
+            world
+        }
+
+        fn eval_sense(world: &mut World, rules: &RuleSet, condition: SwarmCondition) -> InstrIdx {
+            let mut rng = Rng::new(0);
+            let mut ctx = EvalContext {
+                rules,
+                census: SwarmCensus::of(world, Color::Red),
+                rng: &mut rng,
+                scent: None,
+            };
+            let instr = Instr::SenseSwarm {
+                condition,
+                success_instr: 1,
+                fail_instr: 2,
+            };
+            instr.eval(&mut world.ant_mut(0).unwrap(), &mut ctx)
+        }
+
+        #[test]
+        fn census() {
+            let world = world_with_swarms(2, 3);
+            assert_eq!(
+                SwarmCensus::of(&world, Color::Red),
+                SwarmCensus {
+                    friends: 2,
+                    foes: 3,
+                    score: 0,
+                    foe_score: 0,
+                }
+            );
+        }
+
+        #[test]
+        fn size_below() {
+            let mut world = world_with_swarms(2, 3);
+            let rules = RuleSet::extended();
+            assert_eq!(
+                eval_sense(&mut world, &rules, SwarmCondition::SizeBelow(3)),
+                1
+            );
+            assert_eq!(
+                eval_sense(&mut world, &rules, SwarmCondition::SizeBelow(2)),
+                2
+            );
+        }
+
+        #[test]
+        fn outnumbered() {
+            let rules = RuleSet::extended();
+            let mut world = world_with_swarms(2, 3);
+            assert_eq!(
+                eval_sense(&mut world, &rules, SwarmCondition::Outnumbered),
+                1
+            );
+            let mut world = world_with_swarms(3, 3);
+            assert_eq!(
+                eval_sense(&mut world, &rules, SwarmCondition::Outnumbered),
+                2
+            );
+        }
+
+        #[test]
+        fn score_ahead() {
+            let rules = RuleSet::extended();
+            let mut world = world_with_swarms(1, 1);
+            assert_eq!(
+                eval_sense(&mut world, &rules, SwarmCondition::ScoreAhead),
+                2
+            );
+
+            let home = Position { x: 5, y: 5 };
+            let mut grid = Grid::new(10, 10);
+            *grid.cell_at_mut(home).unwrap() = Cell::new_anthill(Color::Red);
+            grid.cell_at_mut(home).unwrap().try_drop_food().unwrap();
+            let mut world = World::new(grid);
+            world.add_ant(Color::Red, home).unwrap();
+            assert_eq!(
+                eval_sense(&mut world, &rules, SwarmCondition::ScoreAhead),
+                1
+            );
+        }
+
+        #[test]
+        fn disabled_in_classic() {
+            let mut world = world_with_swarms(2, 3);
+            let rules = RuleSet::classic();
+            assert_eq!(
+                eval_sense(&mut world, &rules, SwarmCondition::SizeBelow(3)),
+                2
+            );
+            assert_eq!(
+                eval_sense(&mut world, &rules, SwarmCondition::Outnumbered),
+                2
+            );
+        }
+    }
+
+    mod flip {
+        use super::*;
+
+        fn flips(n: u32, seed: u64, times: usize) -> Vec<InstrIdx> {
+            let mut world = World::new(Grid::new(10, 10));
+            let id = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+            let rules = RuleSet::classic();
+            let mut rng = Rng::new(seed);
+            let mut ctx = EvalContext {
+                rules: &rules,
+                census: SwarmCensus::of(&world, Color::Red),
+                rng: &mut rng,
+                scent: None,
+            };
+            let instr = Instr::Flip {
+                n,
+                success_instr: 1,
+                fail_instr: 2,
+            };
+            let mut ant = world.ant_mut(id).unwrap();
+            (0..times).map(|_| instr.eval(&mut ant, &mut ctx)).collect()
+        }
+
+        #[test]
+        fn one_always_succeeds() {
+            assert!(flips(1, 3, 50).iter().all(|&next| next == 1));
+        }
+
+        #[test]
+        fn zero_always_fails() {
+            assert!(flips(0, 3, 50).iter().all(|&next| next == 2));
+        }
+
+        #[test]
+        fn takes_both_branches() {
+            let outcomes = flips(2, 3, 100);
+            assert!(outcomes.contains(&1));
+            assert!(outcomes.contains(&2));
+        }
+
+        #[test]
+        fn reproducible() {
+            assert_eq!(flips(3, 11, 100), flips(3, 11, 100));
+        }
+    }
+
+    mod drop_food {
+        use super::*;
+
+        fn drop(world: &mut World, id: AntId, rules: &RuleSet) -> (InstrIdx, Option<Action>) {
+            let mut rng = Rng::new(0);
+            let mut ctx = EvalContext {
+                rules,
+                census: SwarmCensus::of(world, Color::Red),
+                rng: &mut rng,
+                scent: None,
+            };
+            let instr = Instr::DropFood {
+                success_instr: 1,
+                fail_instr: 2,
+            };
+            instr.execute(&mut world.ant_mut(id).unwrap(), &mut ctx)
+        }
+
+        // An ant carrying food on a cell already holding one piece, with
+        // walls all around.
+        fn walled_in() -> (World, AntId) {
+            let mut grid = Grid::with_wall_border(3, 3);
+            let center = Position { x: 1, y: 1 };
+            for _ in 0..2 {
+                grid.cell_at_mut(center).unwrap().try_drop_food().unwrap();
+            }
+            let mut world = World::new(grid);
+            let id = world.add_ant(Color::Red, center).unwrap();
+            world.ant_mut(id).unwrap().pickup_food().unwrap();
+            (world, id)
+        }
+
+        #[test]
+        fn without_food_fails() {
+            let mut world = World::new(Grid::new(3, 3));
+            let id = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+            assert_eq!(drop(&mut world, id, &RuleSet::classic()), (2, None));
+        }
+
+        #[test]
+        fn uncapped_succeeds() {
+            let (mut world, id) = walled_in();
+            assert_eq!(
+                drop(&mut world, id, &RuleSet::classic()),
+                (1, Some(Action::DropFood))
+            );
+            assert!(!world.ant(id).unwrap().carries_food());
+        }
+
+        #[test]
+        fn full_cell_fails() {
+            for overflow in [
+                FoodOverflow::Fail,
+                FoodOverflow::Spill,
+                FoodOverflow::Nearest,
+            ] {
+                let (mut world, id) = walled_in();
+                let mut rules = RuleSet::classic();
+                rules.food_cap = Some(FoodCap { limit: 1, overflow });
+                assert_eq!(drop(&mut world, id, &rules), (2, None), "{:?}", overflow);
+                assert!(world.ant(id).unwrap().carries_food());
+            }
+        }
+
+        #[test]
+        fn single_target_is_both_branches() {
+            let program = parse_program("DropFood 0\nDropFood 0 1").unwrap();
+            assert_eq!(
+                program,
+                vec![
+                    Instr::DropFood {
+                        success_instr: 0,
+                        fail_instr: 0,
+                    },
+                    Instr::DropFood {
+                        success_instr: 0,
+                        fail_instr: 1,
+                    },
+                ]
+            );
+        }
+    }
+
+    mod registers {
+        use super::*;
+
+        fn run(world: &mut World, id: AntId, instr: Instr) -> InstrIdx {
+            let rules = RuleSet::default();
+            let mut rng = Rng::new(0);
+            let mut ctx = EvalContext {
+                rules: &rules,
+                census: SwarmCensus::of(world, Color::Red),
+                rng: &mut rng,
+                scent: None,
+            };
+            instr.eval(&mut world.ant_mut(id).unwrap(), &mut ctx)
+        }
+
+        #[test]
+        fn arithmetic() {
+            let mut world = World::new(Grid::new(3, 3));
+            let id = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+            let program = parse_program(
+                "
+                Set r1 250 1
+                Add r1 10 2
+                Dec r2 3
+                Dec r1 4
+                JumpIfZero r2 5 6
+                JumpIfZero r1 5 6
+                ",
+            )
+            .unwrap();
+            assert_eq!(run(&mut world, id, program[0]), 1);
+            assert_eq!(world.ant(id).unwrap().registers(), [0, 250, 0, 0]);
+            run(&mut world, id, program[1]);
+            assert_eq!(world.ant(id).unwrap().registers(), [0, 4, 0, 0]);
+            run(&mut world, id, program[2]);
+            run(&mut world, id, program[3]);
+            assert_eq!(world.ant(id).unwrap().registers(), [0, 3, 0, 0]);
+            assert_eq!(run(&mut world, id, program[4]), 5);
+            assert_eq!(run(&mut world, id, program[5]), 6);
+        }
+
+        #[test]
+        fn out_of_range() {
+            let mut world = World::new(Grid::new(3, 3));
+            let id = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+            assert_eq!(
+                world.ant_mut(id).unwrap().set_register(4, 1),
+                Err(WorldError::InvalidRegister)
+            );
+            let set = Instr::Set {
+                register: 9,
+                value: 1,
+                next_instr: 1,
+            };
+            assert_eq!(run(&mut world, id, set), 1);
+            assert_eq!(world.ant_mut(id).unwrap().register(9), 0);
+        }
+    }
+
+    mod calls {
+        use super::*;
+
+        fn run(world: &mut World, id: AntId, instr: Instr) -> InstrIdx {
+            let rules = RuleSet::default();
+            let mut rng = Rng::new(0);
+            let mut ctx = EvalContext {
+                rules: &rules,
+                census: SwarmCensus::of(world, Color::Red),
+                rng: &mut rng,
+                scent: None,
+            };
+            instr.eval(&mut world.ant_mut(id).unwrap(), &mut ctx)
+        }
+
+        #[test]
+        fn call_and_return() {
+            let mut world = World::new(Grid::new(3, 3));
+            let id = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+            let program = parse_program("Call sub 1\nsub: Return").unwrap();
+            assert_eq!(
+                program[0],
+                Instr::Call {
+                    target: 1,
+                    next_instr: 1,
+                }
+            );
+            assert_eq!(run(&mut world, id, program[0]), 1);
+            assert_eq!(world.ant(id).unwrap().call_stack().frames(), [1]);
+            assert_eq!(run(&mut world, id, program[1]), 1);
+            assert!(world.ant(id).unwrap().call_stack().is_empty());
+            // Nothing to return to: start over.
+            assert_eq!(run(&mut world, id, program[1]), 0);
+        }
+
+        #[test]
+        fn overflow_forgets_outermost() {
+            let mut world = World::new(Grid::new(3, 3));
+            let id = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+            for next_instr in 0..CALL_STACK_DEPTH + 2 {
+                let call = Instr::Call {
+                    target: 0,
+                    next_instr,
+                };
+                run(&mut world, id, call);
+            }
+            let stack = world.ant(id).unwrap().call_stack();
+            assert_eq!(stack.depth(), CALL_STACK_DEPTH);
+            assert_eq!(stack.frames()[0], 2);
+            for expected in (2..CALL_STACK_DEPTH + 2).rev() {
+                assert_eq!(run(&mut world, id, Instr::Return), expected);
+            }
+            assert_eq!(run(&mut world, id, Instr::Return), 0);
+        }
+    }
+
+    mod opcodes {
+        use super::*;
+
+        // Opcodes are stored and sent around; a change here breaks
+        // everything written with the old numbers.
+        const OPCODES: [(InstrKind, u8); InstrKind::COUNT] = [
+            (InstrKind::Turn, 0),
+            (InstrKind::Move, 1),
+            (InstrKind::Direction, 2),
+            (InstrKind::PickUpFood, 3),
+            (InstrKind::DropFood, 4),
+            (InstrKind::Mark, 5),
+            (InstrKind::Unmark, 6),
+            (InstrKind::Sense, 7),
+            (InstrKind::SenseSwarm, 8),
+            (InstrKind::Flip, 9),
+            (InstrKind::Eat, 10),
+            (InstrKind::Set, 11),
+            (InstrKind::Add, 12),
+            (InstrKind::Dec, 13),
+            (InstrKind::JumpIfZero, 14),
+            (InstrKind::Call, 15),
+            (InstrKind::Return, 16),
+            (InstrKind::SenseScent, 17),
+        ];
+
+        #[test]
+        fn stable() {
+            for (kind, opcode) in OPCODES {
+                assert_eq!(kind.opcode(), opcode, "{:?} was renumbered", kind);
+                assert_eq!(InstrKind::from_opcode(opcode), Some(kind));
+            }
+            assert_eq!(InstrKind::from_opcode(InstrKind::COUNT as u8), None);
+            assert_eq!(InstrKind::from_opcode(u8::MAX), None);
+        }
+
+        #[test]
+        fn every_instruction_has_one() {
+            let program = parse_program(
+                "
+                Turn Left 0
+                Move 0 0
+                Direction Left 0 0
+                PickUpFood 0 0
+                DropFood 0 0
+                Mark 0 0
+                Unmark 0 0
+                Sense Here Food 0 0
+                SenseSwarm Outnumbered 0 0
+                Flip 2 0 0
+                Eat 0 0
+                Set r0 1 0
+                Add r1 2 0
+                Dec r2 0
+                JumpIfZero r3 0 0
+                Call 0 0
+                Return
+                SenseScent Ahead Home 0 0
+                ",
+            )
+            .unwrap();
+            assert_eq!(program.len(), InstrKind::COUNT);
+            for (instr, kind) in program.iter().zip(InstrKind::ALL) {
+                assert_eq!(instr.kind(), kind);
+                assert!(instr.to_string().starts_with(kind.name()));
+            }
+        }
+    }
+
+    mod parse {
+        use super::*;
+
+        #[test]
+        fn labels_and_indices() {
+            let program = parse_program(
+                "
+                ; walk until blocked, then turn
+                start:  Move start turn
+                turn:   Turn Right 0
+                ",
+            )
+            .unwrap();
+            assert_eq!(
+                program,
+                vec![
+                    Instr::Move {
+                        success_instr: 0,
+                        fail_instr: 1,
+                    },
+                    Instr::Turn {
+                        direction: TurnDirection::Right,
+                        next_instr: 0,
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn label_on_own_line() {
+            let program = parse_program("a:\nb: \n DropFood b\nc: DropFood a").unwrap();
+            assert_eq!(
+                program,
+                vec![
+                    Instr::DropFood {
+                        success_instr: 0,
+                        fail_instr: 0,
+                    },
+                    Instr::DropFood {
+                        success_instr: 0,
+                        fail_instr: 0,
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn every_instruction() {
+            let source = "
+                Turn Left 1
+                Move 2 3
+                Direction UpLeft 4 5
+                PickUpFood 6 7
+                DropFood 8
+                Mark 2 9
+                Unmark 5 10
+                Sense RightAhead Marker 4 11 12
+                Sense Here FoeHome 13 14
+                SenseSwarm SizeBelow 10 15 16
+                SenseSwarm ScoreAhead 17 18
+                Flip 3 19 20
+            ";
+            let program = parse_program(source).unwrap();
+            assert_eq!(
+                program,
+                vec![
+                    Instr::Turn {
+                        direction: TurnDirection::Left,
+                        next_instr: 1,
+                    },
+                    Instr::Move {
+                        success_instr: 2,
+                        fail_instr: 3,
+                    },
+                    Instr::Direction {
+                        direction: Direction::UpLeft,
+                        success_instr: 4,
+                        fail_instr: 5,
+                    },
+                    Instr::PickUpFood {
+                        success_instr: 6,
+                        fail_instr: 7,
+                    },
+                    Instr::DropFood {
+                        success_instr: 8,
+                        fail_instr: 8,
+                    },
+                    Instr::Mark {
+                        marker: 2,
+                        next_instr: 9,
+                    },
+                    Instr::Unmark {
+                        marker: 5,
+                        next_instr: 10,
+                    },
+                    Instr::Sense {
+                        sense_dir: SenseDirection::RightAhead,
+                        condition: SenseCondition::Marker(4),
+                        success_instr: 11,
+                        fail_instr: 12,
+                    },
+                    Instr::Sense {
+                        sense_dir: SenseDirection::Here,
+                        condition: SenseCondition::FoeHome,
+                        success_instr: 13,
+                        fail_instr: 14,
+                    },
+                    Instr::SenseSwarm {
+                        condition: SwarmCondition::SizeBelow(10),
+                        success_instr: 15,
+                        fail_instr: 16,
+                    },
+                    Instr::SenseSwarm {
+                        condition: SwarmCondition::ScoreAhead,
+                        success_instr: 17,
+                        fail_instr: 18,
+                    },
+                    Instr::Flip {
+                        n: 3,
+                        success_instr: 19,
+                        fail_instr: 20,
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn case_insensitive_keywords() {
+            assert_eq!(
+                parse_program("sense ahead food 0 0"),
+                parse_program("Sense Ahead Food 0 0")
+            );
+        }
+
+        fn error(source: &str) -> AsmError {
+            parse_program(source).unwrap_err()
+        }
+
+        #[test]
+        fn unknown_label() {
+            assert_eq!(
+                error("start: Move start\n\nMove found start"),
+                AsmError {
+                    line: 1,
+                    kind: AsmErrorKind::MissingOperand,
+                }
+            );
+            assert_eq!(
+                error("start: Move start start\n\nMove found start"),
+                AsmError {
+                    line: 3,
+                    kind: AsmErrorKind::UnknownLabel("found".to_string()),
+                }
+            );
+        }
+
+        #[test]
+        fn malformed_operands() {
+            assert_eq!(
+                error("Turn Up 0").kind,
+                AsmErrorKind::InvalidOperand("Up".to_string())
+            );
+            assert_eq!(
+                error("Flip x 0 0").kind,
+                AsmErrorKind::InvalidOperand("x".to_string())
+            );
+            assert_eq!(
+                error("Move 0 1x").kind,
+                AsmErrorKind::InvalidOperand("1x".to_string())
+            );
+            assert_eq!(
+                error("Set r4 1 0").kind,
+                AsmErrorKind::InvalidOperand("r4".to_string())
+            );
+            assert_eq!(
+                error("Dec 0 0").kind,
+                AsmErrorKind::InvalidOperand("0".to_string())
+            );
+            assert_eq!(
+                error("DropFood 0 0 0").kind,
+                AsmErrorKind::UnexpectedOperand("0".to_string())
+            );
+            assert_eq!(
+                error("Jump 0").kind,
+                AsmErrorKind::UnknownInstruction("Jump".to_string())
+            );
+        }
+
+        #[test]
+        fn bad_labels() {
+            assert_eq!(
+                error("a: DropFood 0\na: DropFood 0"),
+                AsmError {
+                    line: 2,
+                    kind: AsmErrorKind::DuplicateLabel("a".to_string()),
+                }
+            );
+            assert_eq!(
+                error("1a: DropFood 0").kind,
+                AsmErrorKind::InvalidLabel("1a".to_string())
+            );
+        }
+    }
+
+    mod disassemble {
+        use super::*;
+
+        const SOURCE: &str = "
+            start:  Sense Ahead Food found search
+            search: Flip 3 turn start
+            turn:   Turn Left start
+            found:  Move take start
+            take:   PickUpFood home start
+            home:   Mark 2 home2
+            home2:  Unmark 1 drop
+            drop:   DropFood start drop
+                    DropFood start
+                    Direction DownLeft start 99
+                    Sense LeftAhead Marker 3 start start
+                    SenseSwarm SizeBelow 4 start start
+        ";
+
+        #[test]
+        fn display() {
+            let instr = Instr::Sense {
+                sense_dir: SenseDirection::Here,
+                condition: SenseCondition::Marker(2),
+                success_instr: 4,
+                fail_instr: 7,
+            };
+            assert_eq!(instr.to_string(), "Sense Here Marker 2 4 7");
+            let instr = Instr::SenseSwarm {
+                condition: SwarmCondition::Outnumbered,
+                success_instr: 0,
+                fail_instr: 1,
+            };
+            assert_eq!(instr.to_string(), "SenseSwarm Outnumbered 0 1");
+        }
+
+        #[test]
+        fn synthesizes_labels() {
+            let program = parse_program("Move 0 1\nDropFood 5\n").unwrap();
+            assert_eq!(disassemble(&program), "L0: Move L0 L1\nL1: DropFood 5\n");
+        }
+
+        #[test]
+        fn round_trip() {
+            let program = parse_program(SOURCE).unwrap();
+            let text = disassemble(&program);
+            assert_eq!(parse_program(&text), Ok(program));
+        }
+
+        #[test]
+        fn display_round_trip() {
+            let program = parse_program(SOURCE).unwrap();
+            let text: String = program.iter().map(|instr| format!("{}\n", instr)).collect();
+            assert_eq!(parse_program(&text), Ok(program));
+        }
+
+        #[test]
+        fn map_targets() {
+            let instr = Instr::Flip {
+                n: 2,
+                success_instr: 1,
+                fail_instr: 2,
+            };
+            assert_eq!(instr.targets(), vec![1, 2]);
+            assert_eq!(
+                instr.map_targets(|target| target * 10),
+                Instr::Flip {
+                    n: 2,
+                    success_instr: 10,
+                    fail_instr: 20,
+                }
+            );
+        }
+    }
+
+    mod validate {
+        use super::*;
+
+        fn errors(source: &str, options: ValidateOptions) -> Vec<ProgramError> {
+            let program = parse_program(source).unwrap();
+            validate_with(&program, options).err().unwrap_or_default()
+        }
+
+        #[test]
+        fn valid() {
+            let program = parse_program("a: Move a b\nb: Turn Left a").unwrap();
+            assert_eq!(validate(&program), Ok(()));
+        }
+
+        #[test]
+        fn empty() {
+            assert_eq!(validate(&vec![]), Err(vec![ProgramError::Empty]));
+            assert!(ProgramError::Empty.is_fatal());
+        }
+
+        #[test]
+        fn out_of_bounds() {
+            assert_eq!(
+                errors("Move 1 2\nDropFood 7", ValidateOptions::default()),
+                vec![
+                    ProgramError::TargetOutOfBounds {
+                        instr: 0,
+                        target: 2
+                    },
+                    ProgramError::TargetOutOfBounds {
+                        instr: 1,
+                        target: 7
+                    },
+                ]
+            );
+        }
+
+        #[test]
+        fn unreachable() {
+            let found = errors(
+                "Move 0 2\nDropFood 1\nTurn Left 0\nDropFood 1",
+                ValidateOptions::default(),
+            );
+            assert_eq!(
+                found,
+                vec![
+                    ProgramError::Unreachable { instr: 1 },
+                    ProgramError::Unreachable { instr: 3 },
+                ]
+            );
+            assert!(found.iter().all(|error| !error.is_fatal()));
+        }
+
+        #[test]
+        fn self_loops() {
+            let source = "Move 0 1\nSense Ahead Wall 1 1";
+            assert_eq!(errors(source, ValidateOptions::default()), vec![]);
+            assert_eq!(
+                errors(source, ValidateOptions { self_loops: true }),
+                vec![ProgramError::SelfLoop { instr: 1 }]
+            );
+            // A return goes wherever the call came from.
+            let source = "Call 1 0\nReturn";
+            assert_eq!(errors(source, ValidateOptions { self_loops: true }), vec![]);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    mod serde {
+        use super::*;
+
+        #[test]
+        fn program_round_trip() {
+            let program = parse_program(
+                "
+                start: Sense LeftAhead Marker 2 found start
+                found: SenseSwarm SizeBelow 3 start flip
+                flip:  Flip 4 start turn
+                turn:  Turn Right start
+                ",
+            )
+            .unwrap();
+            let json = serde_json::to_string(&program).unwrap();
+            let loaded: Program = serde_json::from_str(&json).unwrap();
+            assert_eq!(loaded, program);
+        }
+    }
+}
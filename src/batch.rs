@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+
+use crate::asm::*;
+use crate::rules::*;
+use crate::sim::*;
+use crate::victory::*;
+use crate::world::*;
+
+// Many independent matches on one map, played in parallel on rayon's
+// thread pool, for parameter sweeps. Each match gets its own seed and ends
+// after `rounds` rounds unless the victory condition settles it earlier.
+#[derive(Clone)]
+pub struct Batch {
+    world: World,
+    programs: HashMap<Color, Program>,
+    rounds: u32,
+    rules: RuleSet,
+    turn_order: TurnOrder,
+    victory_condition: Option<VictoryCondition>,
+}
+
+impl Batch {
+    pub fn new(world: World, programs: HashMap<Color, Program>, rounds: u32) -> Self {
+        Batch {
+            world,
+            programs,
+            rounds,
+            rules: RuleSet::default(),
+            turn_order: TurnOrder::default(),
+            victory_condition: None,
+        }
+    }
+
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn with_turn_order(mut self, turn_order: TurnOrder) -> Self {
+        self.turn_order = turn_order;
+        self
+    }
+
+    pub fn with_victory_condition(mut self, condition: VictoryCondition) -> Self {
+        self.victory_condition = Some(condition);
+        self
+    }
+
+    // The simulator that plays the match for `seed`.
+    pub fn simulator(&self, seed: u64) -> Result<Simulator, SimulatorError> {
+        let mut simulator = Simulator::headless(self.world.clone(), self.programs.clone(), seed)?
+            .with_rules(self.rules.clone())
+            .with_turn_order(self.turn_order)
+            .with_end_condition(EndCondition::MaxRounds(self.rounds));
+        if let Some(condition) = &self.victory_condition {
+            simulator = simulator.with_victory_condition(condition.clone());
+        }
+        Ok(simulator)
+    }
+
+    // One result per seed, in the order of `seeds`. The programs are
+    // checked once, before any match starts.
+    pub fn run(&self, seeds: &[u64]) -> Result<Vec<MatchResult>, SimulatorError> {
+        if seeds.is_empty() {
+            return Ok(Vec::new());
+        }
+        self.simulator(seeds[0])?;
+        Ok(run_matches(seeds, |&seed| {
+            self.simulator(seed)
+                .expect("programs are validated up front")
+        }))
+    }
+
+    // The same seeds played under each of `orders`, one report per order.
+    pub fn fairness(
+        &self,
+        seeds: &[u64],
+        orders: &[TurnOrder],
+    ) -> Result<Vec<Fairness>, SimulatorError> {
+        orders
+            .iter()
+            .map(|&order| {
+                let results = self.clone().with_turn_order(order).run(seeds)?;
+                Ok(Fairness::of(order, &results))
+            })
+            .collect()
+    }
+}
+
+// How a turn order treats Black and Red over a batch. Black acts first
+// under `TurnOrder::ByColor`, so with the same program on both sides a
+// `mean_delta` well away from zero, in units of `std_error`, is a
+// first-mover advantage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fairness {
+    pub turn_order: TurnOrder,
+    pub matches: usize,
+    // Black's score minus Red's, averaged over the matches.
+    pub mean_delta: f64,
+    pub std_error: f64,
+    pub black_wins: usize,
+    pub red_wins: usize,
+    pub draws: usize,
+}
+
+impl Fairness {
+    pub fn of(turn_order: TurnOrder, results: &[MatchResult]) -> Self {
+        let deltas: Vec<_> = results
+            .iter()
+            .map(|result| {
+                f64::from(result.score(Color::Black)) - f64::from(result.score(Color::Red))
+            })
+            .collect();
+        let n = deltas.len() as f64;
+        let mean_delta = deltas.iter().sum::<f64>() / n.max(1.0);
+        let variance = deltas
+            .iter()
+            .map(|delta| (delta - mean_delta).powi(2))
+            .sum::<f64>()
+            / (n - 1.0).max(1.0);
+        let wins = |outcome| {
+            results
+                .iter()
+                .filter(|result| result.outcome == outcome)
+                .count()
+        };
+        Fairness {
+            turn_order,
+            matches: results.len(),
+            mean_delta,
+            std_error: (variance / n.max(1.0)).sqrt(),
+            black_wins: wins(Victory::Winner(Color::Black)),
+            red_wins: wins(Victory::Winner(Color::Red)),
+            draws: wins(Victory::Draw),
+        }
+    }
+}
+
+// Plays a match for every job in parallel and returns the results in the
+// order of `jobs`. `setup` builds each simulator on the thread that plays
+// it, since renderers and brains need not be `Send`; it must give the
+// match an end condition.
+pub fn run_matches<T: Sync>(
+    jobs: &[T],
+    setup: impl Fn(&T) -> Simulator + Sync,
+) -> Vec<MatchResult> {
+    jobs.par_iter()
+        .map(|job| {
+            let mut simulator = setup(job);
+            simulator.run_until(Simulator::is_over);
+            simulator.result().unwrap()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Wanders at random, bringing home whatever food it stumbles on.
+    const WANDERER: &str = "
+        start:   PickUpFood home look
+        look:    Flip 3 turn walk
+        turn:    Turn Left walk
+        walk:    Move start turn
+        home:    Sense Here Home drop step
+        step:    Flip 2 left forward
+        left:    Turn Right forward
+        forward: Move home home
+        drop:    DropFood start start
+    ";
+
+    const MAP: &str = "\
+# # # # # # # #
+ # + r 3 . 2 . #
+# + . 4 . 3 b #
+ # r . 5 . b - #
+# # # # # # # #
+";
+
+    fn batch() -> Batch {
+        let program = parse_program(WANDERER).unwrap();
+        let programs = HashMap::from([(Color::Red, program.clone()), (Color::Black, program)]);
+        Batch::new(MAP.parse().unwrap(), programs, 200)
+    }
+
+    fn assert_send_sync<T: Send + Sync>() {}
+
+    #[test]
+    fn shareable() {
+        assert_send_sync::<World>();
+        assert_send_sync::<Program>();
+        assert_send_sync::<MatchResult>();
+        assert_send_sync::<Batch>();
+    }
+
+    #[test]
+    fn matches_sequential_runs() {
+        let batch = batch();
+        let seeds: Vec<u64> = (0..16).collect();
+        let results = batch.run(&seeds).unwrap();
+        assert_eq!(results.len(), seeds.len());
+        for (&seed, result) in seeds.iter().zip(&results) {
+            let mut simulator = batch.simulator(seed).unwrap();
+            simulator.run_until(Simulator::is_over);
+            assert_eq!(simulator.result().as_ref(), Some(result));
+        }
+        assert!(results.iter().any(|result| *result != results[0]));
+        assert_eq!(batch.run(&[]), Ok(Vec::new()));
+    }
+
+    #[test]
+    fn fairness_per_turn_order() {
+        let batch = batch();
+        let seeds: Vec<u64> = (0..8).collect();
+        let orders = [
+            TurnOrder::ByColor,
+            TurnOrder::ById,
+            TurnOrder::Alternating,
+            TurnOrder::Shuffled,
+        ];
+        let reports = batch.fairness(&seeds, &orders).unwrap();
+        for (report, order) in reports.iter().zip(orders) {
+            assert_eq!(report.turn_order, order);
+            assert_eq!(report.matches, seeds.len());
+            assert_eq!(
+                report.black_wins + report.red_wins + report.draws,
+                seeds.len()
+            );
+            let results = batch.clone().with_turn_order(order).run(&seeds).unwrap();
+            assert_eq!(*report, Fairness::of(order, &results));
+        }
+
+        let fair = Fairness::of(TurnOrder::ByColor, &[]);
+        assert_eq!(
+            (fair.matches, fair.mean_delta, fair.std_error),
+            (0, 0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_programs() {
+        let programs = HashMap::from([(Color::Red, Program::new())]);
+        let batch = Batch::new(MAP.parse().unwrap(), programs, 10);
+        assert_eq!(
+            batch.run(&[1, 2]).err(),
+            Some(SimulatorError::InvalidProgram {
+                color: Color::Red,
+                errors: vec![ProgramError::Empty],
+            })
+        );
+    }
+
+    #[test]
+    fn jobs_keep_their_order() {
+        let rounds = [3, 1, 4, 1, 5];
+        let results = run_matches(&rounds, |&rounds| {
+            Simulator::headless(World::new(Grid::new(4, 4)), HashMap::new(), 0)
+                .unwrap()
+                .with_end_condition(EndCondition::MaxRounds(rounds))
+        });
+        let played: Vec<_> = results.iter().map(|result| result.round).collect();
+        assert_eq!(played, rounds);
+    }
+}
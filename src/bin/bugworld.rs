@@ -0,0 +1,343 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use bugworld_proto::asm::*;
+use bugworld_proto::migrate::*;
+use bugworld_proto::renderers::terminal::*;
+use bugworld_proto::repl::*;
+use bugworld_proto::sim::*;
+use bugworld_proto::victory::*;
+use bugworld_proto::world::*;
+
+const USAGE: &str = "\
+usage: bugworld <map> <black program> <red program> [options]
+       bugworld bench <map> <black program> <red program> [options]
+       bugworld repl [map]
+       bugworld completions    print a bash completion script
+
+options:
+  --steps N        rounds to play (default 100000)
+  --seed S         seed for the match rng (default 0)
+  --render         draw the world in the terminal every round
+  --fps N          with --render, at most N frames a second
+  --replay FILE    write a replay of the match to FILE
+  --audit-rng      with --replay, log every random draw in the replay
+  --json           print the result as JSON
+  --baseline FILE  with bench, compare against an earlier bench's output
+  --threshold PCT  with --baseline, the slowdown or growth that counts as
+                   a regression (default 5)";
+
+struct Options {
+    map: String,
+    programs: [String; 2],
+    steps: u32,
+    seed: u64,
+    render: bool,
+    fps: Option<u32>,
+    replay: Option<String>,
+    audit_rng: bool,
+    json: bool,
+    baseline: Option<String>,
+    threshold: f64,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Options, String> {
+    let mut files = Vec::new();
+    let (mut steps, mut seed, mut fps) = (100_000, 0, None);
+    let (mut render, mut replay, mut audit_rng, mut json) = (false, None, false, false);
+    let (mut baseline, mut threshold) = (None, 5.0);
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or(format!("{} needs a value", name));
+        match arg.as_str() {
+            "--steps" => steps = number(&value("--steps")?)?,
+            "--seed" => seed = number(&value("--seed")?)?,
+            "--fps" => fps = Some(number(&value("--fps")?)?).filter(|&fps| fps > 0),
+            "--render" => render = true,
+            "--replay" => replay = Some(value("--replay")?),
+            "--audit-rng" => audit_rng = true,
+            "--json" => json = true,
+            "--baseline" => baseline = Some(value("--baseline")?),
+            "--threshold" => threshold = number(&value("--threshold")?)?,
+            _ if arg.starts_with("--") => return Err(format!("unknown option {}", arg)),
+            _ => files.push(arg),
+        }
+    }
+    let [map, black, red]: [String; 3] = files
+        .try_into()
+        .map_err(|_| "expected a map and two programs".to_string())?;
+    Ok(Options {
+        map,
+        programs: [black, red],
+        steps,
+        seed,
+        render,
+        fps,
+        replay,
+        audit_rng,
+        json,
+        baseline,
+        threshold,
+    })
+}
+
+fn number<T: std::str::FromStr>(s: &str) -> Result<T, String> {
+    s.parse().map_err(|_| format!("{} is not a number", s))
+}
+
+fn read(path: &str) -> Result<String, String> {
+    fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))
+}
+
+fn simulator(options: &Options) -> Result<Simulator, String> {
+    let world: World = read(&options.map)?
+        .parse()
+        .map_err(|error| format!("{}: {}", options.map, error))?;
+    let mut programs = HashMap::new();
+    for (color, path) in [Color::Black, Color::Red]
+        .into_iter()
+        .zip(&options.programs)
+    {
+        let program =
+            parse_program(&read(path)?).map_err(|error| format!("{}: {}", path, error))?;
+        programs.insert(color, program);
+    }
+    let simulator = Simulator::headless(world, programs, options.seed)
+        .map_err(|error| error.to_string())?
+        .with_end_condition(EndCondition::MaxRounds(options.steps));
+    Ok(simulator)
+}
+
+fn run(options: &Options) -> Result<MatchResult, String> {
+    let mut simulator = simulator(options)?;
+    if options.audit_rng {
+        simulator = simulator.with_rng_audit();
+    }
+    if options.render {
+        let renderer = TerminalRenderer::stdout();
+        simulator = simulator.with_renderer(Box::new(match options.fps {
+            Some(fps) => renderer.with_frame_rate(fps),
+            None => renderer,
+        }));
+    }
+    if options.replay.is_some() {
+        simulator.start_recording();
+    }
+    simulator.run_until(Simulator::is_over);
+    if let (Some(path), Some(replay)) = (&options.replay, simulator.take_replay()) {
+        fs::write(path, save_replay(&replay)).map_err(|error| format!("{}: {}", path, error))?;
+    }
+    Ok(simulator.result().expect("the match ran to its end"))
+}
+
+fn report(result: &MatchResult, json: bool) -> String {
+    let colors = [Color::Black, Color::Red];
+    let winner = match result.outcome {
+        Victory::Winner(color) => Some(format!("{:?}", color)),
+        Victory::Draw => None,
+    };
+    if json {
+        let scores: serde_json::Map<_, _> = colors
+            .iter()
+            .map(|color| (format!("{:?}", color), result.scores[color.index()].into()))
+            .collect();
+        let report = serde_json::json!({
+            "rounds": result.round,
+            "scores": scores,
+            "winner": winner,
+        });
+        return report.to_string();
+    }
+    let mut lines: Vec<_> = colors
+        .iter()
+        .map(|color| format!("{:?}: {}", color, result.scores[color.index()]))
+        .collect();
+    lines.push(match winner {
+        Some(winner) => format!("{} wins after {} rounds", winner, result.round),
+        None => format!("draw after {} rounds", result.round),
+    });
+    lines.join("\n")
+}
+
+// Counts heap allocations and the most bytes live at once, for `bench`.
+struct CountingAlloc;
+
+static ALLOCATIONS: AtomicU64 = AtomicU64::new(0);
+static LIVE_BYTES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_BYTES: AtomicUsize = AtomicUsize::new(0);
+
+fn grow(bytes: usize) {
+    let live = LIVE_BYTES.fetch_add(bytes, Ordering::Relaxed) + bytes;
+    PEAK_BYTES.fetch_max(live, Ordering::Relaxed);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = System.alloc(layout);
+        if !ptr.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            grow(layout.size());
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout);
+        LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new = System.realloc(ptr, layout, new_size);
+        if !new.is_null() {
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+            LIVE_BYTES.fetch_sub(layout.size(), Ordering::Relaxed);
+            grow(new_size);
+        }
+        new
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+// Lower is better for all but the first.
+const METRICS: [&str; 3] = ["rounds_per_sec", "allocations", "peak_bytes"];
+
+// Plays the match headless and prints how fast it went as JSON. Against a
+// baseline, each metric also gets its change and whether that is a
+// regression, and any regression fails the command.
+fn bench(options: &Options) -> Result<bool, String> {
+    let baseline = match &options.baseline {
+        Some(path) => {
+            let baseline: serde_json::Value = serde_json::from_str(&read(path)?)
+                .map_err(|error| format!("{}: {}", path, error))?;
+            let metrics = METRICS.map(|metric| baseline[metric].as_f64().ok_or(metric));
+            match metrics {
+                [Ok(rounds_per_sec), Ok(allocations), Ok(peak_bytes)] => {
+                    Some([rounds_per_sec, allocations, peak_bytes])
+                }
+                _ => return Err(format!("{}: not the output of bugworld bench", path)),
+            }
+        }
+        None => None,
+    };
+    let mut simulator = simulator(options)?;
+    ALLOCATIONS.store(0, Ordering::Relaxed);
+    PEAK_BYTES.store(LIVE_BYTES.load(Ordering::Relaxed), Ordering::Relaxed);
+    let start = Instant::now();
+    simulator.run_until(Simulator::is_over);
+    let seconds = start.elapsed().as_secs_f64();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed);
+    let peak_bytes = PEAK_BYTES.load(Ordering::Relaxed);
+    let rounds = simulator.result().expect("the match ran to its end").round;
+    let rounds_per_sec = f64::from(rounds) / seconds;
+
+    let mut report = serde_json::json!({
+        "rounds": rounds,
+        "seconds": seconds,
+        "rounds_per_sec": rounds_per_sec,
+        "allocations": allocations,
+        "peak_bytes": peak_bytes,
+    });
+    let mut passed = true;
+    if let Some(baseline) = baseline {
+        let current = [rounds_per_sec, allocations as f64, peak_bytes as f64];
+        let mut comparison = serde_json::Map::new();
+        for (i, metric) in METRICS.into_iter().enumerate() {
+            let change = (current[i] - baseline[i]) / baseline[i] * 100.0;
+            let worse = if i == 0 { -change } else { change };
+            let regressed = worse > options.threshold;
+            passed &= !regressed;
+            let entry = serde_json::json!({
+                "baseline": baseline[i],
+                "change_pct": change,
+                "regressed": regressed,
+            });
+            comparison.insert(metric.to_string(), entry);
+        }
+        report["baseline"] = comparison.into();
+    }
+    println!("{}", report);
+    Ok(passed)
+}
+
+// Reads commands from stdin until it closes or says `quit`; errors are
+// printed and the session goes on.
+fn repl(map: Option<String>) -> Result<(), String> {
+    let world = match map {
+        Some(path) => read(&path)?
+            .parse()
+            .map_err(|error| format!("{}: {}", path, error))?,
+        None => World::new(Grid::new(10, 10)),
+    };
+    let mut repl = Repl::new(world);
+    println!("{}\nhelp, quit", REPL_HELP);
+    for line in io::stdin().lock().lines() {
+        let line = line.map_err(|error| error.to_string())?;
+        match line.trim() {
+            "quit" => break,
+            "help" => println!("{}", REPL_HELP),
+            line => match repl.run(line) {
+                Ok(output) if output.is_empty() => {}
+                Ok(output) => println!("{}", output.trim_end()),
+                Err(error) => eprintln!("{}", error),
+            },
+        }
+    }
+    Ok(())
+}
+
+// Subcommands and options, with files completed for everything else.
+const COMPLETIONS: &str = "\
+complete -o default -W 'bench repl completions --steps --seed --render --fps --replay \
+--audit-rng --json --baseline --threshold' bugworld";
+
+fn main() -> ExitCode {
+    let mut args = env::args().skip(1).peekable();
+    if args.peek().map(String::as_str) == Some("completions") {
+        println!("{}", COMPLETIONS);
+        return ExitCode::SUCCESS;
+    }
+    if args.peek().map(String::as_str) == Some("repl") {
+        return match repl(args.nth(1)) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(error) => {
+                eprintln!("bugworld: {}", error);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    let benching = args.next_if(|arg| arg == "bench").is_some();
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(error) => {
+            eprintln!("bugworld: {}\n\n{}", error, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+    if benching {
+        return match bench(&options) {
+            Ok(true) => ExitCode::SUCCESS,
+            Ok(false) => ExitCode::FAILURE,
+            Err(error) => {
+                eprintln!("bugworld: {}", error);
+                ExitCode::FAILURE
+            }
+        };
+    }
+    match run(&options) {
+        Ok(result) => {
+            println!("{}", report(&result, options.json));
+            ExitCode::SUCCESS
+        }
+        Err(error) => {
+            eprintln!("bugworld: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::asm::*;
+use crate::data::*;
+use crate::observation::*;
+use crate::world::*;
+
+// Decides for the ants of one swarm, one action a round, from what each ant
+// can see. `None` means the ant does nothing this round. The simulator
+// applies the action and reports back whether the world allowed it.
+pub trait Brain {
+    fn decide(&mut self, ant: Ant<'_>, view: &Observation) -> Option<Action>;
+
+    fn outcome(&mut self, ant: AntId, action: Action, applied: bool) {
+        let _ = (ant, action, applied);
+    }
+
+    // Why the brain stopped deciding, if it has.
+    fn failure(&self) -> Option<String> {
+        None
+    }
+
+    // A program for the simulator to run in place of asking the brain.
+    fn program(&self) -> Option<&Program> {
+        None
+    }
+}
+
+// An asm program handed over as a brain, for code that picks a brain for
+// each team. The simulator runs it with its own interpreter, as the team's
+// program, so it plays exactly as the program would: under the same cost
+// table, instruction budget and scent. Outside a simulator it has nothing
+// to run it with and decides nothing.
+pub struct AsmBrain {
+    program: Program,
+}
+
+impl AsmBrain {
+    // Programs the simulator would refuse are refused here.
+    pub fn new(program: Program) -> Result<Self, Vec<ProgramError>> {
+        if let Err(errors) = validate(&program) {
+            let errors: Vec<_> = errors.into_iter().filter(|e| e.is_fatal()).collect();
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+        }
+        Ok(AsmBrain { program })
+    }
+}
+
+impl Brain for AsmBrain {
+    fn decide(&mut self, _ant: Ant<'_>, _view: &Observation) -> Option<Action> {
+        None
+    }
+
+    fn program(&self) -> Option<&Program> {
+        Some(&self.program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_what_the_simulator_would() {
+        let program = parse_program("start: Move start start").unwrap();
+        let brain = AsmBrain::new(program.clone()).unwrap();
+        assert_eq!(brain.program(), Some(&program));
+        let broken = vec![Instr::Move {
+            success_instr: 0,
+            fail_instr: 3,
+        }];
+        assert!(AsmBrain::new(broken).is_err());
+    }
+}
@@ -0,0 +1,421 @@
+use std::fmt;
+
+use crate::asm::*;
+use crate::scent::*;
+use crate::snapshot::*;
+use crate::world::*;
+
+// A compact binary form of programs, for sending them over the wire and
+// storing many of them. After the magic bytes and a little-endian version
+// come the instruction count and each instruction as its opcode followed by
+// its operands, in the order the asm source lists them. Targets and other
+// numbers are LEB128 varints; enum operands are a byte each, numbered as
+// below.
+pub const BYTECODE_MAGIC: [u8; 4] = *b"BWBC";
+pub const BYTECODE_VERSION: u16 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BytecodeError {
+    NotBytecode,
+    UnsupportedVersion(u16),
+    // Cut short, a bad varint or bytes after the last instruction.
+    Malformed(SnapshotError),
+    UnknownOpcode { instr: InstrIdx, opcode: u8 },
+    InvalidOperand { instr: InstrIdx },
+    // Decoded fine, but the program could not run; only fatal errors are
+    // kept, lints do not stop a program from loading.
+    InvalidProgram(Vec<ProgramError>),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => write!(f, "not program bytecode"),
+            BytecodeError::UnsupportedVersion(version) => {
+                write!(f, "unsupported bytecode version {}", version)
+            }
+            BytecodeError::Malformed(error) => write!(f, "malformed bytecode: {}", error),
+            BytecodeError::UnknownOpcode { instr, opcode } => {
+                write!(f, "instruction {} has unknown opcode {}", instr, opcode)
+            }
+            BytecodeError::InvalidOperand { instr } => {
+                write!(f, "instruction {} has an invalid operand", instr)
+            }
+            BytecodeError::InvalidProgram(errors) => {
+                write!(f, "invalid program: {}", describe_errors(errors))
+            }
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            BytecodeError::Malformed(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<SnapshotError> for BytecodeError {
+    fn from(error: SnapshotError) -> Self {
+        BytecodeError::Malformed(error)
+    }
+}
+
+pub fn program_to_bytes(program: &Program) -> Vec<u8> {
+    let mut out = Encoder::bare();
+    for byte in BYTECODE_MAGIC
+        .into_iter()
+        .chain(BYTECODE_VERSION.to_le_bytes())
+    {
+        out.u8(byte);
+    }
+    out.usize(program.len());
+    for &instr in program {
+        encode_instr(instr, &mut out);
+    }
+    out.into_bytes()
+}
+
+// Decodes and validates a program from `program_to_bytes`.
+pub fn program_from_bytes(bytes: &[u8]) -> Result<Program, BytecodeError> {
+    let header = BYTECODE_MAGIC.len() + 2;
+    if bytes.len() < header || bytes[..4] != BYTECODE_MAGIC {
+        return Err(BytecodeError::NotBytecode);
+    }
+    let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+    if version != BYTECODE_VERSION {
+        return Err(BytecodeError::UnsupportedVersion(version));
+    }
+    let mut input = Decoder::bare(&bytes[header..]);
+    let program = (0..input.count()?)
+        .map(|idx| decode_instr(idx, &mut input))
+        .collect::<Result<Program, _>>()?;
+    input.finish()?;
+    if let Err(errors) = validate(&program) {
+        let errors: Vec<_> = errors.into_iter().filter(|e| e.is_fatal()).collect();
+        if !errors.is_empty() {
+            return Err(BytecodeError::InvalidProgram(errors));
+        }
+    }
+    Ok(program)
+}
+
+fn encode_instr(instr: Instr, out: &mut Encoder) {
+    out.u8(instr.kind().opcode());
+    match instr {
+        Instr::Turn { direction, .. } => out.u8(match direction {
+            TurnDirection::Left => 0,
+            TurnDirection::Right => 1,
+        }),
+        Instr::Direction { direction, .. } => out.direction(direction),
+        Instr::Mark { marker, .. } | Instr::Unmark { marker, .. } => out.u8(marker),
+        Instr::Sense {
+            sense_dir,
+            condition,
+            ..
+        } => {
+            out.u8(sense_dir_code(sense_dir));
+            match condition {
+                SenseCondition::Friend => out.u8(0),
+                SenseCondition::Foe => out.u8(1),
+                SenseCondition::Food => out.u8(2),
+                SenseCondition::Wall => out.u8(3),
+                SenseCondition::Home => out.u8(4),
+                SenseCondition::FoeHome => out.u8(5),
+                SenseCondition::Marker(marker) => {
+                    out.u8(6);
+                    out.u8(marker);
+                }
+                SenseCondition::FoeMarker => out.u8(7),
+            }
+        }
+        Instr::SenseSwarm { condition, .. } => match condition {
+            SwarmCondition::SizeBelow(n) => {
+                out.u8(0);
+                out.usize(n);
+            }
+            SwarmCondition::Outnumbered => out.u8(1),
+            SwarmCondition::ScoreAhead => out.u8(2),
+        },
+        Instr::SenseScent {
+            sense_dir, scent, ..
+        } => {
+            out.u8(sense_dir_code(sense_dir));
+            out.u8(match scent {
+                Scent::Food => 0,
+                Scent::Home => 1,
+            });
+        }
+        Instr::Flip { n, .. } => out.u32(n),
+        Instr::Set {
+            register, value, ..
+        }
+        | Instr::Add {
+            register, value, ..
+        } => {
+            out.u8(register);
+            out.u8(value);
+        }
+        Instr::Dec { register, .. } | Instr::JumpIfZero { register, .. } => out.u8(register),
+        _ => {}
+    }
+    for target in instr.targets() {
+        out.usize(target);
+    }
+}
+
+fn sense_dir_code(sense_dir: SenseDirection) -> u8 {
+    match sense_dir {
+        SenseDirection::Here => 0,
+        SenseDirection::Ahead => 1,
+        SenseDirection::LeftAhead => 2,
+        SenseDirection::RightAhead => 3,
+    }
+}
+
+fn sense_dir(code: u8) -> Option<SenseDirection> {
+    match code {
+        0 => Some(SenseDirection::Here),
+        1 => Some(SenseDirection::Ahead),
+        2 => Some(SenseDirection::LeftAhead),
+        3 => Some(SenseDirection::RightAhead),
+        _ => None,
+    }
+}
+
+fn decode_instr(idx: InstrIdx, input: &mut Decoder) -> Result<Instr, BytecodeError> {
+    let invalid = BytecodeError::InvalidOperand { instr: idx };
+    let opcode = input.u8()?;
+    let kind = InstrKind::from_opcode(opcode)
+        .ok_or(BytecodeError::UnknownOpcode { instr: idx, opcode })?;
+    let register = |input: &mut Decoder| -> Result<Register, BytecodeError> {
+        let register = input.u8()?;
+        if usize::from(register) < REGISTERS {
+            Ok(register)
+        } else {
+            Err(invalid.clone())
+        }
+    };
+    // Targets are filled in below, once the operands before them are read.
+    let instr = match kind {
+        InstrKind::Turn => Instr::Turn {
+            direction: match input.u8()? {
+                0 => TurnDirection::Left,
+                1 => TurnDirection::Right,
+                _ => return Err(invalid),
+            },
+            next_instr: 0,
+        },
+        InstrKind::Move => Instr::Move {
+            success_instr: 0,
+            fail_instr: 0,
+        },
+        InstrKind::Direction => Instr::Direction {
+            direction: Direction::try_from(input.u32()?).map_err(|_| invalid.clone())?,
+            success_instr: 0,
+            fail_instr: 0,
+        },
+        InstrKind::PickUpFood => Instr::PickUpFood {
+            success_instr: 0,
+            fail_instr: 0,
+        },
+        InstrKind::DropFood => Instr::DropFood {
+            success_instr: 0,
+            fail_instr: 0,
+        },
+        InstrKind::Mark => Instr::Mark {
+            marker: input.u8()?,
+            next_instr: 0,
+        },
+        InstrKind::Unmark => Instr::Unmark {
+            marker: input.u8()?,
+            next_instr: 0,
+        },
+        InstrKind::Sense => Instr::Sense {
+            sense_dir: sense_dir(input.u8()?).ok_or(invalid.clone())?,
+            condition: match input.u8()? {
+                0 => SenseCondition::Friend,
+                1 => SenseCondition::Foe,
+                2 => SenseCondition::Food,
+                3 => SenseCondition::Wall,
+                4 => SenseCondition::Home,
+                5 => SenseCondition::FoeHome,
+                6 => SenseCondition::Marker(input.u8()?),
+                7 => SenseCondition::FoeMarker,
+                _ => return Err(invalid),
+            },
+            success_instr: 0,
+            fail_instr: 0,
+        },
+        InstrKind::SenseSwarm => Instr::SenseSwarm {
+            condition: match input.u8()? {
+                0 => SwarmCondition::SizeBelow(input.usize()?),
+                1 => SwarmCondition::Outnumbered,
+                2 => SwarmCondition::ScoreAhead,
+                _ => return Err(invalid),
+            },
+            success_instr: 0,
+            fail_instr: 0,
+        },
+        InstrKind::SenseScent => Instr::SenseScent {
+            sense_dir: sense_dir(input.u8()?).ok_or(invalid.clone())?,
+            scent: match input.u8()? {
+                0 => Scent::Food,
+                1 => Scent::Home,
+                _ => return Err(invalid),
+            },
+            success_instr: 0,
+            fail_instr: 0,
+        },
+        InstrKind::Flip => Instr::Flip {
+            n: input.u32()?,
+            success_instr: 0,
+            fail_instr: 0,
+        },
+        InstrKind::Eat => Instr::Eat {
+            success_instr: 0,
+            fail_instr: 0,
+        },
+        InstrKind::Set => Instr::Set {
+            register: register(input)?,
+            value: input.u8()?,
+            next_instr: 0,
+        },
+        InstrKind::Add => Instr::Add {
+            register: register(input)?,
+            value: input.u8()?,
+            next_instr: 0,
+        },
+        InstrKind::Dec => Instr::Dec {
+            register: register(input)?,
+            next_instr: 0,
+        },
+        InstrKind::JumpIfZero => Instr::JumpIfZero {
+            register: register(input)?,
+            success_instr: 0,
+            fail_instr: 0,
+        },
+        InstrKind::Call => Instr::Call {
+            target: 0,
+            next_instr: 0,
+        },
+        InstrKind::Return => Instr::Return,
+    };
+    let mut error = None;
+    let instr = instr.map_targets(|_| match input.usize() {
+        Ok(target) => target,
+        Err(e) => {
+            error.get_or_insert(e);
+            0
+        }
+    });
+    match error {
+        Some(error) => Err(error.into()),
+        None => Ok(instr),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAM: &str = "
+        start:  Sense Ahead Marker 3 found look
+        look:   Sense LeftAhead FoeMarker found walk
+        walk:   SenseSwarm SizeBelow 300 found turn
+        turn:   Turn Right found
+        found:  Flip 1000 move set
+        move:   Move take start
+        take:   PickUpFood drop start
+        drop:   DropFood start 9
+        set:    Set r2 200 add
+        add:    Add r3 7 dec
+        dec:    Dec r1 jump
+        jump:   JumpIfZero r0 call start
+        call:   Call sub face
+        face:   Direction UpLeft mark start
+        mark:   Mark 5 unmark
+        unmark: Unmark 5 eat
+        eat:    Eat smell start
+        smell:  SenseScent RightAhead Home start move
+        sub:    SenseSwarm ScoreAhead back back
+        back:   Return
+    ";
+
+    #[test]
+    fn round_trip() {
+        let program = parse_program(PROGRAM).unwrap();
+        let bytes = program_to_bytes(&program);
+        assert_eq!(program_from_bytes(&bytes), Ok(program.clone()));
+        // Much smaller than the canonical text.
+        assert!(bytes.len() * 4 < disassemble(&program).len());
+        let kinds: Vec<_> = program.iter().map(|instr| instr.kind()).collect();
+        for kind in InstrKind::ALL {
+            assert!(kinds.contains(&kind), "{:?} is not covered", kind);
+        }
+    }
+
+    #[test]
+    fn header() {
+        let bytes = program_to_bytes(&parse_program("Move 0 0").unwrap());
+        assert_eq!(&bytes[..6], b"BWBC\x01\x00");
+        assert_eq!(
+            program_from_bytes(b"BWSN\x01\x00"),
+            Err(BytecodeError::NotBytecode)
+        );
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert_eq!(
+            program_from_bytes(&newer),
+            Err(BytecodeError::UnsupportedVersion(2))
+        );
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let bytes = program_to_bytes(&parse_program("Move 0 0\nSet r1 4 0").unwrap());
+        assert_eq!(
+            program_from_bytes(&bytes[..bytes.len() - 1]),
+            Err(BytecodeError::Malformed(SnapshotError::Truncated))
+        );
+        let mut trailing = bytes.clone();
+        trailing.push(0);
+        assert_eq!(
+            program_from_bytes(&trailing),
+            Err(BytecodeError::Malformed(SnapshotError::TrailingBytes))
+        );
+
+        // Header, count, then `Move 0 0` and `Set r1 4 0`.
+        let mut opcode = bytes.clone();
+        opcode[7] = InstrKind::COUNT as u8;
+        assert_eq!(
+            program_from_bytes(&opcode),
+            Err(BytecodeError::UnknownOpcode {
+                instr: 0,
+                opcode: InstrKind::COUNT as u8
+            })
+        );
+        let mut register = bytes.clone();
+        register[11] = REGISTERS as u8;
+        assert_eq!(
+            program_from_bytes(&register),
+            Err(BytecodeError::InvalidOperand { instr: 1 })
+        );
+
+        let mut target = bytes.clone();
+        target[9] = 2;
+        assert_eq!(
+            program_from_bytes(&target),
+            Err(BytecodeError::InvalidProgram(vec![
+                ProgramError::TargetOutOfBounds {
+                    instr: 0,
+                    target: 2
+                }
+            ]))
+        );
+        // Lints are fine: nothing jumps to the `Set`.
+        assert!(program_from_bytes(&bytes).is_ok());
+    }
+}
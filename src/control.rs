@@ -0,0 +1,157 @@
+use std::ops::ControlFlow;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::sim::*;
+
+// How often a paused run asks its callback whether to go on.
+const PAUSED_POLL: Duration = Duration::from_millis(10);
+
+// Drives a simulator the way a viewer does: paused or running, at most so
+// many steps a second, with a callback between steps that can stop the
+// run or change either setting.
+pub struct SimulationController {
+    simulator: Simulator,
+    paused: bool,
+    step_time: Option<Duration>,
+    last_step: Option<Instant>,
+}
+
+impl SimulationController {
+    // Running, as fast as it can.
+    pub fn new(simulator: Simulator) -> Self {
+        SimulationController {
+            simulator,
+            paused: false,
+            step_time: None,
+            last_step: None,
+        }
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+
+    pub fn simulator_mut(&mut self) -> &mut Simulator {
+        &mut self.simulator
+    }
+
+    pub fn into_simulator(self) -> Simulator {
+        self.simulator
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // `None` takes the brakes off.
+    pub fn set_steps_per_second(&mut self, steps: Option<u32>) {
+        self.step_time = steps.map(|steps| {
+            assert!(steps > 0, "steps per second must be positive");
+            Duration::from_secs(1) / steps
+        });
+    }
+
+    pub fn steps_per_second(&self) -> Option<u32> {
+        self.step_time
+            .map(|step_time| (Duration::from_secs(1).as_nanos() / step_time.as_nanos()) as u32)
+    }
+
+    // Calls `control` before every step, and every few milliseconds while
+    // paused, until it breaks or the match is over. Returns the number of
+    // steps taken.
+    pub fn run(
+        &mut self,
+        mut control: impl FnMut(&mut SimulationController) -> ControlFlow<()>,
+    ) -> u32 {
+        let mut steps = 0;
+        while control(self).is_continue() && !self.simulator.is_over() {
+            if self.paused {
+                thread::sleep(PAUSED_POLL);
+                continue;
+            }
+            self.wait_for_step();
+            self.simulator.step();
+            steps += 1;
+        }
+        steps
+    }
+
+    fn wait_for_step(&mut self) {
+        if let (Some(step_time), Some(last_step)) = (self.step_time, self.last_step) {
+            if let Some(left) = step_time.checked_sub(last_step.elapsed()) {
+                thread::sleep(left);
+            }
+        }
+        self.last_step = Some(Instant::now());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::*;
+    use crate::victory::*;
+    use crate::world::*;
+    use std::collections::HashMap;
+
+    fn controller() -> SimulationController {
+        let mut world = World::new(Grid::new_torus(4, 1));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let program = parse_program("start: Move start start").unwrap();
+        let simulator = Simulator::headless(world, HashMap::from([(Color::Red, program)]), 0);
+        SimulationController::new(simulator.unwrap())
+    }
+
+    #[test]
+    fn pause_and_resume() {
+        let mut controller = controller();
+        let mut calls = 0;
+        let steps = controller.run(|controller| {
+            calls += 1;
+            match calls {
+                3 => controller.pause(),
+                5 => controller.resume(),
+                8 => return ControlFlow::Break(()),
+                _ => {}
+            }
+            ControlFlow::Continue(())
+        });
+        // Calls 3 and 4 found the run paused.
+        assert_eq!(steps, 5);
+        assert_eq!(controller.simulator().round(), 5);
+        assert!(!controller.is_paused());
+    }
+
+    #[test]
+    fn speed() {
+        let mut controller = controller();
+        controller.set_steps_per_second(Some(100));
+        assert_eq!(controller.steps_per_second(), Some(100));
+        let start = Instant::now();
+        let steps = controller.run(|controller| {
+            if controller.simulator().round() == 3 {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        });
+        assert_eq!(steps, 3);
+        assert!(start.elapsed() >= Duration::from_millis(20));
+
+        // Runs stop when the match does, at round 10.
+        let over = controller
+            .into_simulator()
+            .with_end_condition(EndCondition::MaxRounds(10));
+        let mut controller = SimulationController::new(over);
+        assert_eq!(controller.run(|_| ControlFlow::Continue(())), 7);
+    }
+}
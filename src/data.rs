@@ -0,0 +1,165 @@
+use std::fmt;
+
+use crate::asm::*;
+use crate::rules::*;
+use crate::world::*;
+
+// The effect an instruction had on the world. Sensing, flipping and failed
+// actions leave nothing behind; all an ant gets out of those is a jump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum Action {
+    Move,
+    Turn(Direction),
+    PickUpFood,
+    DropFood,
+    Mark(Marker),
+    Unmark(Marker),
+    Eat,
+}
+
+impl Action {
+    // The instruction that takes this action.
+    pub fn kind(self) -> InstrKind {
+        match self {
+            Action::Move => InstrKind::Move,
+            Action::Turn(_) => InstrKind::Turn,
+            Action::PickUpFood => InstrKind::PickUpFood,
+            Action::DropFood => InstrKind::DropFood,
+            Action::Mark(_) => InstrKind::Mark,
+            Action::Unmark(_) => InstrKind::Unmark,
+            Action::Eat => InstrKind::Eat,
+        }
+    }
+
+    // Combat is not part of an action: whoever moved the ant resolves it.
+    // Under a metabolism every action that takes effect costs energy, and
+    // under a rest table it sends the ant to rest. Returns the cells food
+    // spilled onto, if it did not all stay with the ant; the ant's own cells
+    // are the caller's to know.
+    pub fn apply(self, ant: &mut AntMut, rules: &RuleSet) -> Result<Vec<Position>, ActionError> {
+        let position = ant.position();
+        let mut spilled = Vec::new();
+        let result = match self {
+            Action::Move => ant.move_forward(),
+            Action::Turn(direction) => {
+                ant.rotate(direction);
+                Ok(())
+            }
+            Action::PickUpFood => ant.pickup_food().map(|_| ()),
+            Action::DropFood => match rules.food_cap {
+                Some(cap) => ant.drop_food_capped(cap).map(|mut targets| {
+                    // Units fill one cell before moving on to the next.
+                    targets.retain(|&target| target != position);
+                    targets.dedup();
+                    spilled = targets;
+                }),
+                None => ant.drop_food().map(|_| ()),
+            },
+            Action::Mark(marker) => ant.set_marker(marker),
+            Action::Unmark(marker) => ant.clear_marker(marker),
+            Action::Eat => match rules.metabolism {
+                Some(metabolism) => ant.eat(metabolism),
+                None => Err(WorldError::NotHungry),
+            },
+        };
+        if let (Ok(()), Some(metabolism)) = (result, rules.metabolism) {
+            ant.burn(metabolism.per_action);
+        }
+        if let (Ok(()), Some(rests)) = (result, rules.resting) {
+            ant.rest(rests.rest(self.kind()));
+        }
+        result.map(|()| spilled).map_err(|error| ActionError {
+            ant: ant.id(),
+            position,
+            action: self,
+            error,
+        })
+    }
+}
+
+// What an instruction comes to once it is evaluated: a plain jump, or an
+// action for the world to take or refuse, and where to go either way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Jump(InstrIdx),
+    Act {
+        action: Action,
+        success_instr: InstrIdx,
+        fail_instr: InstrIdx,
+    },
+}
+
+impl Command {
+    // The instruction to go on with and the action that took effect, if
+    // any; `take` tries the action.
+    pub fn resolve(self, take: impl FnOnce(Action) -> bool) -> (InstrIdx, Option<Action>) {
+        match self {
+            Command::Jump(next_instr) => (next_instr, None),
+            Command::Act {
+                action,
+                success_instr,
+                fail_instr,
+            } => {
+                if take(action) {
+                    (success_instr, Some(action))
+                } else {
+                    (fail_instr, None)
+                }
+            }
+        }
+    }
+}
+
+impl World {
+    // The one way the engine changes the world on an ant's behalf: program
+    // ants, brains and replays all go through here.
+    pub fn apply(
+        &mut self,
+        id: AntId,
+        action: Action,
+        rules: &RuleSet,
+    ) -> Result<Vec<Position>, ActionError> {
+        match self.ant_mut(id) {
+            Some(mut ant) => action.apply(&mut ant, rules),
+            None => {
+                let dead = self.dead_ant(id);
+                let position = dead.map_or(Position::default(), |ant| ant.position());
+                Err(ActionError {
+                    ant: id,
+                    position,
+                    action,
+                    error: WorldError::NoSuchAnt,
+                })
+            }
+        }
+    }
+}
+
+// An action the world refused, with the ant that tried it and where it
+// stood.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActionError {
+    pub ant: AntId,
+    pub position: Position,
+    pub action: Action,
+    pub error: WorldError,
+}
+
+impl fmt::Display for ActionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ant {} at ({}, {}) could not {:?}: {}",
+            self.ant, self.position.x, self.position.y, self.action, self.error
+        )
+    }
+}
+
+impl std::error::Error for ActionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
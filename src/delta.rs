@@ -0,0 +1,286 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::world::*;
+
+// Cells and ants touched during one or more steps. Renderers look the
+// current contents up in the world; an entry only says it may have changed.
+//
+// Deltas from `World::diff` also carry what every entry changed to, so
+// they can be applied to the world they were taken from. Marking an entry
+// again, or merging in a delta that only marks it, forgets its contents.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct WorldDelta {
+    cells: HashSet<Position>,
+    ants: HashSet<AntId>,
+    new_cells: HashMap<Position, Cell>,
+    new_ants: HashMap<AntId, AntData>,
+    // All of them, when any changed.
+    new_swarms: Option<HashMap<Color, Vec<AntId>>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeltaError {
+    // Marked as changed, but not with what.
+    Unrecorded,
+    OutOfBounds(Position),
+    // New ants have to take the ids right after the world's last.
+    AntIdGap(AntId),
+    // Worlds can only be diffed against one of the same size...
+    SizeMismatch,
+    // ...that has every ant id the first one has, dead or alive.
+    LostAnts,
+}
+
+impl fmt::Display for DeltaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeltaError::Unrecorded => write!(f, "the delta does not say what changed"),
+            DeltaError::OutOfBounds(position) => write!(
+                f,
+                "the delta changes ({}, {}), off the grid",
+                position.x, position.y
+            ),
+            DeltaError::AntIdGap(id) => write!(f, "the delta adds ant {} out of order", id),
+            DeltaError::SizeMismatch => write!(f, "the worlds differ in size"),
+            DeltaError::LostAnts => write!(f, "the other world has fewer ants"),
+        }
+    }
+}
+
+impl std::error::Error for DeltaError {}
+
+impl WorldDelta {
+    pub fn new() -> Self {
+        WorldDelta::default()
+    }
+
+    pub fn cells(&self) -> impl Iterator<Item = Position> + '_ {
+        self.cells.iter().copied()
+    }
+
+    pub fn ants(&self) -> impl Iterator<Item = AntId> + '_ {
+        self.ants.iter().copied()
+    }
+
+    pub fn has_cell(&self, position: Position) -> bool {
+        self.cells.contains(&position)
+    }
+
+    pub fn has_ant(&self, id: AntId) -> bool {
+        self.ants.contains(&id)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cells.is_empty() && self.ants.is_empty() && self.new_swarms.is_none()
+    }
+
+    // What a cell changed to, for deltas from `World::diff`.
+    pub fn new_cell(&self, position: Position) -> Option<&Cell> {
+        self.new_cells.get(&position)
+    }
+
+    pub fn mark_cell(&mut self, position: Position) {
+        self.cells.insert(position);
+        // The simulator marks every step, so skip the hashing when it can.
+        if !self.new_cells.is_empty() {
+            self.new_cells.remove(&position);
+        }
+    }
+
+    pub fn mark_ant(&mut self, id: AntId) {
+        self.ants.insert(id);
+        if !self.new_ants.is_empty() {
+            self.new_ants.remove(&id);
+        }
+    }
+
+    pub fn merge(&mut self, other: &WorldDelta) {
+        for &position in &other.cells {
+            match other.new_cells.get(&position) {
+                Some(cell) => self.new_cells.insert(position, cell.clone()),
+                None => self.new_cells.remove(&position),
+            };
+        }
+        for &id in &other.ants {
+            match other.new_ants.get(&id) {
+                Some(ant) => self.new_ants.insert(id, *ant),
+                None => self.new_ants.remove(&id),
+            };
+        }
+        if other.new_swarms.is_some() {
+            self.new_swarms.clone_from(&other.new_swarms);
+        }
+        self.cells.extend(&other.cells);
+        self.ants.extend(&other.ants);
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.clear();
+        self.ants.clear();
+        self.new_cells.clear();
+        self.new_ants.clear();
+        self.new_swarms = None;
+    }
+}
+
+impl World {
+    // Every cell and ant that is different in `other`, with what it is
+    // there; the world rules are left out. `other` has to be this world
+    // some steps on, or at least have its size and no fewer ant ids.
+    pub fn diff(&self, other: &World) -> Result<WorldDelta, DeltaError> {
+        let (grid, ants, swarms) = self.parts();
+        let (other_grid, other_ants, other_swarms) = other.parts();
+        if (grid.width(), grid.height()) != (other_grid.width(), other_grid.height()) {
+            return Err(DeltaError::SizeMismatch);
+        }
+        if ants.len() > other_ants.len() {
+            return Err(DeltaError::LostAnts);
+        }
+        let mut delta = WorldDelta::new();
+        for ((position, cell), (_, other_cell)) in grid.iter_cells().zip(other_grid.iter_cells()) {
+            if cell != other_cell {
+                delta.cells.insert(position);
+                delta.new_cells.insert(position, other_cell.clone());
+            }
+        }
+        for (id, ant) in other_ants.iter().enumerate() {
+            if ants.get(id) != Some(ant) {
+                delta.ants.insert(id);
+                delta.new_ants.insert(id, *ant);
+            }
+        }
+        if swarms != other_swarms {
+            delta.new_swarms = Some(other_swarms.clone());
+        }
+        Ok(delta)
+    }
+
+    // Brings the world to where `World::diff` found the other one. Nothing
+    // changes unless all of the delta applies.
+    pub fn apply_delta(&mut self, delta: &WorldDelta) -> Result<(), DeltaError> {
+        let (grid, ants, _) = self.parts();
+        for &position in &delta.cells {
+            if !delta.new_cells.contains_key(&position) {
+                return Err(DeltaError::Unrecorded);
+            }
+            if grid.cell_at(position).is_none() {
+                return Err(DeltaError::OutOfBounds(position));
+            }
+        }
+        if delta.ants.iter().any(|id| !delta.new_ants.contains_key(id)) {
+            return Err(DeltaError::Unrecorded);
+        }
+        let mut added: Vec<_> = delta
+            .ants
+            .iter()
+            .copied()
+            .filter(|&id| id >= ants.len())
+            .collect();
+        added.sort();
+        for (&id, expected) in added.iter().zip(ants.len()..) {
+            if id != expected {
+                return Err(DeltaError::AntIdGap(id));
+            }
+        }
+
+        let (grid, ants, swarms) = self.parts_mut();
+        for (&position, cell) in &delta.new_cells {
+            *grid.cell_at_mut(position).unwrap() = cell.clone();
+        }
+        let known = ants.len();
+        for (&id, ant) in delta.new_ants.iter().filter(|(&id, _)| id < known) {
+            ants[id] = *ant;
+        }
+        ants.extend(added.into_iter().map(|id| delta.new_ants[&id]));
+        if let Some(new_swarms) = &delta.new_swarms {
+            swarms.clone_from(new_swarms);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start() -> World {
+        "\
+        # # # # # #
+         # r . 3 b #
+        # . + . . #
+         # # # # # #"
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn diff_and_apply() {
+        let before = start();
+        let mut after = before.clone();
+        after.ant_mut(0).unwrap().move_forward().unwrap();
+        after.ant_mut(1).unwrap().rotate(Direction::UpLeft);
+        after.add_ant(Color::Red, Position { x: 2, y: 2 }).unwrap();
+        after.remove_ant(1).unwrap();
+
+        let delta = before.diff(&after).unwrap();
+        let cells: HashSet<_> = delta.cells().collect();
+        let moved = [(1, 1), (2, 1), (2, 2), (4, 1)].map(|(x, y)| Position { x, y });
+        assert_eq!(cells, HashSet::from(moved));
+        assert_eq!(delta.ants().count(), 3);
+        assert_eq!(
+            delta.new_cell(Position { x: 2, y: 1 }).unwrap().ant(),
+            Some(0)
+        );
+
+        let mut world = before.clone();
+        world.apply_delta(&delta).unwrap();
+        assert!(world == after);
+        assert_eq!(world.state_hash(), after.state_hash());
+        assert_eq!(world.check_invariants(), Ok(()));
+        assert!(after.diff(&world).unwrap().is_empty());
+    }
+
+    #[test]
+    fn merged_diffs_apply_in_one_go() {
+        let first = start();
+        let mut second = first.clone();
+        second.ant_mut(0).unwrap().move_forward().unwrap();
+        let mut third = second.clone();
+        third.ant_mut(0).unwrap().move_forward().unwrap();
+
+        let mut delta = first.diff(&second).unwrap();
+        delta.merge(&second.diff(&third).unwrap());
+        let mut world = first.clone();
+        world.apply_delta(&delta).unwrap();
+        assert!(world == third);
+    }
+
+    #[test]
+    fn apply_checks_first() {
+        let before = start();
+        let mut after = before.clone();
+        after.add_ant(Color::Red, Position { x: 2, y: 2 }).unwrap();
+        let delta = before.diff(&after).unwrap();
+
+        // Marks alone say nothing to apply.
+        let mut marked = delta.clone();
+        marked.mark_ant(2);
+        let mut world = before.clone();
+        assert_eq!(world.apply_delta(&marked), Err(DeltaError::Unrecorded));
+        assert!(world == before);
+
+        // Ant 2 cannot come before ant 1 is there.
+        let mut fewer = before.clone();
+        fewer.parts_mut().1.pop();
+        assert_eq!(fewer.apply_delta(&delta), Err(DeltaError::AntIdGap(2)));
+        assert_eq!(after.diff(&fewer), Err(DeltaError::LostAnts));
+
+        let mut small = World::new(Grid::new(2, 2));
+        assert_eq!(
+            small.apply_delta(&delta),
+            Err(DeltaError::OutOfBounds(Position { x: 2, y: 2 }))
+        );
+        assert_eq!(before.diff(&small), Err(DeltaError::SizeMismatch));
+    }
+}
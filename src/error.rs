@@ -0,0 +1,265 @@
+use std::fmt;
+
+use crate::asm::*;
+use crate::bytecode::*;
+use crate::data::*;
+use crate::delta::*;
+use crate::evolve::*;
+use crate::history::*;
+use crate::lang::*;
+use crate::map::builder::*;
+use crate::map::edit::*;
+use crate::map::*;
+#[cfg(feature = "serde")]
+use crate::migrate::*;
+use crate::proto::*;
+use crate::remote::*;
+use crate::replay::*;
+use crate::sim::*;
+use crate::snapshot::*;
+use crate::tournament::*;
+use crate::tutorial::*;
+use crate::world::*;
+
+// Any error the crate reports, for code that uses several parts of it and
+// wants one type to pass up with `?`. Each module's own error stays the
+// precise one to match on; this one shows and chains exactly as the error
+// it wraps.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    Cell(CellError),
+    World(WorldError),
+    Action(ActionError),
+    Asm(AsmError),
+    Program(ProgramError),
+    Bytecode(BytecodeError),
+    Lang(LangError),
+    Map(MapError),
+    Edit(EditError),
+    Build(BuildError),
+    Delta(DeltaError),
+    Snapshot(SnapshotError),
+    Replay(ReplayError),
+    Simulator(SimulatorError),
+    History(HistoryError),
+    Remote(RemoteError),
+    Proto(ProtoError),
+    Tournament(TournamentError),
+    Evolve(EvolveError),
+    Lesson(LessonError),
+    #[cfg(feature = "serde")]
+    Migrate(MigrateError),
+}
+
+impl Error {
+    fn inner(&self) -> &(dyn std::error::Error + 'static) {
+        match self {
+            Error::Cell(error) => error,
+            Error::World(error) => error,
+            Error::Action(error) => error,
+            Error::Asm(error) => error,
+            Error::Program(error) => error,
+            Error::Bytecode(error) => error,
+            Error::Lang(error) => error,
+            Error::Map(error) => error,
+            Error::Edit(error) => error,
+            Error::Build(error) => error,
+            Error::Delta(error) => error,
+            Error::Snapshot(error) => error,
+            Error::Replay(error) => error,
+            Error::Simulator(error) => error,
+            Error::History(error) => error,
+            Error::Remote(error) => error,
+            Error::Proto(error) => error,
+            Error::Tournament(error) => error,
+            Error::Evolve(error) => error,
+            Error::Lesson(error) => error,
+            #[cfg(feature = "serde")]
+            Error::Migrate(error) => error,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self.inner(), f)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner().source()
+    }
+}
+
+impl From<CellError> for Error {
+    fn from(error: CellError) -> Self {
+        Error::Cell(error)
+    }
+}
+
+impl From<WorldError> for Error {
+    fn from(error: WorldError) -> Self {
+        Error::World(error)
+    }
+}
+
+impl From<ActionError> for Error {
+    fn from(error: ActionError) -> Self {
+        Error::Action(error)
+    }
+}
+
+impl From<AsmError> for Error {
+    fn from(error: AsmError) -> Self {
+        Error::Asm(error)
+    }
+}
+
+impl From<ProgramError> for Error {
+    fn from(error: ProgramError) -> Self {
+        Error::Program(error)
+    }
+}
+
+impl From<BytecodeError> for Error {
+    fn from(error: BytecodeError) -> Self {
+        Error::Bytecode(error)
+    }
+}
+
+impl From<LangError> for Error {
+    fn from(error: LangError) -> Self {
+        Error::Lang(error)
+    }
+}
+
+impl From<MapError> for Error {
+    fn from(error: MapError) -> Self {
+        Error::Map(error)
+    }
+}
+
+impl From<EditError> for Error {
+    fn from(error: EditError) -> Self {
+        Error::Edit(error)
+    }
+}
+
+impl From<BuildError> for Error {
+    fn from(error: BuildError) -> Self {
+        Error::Build(error)
+    }
+}
+
+impl From<DeltaError> for Error {
+    fn from(error: DeltaError) -> Self {
+        Error::Delta(error)
+    }
+}
+
+impl From<SnapshotError> for Error {
+    fn from(error: SnapshotError) -> Self {
+        Error::Snapshot(error)
+    }
+}
+
+impl From<ReplayError> for Error {
+    fn from(error: ReplayError) -> Self {
+        Error::Replay(error)
+    }
+}
+
+impl From<SimulatorError> for Error {
+    fn from(error: SimulatorError) -> Self {
+        Error::Simulator(error)
+    }
+}
+
+impl From<HistoryError> for Error {
+    fn from(error: HistoryError) -> Self {
+        Error::History(error)
+    }
+}
+
+impl From<RemoteError> for Error {
+    fn from(error: RemoteError) -> Self {
+        Error::Remote(error)
+    }
+}
+
+impl From<ProtoError> for Error {
+    fn from(error: ProtoError) -> Self {
+        Error::Proto(error)
+    }
+}
+
+impl From<TournamentError> for Error {
+    fn from(error: TournamentError) -> Self {
+        Error::Tournament(error)
+    }
+}
+
+impl From<EvolveError> for Error {
+    fn from(error: EvolveError) -> Self {
+        Error::Evolve(error)
+    }
+}
+
+impl From<LessonError> for Error {
+    fn from(error: LessonError) -> Self {
+        Error::Lesson(error)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl From<MigrateError> for Error {
+    fn from(error: MigrateError) -> Self {
+        Error::Migrate(error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(map: &str, program: &str) -> Result<Simulator, Error> {
+        let world: World = map.parse()?;
+        let program = parse_program(program)?;
+        let programs = [(Color::Red, program)].into_iter().collect();
+        Ok(Simulator::headless(world, programs, 0)?)
+    }
+
+    #[test]
+    fn wraps_every_module() {
+        let error = load("# # #\n # r #\n# # #", "Move 0 1").err().unwrap();
+        assert!(matches!(error, Error::Simulator(_)));
+        assert_eq!(
+            error.to_string(),
+            "invalid program for Red: instruction 0 jumps to 1, past the end of the program"
+        );
+
+        let error = load("# x #", "Move 0 0").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "line 1, column 3: unexpected character 'x'"
+        );
+        assert!(std::error::Error::source(&error).is_none());
+    }
+
+    #[test]
+    fn actions_say_where_they_failed() {
+        let mut world = World::new(Grid::new(3, 3));
+        let id = world.add_ant(Color::Red, Position { x: 1, y: 2 }).unwrap();
+        let error = Action::DropFood
+            .apply(&mut world.ant_mut(id).unwrap(), &Default::default())
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "ant 0 at (1, 2) could not DropFood: the ant carries no food"
+        );
+        let error = Error::from(error);
+        let source = std::error::Error::source(&error).unwrap();
+        assert_eq!(source.to_string(), "the ant carries no food");
+    }
+}
@@ -0,0 +1,533 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::asm::*;
+use crate::rng::*;
+use crate::rules::*;
+use crate::sim::*;
+use crate::victory::*;
+use crate::world::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvolveConfig {
+    pub population: usize,
+    pub generations: u32,
+    // The best programs of a generation go on to the next unchanged.
+    pub elite: usize,
+    // Chances in 100 that a child is bred from two parents rather than
+    // copied from one, and that it then takes a mutation.
+    pub crossover_rate: u32,
+    pub mutation_rate: u32,
+    // Programs are cut back to this many instructions.
+    pub max_len: usize,
+    // Length of every match.
+    pub rounds: u32,
+}
+
+impl Default for EvolveConfig {
+    fn default() -> Self {
+        EvolveConfig {
+            population: 32,
+            generations: 20,
+            elite: 4,
+            crossover_rate: 30,
+            mutation_rate: 90,
+            max_len: 64,
+            rounds: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EvolveError {
+    NoMaps,
+    EmptyPopulation,
+    // `max_len` leaves no room for a single instruction.
+    ZeroMaxLen,
+    InvalidProgram(Vec<ProgramError>),
+    InvalidOpponent(Vec<ProgramError>),
+    InvalidSeed {
+        seed: usize,
+        errors: Vec<ProgramError>,
+    },
+}
+
+impl fmt::Display for EvolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EvolveError::NoMaps => write!(f, "evolution needs a map to play on"),
+            EvolveError::EmptyPopulation => write!(f, "the population is empty"),
+            EvolveError::ZeroMaxLen => write!(f, "programs cannot be cut back to nothing"),
+            EvolveError::InvalidProgram(errors) => {
+                write!(f, "invalid program: {}", describe_errors(errors))
+            }
+            EvolveError::InvalidOpponent(errors) => {
+                write!(f, "invalid opponent: {}", describe_errors(errors))
+            }
+            EvolveError::InvalidSeed { seed, errors } => {
+                write!(
+                    f,
+                    "seed program {} is invalid: {}",
+                    seed,
+                    describe_errors(errors)
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for EvolveError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Scored {
+    pub program: Program,
+    // Food brought home less food the opponent brought home, over every
+    // match played.
+    pub fitness: i64,
+}
+
+// Searches for programs that beat `opponent`. Every candidate plays each
+// map twice, once with each color, and the fittest breed the next
+// generation through crossover and mutation. Match seeds depend on the map
+// and color only, so a program scores the same in every generation and
+// the whole run is fixed by `seed`.
+pub struct Evolution {
+    opponent: Program,
+    maps: Vec<World>,
+    seeds: Vec<Program>,
+    config: EvolveConfig,
+    seed: u64,
+    rules: RuleSet,
+}
+
+impl Evolution {
+    pub fn new(opponent: Program, maps: Vec<World>) -> Self {
+        Evolution {
+            opponent,
+            maps,
+            seeds: Vec::new(),
+            config: EvolveConfig::default(),
+            seed: 0,
+            rules: RuleSet::default(),
+        }
+    }
+
+    // Programs to start from; the rest of the first generation are their
+    // mutants. Without any, the search starts from random programs.
+    pub fn with_seed_programs(mut self, programs: Vec<Program>) -> Self {
+        self.seeds = programs;
+        self
+    }
+
+    pub fn with_config(mut self, config: EvolveConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    // The last generation, fittest first.
+    pub fn run(&self) -> Result<Vec<Scored>, EvolveError> {
+        self.check()?;
+        let mut rng = Rng::stream(self.seed, &[u64::MAX]);
+        let mut population: Vec<_> = self
+            .first_generation(&mut rng)
+            .into_iter()
+            .map(|program| self.score(program))
+            .collect::<Result<_, _>>()?;
+        sort(&mut population);
+        for _ in 0..self.config.generations {
+            let mut next: Vec<_> = population.iter().take(self.config.elite).cloned().collect();
+            while next.len() < self.config.population {
+                let child = self.breed(&population, &mut rng);
+                next.push(self.score(child)?);
+            }
+            population = next;
+            sort(&mut population);
+        }
+        Ok(population)
+    }
+
+    // How `program` does against the opponent, see `Scored`.
+    pub fn fitness(&self, program: &Program) -> Result<i64, EvolveError> {
+        if let Some(errors) = fatal_errors(program) {
+            return Err(EvolveError::InvalidProgram(errors));
+        }
+        if let Some(errors) = fatal_errors(&self.opponent) {
+            return Err(EvolveError::InvalidOpponent(errors));
+        }
+        let mut fitness = 0;
+        for (map, world) in self.maps.iter().enumerate() {
+            for color in [Color::Red, Color::Black] {
+                let programs = HashMap::from([
+                    (color, program.clone()),
+                    (color.enemy(), self.opponent.clone()),
+                ]);
+                let seed = Rng::stream(self.seed, &[map as u64, color.index() as u64]).next_u64();
+                let mut simulator = Simulator::headless(world.clone(), programs, seed)
+                    .map_err(|error| match error {
+                        SimulatorError::InvalidProgram { errors, .. } => {
+                            EvolveError::InvalidProgram(errors)
+                        }
+                        // Only castes are refused otherwise.
+                        error => unreachable!("{}", error),
+                    })?
+                    .with_rules(self.rules.clone())
+                    .with_end_condition(EndCondition::MaxRounds(self.config.rounds));
+                simulator.run_until(Simulator::is_over);
+                let result = simulator.result().unwrap();
+                fitness += result.score(color) as i64 - result.score(color.enemy()) as i64;
+            }
+        }
+        Ok(fitness)
+    }
+
+    fn check(&self) -> Result<(), EvolveError> {
+        if self.maps.is_empty() {
+            return Err(EvolveError::NoMaps);
+        }
+        if self.config.population == 0 {
+            return Err(EvolveError::EmptyPopulation);
+        }
+        if self.config.max_len == 0 {
+            return Err(EvolveError::ZeroMaxLen);
+        }
+        if let Some(errors) = fatal_errors(&self.opponent) {
+            return Err(EvolveError::InvalidOpponent(errors));
+        }
+        for (seed, program) in self.seeds.iter().enumerate() {
+            if let Some(errors) = fatal_errors(program) {
+                return Err(EvolveError::InvalidSeed { seed, errors });
+            }
+        }
+        Ok(())
+    }
+
+    fn first_generation(&self, rng: &mut Rng) -> Vec<Program> {
+        let (size, max_len) = (self.config.population, self.config.max_len);
+        if self.seeds.is_empty() {
+            return (0..size)
+                .map(|_| random_program(rng, max_len.min(8)))
+                .collect();
+        }
+        let seeds: Vec<_> = (self.seeds.iter().cloned())
+            .map(|mut seed| {
+                truncate(&mut seed, max_len);
+                seed
+            })
+            .collect();
+        let mut programs: Vec<_> = seeds.iter().take(size).cloned().collect();
+        while programs.len() < size {
+            let parent = &seeds[rng.below(seeds.len() as u32) as usize];
+            let mut child = mutate(parent, rng, max_len);
+            truncate(&mut child, max_len);
+            programs.push(child);
+        }
+        programs
+    }
+
+    fn breed(&self, population: &[Scored], rng: &mut Rng) -> Program {
+        let mut child = select(population, rng).clone();
+        if rng.below(100) < self.config.crossover_rate {
+            child = crossover(&child, select(population, rng), rng);
+        }
+        if rng.below(100) < self.config.mutation_rate {
+            child = mutate(&child, rng, self.config.max_len);
+        }
+        truncate(&mut child, self.config.max_len);
+        child
+    }
+
+    fn score(&self, program: Program) -> Result<Scored, EvolveError> {
+        let fitness = self.fitness(&program)?;
+        Ok(Scored { program, fitness })
+    }
+}
+
+fn fatal_errors(program: &Program) -> Option<Vec<ProgramError>> {
+    let errors: Vec<_> = validate(program)
+        .err()?
+        .into_iter()
+        .filter(|error| error.is_fatal())
+        .collect();
+    (!errors.is_empty()).then_some(errors)
+}
+
+// Fittest first; among equals, the older program.
+fn sort(population: &mut [Scored]) {
+    population.sort_by_key(|scored| Reverse(scored.fitness));
+}
+
+// The fitter of two programs picked at random from a sorted population.
+fn select<'a>(population: &'a [Scored], rng: &mut Rng) -> &'a Program {
+    let len = population.len() as u32;
+    let pick = rng.below(len).min(rng.below(len));
+    &population[pick as usize].program
+}
+
+// One random change: swapping the two branches of an instruction,
+// pointing one branch elsewhere, replacing, inserting or deleting an
+// instruction. Every branch stays inside the program.
+pub fn mutate(program: &Program, rng: &mut Rng, max_len: usize) -> Program {
+    let mut program = program.clone();
+    let len = program.len();
+    let at = rng.below(len as u32) as usize;
+    match rng.below(5) {
+        0 => {
+            let mut targets = program[at].targets();
+            targets.reverse();
+            let mut targets = targets.into_iter();
+            program[at] = program[at].map_targets(|target| targets.next().unwrap_or(target));
+        }
+        1 => {
+            let targets = program[at].targets().len() as u32;
+            if targets > 0 {
+                let which = rng.below(targets);
+                let new_target = rng.below(len as u32) as usize;
+                let mut index = 0;
+                program[at] = program[at].map_targets(|target| {
+                    index += 1;
+                    if index - 1 == which {
+                        new_target
+                    } else {
+                        target
+                    }
+                });
+            }
+        }
+        2 => program[at] = random_instr(rng, len),
+        3 if len < max_len => {
+            // Jumps to `at` now reach the new instruction, which carries on
+            // to the one it displaced.
+            for instr in program.iter_mut() {
+                *instr = instr.map_targets(|target| target + (target > at) as usize);
+            }
+            program.insert(at, random_instr(rng, len).map_targets(|_| at + 1));
+        }
+        _ if len > 1 => {
+            // Jumps to the deleted instruction go where it would have.
+            let next = program[at].targets().first().copied().unwrap_or(0);
+            program.remove(at);
+            let shift = |target: InstrIdx| {
+                let target = if target == at { next } else { target };
+                match target {
+                    t if t == at => 0,
+                    t if t > at => t - 1,
+                    t => t,
+                }
+            };
+            for instr in program.iter_mut() {
+                *instr = instr.map_targets(shift);
+            }
+        }
+        _ => {}
+    }
+    program
+}
+
+// The start of `a` followed by the end of `b`, cut at random points.
+// Branches from `b` keep their distance to the cut where they can and wrap
+// round otherwise.
+pub fn crossover(a: &Program, b: &Program, rng: &mut Rng) -> Program {
+    let cut_a = 1 + rng.below(a.len() as u32) as usize;
+    let cut_b = rng.below(b.len() as u32) as usize;
+    let mut child: Program = a[..cut_a].to_vec();
+    child.extend(
+        b[cut_b..]
+            .iter()
+            .map(|instr| instr.map_targets(|target| (target + cut_a).saturating_sub(cut_b))),
+    );
+    let len = child.len();
+    child
+        .into_iter()
+        .map(|instr| instr.map_targets(|target| target % len))
+        .collect()
+}
+
+fn truncate(program: &mut Program, max_len: usize) {
+    if program.len() > max_len {
+        program.truncate(max_len);
+        for instr in program.iter_mut() {
+            *instr = instr.map_targets(|target| target % max_len);
+        }
+    }
+}
+
+pub fn random_program(rng: &mut Rng, len: usize) -> Program {
+    (0..len).map(|_| random_instr(rng, len)).collect()
+}
+
+// An instruction of the classic game with random operands and branches
+// into a program of `len` instructions.
+fn random_instr(rng: &mut Rng, len: usize) -> Instr {
+    let mut target = || rng.below(len as u32) as usize;
+    let (success_instr, fail_instr, next_instr) = (target(), target(), target());
+    match rng.below(8) {
+        0 => Instr::Turn {
+            direction: TURN_DIRECTIONS[rng.below(2) as usize].1,
+            next_instr,
+        },
+        1 => Instr::Move {
+            success_instr,
+            fail_instr,
+        },
+        2 => Instr::PickUpFood {
+            success_instr,
+            fail_instr,
+        },
+        3 => Instr::DropFood {
+            success_instr,
+            fail_instr,
+        },
+        4 => Instr::Mark {
+            marker: rng.below(MARKERS_PER_COLOR as u32) as Marker,
+            next_instr,
+        },
+        5 => Instr::Unmark {
+            marker: rng.below(MARKERS_PER_COLOR as u32) as Marker,
+            next_instr,
+        },
+        6 => {
+            let sense_dir = SENSE_DIRECTIONS[rng.below(4) as usize].1;
+            let condition = match rng.below(SENSE_CONDITIONS.len() as u32 + 1) as usize {
+                i if i < SENSE_CONDITIONS.len() => SENSE_CONDITIONS[i].1,
+                _ => SenseCondition::Marker(rng.below(MARKERS_PER_COLOR as u32) as Marker),
+            };
+            Instr::Sense {
+                sense_dir,
+                condition,
+                success_instr,
+                fail_instr,
+            }
+        }
+        _ => Instr::Flip {
+            n: 2 + rng.below(5),
+            success_instr,
+            fail_instr,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IDLER: &str = "start: Turn Left start";
+
+    // Walks ahead until blocked, picks up the food it is standing on, then
+    // turns round and walks back until blocked again to drop it.
+    const FORAGER: &str = "
+        out:  Move out pick
+        pick: PickUpFood turn turn
+        turn: Turn Left t2
+        t2:   Turn Left t3
+        t3:   Turn Left home
+        home: Move home drop
+        drop: DropFood drop drop
+    ";
+
+    const MAP: &str = "\
+# # # # # # #
+ # + r 4 b - #
+# # # # # # #
+";
+
+    fn fatal(program: &Program) -> bool {
+        fatal_errors(program).is_some()
+    }
+
+    #[test]
+    fn operators_keep_programs_valid() {
+        let forager = parse_program(FORAGER).unwrap();
+        let mut rng = Rng::new(7);
+        let mut program = forager.clone();
+        for _ in 0..500 {
+            program = mutate(&program, &mut rng, 12);
+            assert!(!program.is_empty() && program.len() <= 12);
+            assert!(!fatal(&program), "{}", disassemble(&program));
+            let child = crossover(&program, &forager, &mut rng);
+            assert!(!fatal(&child), "{}", disassemble(&child));
+        }
+        assert!(!fatal(&random_program(&mut rng, 5)));
+    }
+
+    #[test]
+    fn finds_a_forager() {
+        let maps = vec![MAP.parse().unwrap()];
+        let config = EvolveConfig {
+            population: 8,
+            generations: 3,
+            elite: 2,
+            rounds: 40,
+            ..EvolveConfig::default()
+        };
+        let forager = parse_program(FORAGER).unwrap();
+        let idler = parse_program(IDLER).unwrap();
+        let evolution = Evolution::new(idler.clone(), maps)
+            .with_config(config)
+            .with_seed_programs(vec![idler.clone(), forager.clone()])
+            .with_seed(3);
+        let best = evolution.run().unwrap();
+        assert_eq!(best.len(), 8);
+        assert!(best.windows(2).all(|w| w[0].fitness >= w[1].fitness));
+        assert!(best[0].fitness >= evolution.fitness(&forager).unwrap());
+        assert!(evolution.fitness(&forager).unwrap() > 0);
+        assert_eq!(evolution.fitness(&idler), Ok(0));
+        assert!(evolution.run().unwrap() == best);
+    }
+
+    #[test]
+    fn first_generation_fits_max_len() {
+        let forager = parse_program(FORAGER).unwrap();
+        let config = EvolveConfig {
+            population: 6,
+            max_len: 3,
+            ..EvolveConfig::default()
+        };
+        let maps = vec![MAP.parse().unwrap()];
+        let mut rng = Rng::new(1);
+        for seeds in [vec![], vec![forager]] {
+            let evolution = Evolution::new(parse_program(IDLER).unwrap(), maps.clone())
+                .with_config(config)
+                .with_seed_programs(seeds);
+            for program in evolution.first_generation(&mut rng) {
+                assert!((1..=3).contains(&program.len()));
+                assert!(!fatal(&program), "{}", disassemble(&program));
+            }
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let idler = parse_program(IDLER).unwrap();
+        assert_eq!(
+            Evolution::new(idler.clone(), Vec::new()).run(),
+            Err(EvolveError::NoMaps)
+        );
+        let broken = vec![Instr::Move {
+            success_instr: 0,
+            fail_instr: 3,
+        }];
+        let evolution = Evolution::new(idler.clone(), vec![MAP.parse().unwrap()]);
+        let error = (evolution.fitness(&broken)).unwrap_err();
+        assert!(matches!(error, EvolveError::InvalidProgram(_)));
+        let error = (evolution.with_seed_programs(vec![broken]).run()).unwrap_err();
+        assert!(matches!(error, EvolveError::InvalidSeed { seed: 0, .. }));
+
+        let config = EvolveConfig {
+            max_len: 0,
+            ..EvolveConfig::default()
+        };
+        let evolution = Evolution::new(idler, vec![MAP.parse().unwrap()]).with_config(config);
+        assert_eq!(evolution.run(), Err(EvolveError::ZeroMaxLen));
+    }
+}
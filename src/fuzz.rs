@@ -0,0 +1,104 @@
+use arbitrary::{Arbitrary, Result, Unstructured};
+
+use crate::asm::*;
+use crate::world::*;
+
+// Bounds that keep generated inputs small enough to simulate quickly.
+pub const MAX_GRID_SIDE: usize = 32;
+pub const MAX_PROGRAM_LEN: usize = 64;
+
+// Free cells carry at most 9 food, the most a text map can express.
+impl<'a> Arbitrary<'a> for Cell {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        if u.ratio(1, 4)? {
+            return Ok(Cell::Wall);
+        }
+        let mut markers = Markers::default();
+        for color in [Color::Black, Color::Red] {
+            for marker in 0..MARKERS_PER_COLOR {
+                if u.ratio(1, 8)? {
+                    markers.set(color, marker);
+                }
+            }
+        }
+        Ok(Cell::FreeCell {
+            ant_id: None,
+            food: u.int_in_range(0..=9)?,
+            markers,
+            anthill: u.arbitrary()?,
+        })
+    }
+}
+
+// Grids come without ants; put them in through `World::add_ant`.
+impl<'a> Arbitrary<'a> for Grid {
+    fn arbitrary(u: &mut Unstructured<'a>) -> Result<Self> {
+        let width = u.int_in_range(1..=MAX_GRID_SIDE)?;
+        let height = u.int_in_range(1..=MAX_GRID_SIDE)?;
+        let mut grid = Grid::new(width, height);
+        for y in 0..height as i32 {
+            for x in 0..width as i32 {
+                *grid.cell_at_mut(Position { x, y }).unwrap() = u.arbitrary()?;
+            }
+        }
+        Ok(grid)
+    }
+}
+
+// A derived `Vec<Instr>` jumps almost anywhere; this keeps every target
+// inside the program so the result passes the simulator's validation.
+pub fn arbitrary_program(u: &mut Unstructured) -> Result<Program> {
+    let len = u.int_in_range(1..=MAX_PROGRAM_LEN)?;
+    (0..len)
+        .map(|_| {
+            let instr: Instr = u.arbitrary()?;
+            Ok(instr.map_targets(|target| target % len))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(seed: u64) -> Vec<u8> {
+        let mut rng = crate::rng::Rng::new(seed);
+        (0..4096).map(|_| rng.next_u64() as u8).collect()
+    }
+
+    #[test]
+    fn programs_are_valid() {
+        for seed in 0..50 {
+            let data = bytes(seed);
+            let program = arbitrary_program(&mut Unstructured::new(&data)).unwrap();
+            let fatal = validate(&program)
+                .err()
+                .unwrap_or_default()
+                .into_iter()
+                .any(|error| error.is_fatal());
+            assert!(!fatal);
+        }
+    }
+
+    #[test]
+    fn grids_have_no_ants() {
+        for seed in 0..50 {
+            let data = bytes(seed);
+            let grid = Grid::arbitrary(&mut Unstructured::new(&data)).unwrap();
+            assert!((1..=MAX_GRID_SIDE).contains(&grid.width()));
+            assert!((1..=MAX_GRID_SIDE).contains(&grid.height()));
+            for y in 0..grid.height() as i32 {
+                for x in 0..grid.width() as i32 {
+                    assert!(!grid.cell_at(Position { x, y }).unwrap().has_ant());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_input_still_generates() {
+        let mut u = Unstructured::new(&[]);
+        assert!(!arbitrary_program(&mut u).unwrap().is_empty());
+        assert!(Grid::arbitrary(&mut u).is_ok());
+    }
+}
@@ -0,0 +1,199 @@
+use crate::world::*;
+
+// Geometry of the unbounded hex plane, for map generators, sensing radii
+// and tooling; `Grid` adds the edges and the wrap of a torus on top.
+//
+// A `Position` is in axial coordinates: `Right` adds one to `x` and
+// `DownRight` one to `y`, whatever the row. Text maps indent alternate
+// rows only to hint at the hex shape. The cube form adds `z = -x - y`,
+// which treats the three axes alike and is what rounding works in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+pub struct Cube {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl Cube {
+    // The cube cell containing a fractional point with `x + y + z == 0`.
+    pub fn round(x: f64, y: f64, z: f64) -> Cube {
+        let (mut rx, mut ry, mut rz) = (x.round(), y.round(), z.round());
+        let (dx, dy, dz) = ((rx - x).abs(), (ry - y).abs(), (rz - z).abs());
+        // The coordinate that rounded furthest is rebuilt from the others.
+        if dx > dy && dx > dz {
+            rx = -ry - rz;
+        } else if dy > dz {
+            ry = -rx - rz;
+        } else {
+            rz = -rx - ry;
+        }
+        Cube {
+            x: rx as i32,
+            y: ry as i32,
+            z: rz as i32,
+        }
+    }
+}
+
+impl From<Position> for Cube {
+    fn from(position: Position) -> Self {
+        Cube {
+            x: position.x,
+            y: position.y,
+            z: -position.x - position.y,
+        }
+    }
+}
+
+impl From<Cube> for Position {
+    fn from(cube: Cube) -> Self {
+        debug_assert_eq!(cube.x + cube.y + cube.z, 0, "not a cube coordinate");
+        Position {
+            x: cube.x,
+            y: cube.y,
+        }
+    }
+}
+
+pub fn distance(a: Position, b: Position) -> u32 {
+    a.distance(b)
+}
+
+// The six adjacent cells, in direction order starting from `Right`.
+pub fn neighbors(position: Position) -> impl Iterator<Item = Position> {
+    Direction::all().map(move |direction| position.translate(direction))
+}
+
+// Cells exactly `radius` steps away from `center`, walked clockwise
+// starting from the corner in the `UpLeft` direction.
+pub fn ring(center: Position, radius: u32) -> impl Iterator<Item = Position> {
+    let start = center.translate_by(Direction::UpLeft, radius as i32);
+    let len = if radius == 0 { 1 } else { 6 * radius };
+    (0..len).scan(start, move |position, i| {
+        let current = *position;
+        if let Some(side) = i.checked_div(radius) {
+            *position = position.translate(Direction::try_from(side).unwrap());
+        }
+        Some(current)
+    })
+}
+
+// Cells within `max_radius` of `center`, ring by ring.
+pub fn spiral(center: Position, max_radius: u32) -> impl Iterator<Item = Position> {
+    (0..=max_radius).flat_map(move |radius| ring(center, radius))
+}
+
+// `offset` from the origin turned clockwise about it by `turns` sixths of
+// a full turn, as `Direction::rotated` turns a direction.
+pub fn rotate(offset: Position, turns: i32) -> Position {
+    let mut cube = Cube::from(offset);
+    for _ in 0..turns.rem_euclid(6) {
+        cube = Cube {
+            x: -cube.y,
+            y: -cube.z,
+            z: -cube.x,
+        };
+    }
+    Position::from(cube)
+}
+
+// The cells a straight line from `a` to `b` passes through, both ends
+// included, each adjacent to the next. Lines running exactly along a
+// cell edge always fall to the same side.
+pub fn line(a: Position, b: Position) -> Vec<Position> {
+    let steps = a.distance(b);
+    let (from, to) = (Cube::from(a), Cube::from(b));
+    // Nudged off the edges so rounding never has to break a tie.
+    let start = (
+        f64::from(from.x) + 1e-6,
+        f64::from(from.y) + 2e-6,
+        f64::from(from.z) - 3e-6,
+    );
+    let delta = (
+        f64::from(to.x - from.x),
+        f64::from(to.y - from.y),
+        f64::from(to.z - from.z),
+    );
+    (0..=steps)
+        .map(|i| {
+            let t = if steps == 0 {
+                0.0
+            } else {
+                f64::from(i) / f64::from(steps)
+            };
+            Position::from(Cube::round(
+                start.0 + delta.0 * t,
+                start.1 + delta.1 * t,
+                start.2 + delta.2 * t,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CENTER: Position = Position { x: 3, y: -2 };
+
+    #[test]
+    fn cube_round_trip() {
+        let cube = Cube::from(CENTER);
+        assert_eq!(cube, Cube { x: 3, y: -2, z: -1 });
+        assert_eq!(Position::from(cube), CENTER);
+        assert_eq!(Cube::round(0.4, 0.4, -0.8), Cube { x: 0, y: 1, z: -1 });
+    }
+
+    #[test]
+    fn rotation() {
+        let origin = Position::default();
+        for direction in Direction::all() {
+            let step = origin.translate(direction);
+            assert_eq!(rotate(step, 1), origin.translate(direction.rotated(1)));
+            assert_eq!(rotate(step, -2), origin.translate(direction.rotated(-2)));
+        }
+        assert_eq!(rotate(CENTER, 6), CENTER);
+        assert_eq!(
+            distance(rotate(CENTER, 2), origin),
+            distance(CENTER, origin)
+        );
+    }
+
+    #[test]
+    fn neighbors_are_adjacent() {
+        let around: Vec<_> = neighbors(CENTER).collect();
+        assert_eq!(around.len(), 6);
+        assert_eq!(around[0], Position { x: 4, y: -2 });
+        assert!(around.iter().all(|&p| distance(p, CENTER) == 1));
+    }
+
+    #[test]
+    fn rings_and_spirals() {
+        for radius in 0..5 {
+            let cells: Vec<_> = ring(CENTER, radius).collect();
+            assert_eq!(cells.len(), (6 * radius).max(1) as usize);
+            assert!(cells.iter().all(|&p| distance(p, CENTER) == radius));
+        }
+        let mut cells: Vec<_> = spiral(CENTER, 3).map(|p| (p.x, p.y)).collect();
+        assert_eq!(cells.len(), 37);
+        cells.sort();
+        cells.dedup();
+        assert_eq!(cells.len(), 37);
+    }
+
+    #[test]
+    fn lines() {
+        assert_eq!(line(CENTER, CENTER), [CENTER]);
+        let straight = line(CENTER, CENTER.translate_by(Direction::DownLeft, 4));
+        assert_eq!(straight.len(), 5);
+        for (i, &p) in straight.iter().enumerate() {
+            assert_eq!(p, CENTER.translate_by(Direction::DownLeft, i as i32));
+        }
+
+        let end = Position { x: -4, y: 5 };
+        let cells = line(CENTER, end);
+        assert_eq!(cells.len(), distance(CENTER, end) as usize + 1);
+        assert_eq!((cells[0], *cells.last().unwrap()), (CENTER, end));
+        assert!(cells.windows(2).all(|pair| distance(pair[0], pair[1]) == 1));
+    }
+}
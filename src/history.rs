@@ -0,0 +1,173 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::snapshot::*;
+use crate::world::*;
+
+// How much of the past `Simulator::start_history` keeps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HistoryConfig {
+    // Rounds between full snapshots. The rounds in between are kept as
+    // what changed since the round before, so seeking replays at most this
+    // many of them.
+    pub keyframe_interval: u32,
+    // Bytes of snapshots and changes to keep. The oldest snapshot goes
+    // first, together with the rounds that build on it; the newest one is
+    // kept whatever its size.
+    pub memory_budget: usize,
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            keyframe_interval: 32,
+            memory_budget: 16 << 20,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryError {
+    NotRecording,
+    // The round was never played, or was forgotten to stay in budget.
+    NotKept(u32),
+    // There is no round before the first one.
+    AtStart,
+    // A kept round would not load into the simulator as it is now.
+    Snapshot(SnapshotError),
+}
+
+impl fmt::Display for HistoryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            HistoryError::NotRecording => write!(f, "no history is being kept"),
+            HistoryError::NotKept(round) => write!(f, "round {} is not in the history", round),
+            HistoryError::AtStart => write!(f, "already at the first round"),
+            HistoryError::Snapshot(_) => write!(f, "the kept round could not be loaded"),
+        }
+    }
+}
+
+impl std::error::Error for HistoryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            HistoryError::Snapshot(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+enum Frame {
+    // A full `Simulator::snapshot`.
+    Key(Vec<u8>),
+    // The changes since the frame before.
+    Patch(Vec<u8>),
+}
+
+impl Frame {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Frame::Key(bytes) | Frame::Patch(bytes) => bytes,
+        }
+    }
+}
+
+// One frame per round, from `first_round` on.
+pub(crate) struct History {
+    config: HistoryConfig,
+    first_round: u32,
+    frames: VecDeque<Frame>,
+    bytes: usize,
+    // The world as of the round just played, which the next patch is taken
+    // against.
+    base: World,
+}
+
+impl History {
+    pub(crate) fn new(config: HistoryConfig, round: u32, snapshot: Vec<u8>, world: World) -> Self {
+        let mut history = History {
+            config,
+            first_round: round,
+            frames: VecDeque::new(),
+            bytes: 0,
+            base: world,
+        };
+        history.push(Frame::Key(snapshot));
+        history
+    }
+
+    pub(crate) fn config(&self) -> HistoryConfig {
+        self.config
+    }
+
+    pub(crate) fn rounds(&self) -> RangeInclusive<u32> {
+        self.first_round..=self.first_round + self.frames.len() as u32 - 1
+    }
+
+    pub(crate) fn base(&self) -> &World {
+        &self.base
+    }
+
+    pub(crate) fn set_base(&mut self, world: World) {
+        self.base = world;
+    }
+
+    // Whether the next round should be a full snapshot.
+    pub(crate) fn needs_key(&self) -> bool {
+        let since_key = self
+            .frames
+            .iter()
+            .rev()
+            .take_while(|frame| matches!(frame, Frame::Patch(_)))
+            .count() as u32;
+        since_key + 1 >= self.config.keyframe_interval.max(1)
+    }
+
+    // Adds `round`, forgetting any rounds after it left over from before
+    // a seek.
+    pub(crate) fn push_round(&mut self, round: u32, key: bool, bytes: Vec<u8>, world: World) {
+        let keep = round.saturating_sub(self.first_round) as usize;
+        while self.frames.len() > keep {
+            let frame = self.frames.pop_back().unwrap();
+            self.bytes -= frame.bytes().len();
+        }
+        self.push(if key {
+            Frame::Key(bytes)
+        } else {
+            Frame::Patch(bytes)
+        });
+        self.base = world;
+    }
+
+    fn push(&mut self, frame: Frame) {
+        self.bytes += frame.bytes().len();
+        self.frames.push_back(frame);
+        while self.bytes > self.config.memory_budget {
+            let Some(next_key) =
+                (1..self.frames.len()).find(|&i| matches!(self.frames[i], Frame::Key(_)))
+            else {
+                break;
+            };
+            for frame in self.frames.drain(..next_key) {
+                self.bytes -= frame.bytes().len();
+            }
+            self.first_round += next_key as u32;
+        }
+    }
+
+    // The snapshot to restore for `round` and the patches to apply after
+    // it, oldest first.
+    pub(crate) fn frames_to(&self, round: u32) -> Result<(&[u8], Vec<&[u8]>), HistoryError> {
+        if !self.rounds().contains(&round) {
+            return Err(HistoryError::NotKept(round));
+        }
+        let index = (round - self.first_round) as usize;
+        let key = (0..=index)
+            .rev()
+            .find(|&i| matches!(self.frames[i], Frame::Key(_)))
+            .expect("history starts with a keyframe");
+        let patches = (key + 1..=index).map(|i| self.frames[i].bytes()).collect();
+        Ok((self.frames[key].bytes(), patches))
+    }
+}
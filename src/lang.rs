@@ -0,0 +1,798 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::asm::*;
+use crate::world::*;
+
+// A small structured language that compiles to asm, so that nobody has to
+// count jump targets by hand. A program is a list of named states and ants
+// start in the first one. A state runs its statements top to bottom and
+// then starts over, until a `goto` moves the ant to another state:
+//
+//   state search {
+//       if sense(ahead, food) { move  goto fetch }
+//       if not move { turn left }
+//   }
+//   state fetch {
+//       if pickup { goto search }
+//   }
+//
+// Statements are the actions `move`, `pickup`, `drop`, `eat`, `turn left`,
+// `turn right`, `mark <n>` and `unmark <n>`, and
+//
+//   goto <state>
+//   if <condition> { ... } else { ... }
+//   while <condition> { ... }
+//   repeat <n> { ... }
+//
+// Conditions are `sense(<sense dir>, <condition>)`, `flip(<n>)`,
+// `facing(<direction>)` and the actions that can fail, which count as true
+// when they succeed, combined with `not`, `and`, `or` and parentheses.
+// Keywords and operands are the same as in the asm and case-insensitive.
+// `;` starts a comment.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LangErrorKind {
+    UnexpectedToken(String),
+    UnexpectedEnd,
+    InvalidOperand(String),
+    NoStates,
+    DuplicateState(String),
+    UnknownState(String),
+    // The state can go round without running a single instruction.
+    EmptyState(String),
+    // What the validator found in the compiled program, such as code after
+    // a `goto` or a state nothing leads to.
+    Program(ProgramError),
+}
+
+impl fmt::Display for LangErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LangErrorKind::UnexpectedToken(token) => write!(f, "unexpected `{}`", token),
+            LangErrorKind::UnexpectedEnd => write!(f, "unexpected end of program"),
+            LangErrorKind::InvalidOperand(operand) => write!(f, "invalid operand `{}`", operand),
+            LangErrorKind::NoStates => write!(f, "the program has no states"),
+            LangErrorKind::DuplicateState(name) => write!(f, "state `{}` is defined twice", name),
+            LangErrorKind::UnknownState(name) => write!(f, "unknown state `{}`", name),
+            LangErrorKind::EmptyState(name) => {
+                write!(f, "state `{}` can go round without running anything", name)
+            }
+            LangErrorKind::Program(error) => error.fmt(f),
+        }
+    }
+}
+
+// `line` is 1-based.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LangError {
+    pub line: usize,
+    pub kind: LangErrorKind,
+}
+
+impl fmt::Display for LangError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.kind)
+    }
+}
+
+impl std::error::Error for LangError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Move,
+    PickUp,
+    Drop,
+    Eat,
+    Turn(TurnDirection),
+    Mark(Marker),
+    Unmark(Marker),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Cond {
+    Sense(SenseDirection, SenseCondition),
+    Flip(u32),
+    Facing(Direction),
+    Step(Step),
+    Not(Box<Cond>),
+    And(Box<Cond>, Box<Cond>),
+    Or(Box<Cond>, Box<Cond>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StmtKind<'a> {
+    Step(Step),
+    Goto(&'a str),
+    If(Cond, Vec<Stmt<'a>>, Vec<Stmt<'a>>),
+    While(Cond, Vec<Stmt<'a>>),
+    Repeat(u32, Vec<Stmt<'a>>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Stmt<'a> {
+    line: usize,
+    kind: StmtKind<'a>,
+}
+
+struct State<'a> {
+    line: usize,
+    name: &'a str,
+    body: Vec<Stmt<'a>>,
+}
+
+fn tokenize(source: &str) -> Vec<(usize, &str)> {
+    let mut tokens = Vec::new();
+    for (line_idx, line) in source.lines().enumerate() {
+        let line_no = line_idx + 1;
+        let code = line.split(';').next().unwrap();
+        let mut start = None;
+        for (i, c) in code.char_indices() {
+            let punct = matches!(c, '{' | '}' | '(' | ')' | ',');
+            if c.is_whitespace() || punct {
+                if let Some(s) = start.take() {
+                    tokens.push((line_no, &code[s..i]));
+                }
+                if punct {
+                    tokens.push((line_no, &code[i..i + 1]));
+                }
+            } else if start.is_none() {
+                start = Some(i);
+            }
+        }
+        if let Some(s) = start {
+            tokens.push((line_no, &code[s..]));
+        }
+    }
+    tokens
+}
+
+struct Parser<'a> {
+    tokens: Vec<(usize, &'a str)>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn line(&self) -> usize {
+        match self.tokens.get(self.pos) {
+            Some(&(line, _)) => line,
+            None => self.tokens.last().map_or(1, |&(line, _)| line),
+        }
+    }
+
+    fn error(&self, kind: LangErrorKind) -> LangError {
+        LangError {
+            line: self.line(),
+            kind,
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.pos).map(|&(_, token)| token)
+    }
+
+    fn peek_is(&self, keyword: &str) -> bool {
+        self.peek()
+            .is_some_and(|token| token.eq_ignore_ascii_case(keyword))
+    }
+
+    fn next(&mut self) -> Result<&'a str, LangError> {
+        let token = self
+            .peek()
+            .ok_or_else(|| self.error(LangErrorKind::UnexpectedEnd))?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, keyword: &str) -> bool {
+        let found = self.peek_is(keyword);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, keyword: &str) -> Result<(), LangError> {
+        if self.eat(keyword) {
+            return Ok(());
+        }
+        let token = self.next()?;
+        self.pos -= 1;
+        Err(self.error(LangErrorKind::UnexpectedToken(token.to_string())))
+    }
+
+    fn invalid(&mut self, token: &str) -> LangError {
+        self.pos -= 1;
+        self.error(LangErrorKind::InvalidOperand(token.to_string()))
+    }
+
+    fn number<T: std::str::FromStr>(&mut self) -> Result<T, LangError> {
+        let token = self.next()?;
+        token.parse().map_err(|_| self.invalid(token))
+    }
+
+    fn keyword<T: Copy>(&mut self, options: &[(&str, T)]) -> Result<T, LangError> {
+        let token = self.next()?;
+        options
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(token))
+            .map(|&(_, value)| value)
+            .ok_or_else(|| self.invalid(token))
+    }
+
+    fn name(&mut self) -> Result<&'a str, LangError> {
+        let token = self.next()?;
+        if is_label(token) {
+            Ok(token)
+        } else {
+            Err(self.invalid(token))
+        }
+    }
+
+    fn states(&mut self) -> Result<Vec<State<'a>>, LangError> {
+        let mut states = Vec::new();
+        while self.peek().is_some() {
+            let line = self.line();
+            self.expect("state")?;
+            let name = self.name()?;
+            let body = self.block()?;
+            states.push(State { line, name, body });
+        }
+        Ok(states)
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt<'a>>, LangError> {
+        self.expect("{")?;
+        let mut stmts = Vec::new();
+        while !self.eat("}") {
+            stmts.push(self.stmt()?);
+        }
+        Ok(stmts)
+    }
+
+    // The actions that can fail, usable as conditions too.
+    fn fallible_step(&mut self) -> Option<Step> {
+        let step = [
+            ("move", Step::Move),
+            ("pickup", Step::PickUp),
+            ("drop", Step::Drop),
+            ("eat", Step::Eat),
+        ]
+        .into_iter()
+        .find(|(name, _)| self.peek_is(name))
+        .map(|(_, step)| step)?;
+        self.pos += 1;
+        Some(step)
+    }
+
+    fn stmt(&mut self) -> Result<Stmt<'a>, LangError> {
+        let line = self.line();
+        let kind = if let Some(step) = self.fallible_step() {
+            StmtKind::Step(step)
+        } else if self.eat("turn") {
+            StmtKind::Step(Step::Turn(self.keyword(&TURN_DIRECTIONS)?))
+        } else if self.eat("mark") {
+            StmtKind::Step(Step::Mark(self.number()?))
+        } else if self.eat("unmark") {
+            StmtKind::Step(Step::Unmark(self.number()?))
+        } else if self.eat("goto") {
+            StmtKind::Goto(self.name()?)
+        } else if self.eat("if") {
+            return self.if_stmt(line);
+        } else if self.eat("while") {
+            StmtKind::While(self.cond()?, self.block()?)
+        } else if self.eat("repeat") {
+            StmtKind::Repeat(self.number()?, self.block()?)
+        } else {
+            let token = self.next()?;
+            self.pos -= 1;
+            return Err(self.error(LangErrorKind::UnexpectedToken(token.to_string())));
+        };
+        Ok(Stmt { line, kind })
+    }
+
+    fn if_stmt(&mut self, line: usize) -> Result<Stmt<'a>, LangError> {
+        let cond = self.cond()?;
+        let then = self.block()?;
+        let otherwise = if !self.eat("else") {
+            Vec::new()
+        } else if self.peek_is("if") {
+            let line = self.line();
+            self.pos += 1;
+            vec![self.if_stmt(line)?]
+        } else {
+            self.block()?
+        };
+        Ok(Stmt {
+            line,
+            kind: StmtKind::If(cond, then, otherwise),
+        })
+    }
+
+    fn cond(&mut self) -> Result<Cond, LangError> {
+        let mut cond = self.and_cond()?;
+        while self.eat("or") {
+            cond = Cond::Or(Box::new(cond), Box::new(self.and_cond()?));
+        }
+        Ok(cond)
+    }
+
+    fn and_cond(&mut self) -> Result<Cond, LangError> {
+        let mut cond = self.unary_cond()?;
+        while self.eat("and") {
+            cond = Cond::And(Box::new(cond), Box::new(self.unary_cond()?));
+        }
+        Ok(cond)
+    }
+
+    fn unary_cond(&mut self) -> Result<Cond, LangError> {
+        if self.eat("not") {
+            return Ok(Cond::Not(Box::new(self.unary_cond()?)));
+        }
+        if self.eat("(") {
+            let cond = self.cond()?;
+            self.expect(")")?;
+            return Ok(cond);
+        }
+        if let Some(step) = self.fallible_step() {
+            return Ok(Cond::Step(step));
+        }
+        let cond = if self.eat("sense") {
+            self.expect("(")?;
+            let sense_dir = self.keyword(&SENSE_DIRECTIONS)?;
+            self.expect(",")?;
+            let condition = if self.eat("marker") {
+                SenseCondition::Marker(self.number()?)
+            } else {
+                self.keyword(&SENSE_CONDITIONS)?
+            };
+            Cond::Sense(sense_dir, condition)
+        } else if self.eat("flip") {
+            self.expect("(")?;
+            Cond::Flip(self.number()?)
+        } else if self.eat("facing") {
+            self.expect("(")?;
+            Cond::Facing(self.keyword(&DIRECTIONS)?)
+        } else {
+            let token = self.next()?;
+            self.pos -= 1;
+            return Err(self.error(LangErrorKind::UnexpectedToken(token.to_string())));
+        };
+        self.expect(")")?;
+        Ok(cond)
+    }
+}
+
+type LabelId = usize;
+
+#[derive(Clone, Copy)]
+enum Label {
+    Unplaced,
+    At(InstrIdx),
+    // Wherever the other label ends up, for code that emits nothing.
+    Alias(LabelId),
+}
+
+// Emits instructions whose targets are label ids, resolved once all code
+// is in place.
+struct Codegen<'a> {
+    program: Program,
+    lines: Vec<usize>,
+    labels: Vec<Label>,
+    states: HashMap<&'a str, LabelId>,
+}
+
+impl<'a> Codegen<'a> {
+    fn label(&mut self) -> LabelId {
+        self.labels.push(Label::Unplaced);
+        self.labels.len() - 1
+    }
+
+    fn alias(&mut self, label: LabelId, target: LabelId) {
+        self.labels[label] = Label::Alias(target);
+    }
+
+    fn emit(&mut self, entry: LabelId, line: usize, instr: Instr) {
+        self.labels[entry] = Label::At(self.program.len());
+        self.program.push(instr);
+        self.lines.push(line);
+    }
+
+    fn resolve(&self, mut label: LabelId) -> Option<InstrIdx> {
+        for _ in 0..self.labels.len() {
+            match self.labels[label] {
+                Label::At(idx) => return Some(idx),
+                Label::Alias(target) => label = target,
+                Label::Unplaced => return None,
+            }
+        }
+        None
+    }
+
+    fn block(
+        &mut self,
+        stmts: &[Stmt<'a>],
+        mut entry: LabelId,
+        next: LabelId,
+    ) -> Result<(), LangError> {
+        let Some((last, rest)) = stmts.split_last() else {
+            self.alias(entry, next);
+            return Ok(());
+        };
+        for stmt in rest {
+            let after = self.label();
+            self.stmt(stmt, entry, after)?;
+            entry = after;
+        }
+        self.stmt(last, entry, next)
+    }
+
+    fn stmt(&mut self, stmt: &Stmt<'a>, entry: LabelId, next: LabelId) -> Result<(), LangError> {
+        let line = stmt.line;
+        match &stmt.kind {
+            &StmtKind::Step(step) => self.emit(entry, line, step_instr(step, next, next)),
+            StmtKind::Goto(name) => {
+                let &state = self.states.get(name).ok_or_else(|| LangError {
+                    line,
+                    kind: LangErrorKind::UnknownState(name.to_string()),
+                })?;
+                self.alias(entry, state);
+            }
+            StmtKind::If(cond, then, otherwise) => {
+                let (then_label, else_label) = (self.label(), self.label());
+                self.cond(cond, line, entry, then_label, else_label);
+                self.block(then, then_label, next)?;
+                self.block(otherwise, else_label, next)?;
+            }
+            StmtKind::While(cond, body) => {
+                let body_label = self.label();
+                self.cond(cond, line, entry, body_label, next);
+                self.block(body, body_label, entry)?;
+            }
+            &StmtKind::Repeat(times, ref body) => {
+                let mut entry = entry;
+                for _ in 1..times {
+                    let after = self.label();
+                    self.block(body, entry, after)?;
+                    entry = after;
+                }
+                if times == 0 {
+                    self.alias(entry, next);
+                } else {
+                    self.block(body, entry, next)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn cond(&mut self, cond: &Cond, line: usize, entry: LabelId, yes: LabelId, no: LabelId) {
+        let instr = match cond {
+            &Cond::Sense(sense_dir, condition) => Instr::Sense {
+                sense_dir,
+                condition,
+                success_instr: yes,
+                fail_instr: no,
+            },
+            &Cond::Flip(n) => Instr::Flip {
+                n,
+                success_instr: yes,
+                fail_instr: no,
+            },
+            &Cond::Facing(direction) => Instr::Direction {
+                direction,
+                success_instr: yes,
+                fail_instr: no,
+            },
+            &Cond::Step(step) => step_instr(step, yes, no),
+            Cond::Not(cond) => return self.cond(cond, line, entry, no, yes),
+            Cond::And(left, right) => {
+                let middle = self.label();
+                self.cond(left, line, entry, middle, no);
+                return self.cond(right, line, middle, yes, no);
+            }
+            Cond::Or(left, right) => {
+                let middle = self.label();
+                self.cond(left, line, entry, yes, middle);
+                return self.cond(right, line, middle, yes, no);
+            }
+        };
+        self.emit(entry, line, instr);
+    }
+}
+
+fn step_instr(step: Step, success_instr: LabelId, fail_instr: LabelId) -> Instr {
+    match step {
+        Step::Move => Instr::Move {
+            success_instr,
+            fail_instr,
+        },
+        Step::PickUp => Instr::PickUpFood {
+            success_instr,
+            fail_instr,
+        },
+        Step::Drop => Instr::DropFood {
+            success_instr,
+            fail_instr,
+        },
+        Step::Eat => Instr::Eat {
+            success_instr,
+            fail_instr,
+        },
+        Step::Turn(direction) => Instr::Turn {
+            direction,
+            next_instr: success_instr,
+        },
+        Step::Mark(marker) => Instr::Mark {
+            marker,
+            next_instr: success_instr,
+        },
+        Step::Unmark(marker) => Instr::Unmark {
+            marker,
+            next_instr: success_instr,
+        },
+    }
+}
+
+pub fn compile(source: &str) -> Result<Program, LangError> {
+    let mut parser = Parser {
+        tokens: tokenize(source),
+        pos: 0,
+    };
+    let states = parser.states()?;
+    let Some(first) = states.first() else {
+        return Err(LangError {
+            line: 1,
+            kind: LangErrorKind::NoStates,
+        });
+    };
+
+    let mut gen = Codegen {
+        program: Vec::new(),
+        lines: Vec::new(),
+        labels: Vec::new(),
+        states: HashMap::new(),
+    };
+    for state in &states {
+        let label = gen.label();
+        if gen.states.insert(state.name, label).is_some() {
+            return Err(LangError {
+                line: state.line,
+                kind: LangErrorKind::DuplicateState(state.name.to_string()),
+            });
+        }
+    }
+    for state in &states {
+        let label = gen.states[state.name];
+        gen.block(&state.body, label, label)?;
+    }
+    for state in &states {
+        if gen.resolve(gen.states[state.name]).is_none() {
+            return Err(LangError {
+                line: state.line,
+                kind: LangErrorKind::EmptyState(state.name.to_string()),
+            });
+        }
+    }
+
+    // Every label leads to a state or to code by now.
+    let resolved: Vec<InstrIdx> = (0..gen.labels.len())
+        .map(|label| gen.resolve(label).unwrap())
+        .collect();
+    let mut program: Program = gen
+        .program
+        .iter()
+        .map(|instr| instr.map_targets(|label| resolved[label]))
+        .collect();
+    let mut lines = gen.lines;
+    // Ants start at the first instruction, which need not be the first
+    // state's if it begins with a `goto`: jump there from the front.
+    let start = resolved[gen.states[first.name]];
+    if start != 0 {
+        program = program
+            .into_iter()
+            .map(|instr| instr.map_targets(|target| target + 1))
+            .collect();
+        program.insert(
+            0,
+            Instr::Direction {
+                direction: Direction::Right,
+                success_instr: start + 1,
+                fail_instr: start + 1,
+            },
+        );
+        lines.insert(0, first.line);
+    }
+
+    if let Err(errors) = validate(&program) {
+        let error = errors[0];
+        let line = match error {
+            ProgramError::TargetOutOfBounds { instr, .. } | ProgramError::Unreachable { instr } => {
+                lines[instr]
+            }
+            _ => first.line,
+        };
+        return Err(LangError {
+            line,
+            kind: LangErrorKind::Program(error),
+        });
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> LangError {
+        compile(source).unwrap_err()
+    }
+
+    #[test]
+    fn states_loop_until_goto() {
+        let program = compile(
+            "
+            state search {
+                if sense(ahead, food) { move  goto fetch }
+                if not move { turn left }
+            }
+            state fetch {
+                if pickup { goto search }
+            }
+            ",
+        )
+        .unwrap();
+        let expected = parse_program(
+            "
+            search: Sense Ahead Food found walk
+            found:  Move fetch fetch
+            walk:   Move search turn
+            turn:   Turn Left search
+            fetch:  PickUpFood search fetch
+            ",
+        )
+        .unwrap();
+        assert_eq!(program, expected);
+    }
+
+    #[test]
+    fn conditions() {
+        let program = compile(
+            "
+            state start {
+                if flip(3) and not (facing(left) or sense(here, marker 2)) {
+                    drop
+                } else if eat {
+                    mark 1
+                }
+            }
+            ",
+        )
+        .unwrap();
+        let expected = parse_program(
+            "
+            start:  Flip 3 facing other
+            facing: Direction Left other marker
+            marker: Sense Here Marker 2 other yes
+            yes:    DropFood start
+            other:  Eat ate start
+            ate:    Mark 1 start
+            ",
+        )
+        .unwrap();
+        assert_eq!(program, expected);
+    }
+
+    #[test]
+    fn while_and_repeat() {
+        let program = compile(
+            "
+            state start {
+                while not sense(ahead, wall) { move }
+                repeat 3 { turn right }
+                repeat 0 { eat }
+            }
+            ",
+        )
+        .unwrap();
+        let expected = parse_program(
+            "
+            start: Sense Ahead Wall turn walk
+            walk:  Move start start
+            turn:  Turn Right 3
+                   Turn Right 4
+                   Turn Right start
+            ",
+        )
+        .unwrap();
+        assert_eq!(program, expected);
+    }
+
+    #[test]
+    fn leading_goto_jumps() {
+        let program = compile(
+            "
+            state a { goto b }
+            state c { move  goto a }
+            state b { turn left  goto c }
+            ",
+        )
+        .unwrap();
+        let expected = parse_program(
+            "
+               Direction Right b b
+            c: Move b b
+            b: Turn Left c
+            ",
+        )
+        .unwrap();
+        assert_eq!(program, expected);
+    }
+
+    #[test]
+    fn solves_a_lesson() {
+        let forager = compile(
+            "
+            state out {
+                if not move { if pickup { goto back } }
+            }
+            state back {
+                repeat 3 { turn left }
+                goto home
+            }
+            state home {
+                if not move { drop }
+            }
+            ",
+        )
+        .unwrap();
+        let lesson = crate::tutorial::lesson("bring-it-home").unwrap();
+        assert!(lesson.check(&forager).is_ok());
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(error("").kind, LangErrorKind::NoStates);
+        assert_eq!(
+            error("state a { move }\nstate a { move }"),
+            LangError {
+                line: 2,
+                kind: LangErrorKind::DuplicateState("a".to_string()),
+            }
+        );
+        assert_eq!(
+            error("state a {\n  goto nowhere\n}"),
+            LangError {
+                line: 2,
+                kind: LangErrorKind::UnknownState("nowhere".to_string()),
+            }
+        );
+        assert_eq!(
+            error("state a { move }\nstate b { repeat 0 { move } }"),
+            LangError {
+                line: 2,
+                kind: LangErrorKind::EmptyState("b".to_string()),
+            }
+        );
+        assert_eq!(
+            error("state a { turn up }").kind,
+            LangErrorKind::InvalidOperand("up".to_string())
+        );
+        assert_eq!(
+            error("state a { fly }").kind,
+            LangErrorKind::UnexpectedToken("fly".to_string())
+        );
+        assert_eq!(error("state a { move").kind, LangErrorKind::UnexpectedEnd);
+    }
+
+    #[test]
+    fn validator_errors_point_at_the_source() {
+        assert_eq!(
+            error("state a {\n  move\n  goto a\n  turn left\n}"),
+            LangError {
+                line: 4,
+                kind: LangErrorKind::Program(ProgramError::Unreachable { instr: 1 }),
+            }
+        );
+    }
+}
@@ -1,3 +1,45 @@
 pub mod asm;
+#[cfg(feature = "rayon")]
+pub mod batch;
+pub mod brain;
+pub mod bytecode;
+pub mod control;
+pub mod data;
+pub mod delta;
+pub mod error;
+pub mod evolve;
+#[cfg(feature = "arbitrary")]
+pub mod fuzz;
+pub mod hex;
+pub mod history;
+pub mod lang;
+pub mod map;
+pub mod mapgen;
+#[cfg(feature = "serde")]
+pub mod migrate;
+pub mod observation;
+pub mod observer;
+pub mod profile;
+pub mod proto;
+pub mod remote;
+pub mod renderers;
+pub mod repl;
+pub mod replay;
+pub mod rng;
+pub mod rules;
+pub mod scent;
+pub mod shared;
 pub mod sim;
+pub mod snapshot;
+pub mod stats;
+#[cfg(feature = "proptest")]
+pub mod strategies;
+pub mod testing;
+pub mod tournament;
+pub mod trace;
+pub mod tutorial;
+pub mod types;
+pub mod victory;
+#[cfg(feature = "wasm")]
+pub mod wasm;
 pub mod world;
@@ -0,0 +1,542 @@
+pub mod builder;
+pub mod edit;
+pub mod transform;
+
+use std::fmt;
+use std::str::FromStr;
+
+use crate::world::*;
+
+// Text maps have one line per row and one character per cell; whitespace
+// between cells is ignored, so rows may be indented to show the hex layout.
+//
+//   #      wall
+//   .      free cell
+//   1-9    free cell with that much food
+//   + -    red / black anthill cell
+//   r b    free cell with a red / black ant facing right
+//
+// Classic maps are enclosed in walls, so by default every border cell has
+// to be `#`; `Borders` relaxes that for fragments and hand-drawn maps.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Borders {
+    #[default]
+    Require,
+    // Border cells are turned into walls; ants standing on them are dropped.
+    Seal,
+    Open,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapErrorKind {
+    Empty,
+    UnexpectedChar(char),
+    RaggedRow { expected: usize, found: usize },
+    UnsealedBorder,
+}
+
+impl fmt::Display for MapErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapErrorKind::Empty => write!(f, "the map is empty"),
+            MapErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c),
+            MapErrorKind::RaggedRow { expected, found } => {
+                write!(f, "row has {} cells, expected {}", found, expected)
+            }
+            MapErrorKind::UnsealedBorder => write!(f, "the border is not all walls"),
+        }
+    }
+}
+
+// Lines and columns are 1-based; columns count characters, not cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MapError {
+    pub line: usize,
+    pub column: usize,
+    pub kind: MapErrorKind,
+}
+
+impl fmt::Display for MapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "line {}, column {}: {}",
+            self.line, self.column, self.kind
+        )
+    }
+}
+
+impl std::error::Error for MapError {}
+
+struct ParsedMap {
+    grid: Grid,
+    ants: Vec<(Color, Position)>,
+}
+
+fn parse_cell(c: char) -> Option<(Cell, Option<Color>)> {
+    let cell = match c {
+        '#' => Cell::Wall,
+        '.' => Cell::default(),
+        '+' => Cell::new_anthill(Color::Red),
+        '-' => Cell::new_anthill(Color::Black),
+        'r' => return Some((Cell::default(), Some(Color::Red))),
+        'b' => return Some((Cell::default(), Some(Color::Black))),
+        '1'..='9' => {
+            let mut cell = Cell::default();
+            for _ in 0..c.to_digit(10).unwrap() {
+                cell.try_drop_food().unwrap();
+            }
+            cell
+        }
+        _ => return None,
+    };
+    Some((cell, None))
+}
+
+fn parse_map(s: &str, borders: Borders) -> Result<ParsedMap, MapError> {
+    let mut rows: Vec<Vec<Cell>> = Vec::new();
+    let mut locations: Vec<Vec<(usize, usize)>> = Vec::new();
+    let mut ants = Vec::new();
+    let mut last_line = 0;
+    for (line_idx, line) in s.lines().enumerate() {
+        last_line = line_idx + 1;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let y = rows.len() as i32;
+        let mut row = Vec::new();
+        let mut row_locations = Vec::new();
+        for (column_idx, c) in line.chars().enumerate() {
+            if c.is_whitespace() {
+                continue;
+            }
+            let error = |kind| MapError {
+                line: line_idx + 1,
+                column: column_idx + 1,
+                kind,
+            };
+            let (cell, ant) = parse_cell(c).ok_or(error(MapErrorKind::UnexpectedChar(c)))?;
+            if let Some(expected) = rows.first().map(Vec::len) {
+                if row.len() == expected {
+                    return Err(error(MapErrorKind::RaggedRow {
+                        expected,
+                        found: line.chars().filter(|c| !c.is_whitespace()).count(),
+                    }));
+                }
+            }
+            if let Some(color) = ant {
+                ants.push((
+                    color,
+                    Position {
+                        x: row.len() as i32,
+                        y,
+                    },
+                ));
+            }
+            row.push(cell);
+            row_locations.push((line_idx + 1, column_idx + 1));
+        }
+        if let Some(expected) = rows.first().map(Vec::len) {
+            if row.len() != expected {
+                return Err(MapError {
+                    line: line_idx + 1,
+                    column: line.chars().count() + 1,
+                    kind: MapErrorKind::RaggedRow {
+                        expected,
+                        found: row.len(),
+                    },
+                });
+            }
+        }
+        rows.push(row);
+        locations.push(row_locations);
+    }
+    if rows.is_empty() {
+        return Err(MapError {
+            line: last_line.max(1),
+            column: 1,
+            kind: MapErrorKind::Empty,
+        });
+    }
+
+    let mut grid = Grid::new(rows[0].len(), rows.len());
+    for (y, row) in rows.into_iter().enumerate() {
+        for (x, cell) in row.into_iter().enumerate() {
+            let position = Position {
+                x: x as i32,
+                y: y as i32,
+            };
+            *grid.cell_at_mut(position).unwrap() = cell;
+        }
+    }
+    match borders {
+        Borders::Require => {
+            let unsealed = grid
+                .border()
+                .find(|&position| grid.cell_at(position) != Some(&Cell::Wall));
+            if let Some(position) = unsealed {
+                let (line, column) = locations[position.y as usize][position.x as usize];
+                return Err(MapError {
+                    line,
+                    column,
+                    kind: MapErrorKind::UnsealedBorder,
+                });
+            }
+        }
+        Borders::Seal => {
+            grid.seal_borders();
+            ants.retain(|&(_, position)| !grid.is_border(position));
+        }
+        Borders::Open => {}
+    }
+    Ok(ParsedMap { grid, ants })
+}
+
+// Ant characters are read as plain free cells.
+pub fn parse_grid(s: &str, borders: Borders) -> Result<Grid, MapError> {
+    parse_map(s, borders).map(|map| map.grid)
+}
+
+pub fn parse_world(s: &str, borders: Borders) -> Result<World, MapError> {
+    let ParsedMap { grid, ants } = parse_map(s, borders)?;
+    let mut world = World::new(grid);
+    for (color, position) in ants {
+        world.add_ant(color, position).unwrap();
+    }
+    Ok(world)
+}
+
+impl FromStr for Grid {
+    type Err = MapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_grid(s, Borders::default())
+    }
+}
+
+impl FromStr for World {
+    type Err = MapError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_world(s, Borders::default())
+    }
+}
+
+// The text format only knows Black and Red; other teams show as `?`.
+fn cell_char(cell: &Cell, ant: Option<Color>) -> char {
+    match (cell, ant) {
+        (Cell::Wall, _) => '#',
+        (_, Some(Color::Red)) => 'r',
+        (_, Some(Color::Black)) => 'b',
+        (_, Some(_)) => '?',
+        _ => match cell.anthill() {
+            Some(Color::Red) => '+',
+            Some(Color::Black) => '-',
+            Some(_) => '?',
+            None if cell.has_food() => char::from_digit(cell.food().min(9), 10).unwrap(),
+            None => '.',
+        },
+    }
+}
+
+fn format_map(grid: &Grid, ant_color: impl Fn(AntId) -> Color) -> String {
+    let mut out = String::new();
+    for y in 0..grid.height() as i32 {
+        if y % 2 == 1 {
+            out.push(' ');
+        }
+        for x in 0..grid.width() as i32 {
+            if x > 0 {
+                out.push(' ');
+            }
+            let cell = grid.cell_at(Position { x, y }).unwrap();
+            out.push(cell_char(cell, cell.ant().map(&ant_color)));
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// The inverse of `from_str`. Only what the map format can express is
+// written: markers, ant state and food under ants or anthills are dropped,
+// and food piles are capped at 9.
+impl Grid {
+    pub fn to_map_string(&self) -> String {
+        format_map(self, |_| unreachable!())
+    }
+}
+
+impl World {
+    pub fn to_map_string(&self) -> String {
+        format_map(self.grid(), |id| self.ant(id).unwrap().color())
+    }
+}
+
+// Team letters in pictures: the map format's `b` and `r`, then `c` to `h`.
+const TEAM_LETTERS: [u8; MAX_TEAMS] = *b"brcdefgh";
+
+fn direction_tick(direction: Direction) -> char {
+    match direction {
+        Direction::Right => '>',
+        Direction::DownRight => '\\',
+        Direction::DownLeft => '/',
+        Direction::Left => '<',
+        Direction::UpLeft => '`',
+        Direction::UpRight => '\'',
+    }
+}
+
+// A picture for debugging: like the map format, with odd rows shifted right
+// by half a cell, but two characters per cell to fit what the format
+// leaves out.
+//
+//   ##     wall
+//   .      free cell
+//   1-9 *  that much food, `*` past 9
+//   +r     anthill, with its team letter
+//   r>     ant with its team letter, capitalised while it carries food,
+//          and where it faces: > \ / < ` ' from Right clockwise
+//
+// `ant` gives the two characters for the ant on a cell.
+fn write_picture(
+    f: &mut fmt::Formatter,
+    grid: &Grid,
+    ant: impl Fn(AntId) -> [char; 2],
+) -> fmt::Result {
+    for y in 0..grid.height() as i32 {
+        let mut row = String::new();
+        if y % 2 == 1 {
+            row.push(' ');
+        }
+        for x in 0..grid.width() as i32 {
+            let cell = grid.cell_at(Position { x, y }).unwrap();
+            let chars = match (cell, cell.ant(), cell.anthill()) {
+                (Cell::Wall, _, _) => ['#', '#'],
+                (_, Some(id), _) => ant(id),
+                (_, None, Some(color)) => ['+', char::from(TEAM_LETTERS[color.index()])],
+                _ if cell.food() > 9 => ['*', ' '],
+                _ if cell.has_food() => [char::from_digit(cell.food(), 10).unwrap(), ' '],
+                _ => ['.', ' '],
+            };
+            row.extend(chars);
+        }
+        writeln!(f, "{}", row.trim_end())?;
+    }
+    Ok(())
+}
+
+// Ants show as `@`: a grid alone knows nothing about them.
+impl fmt::Display for Grid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_picture(f, self, |_| ['@', ' '])
+    }
+}
+
+// Cells pointing at an ant that is not there show as `??` rather than
+// panicking, since broken worlds are what this often gets printed for.
+impl fmt::Display for World {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_picture(f, self.grid(), |id| match self.ant(id) {
+            Some(ant) => {
+                let letter = char::from(TEAM_LETTERS[ant.color().index()]);
+                let letter = if ant.carries_food() {
+                    letter.to_ascii_uppercase()
+                } else {
+                    letter
+                };
+                [letter, direction_tick(ant.direction())]
+            }
+            None => ['?', '?'],
+        })
+    }
+}
+
+// The picture, so failing tests and generated cases show something
+// readable.
+impl fmt::Debug for World {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "World")?;
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "\
+# # # # #
+ # r 3 - #
+# . + b #
+ # # # # #
+";
+
+    #[test]
+    fn grid_cells() {
+        let grid: Grid = MAP.parse().unwrap();
+        assert_eq!(grid.width(), 5);
+        assert_eq!(grid.height(), 4);
+        assert_eq!(grid.cell_at(Position { x: 0, y: 0 }), Some(&Cell::Wall));
+        assert_eq!(
+            grid.cell_at(Position { x: 1, y: 1 }),
+            Some(&Cell::default())
+        );
+        assert_eq!(grid.cell_at(Position { x: 2, y: 1 }).unwrap().food(), 3);
+        assert_eq!(
+            grid.cell_at(Position { x: 3, y: 1 }),
+            Some(&Cell::new_anthill(Color::Black))
+        );
+        assert_eq!(
+            grid.cell_at(Position { x: 2, y: 2 }),
+            Some(&Cell::new_anthill(Color::Red))
+        );
+        assert_eq!(grid.ant_at(Position { x: 1, y: 1 }), None);
+    }
+
+    #[test]
+    fn world_ants() {
+        let world: World = MAP.parse().unwrap();
+        let red: Vec<_> = world.swarm(Color::Red).map(|ant| ant.position()).collect();
+        let black: Vec<_> = world
+            .swarm(Color::Black)
+            .map(|ant| ant.position())
+            .collect();
+        assert_eq!(red, vec![Position { x: 1, y: 1 }]);
+        assert_eq!(black, vec![Position { x: 3, y: 2 }]);
+    }
+
+    #[test]
+    fn compact_rows() {
+        let grid = parse_grid("#.1\n+-#\n", Borders::Open).unwrap();
+        assert_eq!(grid.width(), 3);
+        assert_eq!(grid.height(), 2);
+        assert_eq!(grid.cell_at(Position { x: 2, y: 0 }).unwrap().food(), 1);
+    }
+
+    #[test]
+    fn round_trip() {
+        let world: World = MAP.parse().unwrap();
+        assert_eq!(world.to_map_string(), MAP);
+        let grid: Grid = MAP.parse().unwrap();
+        assert_eq!(grid.to_map_string(), MAP.replace(['r', 'b'], "."));
+    }
+
+    #[test]
+    fn unexpected_char() {
+        assert_eq!(
+            "# #\n# x\n".parse::<Grid>().err(),
+            Some(MapError {
+                line: 2,
+                column: 3,
+                kind: MapErrorKind::UnexpectedChar('x'),
+            })
+        );
+    }
+
+    #[test]
+    fn long_row() {
+        assert_eq!(
+            "##\n\n###\n".parse::<Grid>().err(),
+            Some(MapError {
+                line: 3,
+                column: 3,
+                kind: MapErrorKind::RaggedRow {
+                    expected: 2,
+                    found: 3
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn short_row() {
+        assert_eq!(
+            "###\n #\n".parse::<Grid>().err(),
+            Some(MapError {
+                line: 2,
+                column: 3,
+                kind: MapErrorKind::RaggedRow {
+                    expected: 3,
+                    found: 1
+                },
+            })
+        );
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(
+            "\n  \n".parse::<Grid>().err(),
+            Some(MapError {
+                line: 2,
+                column: 1,
+                kind: MapErrorKind::Empty,
+            })
+        );
+    }
+
+    #[test]
+    fn unsealed_border() {
+        assert_eq!(
+            "###\n#.#\n #.#\n".parse::<Grid>().err(),
+            Some(MapError {
+                line: 3,
+                column: 3,
+                kind: MapErrorKind::UnsealedBorder,
+            })
+        );
+        assert_eq!(
+            parse_grid("# r\n", Borders::Require).err(),
+            Some(MapError {
+                line: 1,
+                column: 3,
+                kind: MapErrorKind::UnsealedBorder,
+            })
+        );
+    }
+
+    #[test]
+    fn seal_border() {
+        let world = parse_world("r . 2 .\n . b . r\n+ + - .\n", Borders::Seal).unwrap();
+        assert!(world.grid().is_sealed());
+        assert_eq!(world.to_map_string(), "# # # #\n # b . #\n# # # #\n");
+        assert_eq!(world.swarm_size(Color::Red), 0);
+    }
+
+    #[test]
+    fn open_border() {
+        let world = parse_world("r.\n", Borders::Open).unwrap();
+        assert_eq!(world.swarm_size(Color::Red), 1);
+        assert!(!world.grid().is_sealed());
+    }
+
+    #[test]
+    fn picture() {
+        let mut world: World = "\
+# # # # #
+ # r 3 - #
+# . + b #
+ # # # # #
+"
+        .parse()
+        .unwrap();
+        world.grow_food(Position { x: 2, y: 1 }, 9);
+        world.ant_mut(0).unwrap().rotate(Direction::UpLeft);
+        world.grow_food(Position { x: 1, y: 2 }, 1);
+        let id = world
+            .add_ant(Color::team(2), Position { x: 1, y: 2 })
+            .unwrap();
+        world.ant_mut(id).unwrap().rotate(Direction::DownRight);
+        world.ant_mut(id).unwrap().pickup_food().unwrap();
+        assert_eq!(
+            world.to_string(),
+            "##########\n ##r`* +b##\n##C\\+rb>##\n ##########\n"
+        );
+        assert_eq!(
+            world.grid().to_string(),
+            "##########\n ##@ * +b##\n##@ +r@ ##\n ##########\n"
+        );
+        assert!(format!("{:?}", world).starts_with("World\n##"));
+    }
+}
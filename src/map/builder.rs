@@ -0,0 +1,240 @@
+use std::fmt;
+
+use crate::hex;
+use crate::map::edit::*;
+use crate::world::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuildErrorKind {
+    OutOfBounds,
+    // Food, anthills and ants only go on free cells.
+    Wall,
+    // Two ants at one position.
+    Occupied,
+}
+
+impl fmt::Display for BuildErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BuildErrorKind::OutOfBounds => write!(f, "the position is off the grid"),
+            BuildErrorKind::Wall => write!(f, "food, anthills and ants only go on free cells"),
+            BuildErrorKind::Occupied => write!(f, "there is an ant there already"),
+        }
+    }
+}
+
+// `step` counts the builder calls before the offending one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuildError {
+    pub step: usize,
+    pub position: Position,
+    pub kind: BuildErrorKind,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "step {} at ({}, {}): {}",
+            self.step, self.position.x, self.position.y, self.kind
+        )
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+// Lays out a world call by call, for test scenarios and map generators:
+//
+//   WorldBuilder::new(10, 8)
+//       .border_walls()
+//       .wall_line(Position { x: 5, y: 1 }, Position { x: 5, y: 5 })
+//       .food_at(Position { x: 8, y: 6 }, 5)
+//       .anthill(Color::Red, [Position { x: 1, y: 1 }])
+//       .ant(Color::Red, Position { x: 1, y: 1 }, Direction::Right)
+//       .build()
+//
+// Cells are changed in call order, so later calls win; ants are put in
+// last, on whatever the cells have become. The first invalid call is
+// reported by `build` and every call after it is ignored.
+#[derive(Clone)]
+pub struct WorldBuilder {
+    grid: Grid,
+    ants: Vec<(usize, Color, Position, Direction)>,
+    steps: usize,
+    error: Option<BuildError>,
+}
+
+impl WorldBuilder {
+    // An empty grid of free cells.
+    pub fn new(width: usize, height: usize) -> Self {
+        WorldBuilder::from_grid(Grid::new(width, height))
+    }
+
+    pub fn new_torus(width: usize, height: usize) -> Self {
+        WorldBuilder::from_grid(Grid::new_torus(width, height))
+    }
+
+    // Starts from an existing grid; ants on it are dropped.
+    pub fn from_grid(mut grid: Grid) -> Self {
+        let positions: Vec<_> = grid.iter_cells().map(|(position, _)| position).collect();
+        for position in positions {
+            grid.cell_at_mut(position).unwrap().clear_ant();
+        }
+        WorldBuilder {
+            grid,
+            ants: Vec::new(),
+            steps: 0,
+            error: None,
+        }
+    }
+
+    fn edit(mut self, edits: impl IntoIterator<Item = CellEdit>) -> Self {
+        if self.error.is_none() {
+            let edits: Vec<_> = edits.into_iter().collect();
+            if let Err(error) = self.grid.apply_edits(&edits) {
+                let kind = match error.kind {
+                    EditErrorKind::OutOfBounds => BuildErrorKind::OutOfBounds,
+                    // The grid holds no ants until `build`.
+                    EditErrorKind::Wall | EditErrorKind::StrandsAnt(_) => BuildErrorKind::Wall,
+                };
+                self.error = Some(BuildError {
+                    step: self.steps,
+                    position: edits[error.index].position(),
+                    kind,
+                });
+            }
+        }
+        self.steps += 1;
+        self
+    }
+
+    // Walls every cell with `x` and `y` between the two corners'.
+    pub fn wall_rect(self, corner: Position, opposite: Position) -> Self {
+        let xs = corner.x.min(opposite.x)..=corner.x.max(opposite.x);
+        let ys = corner.y.min(opposite.y)..=corner.y.max(opposite.y);
+        let cells = ys.flat_map(|y| xs.clone().map(move |x| Position { x, y }));
+        self.edit(cells.map(CellEdit::Wall))
+    }
+
+    // Walls the cells a straight line between the ends passes through,
+    // see `hex::line`.
+    pub fn wall_line(self, from: Position, to: Position) -> Self {
+        self.edit(hex::line(from, to).into_iter().map(CellEdit::Wall))
+    }
+
+    pub fn border_walls(self) -> Self {
+        let border: Vec<_> = self.grid.border().collect();
+        self.edit(border.into_iter().map(CellEdit::Wall))
+    }
+
+    // Sets the food on a free cell to `amount`.
+    pub fn food_at(self, position: Position, amount: u32) -> Self {
+        self.edit([CellEdit::Food { position, amount }])
+    }
+
+    pub fn anthill(self, color: Color, positions: impl IntoIterator<Item = Position>) -> Self {
+        self.edit(positions.into_iter().map(|position| CellEdit::Anthill {
+            position,
+            color: Some(color),
+        }))
+    }
+
+    pub fn ant(mut self, color: Color, position: Position, direction: Direction) -> Self {
+        self.ants.push((self.steps, color, position, direction));
+        self.steps += 1;
+        self
+    }
+
+    pub fn build(self) -> Result<World, BuildError> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        let mut world = World::new(self.grid);
+        for (step, color, position, direction) in self.ants {
+            let id = world.add_ant(color, position).map_err(|error| BuildError {
+                step,
+                position,
+                kind: match error {
+                    WorldError::Wall => BuildErrorKind::Wall,
+                    WorldError::Occupied => BuildErrorKind::Occupied,
+                    _ => BuildErrorKind::OutOfBounds,
+                },
+            })?;
+            world.ant_mut(id).unwrap().rotate(direction);
+        }
+        Ok(world)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_map() {
+        let world = WorldBuilder::new(6, 5)
+            .border_walls()
+            .wall_rect(Position { x: 3, y: 1 }, Position { x: 3, y: 2 })
+            .wall_line(Position { x: 2, y: 3 }, Position { x: 3, y: 3 })
+            .food_at(Position { x: 4, y: 3 }, 5)
+            .anthill(
+                Color::Red,
+                [Position { x: 1, y: 1 }, Position { x: 2, y: 1 }],
+            )
+            .ant(Color::Red, Position { x: 1, y: 1 }, Direction::Right)
+            .ant(Color::Black, Position { x: 4, y: 1 }, Direction::Left)
+            .build()
+            .unwrap();
+        let expected: World = "\
+            # # # # # #
+             # r + # b #
+            # . . # . #
+             # . # # 5 #
+            # # # # # #"
+            .parse()
+            .unwrap();
+        assert_eq!(world.to_map_string(), expected.to_map_string());
+        assert_eq!(world.ant(1).unwrap().direction(), Direction::Left);
+        assert_eq!(world.check_invariants(), Ok(()));
+        // The map shows the ant, not the anthill under it.
+        let under = world.grid().cell_at(Position { x: 1, y: 1 }).unwrap();
+        assert_eq!(under.anthill(), Some(Color::Red));
+    }
+
+    #[test]
+    fn reports_the_first_bad_step() {
+        let error = WorldBuilder::new(4, 4)
+            .wall_rect(Position { x: 0, y: 0 }, Position { x: 1, y: 1 })
+            .food_at(Position { x: 2, y: 2 }, 1)
+            .food_at(Position { x: 1, y: 1 }, 1)
+            .food_at(Position { x: 9, y: 9 }, 1)
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            error,
+            BuildError {
+                step: 2,
+                position: Position { x: 1, y: 1 },
+                kind: BuildErrorKind::Wall,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "step 2 at (1, 1): food, anthills and ants only go on free cells"
+        );
+
+        let error = WorldBuilder::new(4, 4)
+            .ant(Color::Red, Position { x: 2, y: 2 }, Direction::Right)
+            .wall_line(Position { x: 0, y: 0 }, Position { x: 3, y: 0 })
+            .ant(Color::Black, Position { x: 2, y: 2 }, Direction::Left)
+            .build()
+            .unwrap_err();
+        assert_eq!(error.step, 2);
+        assert_eq!(error.kind, BuildErrorKind::Occupied);
+        let error = WorldBuilder::new(4, 4)
+            .ant(Color::Red, Position { x: 4, y: 0 }, Direction::Right)
+            .build()
+            .unwrap_err();
+        assert_eq!(error.kind, BuildErrorKind::OutOfBounds);
+    }
+}
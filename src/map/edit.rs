@@ -0,0 +1,212 @@
+use std::fmt;
+
+use crate::world::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CellEdit {
+    Wall(Position),
+    // Turns a wall into an empty free cell; free cells are left alone.
+    Free(Position),
+    Food {
+        position: Position,
+        amount: u32,
+    },
+    Anthill {
+        position: Position,
+        color: Option<Color>,
+    },
+}
+
+impl CellEdit {
+    pub fn position(self) -> Position {
+        match self {
+            CellEdit::Wall(position) | CellEdit::Free(position) => position,
+            CellEdit::Food { position, .. } | CellEdit::Anthill { position, .. } => position,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditErrorKind {
+    OutOfBounds,
+    // Walling in the cell would trap the ant standing on it.
+    StrandsAnt(AntId),
+    // Food and anthills only go on free cells.
+    Wall,
+}
+
+impl fmt::Display for EditErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditErrorKind::OutOfBounds => write!(f, "the position is off the grid"),
+            EditErrorKind::StrandsAnt(id) => write!(f, "the wall would trap ant {}", id),
+            EditErrorKind::Wall => write!(f, "food and anthills only go on free cells"),
+        }
+    }
+}
+
+// `index` is the position of the offending edit in the batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EditError {
+    pub index: usize,
+    pub kind: EditErrorKind,
+}
+
+impl fmt::Display for EditError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "edit {}: {}", self.index, self.kind)
+    }
+}
+
+impl std::error::Error for EditError {}
+
+// The cells a batch replaced, in the order it replaced them.
+#[must_use]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EditUndo {
+    previous: Vec<(Position, Cell)>,
+}
+
+impl EditUndo {
+    pub fn undo(self, grid: &mut Grid) {
+        for (position, cell) in self.previous.into_iter().rev() {
+            *grid.cell_at_mut(position).unwrap() = cell;
+        }
+    }
+}
+
+fn edited(cell: &Cell, edit: CellEdit) -> Result<Cell, EditErrorKind> {
+    let mut cell = cell.clone();
+    match (edit, &mut cell) {
+        (CellEdit::Wall(_), _) => match cell.ant() {
+            Some(id) => return Err(EditErrorKind::StrandsAnt(id)),
+            None => cell = Cell::Wall,
+        },
+        (CellEdit::Free(_), Cell::Wall) => cell = Cell::default(),
+        (CellEdit::Free(_), Cell::FreeCell { .. }) => {}
+        (CellEdit::Food { amount, .. }, Cell::FreeCell { food, .. }) => *food = amount,
+        (CellEdit::Anthill { color, .. }, Cell::FreeCell { anthill, .. }) => *anthill = color,
+        (CellEdit::Food { .. } | CellEdit::Anthill { .. }, Cell::Wall) => {
+            return Err(EditErrorKind::Wall)
+        }
+    }
+    Ok(cell)
+}
+
+impl Grid {
+    // Applies the edits in order. Either all of them take effect or, if
+    // one is invalid, none do.
+    pub fn apply_edits(&mut self, edits: &[CellEdit]) -> Result<EditUndo, EditError> {
+        let mut undo = EditUndo {
+            previous: Vec::with_capacity(edits.len()),
+        };
+        for (index, &edit) in edits.iter().enumerate() {
+            let position = edit.position();
+            let result = self
+                .cell_at(position)
+                .ok_or(EditErrorKind::OutOfBounds)
+                .and_then(|cell| edited(cell, edit));
+            match result {
+                Ok(cell) => {
+                    let old = std::mem::replace(self.cell_at_mut(position).unwrap(), cell);
+                    undo.previous.push((position, old));
+                }
+                Err(kind) => {
+                    undo.undo(self);
+                    return Err(EditError { index, kind });
+                }
+            }
+        }
+        Ok(undo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "\
+# # # #
+ # . 3 #
+# + . #
+ # # # #
+";
+
+    #[test]
+    fn edit_and_undo() {
+        let original: Grid = MAP.parse().unwrap();
+        let mut grid = original.clone();
+        let undo = grid
+            .apply_edits(&[
+                CellEdit::Wall(Position { x: 1, y: 1 }),
+                CellEdit::Free(Position { x: 3, y: 1 }),
+                CellEdit::Food {
+                    position: Position { x: 3, y: 1 },
+                    amount: 5,
+                },
+                CellEdit::Anthill {
+                    position: Position { x: 2, y: 2 },
+                    color: Some(Color::Black),
+                },
+                CellEdit::Food {
+                    position: Position { x: 2, y: 1 },
+                    amount: 0,
+                },
+            ])
+            .unwrap();
+        assert_eq!(
+            grid.to_map_string(),
+            "# # # #\n # # . 5\n# + - #\n # # # #\n"
+        );
+        undo.undo(&mut grid);
+        assert!(grid == original);
+    }
+
+    #[test]
+    fn failed_batch_changes_nothing() {
+        let original: Grid = MAP.parse().unwrap();
+        let mut grid = original.clone();
+        let result = grid.apply_edits(&[
+            CellEdit::Free(Position { x: 0, y: 0 }),
+            CellEdit::Food {
+                position: Position { x: 3, y: 3 },
+                amount: 1,
+            },
+        ]);
+        assert_eq!(
+            result.err(),
+            Some(EditError {
+                index: 1,
+                kind: EditErrorKind::Wall,
+            })
+        );
+        assert!(grid == original);
+
+        let result = grid.apply_edits(&[CellEdit::Wall(Position { x: 4, y: 0 })]);
+        assert_eq!(
+            result.err(),
+            Some(EditError {
+                index: 0,
+                kind: EditErrorKind::OutOfBounds,
+            })
+        );
+    }
+
+    #[test]
+    fn ants_are_not_walled_in() {
+        let world: World = "# # # #\n # r . #\n# # # #\n".parse().unwrap();
+        let mut grid = world.grid().clone();
+        let result = grid.apply_edits(&[
+            CellEdit::Wall(Position { x: 2, y: 1 }),
+            CellEdit::Wall(Position { x: 1, y: 1 }),
+        ]);
+        assert_eq!(
+            result.err(),
+            Some(EditError {
+                index: 1,
+                kind: EditErrorKind::StrandsAnt(0),
+            })
+        );
+        assert!(&grid == world.grid());
+    }
+}
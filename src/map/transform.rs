@@ -0,0 +1,124 @@
+use crate::world::*;
+
+// Transformations work on cell contents only: ants are not carried over to
+// the resulting grid.
+
+fn without_ant(cell: &Cell) -> Cell {
+    let mut cell = cell.clone();
+    cell.clear_ant();
+    cell
+}
+
+fn positions(width: usize, height: usize) -> impl Iterator<Item = Position> {
+    (0..height as i32).flat_map(move |y| (0..width as i32).map(move |x| Position { x, y }))
+}
+
+// Every cell becomes a `factor`×`factor` block of copies of itself, so food
+// and anthill sizes grow along with the map.
+pub fn scale(grid: &Grid, factor: usize) -> Grid {
+    assert!(factor > 0);
+    let mut scaled = Grid::new(grid.width() * factor, grid.height() * factor);
+    for position in positions(scaled.width(), scaled.height()) {
+        let source = Position {
+            x: position.x / factor as i32,
+            y: position.y / factor as i32,
+        };
+        *scaled.cell_at_mut(position).unwrap() = without_ant(grid.cell_at(source).unwrap());
+    }
+    scaled
+}
+
+// Returns `None` unless the whole region lies inside the grid.
+pub fn crop(grid: &Grid, origin: Position, width: usize, height: usize) -> Option<Grid> {
+    let far_corner = Position {
+        x: origin.x + width as i32 - 1,
+        y: origin.y + height as i32 - 1,
+    };
+    if width == 0 || height == 0 || !grid.in_bounds(origin) || !grid.in_bounds(far_corner) {
+        return None;
+    }
+    let mut cropped = Grid::new(width, height);
+    for position in positions(width, height) {
+        let source = Position {
+            x: origin.x + position.x,
+            y: origin.y + position.y,
+        };
+        *cropped.cell_at_mut(position).unwrap() = without_ant(grid.cell_at(source).unwrap());
+    }
+    Some(cropped)
+}
+
+// Surrounds the grid with `border` rings of wall cells.
+pub fn pad(grid: &Grid, border: usize) -> Grid {
+    let mut padded = Grid::new(grid.width() + 2 * border, grid.height() + 2 * border);
+    for position in positions(padded.width(), padded.height()) {
+        let source = Position {
+            x: position.x - border as i32,
+            y: position.y - border as i32,
+        };
+        *padded.cell_at_mut(position).unwrap() = match grid.cell_at(source) {
+            Some(cell) => without_ant(cell),
+            None => Cell::Wall,
+        };
+    }
+    padded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map::*;
+
+    fn grid(map: &str) -> Grid {
+        parse_grid(map, Borders::Open).unwrap()
+    }
+
+    #[test]
+    fn scale_doubles() {
+        let scaled = scale(&grid("#2\n+.\n"), 2);
+        assert_eq!(
+            scaled.to_map_string(),
+            grid("##22\n##22\n++..\n++..\n").to_map_string()
+        );
+    }
+
+    #[test]
+    fn scale_by_one_is_identity() {
+        let original = grid("#2\n+.\n");
+        assert!(scale(&original, 1) == original);
+    }
+
+    #[test]
+    fn crop_region() {
+        let original = grid("####\n#12#\n#34#\n####\n");
+        let cropped = crop(&original, Position { x: 1, y: 1 }, 2, 2).unwrap();
+        assert_eq!(cropped.to_map_string(), grid("12\n34\n").to_map_string());
+    }
+
+    #[test]
+    fn crop_out_of_bounds() {
+        let original = grid("###\n###\n");
+        assert!(crop(&original, Position { x: 1, y: 0 }, 3, 1).is_none());
+        assert!(crop(&original, Position { x: -1, y: 0 }, 1, 1).is_none());
+        assert!(crop(&original, Position { x: 0, y: 0 }, 0, 1).is_none());
+        assert!(crop(&original, Position { x: 0, y: 0 }, 3, 2).is_some());
+    }
+
+    #[test]
+    fn pad_with_walls() {
+        let padded = pad(&grid("1.\n"), 1);
+        assert_eq!(
+            padded.to_map_string(),
+            grid("####\n#1.#\n####\n").to_map_string()
+        );
+    }
+
+    #[test]
+    fn ants_are_dropped() {
+        let world = parse_world("r.\n", Borders::Open).unwrap();
+        let padded = pad(world.grid(), 1);
+        assert!(!padded.cell_at(Position { x: 1, y: 1 }).unwrap().has_ant());
+        let scaled = scale(world.grid(), 2);
+        assert!(!scaled.cell_at(Position { x: 1, y: 1 }).unwrap().has_ant());
+    }
+}
@@ -0,0 +1,249 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::rng::*;
+use crate::world::*;
+
+// Generates walled maps from a seed. Every map is symmetric under a half
+// turn about its centre, which swaps the two anthills (and the directions
+// the ants face), so neither color starts with an advantage. Free cells
+// that cannot be reached from the anthills are walled in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MapGenerator {
+    width: usize,
+    height: usize,
+    wall_blobs: u32,
+    food_clusters: u32,
+    food_per_cell: u32,
+    anthill_radius: u32,
+}
+
+// Layouts where the anthills end up cut off from each other are thrown
+// away; after this many the map is generated without walls.
+const ATTEMPTS: u32 = 32;
+
+impl MapGenerator {
+    pub fn new(width: usize, height: usize) -> Self {
+        MapGenerator {
+            width,
+            height,
+            wall_blobs: 8,
+            food_clusters: 4,
+            food_per_cell: 5,
+            anthill_radius: 2,
+        }
+    }
+
+    pub fn with_wall_blobs(mut self, count: u32) -> Self {
+        self.wall_blobs = count;
+        self
+    }
+
+    pub fn with_food_clusters(mut self, count: u32, food_per_cell: u32) -> Self {
+        self.food_clusters = count;
+        self.food_per_cell = food_per_cell;
+        self
+    }
+
+    pub fn with_anthill_radius(mut self, radius: u32) -> Self {
+        self.anthill_radius = radius;
+        self
+    }
+
+    // Each anthill must fit in its own half of the map, inside the border.
+    fn check_size(&self) {
+        let r = self.anthill_radius as usize;
+        assert!(
+            self.width >= 4 * r + 4 && self.height >= 2 * r + 3,
+            "map too small for the anthills"
+        );
+    }
+
+    pub fn generate(&self, seed: u64) -> World {
+        self.check_size();
+        (0..ATTEMPTS)
+            .find_map(|attempt| {
+                let mut rng = Rng::stream(seed, &[attempt as u64]);
+                self.try_generate(&mut rng, self.wall_blobs)
+            })
+            .unwrap_or_else(|| {
+                let mut rng = Rng::stream(seed, &[ATTEMPTS as u64]);
+                self.try_generate(&mut rng, 0)
+                    .expect("maps without walls are connected")
+            })
+    }
+
+    fn mirror(&self, position: Position) -> Position {
+        Position {
+            x: self.width as i32 - 1 - position.x,
+            y: self.height as i32 - 1 - position.y,
+        }
+    }
+
+    fn random_position(&self, rng: &mut Rng, xs: (i32, i32), ys: (i32, i32)) -> Position {
+        Position {
+            x: xs.0 + rng.below((xs.1 - xs.0 + 1) as u32) as i32,
+            y: ys.0 + rng.below((ys.1 - ys.0 + 1) as u32) as i32,
+        }
+    }
+
+    fn interior(&self, rng: &mut Rng) -> Position {
+        let xs = (1, self.width as i32 - 2);
+        let ys = (1, self.height as i32 - 2);
+        self.random_position(rng, xs, ys)
+    }
+
+    fn try_generate(&self, rng: &mut Rng, wall_blobs: u32) -> Option<World> {
+        let mut grid = Grid::with_wall_border(self.width, self.height);
+        let r = self.anthill_radius as i32;
+
+        // Red's anthill lies in the left half, so its mirror image never
+        // overlaps it.
+        let home = self.random_position(
+            rng,
+            (1 + r, self.width as i32 / 2 - 1 - r),
+            (1 + r, self.height as i32 - 2 - r),
+        );
+        let hill: Vec<_> = grid.spiral(home, self.anthill_radius).collect();
+        // Walls keep one cell clear of the anthills.
+        let reserved: HashSet<_> = grid
+            .spiral(home, self.anthill_radius + 1)
+            .flat_map(|position| [position, self.mirror(position)])
+            .collect();
+
+        let set = |grid: &mut Grid, position: Position, cell: Cell| {
+            if grid.is_border(position) || !grid.in_bounds(position) {
+                return;
+            }
+            *grid.cell_at_mut(position).unwrap() = cell.clone();
+            *grid.cell_at_mut(self.mirror(position)).unwrap() = cell;
+        };
+
+        for _ in 0..wall_blobs {
+            let center = self.interior(rng);
+            let radius = rng.below(3);
+            let blob: Vec<_> = grid.spiral(center, radius).collect();
+            for position in blob {
+                if !reserved.contains(&position) {
+                    set(&mut grid, position, Cell::Wall);
+                }
+            }
+        }
+        for _ in 0..self.food_clusters {
+            let center = self.interior(rng);
+            let cluster: Vec<_> = grid.spiral(center, 1).collect();
+            for position in cluster {
+                if !reserved.contains(&position) {
+                    let mut cell = Cell::default();
+                    for _ in 0..self.food_per_cell {
+                        cell.try_drop_food().unwrap();
+                    }
+                    set(&mut grid, position, cell);
+                }
+            }
+        }
+        for &position in &hill {
+            *grid.cell_at_mut(position).unwrap() = Cell::new_anthill(Color::Red);
+            *grid.cell_at_mut(self.mirror(position)).unwrap() = Cell::new_anthill(Color::Black);
+        }
+
+        let reachable = reachable_from(&grid, home);
+        if !reachable.contains(&self.mirror(home)) {
+            return None;
+        }
+        for y in 0..self.height as i32 {
+            for x in 0..self.width as i32 {
+                let position = Position { x, y };
+                if !reachable.contains(&position) {
+                    *grid.cell_at_mut(position).unwrap() = Cell::Wall;
+                }
+            }
+        }
+
+        let mut world = World::new(grid);
+        for &position in &hill {
+            world.add_ant(Color::Red, position).unwrap();
+        }
+        for &position in &hill {
+            let id = world.add_ant(Color::Black, self.mirror(position)).unwrap();
+            world.ant_mut(id).unwrap().rotate(Direction::Left);
+        }
+        Some(world)
+    }
+}
+
+fn reachable_from(grid: &Grid, start: Position) -> HashSet<Position> {
+    let mut reachable = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+    while let Some(position) = queue.pop_front() {
+        for neighbour in grid.ring(position, 1) {
+            if !matches!(grid.cell_at(neighbour), Some(Cell::Wall)) && reachable.insert(neighbour) {
+                queue.push_back(neighbour);
+            }
+        }
+    }
+    reachable
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(grid: &Grid) -> impl Iterator<Item = Position> {
+        let (width, height) = (grid.width() as i32, grid.height() as i32);
+        (0..height).flat_map(move |y| (0..width).map(move |x| Position { x, y }))
+    }
+
+    #[test]
+    fn deterministic() {
+        let generator = MapGenerator::new(30, 20);
+        assert!(generator.generate(5) == generator.generate(5));
+        assert!(generator.generate(5) != generator.generate(6));
+    }
+
+    #[test]
+    fn symmetric() {
+        let generator = MapGenerator::new(30, 20);
+        for seed in 0..20 {
+            let world = generator.generate(seed);
+            let grid = world.grid();
+            for position in positions(grid) {
+                let (cell, mirrored) = (
+                    grid.cell_at(position).unwrap(),
+                    grid.cell_at(generator.mirror(position)).unwrap(),
+                );
+                assert_eq!(cell.food(), mirrored.food());
+                assert_eq!(cell.free_to_move(), mirrored.free_to_move());
+                assert_eq!(cell.anthill().map(Color::enemy), mirrored.anthill());
+            }
+            assert!(positions(grid).any(|p| grid.cell_at(p).unwrap().has_food()));
+            let red: Vec<_> = world.swarm(Color::Red).collect();
+            let black: Vec<_> = world.swarm(Color::Black).collect();
+            assert_eq!(red.len(), 19);
+            for (red, black) in red.iter().zip(&black) {
+                assert_eq!(generator.mirror(red.position()), black.position());
+                assert_eq!(red.direction(), Direction::Right);
+                assert_eq!(black.direction(), Direction::Left);
+            }
+        }
+    }
+
+    #[test]
+    fn connected_and_sealed() {
+        let generator = MapGenerator::new(24, 16).with_wall_blobs(40);
+        for seed in 0..20 {
+            let world = generator.generate(seed);
+            let grid = world.grid();
+            assert!(grid.is_sealed());
+            let start = world.swarm(Color::Red).next().unwrap().position();
+            let reachable = reachable_from(grid, start);
+            let free = positions(grid).filter(|&p| !matches!(grid.cell_at(p), Some(Cell::Wall)));
+            assert!(free.into_iter().all(|p| reachable.contains(&p)));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "map too small")]
+    fn too_small() {
+        MapGenerator::new(8, 8).generate(0);
+    }
+}
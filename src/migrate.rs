@@ -0,0 +1,258 @@
+use std::fmt;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::replay::*;
+use crate::world::*;
+
+// Saved worlds and replays are JSON documents of the form
+//
+//   { "version": 2, "kind": "world" | "replay", "data": ... }
+//
+// Version 1 is what the serde derives produced before documents were
+// versioned: the bare `data`, with no way to tell the kind but its fields,
+// and rule sets without `costs`. Version 2 ants have a `carries_food` flag
+// where version 3 has a `carried_food` amount. Loading upgrades old
+// documents one version at a time before deserializing.
+pub const CURRENT_VERSION: u64 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    World,
+    Replay,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::World => "world",
+            Kind::Replay => "replay",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Kind> {
+        [Kind::World, Kind::Replay]
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MigrateError {
+    // Not JSON, or the data does not match the current types.
+    Malformed(String),
+    UnknownFormat,
+    // Written by a newer version of the crate.
+    UnsupportedVersion(u64),
+    WrongKind { expected: Kind, found: Kind },
+}
+
+impl fmt::Display for MigrateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MigrateError::Malformed(error) => write!(f, "malformed document: {}", error),
+            MigrateError::UnknownFormat => write!(f, "not a saved world or replay"),
+            MigrateError::UnsupportedVersion(version) => {
+                write!(f, "document version {} is newer than this crate", version)
+            }
+            MigrateError::WrongKind { expected, found } => {
+                write!(
+                    f,
+                    "expected a {}, found a {}",
+                    expected.name(),
+                    found.name()
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for MigrateError {}
+
+impl From<serde_json::Error> for MigrateError {
+    fn from(error: serde_json::Error) -> Self {
+        MigrateError::Malformed(error.to_string())
+    }
+}
+
+// Upgrades a document of any known version to `CURRENT_VERSION`.
+pub fn migrate(mut document: Value) -> Result<Value, MigrateError> {
+    loop {
+        match version_of(&document)? {
+            1 => document = v1_to_v2(document)?,
+            2 => document = v2_to_v3(document)?,
+            CURRENT_VERSION => return Ok(document),
+            version => return Err(MigrateError::UnsupportedVersion(version)),
+        }
+    }
+}
+
+fn version_of(document: &Value) -> Result<u64, MigrateError> {
+    let object = document.as_object().ok_or(MigrateError::UnknownFormat)?;
+    match object.get("version") {
+        Some(version) => version.as_u64().ok_or(MigrateError::UnknownFormat),
+        None => Ok(1),
+    }
+}
+
+fn has_fields(object: &Map<String, Value>, fields: &[&str]) -> bool {
+    fields.iter().all(|field| object.contains_key(*field))
+}
+
+fn v1_to_v2(document: Value) -> Result<Value, MigrateError> {
+    let Value::Object(mut data) = document else {
+        return Err(MigrateError::UnknownFormat);
+    };
+    let kind = if has_fields(&data, &["ants", "swarms", "grid"]) {
+        Kind::World
+    } else if has_fields(&data, &["initial", "rules", "steps", "entries"]) {
+        let rules = data
+            .get_mut("rules")
+            .and_then(Value::as_object_mut)
+            .ok_or(MigrateError::UnknownFormat)?;
+        rules.entry("costs").or_insert_with(|| json!({}));
+        Kind::Replay
+    } else {
+        return Err(MigrateError::UnknownFormat);
+    };
+    Ok(json!({ "version": 2, "kind": kind.name(), "data": data }))
+}
+
+fn v2_to_v3(mut document: Value) -> Result<Value, MigrateError> {
+    let world = match document["kind"].as_str().and_then(Kind::from_name) {
+        Some(Kind::World) => &mut document["data"],
+        Some(Kind::Replay) => &mut document["data"]["initial"],
+        None => return Err(MigrateError::UnknownFormat),
+    };
+    let ants = world["ants"]
+        .as_array_mut()
+        .ok_or(MigrateError::UnknownFormat)?;
+    for ant in ants {
+        let ant = ant.as_object_mut().ok_or(MigrateError::UnknownFormat)?;
+        let carries_food = ant
+            .remove("carries_food")
+            .and_then(|value| value.as_bool())
+            .ok_or(MigrateError::UnknownFormat)?;
+        ant.insert("carried_food".to_string(), json!(carries_food as u32));
+    }
+    document["version"] = json!(3);
+    Ok(document)
+}
+
+fn save<T: Serialize>(kind: Kind, data: &T) -> String {
+    let document = json!({
+        "version": CURRENT_VERSION,
+        "kind": kind.name(),
+        "data": data,
+    });
+    serde_json::to_string(&document).unwrap()
+}
+
+fn load<T: DeserializeOwned>(expected: Kind, s: &str) -> Result<T, MigrateError> {
+    let mut document = migrate(serde_json::from_str(s)?)?;
+    let found = document
+        .get("kind")
+        .and_then(Value::as_str)
+        .and_then(Kind::from_name)
+        .ok_or(MigrateError::UnknownFormat)?;
+    if found != expected {
+        return Err(MigrateError::WrongKind { expected, found });
+    }
+    Ok(serde_json::from_value(document["data"].take())?)
+}
+
+pub fn save_world(world: &World) -> String {
+    save(Kind::World, world)
+}
+
+pub fn load_world(s: &str) -> Result<World, MigrateError> {
+    load(Kind::World, s)
+}
+
+pub fn save_replay(replay: &Replay) -> String {
+    save(Kind::Replay, replay)
+}
+
+pub fn load_replay(s: &str) -> Result<Replay, MigrateError> {
+    load(Kind::Replay, s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::*;
+
+    const V1_WORLD: &str = include_str!("../fixtures/v1/world.json");
+    const V1_REPLAY: &str = include_str!("../fixtures/v1/replay.json");
+    const V2_REPLAY: &str = include_str!("../fixtures/v2/replay.json");
+
+    const MAP: &str = "\
+# # # # #
+ # r 3 - #
+# . + b #
+ # # # # #
+";
+
+    #[test]
+    fn v1_world() {
+        let world = load_world(V1_WORLD).unwrap();
+        assert_world_eq(&world, &MAP.parse().unwrap());
+    }
+
+    #[test]
+    fn v1_replay() {
+        let replay = load_replay(V1_REPLAY).unwrap();
+        assert!(replay.rules().costs.is_empty());
+        assert_eq!(replay.steps(), 3);
+        assert!(replay == load_replay(V2_REPLAY).unwrap());
+
+        let mut player = ReplayPlayer::new(&replay);
+        player.run_to_end().unwrap();
+        assert_eq!(
+            player.world().ant(0).unwrap().position(),
+            Position { x: 2, y: 1 }
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let world: World = MAP.parse().unwrap();
+        assert_world_eq(&load_world(&save_world(&world)).unwrap(), &world);
+        let replay = load_replay(V2_REPLAY).unwrap();
+        assert!(load_replay(&save_replay(&replay)).unwrap() == replay);
+    }
+
+    #[test]
+    fn migrate_is_idempotent() {
+        let once = migrate(serde_json::from_str(V1_REPLAY).unwrap()).unwrap();
+        assert_eq!(migrate(once.clone()).unwrap(), once);
+        let v2 = migrate(serde_json::from_str(V2_REPLAY).unwrap()).unwrap();
+        assert_eq!(once, v2);
+        assert_eq!(v2["data"]["initial"]["ants"][0]["carried_food"], 0);
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            load_world(V1_REPLAY).err(),
+            Some(MigrateError::WrongKind {
+                expected: Kind::World,
+                found: Kind::Replay,
+            })
+        );
+        assert_eq!(
+            load_world(r#"{"version": 4, "kind": "world", "data": {}}"#).err(),
+            Some(MigrateError::UnsupportedVersion(4))
+        );
+        assert_eq!(
+            load_world(r#"{"turtles": []}"#).err(),
+            Some(MigrateError::UnknownFormat)
+        );
+        assert!(matches!(
+            load_world("not json"),
+            Err(MigrateError::Malformed(_))
+        ));
+    }
+}
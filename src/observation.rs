@@ -0,0 +1,244 @@
+use crate::asm::*;
+use crate::hex;
+use crate::world::*;
+
+// What an ant can see of a cell, from the point of view of its own color.
+// Cells off the edge of a bounded map look like walls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CellView {
+    pub wall: bool,
+    pub food: u32,
+    pub friend: bool,
+    pub foe: bool,
+    pub home: bool,
+    pub foe_home: bool,
+    // Bit n set for the ant's own marker n.
+    pub markers: u8,
+    pub foe_marker: bool,
+}
+
+impl CellView {
+    pub fn of(cell: Option<&Cell>, world: &World, color: Color) -> Self {
+        let Some(cell @ Cell::FreeCell { .. }) = cell else {
+            return CellView {
+                wall: true,
+                ..CellView::default()
+            };
+        };
+        let ant = cell
+            .ant()
+            .and_then(|id| world.ant(id))
+            .map(|ant| ant.color());
+        CellView {
+            wall: false,
+            food: cell.food(),
+            friend: ant == Some(color),
+            foe: ant.is_some_and(|ant| ant != color),
+            home: cell.anthill() == Some(color),
+            foe_home: cell.anthill().is_some_and(|home| home != color),
+            markers: (0..MARKERS_PER_COLOR)
+                .filter(|&marker| cell.has_marker(color, marker))
+                .map(|marker| 1 << marker)
+                .sum(),
+            foe_marker: cell.has_foreign_marker(color),
+        }
+    }
+
+    pub fn satisfies(&self, condition: SenseCondition) -> bool {
+        match condition {
+            SenseCondition::Friend => self.friend,
+            SenseCondition::Foe => self.foe,
+            SenseCondition::Food => self.food > 0,
+            SenseCondition::Wall => self.wall,
+            SenseCondition::Home => self.home,
+            SenseCondition::FoeHome => self.foe_home,
+            SenseCondition::Marker(marker) => {
+                marker < MARKERS_PER_COLOR && self.markers & (1 << marker) != 0
+            }
+            SenseCondition::FoeMarker => self.foe_marker,
+        }
+    }
+}
+
+// Everything an ant gets to know before it acts: itself, the cell it stands
+// on and its six neighbours.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Observation {
+    pub round: u32,
+    pub ant: AntId,
+    pub color: Color,
+    pub direction: Direction,
+    pub carried_food: u32,
+    pub here: CellView,
+    // Indexed by `u32::from(direction)`, so starting to the right and going
+    // clockwise.
+    pub neighbours: [CellView; 6],
+    // Only given out when the rules allow swarm sensing.
+    pub census: Option<SwarmCensus>,
+}
+
+impl Observation {
+    // `None` for dead ants.
+    pub fn of(world: &World, id: AntId, round: u32) -> Option<Self> {
+        let ant = world.ant(id)?;
+        let grid = world.grid();
+        let color = ant.color();
+        let view = |position| CellView::of(grid.cell_at(position), world, color);
+        let mut neighbours = [CellView::of(None, world, color); 6];
+        for (direction, cell) in grid.neighbors(ant.position()) {
+            neighbours[u32::from(direction) as usize] = CellView::of(Some(cell), world, color);
+        }
+        Some(Observation {
+            round,
+            ant: id,
+            color,
+            direction: ant.direction(),
+            carried_food: ant.carried_food(),
+            here: view(ant.position()),
+            neighbours,
+            census: None,
+        })
+    }
+
+    // For rules that keep markers private to their team.
+    pub fn hide_foe_markers(&mut self) {
+        for view in std::iter::once(&mut self.here).chain(&mut self.neighbours) {
+            view.foe_marker = false;
+        }
+    }
+
+    pub fn neighbour(&self, direction: Direction) -> &CellView {
+        &self.neighbours[u32::from(direction) as usize]
+    }
+
+    pub fn ahead(&self) -> &CellView {
+        self.neighbour(self.direction)
+    }
+
+    pub fn sensed(&self, sense_dir: SenseDirection) -> &CellView {
+        match sense_dir {
+            SenseDirection::Here => &self.here,
+            SenseDirection::Ahead => self.ahead(),
+            SenseDirection::LeftAhead => self.neighbour(self.direction.rotated(-1)),
+            SenseDirection::RightAhead => self.neighbour(self.direction.rotated(1)),
+        }
+    }
+}
+
+// The cells within some distance of an ant, for brains that see further
+// than the six neighbours. Positions are in the ant's own frame, as if it
+// stood at the origin facing `Right`: (1, 0) is ahead, (1, -1) left-ahead
+// and (0, 1) right-ahead. Walls do not block the view.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Vision {
+    pub radius: u32,
+    // Ring by ring, in the order of `hex::spiral` about the origin.
+    pub cells: Vec<(Position, CellView)>,
+}
+
+impl Vision {
+    pub fn cell(&self, relative: Position) -> Option<&CellView> {
+        self.cells
+            .iter()
+            .find(|(position, _)| *position == relative)
+            .map(|(_, view)| view)
+    }
+}
+
+impl World {
+    // `None` for dead ants.
+    pub fn observe(&self, id: AntId, radius: u32) -> Option<Vision> {
+        let ant = self.ant(id)?;
+        let (origin, color) = (ant.position(), ant.color());
+        let turns = u32::from(ant.direction()) as i32;
+        let cells = hex::spiral(Position::default(), radius)
+            .map(|relative| {
+                let offset = hex::rotate(relative, turns);
+                let position = Position {
+                    x: origin.x + offset.x,
+                    y: origin.y + offset.y,
+                };
+                let view = CellView::of(self.grid().cell_at(position), self, color);
+                (relative, view)
+            })
+            .collect();
+        Some(Vision { radius, cells })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sees_neighbours() {
+        let mut world = World::new(Grid::with_wall_border(5, 4));
+        let red = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+        world
+            .add_ant(Color::Black, Position { x: 2, y: 1 })
+            .unwrap();
+        world.ant_mut(red).unwrap().set_marker(3).unwrap();
+        world.ant_mut(red).unwrap().rotate(Direction::DownRight);
+
+        let observation = Observation::of(&world, red, 7).unwrap();
+        assert_eq!(observation.round, 7);
+        assert_eq!(observation.direction, Direction::DownRight);
+        assert_eq!(observation.here.markers, 1 << 3);
+        assert!(observation.neighbour(Direction::Right).foe);
+        assert!(observation.neighbour(Direction::Left).wall);
+        assert!(observation.neighbour(Direction::UpRight).wall);
+        assert!(!observation.ahead().wall);
+        assert!(observation.sensed(SenseDirection::LeftAhead).foe);
+        assert!(observation
+            .sensed(SenseDirection::Here)
+            .satisfies(SenseCondition::Marker(3)));
+
+        let black = Observation::of(&world, red + 1, 7).unwrap();
+        assert!(!black.neighbour(Direction::Left).friend);
+        assert!(black.neighbour(Direction::Left).foe);
+        assert!(black.neighbour(Direction::Left).foe_marker);
+        let mut private = black;
+        private.hide_foe_markers();
+        assert!(!private.neighbour(Direction::Left).foe_marker);
+    }
+
+    #[test]
+    fn vision_turns_with_the_ant() {
+        let mut grid = Grid::new(7, 7);
+        let food = grid.cell_at_mut(Position { x: 3, y: 4 }).unwrap();
+        food.try_drop_food().unwrap();
+        let mut world = World::new(grid);
+        let ant = world.add_ant(Color::Red, Position { x: 3, y: 3 }).unwrap();
+        world
+            .add_ant(Color::Black, Position { x: 3, y: 1 })
+            .unwrap();
+
+        let vision = world.observe(ant, 2).unwrap();
+        assert_eq!(vision.cells.len(), 19);
+        assert_eq!(vision.cells[0].0, Position::default());
+        assert!(vision.cell(Position { x: 0, y: -2 }).unwrap().foe);
+        assert_eq!(vision.cell(Position { x: 0, y: 1 }).unwrap().food, 1);
+
+        // Facing down-right, the food is straight ahead.
+        world.ant_mut(ant).unwrap().rotate(Direction::DownRight);
+        let vision = world.observe(ant, 2).unwrap();
+        assert_eq!(vision.cell(Position { x: 1, y: 0 }).unwrap().food, 1);
+        assert!(vision.cell(Position { x: -2, y: 0 }).unwrap().foe);
+        let observation = Observation::of(&world, ant, 0).unwrap();
+        assert_eq!(
+            vision.cell(Position { x: 1, y: -1 }),
+            Some(observation.sensed(SenseDirection::LeftAhead))
+        );
+        assert!(
+            world
+                .observe(ant, 4)
+                .unwrap()
+                .cell(Position { x: 4, y: 0 })
+                .unwrap()
+                .wall
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use crate::world::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    AntMoved {
+        ant: AntId,
+        from: Position,
+        to: Position,
+    },
+    AntTurned {
+        ant: AntId,
+        direction: Direction,
+    },
+    // A `Move` that failed because the cell ahead was a wall, off the map
+    // or occupied.
+    AntBlocked {
+        ant: AntId,
+        position: Position,
+    },
+    FoodPickedUp {
+        ant: AntId,
+        position: Position,
+    },
+    // `position` is where the ant stands; with a food cap that spills, the
+    // food may have landed on a neighbour.
+    FoodDropped {
+        ant: AntId,
+        position: Position,
+    },
+    AntKilled {
+        ant: AntId,
+        position: Position,
+    },
+    // Out of energy under a `Metabolism`, at the end of a round.
+    AntStarved {
+        ant: AntId,
+        position: Position,
+    },
+    // Bought by its anthill under a `SpawnRule`, at the end of a round.
+    AntSpawned {
+        ant: AntId,
+        position: Position,
+    },
+    StepCompleted {
+        round: u32,
+    },
+}
+
+pub trait Observer {
+    fn on_event(&mut self, event: Event);
+}
+
+impl<F: FnMut(Event)> Observer for F {
+    fn on_event(&mut self, event: Event) {
+        self(event)
+    }
+}
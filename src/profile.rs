@@ -0,0 +1,185 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::asm::*;
+use crate::trace::*;
+use crate::world::*;
+
+// How often one instruction ran and which way it went. Instructions with a
+// single way on count neither as a success nor as a failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InstrCounts {
+    pub executed: u64,
+    pub succeeded: u64,
+    pub failed: u64,
+}
+
+// The counts for every instruction of one program.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgramProfile {
+    program: Program,
+    counts: Vec<InstrCounts>,
+}
+
+impl ProgramProfile {
+    fn new(program: Program) -> Self {
+        ProgramProfile {
+            counts: vec![InstrCounts::default(); program.len()],
+            program,
+        }
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    // By instruction index.
+    pub fn counts(&self) -> &[InstrCounts] {
+        &self.counts
+    }
+
+    // Instructions that never ran.
+    pub fn dead_code(&self) -> impl Iterator<Item = InstrIdx> + '_ {
+        (self.counts.iter().enumerate())
+            .filter(|(_, counts)| counts.executed == 0)
+            .map(|(index, _)| index)
+    }
+
+    // The `n` instructions that ran most, most first; ties go to the
+    // lower index.
+    pub fn hottest(&self, n: usize) -> Vec<(InstrIdx, u64)> {
+        let mut hot: Vec<_> = (self.counts.iter().enumerate())
+            .map(|(index, counts)| (index, counts.executed))
+            .collect();
+        hot.sort_by_key(|&(index, executed)| (std::cmp::Reverse(executed), index));
+        hot.truncate(n);
+        hot
+    }
+}
+
+// Counts every instruction the programs run over a match, see
+// `Simulator::start_profiling`. Ants run by a brain are not counted.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Profiler {
+    profiles: BTreeMap<(Color, Caste), ProgramProfile>,
+}
+
+impl Profiler {
+    pub(crate) fn new(programs: impl IntoIterator<Item = (Color, Caste, Program)>) -> Self {
+        Profiler {
+            profiles: (programs.into_iter())
+                .map(|(color, caste, program)| ((color, caste), ProgramProfile::new(program)))
+                .collect(),
+        }
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        color: Color,
+        caste: Caste,
+        instr_pointer: InstrIdx,
+        outcome: Outcome,
+    ) {
+        let profile = self.profiles.get_mut(&(color, caste));
+        let Some(counts) = profile.and_then(|profile| profile.counts.get_mut(instr_pointer)) else {
+            return;
+        };
+        counts.executed += 1;
+        match outcome {
+            Outcome::Success => counts.succeeded += 1,
+            Outcome::Fail => counts.failed += 1,
+            Outcome::Next => {}
+        }
+    }
+
+    // The profile of the program `color`'s ants of `caste` run; castes
+    // without a program of their own count under caste 0.
+    pub fn profile(&self, color: Color, caste: Caste) -> Option<&ProgramProfile> {
+        self.profiles.get(&(color, caste))
+    }
+
+    pub fn profiles(&self) -> impl Iterator<Item = (Color, Caste, &ProgramProfile)> {
+        (self.profiles.iter()).map(|(&(color, caste), profile)| (color, caste, profile))
+    }
+}
+
+// A listing of every program with its counts, color by color:
+//
+//   Red
+//    instr   executed    success       fail
+//        0        120         80         40  Move 0 1
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (color, caste, profile) in self.profiles() {
+            match caste {
+                0 => writeln!(f, "{:?}", color)?,
+                _ => writeln!(f, "{:?} caste {}", color, caste)?,
+            }
+            writeln!(
+                f,
+                "{:>6} {:>10} {:>10} {:>10}",
+                "instr", "executed", "success", "fail"
+            )?;
+            for (index, (instr, counts)) in profile.program.iter().zip(&profile.counts).enumerate()
+            {
+                writeln!(
+                    f,
+                    "{:>6} {:>10} {:>10} {:>10}  {}",
+                    index, counts.executed, counts.succeeded, counts.failed, instr
+                )?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn counts_runs_and_branches() {
+        let mut world = World::new(Grid::new(3, 1));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let program = parse_program(
+            "start: Move start blocked
+             blocked: Turn Left start
+             Return",
+        )
+        .unwrap();
+        let programs = HashMap::from([(Color::Red, program)]);
+        let mut simulator = Simulator::headless(world, programs, 0).unwrap();
+        simulator.step();
+        simulator.start_profiling();
+        simulator.run_for(3);
+
+        // The first move came before profiling; then one to the wall, one
+        // into it and the turn.
+        let profiler = simulator.profiler().unwrap();
+        let profile = profiler.profile(Color::Red, 0).unwrap();
+        let counts = profile.counts();
+        assert_eq!(
+            counts[0],
+            InstrCounts {
+                executed: 2,
+                succeeded: 1,
+                failed: 1,
+            }
+        );
+        assert_eq!(counts[1].executed, 1);
+        assert_eq!(profile.dead_code().collect::<Vec<_>>(), vec![2]);
+        assert_eq!(profile.hottest(2), vec![(0, 2), (1, 1)]);
+        assert!(profiler.profile(Color::Black, 0).is_none());
+
+        let report = profiler.to_string();
+        assert!(report.starts_with("Red\n"), "{}", report);
+        assert!(report.contains("     0          2          1          1  Move 0 1\n"));
+
+        assert!(simulator.take_profiler().is_some());
+        simulator.step();
+        assert!(simulator.profiler().is_none());
+    }
+}
@@ -0,0 +1,511 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+
+use crate::delta::*;
+use crate::sim::*;
+use crate::snapshot::*;
+use crate::victory::*;
+use crate::world::*;
+
+// The wire format for spectators. A viewer first gets the whole world, then
+// one message per step with the cells and ants that step touched, and a
+// last one when the match ends. A world sent again mid-match replaces the
+// viewer's. Every message starts with the protocol
+// version and a tag; the body uses the snapshot encoding.
+//
+// On a stream each message is a frame: its length as a little-endian u32,
+// then the message.
+pub const PROTO_VERSION: u8 = 3;
+
+// Frames bigger than this are refused rather than allocated.
+pub const MAX_FRAME_LEN: u32 = 64 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtoError {
+    UnsupportedVersion(u8),
+    UnknownMessage(u8),
+    Malformed(SnapshotError),
+}
+
+impl fmt::Display for ProtoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProtoError::UnsupportedVersion(version) => {
+                write!(f, "unsupported protocol version {}", version)
+            }
+            ProtoError::UnknownMessage(tag) => write!(f, "unknown message type {}", tag),
+            ProtoError::Malformed(error) => write!(f, "malformed message: {}", error),
+        }
+    }
+}
+
+impl std::error::Error for ProtoError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProtoError::Malformed(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<SnapshotError> for ProtoError {
+    fn from(error: SnapshotError) -> Self {
+        ProtoError::Malformed(error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AntState {
+    pub id: AntId,
+    pub color: Color,
+    pub position: Position,
+    pub direction: Direction,
+    pub carried_food: u32,
+    pub alive: bool,
+}
+
+impl AntState {
+    pub fn of(ant: Ant<'_>) -> Self {
+        AntState {
+            id: ant.id(),
+            color: ant.color(),
+            position: ant.position(),
+            direction: ant.direction(),
+            carried_food: ant.carried_food(),
+            alive: ant.is_alive(),
+        }
+    }
+}
+
+#[derive(Clone, PartialEq)]
+pub enum Message {
+    Start {
+        round: u32,
+        world: World,
+    },
+    // `round` is the number of rounds played after the step.
+    Step {
+        round: u32,
+        cells: Vec<(Position, Cell)>,
+        ants: Vec<AntState>,
+    },
+    End(MatchResult),
+}
+
+const START: u8 = 0;
+const STEP: u8 = 1;
+const END: u8 = 2;
+
+impl Message {
+    // The step just taken in `world`, with positions and ids sorted so the
+    // same step always encodes the same way.
+    pub fn step(world: WorldSnapshot<'_>, round: u32, delta: &WorldDelta) -> Self {
+        let mut positions: Vec<_> = delta.cells().collect();
+        positions.sort_by_key(|position| (position.y, position.x));
+        let mut ids: Vec<_> = delta.ants().collect();
+        ids.sort_unstable();
+        Message::Step {
+            round,
+            cells: positions
+                .into_iter()
+                .filter_map(|position| {
+                    let cell = world.cell_at(position)?;
+                    Some((position, cell.clone()))
+                })
+                .collect(),
+            ants: ids
+                .into_iter()
+                .filter_map(|id| world.ant(id).or_else(|| world.dead_ant(id)))
+                .map(AntState::of)
+                .collect(),
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Encoder::bare();
+        out.u8(PROTO_VERSION);
+        match self {
+            Message::Start { round, world } => {
+                out.u8(START);
+                out.u32(*round);
+                world.encode(&mut out);
+            }
+            Message::Step { round, cells, ants } => {
+                out.u8(STEP);
+                out.u32(*round);
+                out.usize(cells.len());
+                for (position, cell) in cells {
+                    out.position(*position);
+                    cell.encode(&mut out);
+                }
+                out.usize(ants.len());
+                for ant in ants {
+                    out.usize(ant.id);
+                    out.color(ant.color);
+                    out.position(ant.position);
+                    out.direction(ant.direction);
+                    out.u32(ant.carried_food);
+                    out.bool(ant.alive);
+                }
+            }
+            Message::End(result) => {
+                out.u8(END);
+                MatchResult::encode(Some(*result), &mut out);
+            }
+        }
+        out.into_bytes()
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Message, ProtoError> {
+        let mut input = Decoder::bare(bytes);
+        let version = input.u8()?;
+        if version != PROTO_VERSION {
+            return Err(ProtoError::UnsupportedVersion(version));
+        }
+        let message = match input.u8()? {
+            START => Message::Start {
+                round: input.u32()?,
+                world: World::decode(&mut input)?,
+            },
+            STEP => {
+                let round = input.u32()?;
+                let cells = (0..input.count()?)
+                    .map(|_| Ok((input.position()?, Cell::decode(&mut input)?)))
+                    .collect::<Result<_, SnapshotError>>()?;
+                let ants = (0..input.count()?)
+                    .map(|_| {
+                        Ok(AntState {
+                            id: input.usize()?,
+                            color: input.color()?,
+                            position: input.position()?,
+                            direction: input.direction()?,
+                            carried_food: input.u32()?,
+                            alive: input.bool()?,
+                        })
+                    })
+                    .collect::<Result<_, SnapshotError>>()?;
+                Message::Step { round, cells, ants }
+            }
+            END => Message::End(MatchResult::decode(&mut input)?.ok_or(SnapshotError::Corrupt)?),
+            tag => return Err(ProtoError::UnknownMessage(tag)),
+        };
+        input.finish()?;
+        Ok(message)
+    }
+}
+
+pub fn write_frame(writer: &mut impl Write, message: &Message) -> io::Result<()> {
+    let bytes = message.encode();
+    let len = u32::try_from(bytes.len())
+        .ok()
+        .filter(|&len| len <= MAX_FRAME_LEN)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "frame too large"))?;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(&bytes)
+}
+
+pub fn read_frame(reader: &mut impl Read) -> io::Result<Message> {
+    let mut len = [0; 4];
+    reader.read_exact(&mut len)?;
+    let len = u32::from_le_bytes(len);
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "frame too large",
+        ));
+    }
+    let mut bytes = vec![0; len as usize];
+    reader.read_exact(&mut bytes)?;
+    Message::decode(&bytes)
+        .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, format!("{:?}", error)))
+}
+
+// Frames bigger than this in total go unsent to a viewer before it is
+// dropped, rather than held for it.
+const MAX_BACKLOG: usize = 2 * MAX_FRAME_LEN as usize;
+
+enum Frame {
+    Whole,
+    Diff(Box<WorldDelta>),
+}
+
+// Collects every frame the simulator renders; the server turns them into
+// messages with the simulator's round once the step is over.
+struct Frames {
+    pending: Rc<RefCell<Vec<Frame>>>,
+}
+
+impl Renderer for Frames {
+    fn render(&mut self, _world: &World) {
+        self.pending.borrow_mut().push(Frame::Whole);
+    }
+
+    fn render_diff(&mut self, _world: &World, delta: &WorldDelta) {
+        self.pending
+            .borrow_mut()
+            .push(Frame::Diff(Box::new(delta.clone())));
+    }
+}
+
+struct Viewer {
+    stream: TcpStream,
+    // Bytes of frames the socket would not take yet.
+    unsent: Vec<u8>,
+}
+
+impl Viewer {
+    // Writes what the socket takes without waiting. Fails once the viewer
+    // is gone or has fallen too far behind.
+    fn send(&mut self) -> io::Result<()> {
+        while !self.unsent.is_empty() {
+            match self.stream.write(&self.unsent) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(written) => drop(self.unsent.drain(..written)),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+        if self.unsent.len() > MAX_BACKLOG {
+            return Err(io::Error::other("viewer fell behind"));
+        }
+        Ok(())
+    }
+}
+
+// Runs a match and streams it to every viewer connected over TCP. Viewers
+// that join late get the world as it is then, and every viewer gets it
+// again whenever the simulator redraws it whole. The server takes the
+// simulator's renderer slot for itself.
+//
+// Sockets never block the match: what a viewer cannot take yet is kept for
+// the next step, and viewers that fall too far behind are dropped.
+pub struct MatchServer {
+    simulator: Simulator,
+    listener: TcpListener,
+    viewers: Vec<Viewer>,
+    pending: Rc<RefCell<Vec<Frame>>>,
+    ended: bool,
+}
+
+impl MatchServer {
+    pub fn bind(simulator: Simulator, address: impl ToSocketAddrs) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let pending = Rc::default();
+        let frames = Frames {
+            pending: Rc::clone(&pending),
+        };
+        Ok(MatchServer {
+            simulator: simulator.with_renderer(Box::new(frames)),
+            listener,
+            viewers: Vec::new(),
+            pending,
+            ended: false,
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+
+    pub fn viewers(&self) -> usize {
+        self.viewers.len()
+    }
+
+    // Takes in everyone waiting to connect and sends them the world.
+    pub fn accept(&mut self) -> io::Result<usize> {
+        let mut greeting = Vec::new();
+        write_frame(&mut greeting, &self.start())?;
+        if let Some(result) = self.simulator.result() {
+            write_frame(&mut greeting, &Message::End(result))?;
+        }
+        let mut accepted = 0;
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) => return Err(error),
+            };
+            stream.set_nonblocking(true)?;
+            stream.set_nodelay(true)?;
+            let mut viewer = Viewer {
+                stream,
+                unsent: greeting.clone(),
+            };
+            if viewer.send().is_ok() {
+                self.viewers.push(viewer);
+                accepted += 1;
+            }
+        }
+        Ok(accepted)
+    }
+
+    // Steps the match and sends the step, and its end if it just ended.
+    // Viewers that cannot be written to are dropped.
+    pub fn step(&mut self) {
+        self.simulator.step();
+        let frames = std::mem::take(&mut *self.pending.borrow_mut());
+        let (world, round) = (self.simulator.world(), self.simulator.round());
+        let mut messages: Vec<_> = frames
+            .into_iter()
+            .map(|frame| match frame {
+                Frame::Whole => self.start(),
+                Frame::Diff(delta) => Message::step(world.snapshot(), round, &delta),
+            })
+            .collect();
+        if !self.ended {
+            if let Some(result) = self.simulator.result() {
+                self.ended = true;
+                messages.push(Message::End(result));
+            }
+        }
+        self.broadcast(&messages);
+    }
+
+    // Sends what viewers could not take so far.
+    pub fn flush(&mut self) {
+        self.viewers.retain_mut(|viewer| viewer.send().is_ok());
+    }
+
+    pub fn run_until_over(&mut self, max_rounds: u32) {
+        for _ in 0..max_rounds {
+            if self.simulator.is_over() {
+                break;
+            }
+            self.step();
+        }
+    }
+
+    fn start(&self) -> Message {
+        Message::Start {
+            round: self.simulator.round(),
+            world: self.simulator.world().clone(),
+        }
+    }
+
+    fn broadcast(&mut self, messages: &[Message]) {
+        let mut bytes = Vec::new();
+        for message in messages {
+            // A frame no viewer can be sent leaves them all out of step.
+            if write_frame(&mut bytes, message).is_err() {
+                self.viewers.clear();
+                return;
+            }
+        }
+        for viewer in &mut self.viewers {
+            viewer.unsent.extend_from_slice(&bytes);
+        }
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    use crate::asm::*;
+
+    fn world() -> World {
+        let mut world = World::new(Grid::new(6, 1));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world
+            .add_ant(Color::Black, Position { x: 5, y: 0 })
+            .unwrap();
+        world
+    }
+
+    fn simulator() -> Simulator {
+        let mut programs = HashMap::new();
+        let program = parse_program("start: Move start start").unwrap();
+        programs.insert(Color::Red, program.clone());
+        programs.insert(Color::Black, program);
+        Simulator::headless(world(), programs, 0)
+            .unwrap()
+            .with_end_condition(EndCondition::MaxRounds(2))
+    }
+
+    #[test]
+    fn round_trip() {
+        let start = Message::Start {
+            round: 3,
+            world: world(),
+        };
+        assert!(Message::decode(&start.encode()) == Ok(start));
+
+        let world = world();
+        let porter = AntState {
+            carried_food: 5,
+            ..AntState::of(world.ant(1).unwrap())
+        };
+        let step = Message::Step {
+            round: 4,
+            cells: vec![(
+                Position { x: 0, y: 0 },
+                world
+                    .grid()
+                    .cell_at(Position { x: 0, y: 0 })
+                    .unwrap()
+                    .clone(),
+            )],
+            ants: vec![porter],
+        };
+        assert!(Message::decode(&step.encode()) == Ok(step));
+
+        let end = Message::End(MatchResult::new(&world, 5, None, EndReason::Victory));
+        assert!(Message::decode(&end.encode()) == Ok(end));
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        let mut bytes =
+            Message::End(MatchResult::new(&world(), 0, None, EndReason::Victory)).encode();
+        assert!(Message::decode(&bytes[..bytes.len() - 1]).is_err());
+        bytes[1] = 9;
+        assert!(matches!(
+            Message::decode(&bytes),
+            Err(ProtoError::UnknownMessage(9))
+        ));
+        bytes[0] = 9;
+        assert!(matches!(
+            Message::decode(&bytes),
+            Err(ProtoError::UnsupportedVersion(9))
+        ));
+    }
+
+    #[test]
+    fn streams_a_match() {
+        let mut server = MatchServer::bind(simulator(), "127.0.0.1:0").unwrap();
+        let mut viewer = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        while server.viewers() == 0 {
+            server.accept().unwrap();
+        }
+        server.run_until_over(10);
+
+        let Message::Start { round: 0, world } = read_frame(&mut viewer).unwrap() else {
+            panic!("expected the world first");
+        };
+        assert_eq!(world.swarm_size(Color::Red), 1);
+        for expected in 1..=2 {
+            let Message::Step { round, ants, .. } = read_frame(&mut viewer).unwrap() else {
+                panic!("expected a step");
+            };
+            assert_eq!(round, expected);
+            assert_eq!(ants[0].position.x, expected as i32);
+        }
+        let Message::End(result) = read_frame(&mut viewer).unwrap() else {
+            panic!("expected the end");
+        };
+        assert_eq!(result.round, 2);
+        assert!(server.simulator().is_over());
+    }
+}
@@ -0,0 +1,290 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+
+use crate::asm::DIRECTIONS;
+use crate::brain::*;
+use crate::data::*;
+use crate::observation::*;
+use crate::world::*;
+
+// Brains that live in another process, written in whatever language, and
+// talk to the simulator over a line-based text protocol.
+//
+// Before the first question the simulator sends
+//
+//     bugworld-remote 2 <color>
+//
+// and then, for every ant that is to act, one line
+//
+//     observe <round> <ant> <direction> <carried food> <here> <6 cells>
+//
+// with the neighbours starting to the right and going clockwise. A cell is
+// `#` for a wall, otherwise `<food>:<flags>:<marker bits>` where the flags
+// are some of `f` (friend), `e` (enemy), `h` (home anthill), `H` (enemy
+// anthill), `m` (enemy marker) or `-` for none. The brain answers each with
+// one line:
+//
+//     Move | Turn <direction> | PickUpFood | DropFood | Eat | Mark <n> | Unmark <n> | Pass
+//
+// Turns are to an absolute direction, named as in the asm. Actions the
+// world does not allow are ignored, as if the ant had passed.
+pub const REMOTE_VERSION: u32 = 2;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RemoteError {
+    Io(io::ErrorKind),
+    Disconnected,
+    BadReply(String),
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RemoteError::Io(kind) => write!(f, "i/o error talking to the brain: {}", kind),
+            RemoteError::Disconnected => write!(f, "the brain disconnected"),
+            RemoteError::BadReply(reply) => write!(f, "bad reply from the brain: {:?}", reply),
+        }
+    }
+}
+
+impl std::error::Error for RemoteError {}
+
+impl From<io::Error> for RemoteError {
+    fn from(error: io::Error) -> Self {
+        RemoteError::Io(error.kind())
+    }
+}
+
+pub struct RemoteBrain {
+    reader: Box<dyn BufRead>,
+    writer: Box<dyn Write>,
+    child: Option<Child>,
+    greeted: bool,
+    // Once the brain has gone, every ant passes.
+    failed: Option<RemoteError>,
+}
+
+impl RemoteBrain {
+    pub fn new(reader: impl BufRead + 'static, writer: impl Write + 'static) -> Self {
+        RemoteBrain {
+            reader: Box::new(reader),
+            writer: Box::new(writer),
+            child: None,
+            greeted: false,
+            failed: None,
+        }
+    }
+
+    // Runs `command` with the protocol on its standard input and output.
+    // The process is killed when the brain is dropped.
+    pub fn spawn(command: &mut Command) -> io::Result<Self> {
+        let mut child = command
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+        let writer = child.stdin.take().unwrap();
+        let reader = BufReader::new(child.stdout.take().unwrap());
+        let mut brain = RemoteBrain::new(reader, writer);
+        brain.child = Some(child);
+        Ok(brain)
+    }
+
+    // The error that made the brain stop answering, if it has.
+    pub fn error(&self) -> Option<&RemoteError> {
+        self.failed.as_ref()
+    }
+
+    // Asks for the ant's action; `None` means it passes. A reply that does
+    // not parse is an error for this ant only, a broken connection ends
+    // the conversation.
+    pub fn request(&mut self, observation: &Observation) -> Result<Option<Action>, RemoteError> {
+        if let Some(error) = &self.failed {
+            return Err(error.clone());
+        }
+        let reply = self
+            .ask(observation)
+            .inspect_err(|error| self.failed = Some(error.clone()))?;
+        parse_reply(&reply)
+    }
+
+    fn ask(&mut self, observation: &Observation) -> Result<String, RemoteError> {
+        if !self.greeted {
+            let color = observation.color;
+            writeln!(
+                self.writer,
+                "bugworld-remote {} {:?}",
+                REMOTE_VERSION, color
+            )?;
+            self.greeted = true;
+        }
+        writeln!(self.writer, "{}", format_observation(observation))?;
+        self.writer.flush()?;
+        let mut reply = String::new();
+        if self.reader.read_line(&mut reply)? == 0 {
+            return Err(RemoteError::Disconnected);
+        }
+        Ok(reply)
+    }
+}
+
+// Ants whose question goes unanswered pass.
+impl Brain for RemoteBrain {
+    fn decide(&mut self, _ant: Ant<'_>, view: &Observation) -> Option<Action> {
+        self.request(view).unwrap_or(None)
+    }
+
+    fn failure(&self) -> Option<String> {
+        self.error().map(|error| format!("{:?}", error))
+    }
+}
+
+impl Drop for RemoteBrain {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+fn direction_name(direction: Direction) -> &'static str {
+    DIRECTIONS
+        .iter()
+        .find(|(_, d)| *d == direction)
+        .map(|(name, _)| *name)
+        .unwrap()
+}
+
+fn format_cell(cell: &CellView) -> String {
+    if cell.wall {
+        return "#".to_string();
+    }
+    let flags: String = [
+        (cell.friend, 'f'),
+        (cell.foe, 'e'),
+        (cell.home, 'h'),
+        (cell.foe_home, 'H'),
+        (cell.foe_marker, 'm'),
+    ]
+    .iter()
+    .filter(|(set, _)| *set)
+    .map(|(_, flag)| *flag)
+    .collect();
+    let flags = if flags.is_empty() { "-" } else { &flags };
+    format!("{}:{}:{}", cell.food, flags, cell.markers)
+}
+
+pub fn format_observation(observation: &Observation) -> String {
+    let mut line = format!(
+        "observe {} {} {} {} {}",
+        observation.round,
+        observation.ant,
+        direction_name(observation.direction),
+        observation.carried_food,
+        format_cell(&observation.here)
+    );
+    for cell in &observation.neighbours {
+        line.push(' ');
+        line.push_str(&format_cell(cell));
+    }
+    line
+}
+
+pub fn parse_reply(line: &str) -> Result<Option<Action>, RemoteError> {
+    let bad = || RemoteError::BadReply(line.trim_end().to_string());
+    let words: Vec<_> = line.split_whitespace().collect();
+    let marker = |word: &str| {
+        word.parse::<Marker>()
+            .ok()
+            .filter(|&marker| marker < MARKERS_PER_COLOR)
+    };
+    let action = match words[..] {
+        ["Pass"] => None,
+        ["Move"] => Some(Action::Move),
+        ["PickUpFood"] => Some(Action::PickUpFood),
+        ["DropFood"] => Some(Action::DropFood),
+        ["Eat"] => Some(Action::Eat),
+        ["Turn", direction] => {
+            let (_, direction) = DIRECTIONS
+                .iter()
+                .find(|(name, _)| *name == direction)
+                .ok_or_else(bad)?;
+            Some(Action::Turn(*direction))
+        }
+        ["Mark", n] => Some(Action::Mark(marker(n).ok_or_else(bad)?)),
+        ["Unmark", n] => Some(Action::Unmark(marker(n).ok_or_else(bad)?)),
+        _ => return Err(bad()),
+    };
+    Ok(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::cell::RefCell;
+    use std::io::Cursor;
+    use std::rc::Rc;
+
+    #[derive(Clone, Default)]
+    struct Sent(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Sent {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn observation() -> Observation {
+        let mut world = World::new(Grid::new(3, 3));
+        let id = world
+            .add_ant(Color::Black, Position { x: 1, y: 1 })
+            .unwrap();
+        world.add_ant(Color::Red, Position { x: 2, y: 1 }).unwrap();
+        world.ant_mut(id).unwrap().rotate(Direction::UpLeft);
+        world.ant_mut(id).unwrap().set_marker(1).unwrap();
+        Observation::of(&world, id, 4).unwrap()
+    }
+
+    #[test]
+    fn replies() {
+        assert_eq!(parse_reply("Pass\n"), Ok(None));
+        assert_eq!(parse_reply("Move"), Ok(Some(Action::Move)));
+        assert_eq!(
+            parse_reply("Turn DownLeft\r\n"),
+            Ok(Some(Action::Turn(Direction::DownLeft)))
+        );
+        assert_eq!(parse_reply("Unmark 5"), Ok(Some(Action::Unmark(5))));
+        for bad in ["", "Mark 6", "Turn Sideways", "Move now"] {
+            assert!(matches!(parse_reply(bad), Err(RemoteError::BadReply(_))));
+        }
+    }
+
+    #[test]
+    fn conversation() {
+        let sent = Sent::default();
+        let mut brain = RemoteBrain::new(Cursor::new("Move\nFly\n"), sent.clone());
+        let observation = observation();
+        assert_eq!(brain.request(&observation), Ok(Some(Action::Move)));
+        assert!(matches!(
+            brain.request(&observation),
+            Err(RemoteError::BadReply(_))
+        ));
+        assert_eq!(brain.request(&observation), Err(RemoteError::Disconnected));
+        assert_eq!(brain.error(), Some(&RemoteError::Disconnected));
+
+        let sent = String::from_utf8(sent.0.take()).unwrap();
+        let lines: Vec<_> = sent.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "bugworld-remote 2 Black");
+        assert_eq!(
+            lines[1],
+            "observe 4 0 UpLeft 0 0:f:2 0:e:0 0:-:0 0:-:0 0:-:0 0:-:0 0:-:0"
+        );
+    }
+}
@@ -0,0 +1,283 @@
+#[cfg(feature = "egui")]
+pub mod gui;
+pub mod svg;
+#[cfg(feature = "tui")]
+pub mod terminal;
+pub mod testing;
+pub mod trails;
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+
+use crate::delta::*;
+use crate::sim::*;
+use crate::world::*;
+
+pub struct NullRenderer;
+
+impl Renderer for NullRenderer {
+    fn render(&mut self, _world: &World) {}
+
+    fn render_diff(&mut self, _world: &World, _delta: &WorldDelta) {}
+}
+
+// Forwards only every `n`th frame to the wrapped renderer. Diffs of the
+// skipped frames are merged into the next forwarded one.
+pub struct EveryNth<R> {
+    renderer: R,
+    n: u32,
+    skipped: u32,
+    pending: WorldDelta,
+}
+
+impl<R: Renderer> EveryNth<R> {
+    pub fn new(renderer: R, n: u32) -> Self {
+        assert!(n > 0);
+        EveryNth {
+            renderer,
+            n,
+            skipped: 0,
+            pending: WorldDelta::new(),
+        }
+    }
+
+    pub fn into_inner(self) -> R {
+        self.renderer
+    }
+}
+
+impl<R: Renderer> Renderer for EveryNth<R> {
+    fn render(&mut self, world: &World) {
+        self.skipped += 1;
+        if self.skipped == self.n {
+            self.skipped = 0;
+            self.pending.clear();
+            self.renderer.render(world);
+        }
+    }
+
+    fn render_diff(&mut self, world: &World, delta: &WorldDelta) {
+        self.skipped += 1;
+        self.pending.merge(delta);
+        if self.skipped == self.n {
+            self.skipped = 0;
+            self.renderer.render_diff(world, &self.pending);
+            self.pending.clear();
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DropPolicy {
+    DropNewest,
+    DropOldest,
+}
+
+struct FrameQueue {
+    frames: VecDeque<World>,
+    dropped: u64,
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<FrameQueue>,
+    ready: Condvar,
+    capacity: usize,
+    policy: DropPolicy,
+}
+
+impl Shared {
+    fn dropped_frames(&self) -> u64 {
+        self.queue.lock().unwrap().dropped
+    }
+}
+
+// Hands frames to another thread through a bounded queue. Rendering never
+// blocks the simulation: when the queue is full a frame is dropped
+// according to the policy and counted.
+pub struct ChannelRenderer {
+    shared: Arc<Shared>,
+}
+
+pub struct FrameReceiver {
+    shared: Arc<Shared>,
+}
+
+pub fn frame_channel(capacity: usize, policy: DropPolicy) -> (ChannelRenderer, FrameReceiver) {
+    assert!(capacity > 0);
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(FrameQueue {
+            frames: VecDeque::with_capacity(capacity),
+            dropped: 0,
+            closed: false,
+        }),
+        ready: Condvar::new(),
+        capacity,
+        policy,
+    });
+    (
+        ChannelRenderer {
+            shared: shared.clone(),
+        },
+        FrameReceiver { shared },
+    )
+}
+
+impl ChannelRenderer {
+    pub fn dropped_frames(&self) -> u64 {
+        self.shared.dropped_frames()
+    }
+}
+
+impl Renderer for ChannelRenderer {
+    fn render(&mut self, world: &World) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.frames.len() == self.shared.capacity {
+            queue.dropped += 1;
+            match self.shared.policy {
+                DropPolicy::DropNewest => return,
+                DropPolicy::DropOldest => {
+                    queue.frames.pop_front();
+                }
+            }
+        }
+        queue.frames.push_back(world.clone());
+        self.shared.ready.notify_one();
+    }
+}
+
+impl Drop for ChannelRenderer {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.ready.notify_all();
+    }
+}
+
+impl FrameReceiver {
+    // Blocks until a frame is available. Returns `None` once the renderer
+    // is gone and the queue is drained.
+    pub fn recv(&self) -> Option<World> {
+        let mut queue = self.shared.queue.lock().unwrap();
+        loop {
+            if let Some(world) = queue.frames.pop_front() {
+                return Some(world);
+            }
+            if queue.closed {
+                return None;
+            }
+            queue = self.shared.ready.wait(queue).unwrap();
+        }
+    }
+
+    pub fn try_recv(&self) -> Option<World> {
+        self.shared.queue.lock().unwrap().frames.pop_front()
+    }
+
+    pub fn dropped_frames(&self) -> u64 {
+        self.shared.dropped_frames()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::thread;
+
+    struct Counter(u32);
+
+    impl Renderer for Counter {
+        fn render(&mut self, _world: &World) {
+            self.0 += 1;
+        }
+    }
+
+    fn world_with_ant_at(x: i32) -> World {
+        let mut world = World::new(Grid::new(10, 1));
+        world.add_ant(Color::Red, Position { x, y: 0 }).unwrap();
+        world
+    }
+
+    fn ant_x(world: &World) -> i32 {
+        world.ant(0).unwrap().position().x
+    }
+
+    #[test]
+    fn every_nth() {
+        let world = World::new(Grid::new(1, 1));
+        let mut renderer = EveryNth::new(Counter(0), 3);
+        for _ in 0..10 {
+            renderer.render(&world);
+        }
+        assert_eq!(renderer.into_inner().0, 3);
+    }
+
+    struct DiffLog(Vec<WorldDelta>);
+
+    impl Renderer for DiffLog {
+        fn render(&mut self, _world: &World) {
+            panic!("expected a diff");
+        }
+
+        fn render_diff(&mut self, _world: &World, delta: &WorldDelta) {
+            self.0.push(delta.clone());
+        }
+    }
+
+    #[test]
+    fn every_nth_merges_diffs() {
+        let world = World::new(Grid::new(5, 1));
+        let mut renderer = EveryNth::new(DiffLog(Vec::new()), 2);
+        for x in 0..5 {
+            let mut delta = WorldDelta::new();
+            delta.mark_cell(Position { x, y: 0 });
+            renderer.render_diff(&world, &delta);
+        }
+        let frames = renderer.into_inner().0;
+        assert_eq!(frames.len(), 2);
+        assert!(frames[0].has_cell(Position { x: 0, y: 0 }));
+        assert!(frames[0].has_cell(Position { x: 1, y: 0 }));
+        assert_eq!(frames[1].cells().count(), 2);
+        assert!(!frames[1].has_cell(Position { x: 1, y: 0 }));
+    }
+
+    #[test]
+    fn drop_newest() {
+        let (mut renderer, receiver) = frame_channel(2, DropPolicy::DropNewest);
+        for x in 0..5 {
+            renderer.render(&world_with_ant_at(x));
+        }
+        assert_eq!(renderer.dropped_frames(), 3);
+        assert_eq!(receiver.try_recv().map(|w| ant_x(&w)), Some(0));
+        assert_eq!(receiver.try_recv().map(|w| ant_x(&w)), Some(1));
+        assert!(receiver.try_recv().is_none());
+    }
+
+    #[test]
+    fn drop_oldest() {
+        let (mut renderer, receiver) = frame_channel(2, DropPolicy::DropOldest);
+        for x in 0..5 {
+            renderer.render(&world_with_ant_at(x));
+        }
+        assert_eq!(receiver.dropped_frames(), 3);
+        assert_eq!(receiver.try_recv().map(|w| ant_x(&w)), Some(3));
+        assert_eq!(receiver.try_recv().map(|w| ant_x(&w)), Some(4));
+    }
+
+    #[test]
+    fn receive_on_another_thread() {
+        let (mut renderer, receiver) = frame_channel(16, DropPolicy::DropNewest);
+        let consumer = thread::spawn(move || {
+            let mut frames = Vec::new();
+            while let Some(world) = receiver.recv() {
+                frames.push(ant_x(&world));
+            }
+            frames
+        });
+        for x in 0..5 {
+            renderer.render(&world_with_ant_at(x));
+        }
+        drop(renderer);
+        assert_eq!(consumer.join().unwrap(), vec![0, 1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,405 @@
+use std::sync::{Arc, Mutex};
+
+use egui::{Color32, Pos2, Response, Sense, Shape, Stroke, Ui, Vec2};
+
+use crate::delta::*;
+use crate::renderers::trails::*;
+use crate::sim::*;
+use crate::world::*;
+
+// Cell widths, in points, that zooming stays between.
+const MIN_CELL_SIZE: f32 = 2.0;
+const MAX_CELL_SIZE: f32 = 200.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    pub background: Color32,
+    pub wall: Color32,
+    pub ground: Color32,
+    pub food: Color32,
+    // Ants, and faded, anthills and markers; by team index.
+    pub teams: [Color32; MAX_TEAMS],
+    // Trails behind ants carrying food, see `CarryTrails`. `None` leaves
+    // them out.
+    pub trail: Option<Color32>,
+}
+
+// The colors of the SVG renderer's default scheme.
+impl Default for Palette {
+    fn default() -> Self {
+        Palette {
+            background: Color32::WHITE,
+            wall: Color32::from_rgb(0x55, 0x55, 0x55),
+            ground: Color32::from_rgb(0xf4, 0xec, 0xd8),
+            food: Color32::from_rgb(0xe6, 0xb8, 0x00),
+            teams: [
+                Color32::from_rgb(0x22, 0x22, 0x22),
+                Color32::from_rgb(0xd6, 0x27, 0x28),
+                Color32::from_rgb(0x2c, 0xa0, 0x2c),
+                Color32::from_rgb(0x1f, 0x77, 0xb4),
+                Color32::from_rgb(0xff, 0x7f, 0x0e),
+                Color32::from_rgb(0x94, 0x67, 0xbd),
+                Color32::from_rgb(0x8c, 0x56, 0x4b),
+                Color32::from_rgb(0xe3, 0x77, 0xc2),
+            ],
+            trail: None,
+        }
+    }
+}
+
+// What one cell shows, copied out of the world so the view never has to
+// lock the simulation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+struct Sprite {
+    wall: bool,
+    food: u32,
+    anthill: Option<Color>,
+    markers: Markers,
+    // Color, facing and whether it carries food.
+    ant: Option<(Color, Direction, bool)>,
+}
+
+impl Sprite {
+    fn new(world: WorldSnapshot<'_>, cell: &Cell) -> Self {
+        Sprite {
+            wall: *cell == Cell::Wall,
+            food: cell.food(),
+            anthill: cell.anthill(),
+            markers: cell.markers(),
+            ant: (cell.ant())
+                .and_then(|id| world.ant(id))
+                .map(|ant| (ant.color(), ant.direction(), ant.carries_food())),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Scene {
+    width: usize,
+    height: usize,
+    sprites: Vec<Sprite>,
+    trails: CarryTrails,
+    frames: u64,
+}
+
+impl Scene {
+    fn redraw(&mut self, world: WorldSnapshot<'_>) {
+        self.width = world.width();
+        self.height = world.height();
+        self.sprites.clear();
+        self.sprites
+            .extend(world.cells().map(|(_, cell)| Sprite::new(world, cell)));
+    }
+
+    fn redraw_cell(&mut self, world: WorldSnapshot<'_>, position: Position) {
+        if let Some(cell) = world.cell_at(position) {
+            let index = position.y as usize * self.width + position.x as usize;
+            self.sprites[index] = Sprite::new(world, cell);
+        }
+    }
+}
+
+// Hands frames to a `WorldView` that draws them in an egui `Ui`, on the
+// same thread or another. Diffs update only the cells they touch. In an
+// app the simulation usually runs on a thread of its own:
+//
+//   let (renderer, mut view) = gui_renderer();
+//   let renderer = renderer.with_context(ctx.clone());
+//   thread::spawn(move || {
+//       let mut simulator = new_simulator().with_renderer(Box::new(renderer));
+//       simulator.run_for(1000);
+//   });
+//   ...
+//   egui::CentralPanel::default().show(ctx, |ui| view.show(ui));
+pub struct GuiRenderer {
+    scene: Arc<Mutex<Scene>>,
+    context: Option<egui::Context>,
+}
+
+pub fn gui_renderer() -> (GuiRenderer, WorldView) {
+    let scene = Arc::new(Mutex::new(Scene {
+        trails: CarryTrails::new(8),
+        ..Scene::default()
+    }));
+    (
+        GuiRenderer {
+            scene: scene.clone(),
+            context: None,
+        },
+        WorldView {
+            scene,
+            palette: Palette::default(),
+            cell_size: 20.0,
+            offset: Vec2::ZERO,
+        },
+    )
+}
+
+impl GuiRenderer {
+    // Asks `context` to repaint after every frame, so the view keeps up
+    // with a simulation running on another thread.
+    pub fn with_context(mut self, context: egui::Context) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    fn repaint(&self) {
+        if let Some(context) = &self.context {
+            context.request_repaint();
+        }
+    }
+}
+
+impl Renderer for GuiRenderer {
+    fn render(&mut self, world: &World) {
+        let mut scene = self.scene.lock().unwrap();
+        scene.redraw(world.snapshot());
+        scene.trails.update(world.snapshot());
+        scene.frames += 1;
+        drop(scene);
+        self.repaint();
+    }
+
+    fn render_diff(&mut self, world: &World, delta: &WorldDelta) {
+        let mut scene = self.scene.lock().unwrap();
+        let world = world.snapshot();
+        if (scene.width, scene.height) != (world.width(), world.height()) {
+            scene.redraw(world);
+        } else {
+            for position in delta.cells() {
+                scene.redraw_cell(world, position);
+            }
+            // Turning ants touch no cell.
+            for ant in delta.ants().filter_map(|id| world.ant(id)) {
+                scene.redraw_cell(world, ant.position());
+            }
+        }
+        scene.trails.update(world);
+        scene.frames += 1;
+        drop(scene);
+        self.repaint();
+    }
+}
+
+// A widget showing the latest frame, laid out as `draw_svg` does. Drag to
+// pan; scroll or pinch to zoom around the pointer.
+pub struct WorldView {
+    scene: Arc<Mutex<Scene>>,
+    palette: Palette,
+    // The width of a hex, in points.
+    cell_size: f32,
+    // Where the top left corner of the grid is, from that of the widget.
+    offset: Vec2,
+}
+
+impl WorldView {
+    pub fn with_palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    // Frames rendered so far.
+    pub fn frames(&self) -> u64 {
+        self.scene.lock().unwrap().frames
+    }
+
+    pub fn cell_size(&self) -> f32 {
+        self.cell_size
+    }
+
+    pub fn reset_view(&mut self) {
+        self.cell_size = 20.0;
+        self.offset = Vec2::ZERO;
+    }
+
+    // Takes up all the room `ui` has left.
+    pub fn show(&mut self, ui: &mut Ui) -> Response {
+        let (response, painter) = ui.allocate_painter(ui.available_size(), Sense::drag());
+        let rect = response.rect;
+        self.offset += response.drag_delta();
+        if let Some(pointer) = response.hover_pos() {
+            let (zoom, scroll) =
+                ui.input(|input| (input.zoom_delta(), input.smooth_scroll_delta.y));
+            let factor = zoom * (scroll / 200.0).exp();
+            self.zoom(factor, pointer - rect.min);
+        }
+
+        painter.rect_filled(rect, 0.0, self.palette.background);
+        let scene = self.scene.lock().unwrap();
+        let origin = rect.min + self.offset;
+        let radius = self.cell_size / 3f32.sqrt();
+        let clip = painter.clip_rect().expand(radius);
+        for (index, sprite) in scene.sprites.iter().enumerate() {
+            let (x, y) = (index % scene.width, index / scene.width);
+            let center = origin + cell_center(x, y, self.cell_size);
+            if clip.contains(center) {
+                painter.extend(self.sprite_shapes(sprite, center, radius));
+            }
+        }
+        if let Some(color) = self.palette.trail {
+            let center = |position: Position| {
+                origin + cell_center(position.x as usize, position.y as usize, self.cell_size)
+            };
+            for (from, to, opacity) in scene.trails.segments() {
+                let stroke = Stroke::new(radius * 0.25, color.gamma_multiply(opacity as f32));
+                painter.line_segment([center(from), center(to)], stroke);
+            }
+        }
+        response
+    }
+
+    // Scales by `factor` keeping the point `anchor` from the widget's
+    // corner in place.
+    fn zoom(&mut self, factor: f32, anchor: Vec2) {
+        let cell_size = (self.cell_size * factor).clamp(MIN_CELL_SIZE, MAX_CELL_SIZE);
+        let factor = cell_size / self.cell_size;
+        self.offset = anchor - (anchor - self.offset) * factor;
+        self.cell_size = cell_size;
+    }
+
+    fn sprite_shapes(&self, sprite: &Sprite, center: Pos2, radius: f32) -> Vec<Shape> {
+        let palette = &self.palette;
+        let hex: Vec<_> = (0..6)
+            .map(|i| center + radius * angle(60.0 * i as f32 - 90.0))
+            .collect();
+        let ground = if sprite.wall {
+            palette.wall
+        } else {
+            palette.ground
+        };
+        let mut shapes = vec![Shape::convex_polygon(hex.clone(), ground, Stroke::NONE)];
+        if let Some(color) = sprite.anthill {
+            let fill = palette.teams[color.index()].gamma_multiply(0.35);
+            shapes.push(Shape::convex_polygon(hex, fill, Stroke::NONE));
+        }
+        // A dot per team with any marker, around the edge.
+        for color in Color::all().filter(|&color| sprite.markers.has_any(color)) {
+            let position = center + radius * 0.7 * angle(45.0 * color.index() as f32 - 90.0);
+            let fill = palette.teams[color.index()].gamma_multiply(0.6);
+            shapes.push(Shape::circle_filled(position, radius * 0.12, fill));
+        }
+        if sprite.food > 0 {
+            let size = sprite.food.min(9) as f32 / 9.0;
+            shapes.push(Shape::circle_filled(
+                center,
+                radius * (0.2 + 0.4 * size),
+                palette.food,
+            ));
+        }
+        if let Some((color, direction, carries_food)) = sprite.ant {
+            let facing = 60.0 * direction as u32 as f32;
+            let points = [(0.6, 0.0), (-0.4, -0.45), (-0.4, 0.45)]
+                .map(|(x, y)| center + radius * rotate(Vec2::new(x, y), facing))
+                .to_vec();
+            let outline = if carries_food {
+                Stroke::new(radius * 0.15, palette.food)
+            } else {
+                Stroke::NONE
+            };
+            shapes.push(Shape::convex_polygon(
+                points,
+                palette.teams[color.index()],
+                outline,
+            ));
+        }
+        shapes
+    }
+}
+
+// Pointy-topped hexes with odd rows shifted right by half a cell.
+fn cell_center(x: usize, y: usize, cell_size: f32) -> Vec2 {
+    let radius = cell_size / 3f32.sqrt();
+    Vec2::new(
+        cell_size * (x as f32 + 0.5 + 0.5 * (y % 2) as f32),
+        radius * (1.5 * y as f32 + 1.0),
+    )
+}
+
+// A unit vector, clockwise from the right as the screen's y points down.
+fn angle(degrees: f32) -> Vec2 {
+    Vec2::angled(degrees.to_radians())
+}
+
+fn rotate(v: Vec2, degrees: f32) -> Vec2 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    Vec2::new(v.x * cos - v.y * sin, v.x * sin + v.y * cos)
+}
+
+// The area `WorldView` needs to show a `width` by `height` grid in full.
+pub fn grid_size(width: usize, height: usize, cell_size: f32) -> Vec2 {
+    let radius = cell_size / 3f32.sqrt();
+    Vec2::new(
+        cell_size * (width as f32 + 0.5),
+        radius * (1.5 * height as f32 + 0.5),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world_with_ant(ant: Position) -> World {
+        let mut grid = Grid::new(3, 2);
+        *grid.cell_at_mut(Position { x: 2, y: 1 }).unwrap() = Cell::Wall;
+        let mut world = World::new(grid);
+        world.add_ant(Color::Red, ant).unwrap();
+        world
+    }
+
+    #[test]
+    fn diffs_update_the_scene() {
+        let (mut renderer, view) = gui_renderer();
+        let mut world = world_with_ant(Position { x: 0, y: 0 });
+        renderer.render(&world);
+        assert_eq!(view.frames(), 1);
+
+        world.ant_mut(0).unwrap().rotate(Direction::Left);
+        let mut delta = WorldDelta::new();
+        delta.mark_ant(0);
+        renderer.render_diff(&world, &delta);
+        {
+            let scene = view.scene.lock().unwrap();
+            assert_eq!(scene.frames, 2);
+            assert_eq!(
+                scene.sprites[0].ant,
+                Some((Color::Red, Direction::Left, false))
+            );
+            assert!(scene.sprites[5].wall);
+        }
+
+        // Only what the delta touched is redrawn.
+        let moved = world_with_ant(Position { x: 1, y: 0 });
+        let mut delta = WorldDelta::new();
+        delta.mark_cell(Position { x: 1, y: 0 });
+        renderer.render_diff(&moved, &delta);
+        let scene = view.scene.lock().unwrap();
+        assert!(scene.sprites[0].ant.is_some());
+        assert!(scene.sprites[1].ant.is_some());
+    }
+
+    #[test]
+    fn paints_and_zooms() {
+        let (mut renderer, mut view) = gui_renderer();
+        renderer.render(&world_with_ant(Position { x: 0, y: 0 }));
+        let context = egui::Context::default();
+        let paint = |view: &mut WorldView| {
+            let output = context.run(egui::RawInput::default(), |context| {
+                egui::CentralPanel::default().show(context, |ui| {
+                    view.show(ui);
+                });
+            });
+            output.shapes.len()
+        };
+        // The background, five hexes and a wall, and the ant.
+        assert!(paint(&mut view) >= 7);
+
+        view.zoom(2.0, Vec2::new(10.0, 10.0));
+        assert_eq!(view.cell_size(), 40.0);
+        assert_eq!(view.offset, Vec2::new(-10.0, -10.0));
+        view.zoom(1000.0, Vec2::ZERO);
+        assert_eq!(view.cell_size(), MAX_CELL_SIZE);
+        view.reset_view();
+        assert_eq!(view.cell_size(), 20.0);
+        assert_eq!(grid_size(3, 2, 20.0).x, 70.0);
+    }
+}
@@ -0,0 +1,390 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use crate::renderers::trails::*;
+use crate::sim::*;
+use crate::world::*;
+
+// SVG colors, any form SVG accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColorScheme {
+    pub background: String,
+    pub wall: String,
+    pub ground: String,
+    pub food: String,
+    // Ants and, faded, anthills; by team index.
+    pub teams: [String; MAX_TEAMS],
+    // Trails behind ants carrying food, see `CarryTrails`. `None` leaves
+    // them out.
+    pub trail: Option<String>,
+}
+
+impl Default for ColorScheme {
+    fn default() -> Self {
+        let teams = [
+            "#222222", "#d62728", "#2ca02c", "#1f77b4", "#ff7f0e", "#9467bd", "#8c564b", "#e377c2",
+        ];
+        ColorScheme {
+            background: "#ffffff".to_string(),
+            wall: "#555555".to_string(),
+            ground: "#f4ecd8".to_string(),
+            food: "#e6b800".to_string(),
+            teams: teams.map(str::to_string),
+            trail: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Svg,
+    #[cfg(feature = "png")]
+    Png,
+}
+
+impl ImageFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Svg => "svg",
+            #[cfg(feature = "png")]
+            ImageFormat::Png => "png",
+        }
+    }
+}
+
+// Draws the world as pointy-topped hexes, odd rows shifted right by half a
+// cell: food as a disc that grows with the pile (up to 9), ants as a
+// triangle pointing the way they face, outlined in the food color while
+// they carry some. `cell_size` is the width of a hex in pixels.
+pub fn draw_svg(world: WorldSnapshot<'_>, cell_size: f64, scheme: &ColorScheme) -> String {
+    draw_svg_with_trails(world, &CarryTrails::default(), cell_size, scheme)
+}
+
+// `draw_svg` with `trails` drawn over the world, if the scheme has a color
+// for them.
+pub fn draw_svg_with_trails(
+    world: WorldSnapshot<'_>,
+    trails: &CarryTrails,
+    cell_size: f64,
+    scheme: &ColorScheme,
+) -> String {
+    let radius = cell_size / 3f64.sqrt();
+    let width = cell_size * (world.width() as f64 + 0.5);
+    let height = radius * (1.5 * world.height() as f64 + 0.5);
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{:.0}" height="{:.0}" viewBox="0 0 {:.2} {:.2}">"#,
+        width.ceil(),
+        height.ceil(),
+        width,
+        height
+    );
+    let _ = writeln!(
+        out,
+        r#"<rect width="100%" height="100%" fill="{}"/>"#,
+        scheme.background
+    );
+    for (position, cell) in world.cells() {
+        let (x, y) = center(position, cell_size);
+        let fill = match cell {
+            Cell::Wall => &scheme.wall,
+            _ => &scheme.ground,
+        };
+        let corners: Vec<_> = (0..6)
+            .map(|i| {
+                let angle = (60.0 * i as f64 - 90.0).to_radians();
+                format!(
+                    "{:.2},{:.2}",
+                    x + radius * angle.cos(),
+                    y + radius * angle.sin()
+                )
+            })
+            .collect();
+        let _ = writeln!(
+            out,
+            r#"<polygon points="{}" fill="{}"/>"#,
+            corners.join(" "),
+            fill
+        );
+        if let Some(color) = cell.anthill() {
+            let _ = writeln!(
+                out,
+                r#"<polygon points="{}" fill="{}" fill-opacity="0.35"/>"#,
+                corners.join(" "),
+                scheme.teams[color.index()]
+            );
+        }
+        if cell.has_food() {
+            let size = cell.food().min(9) as f64 / 9.0;
+            let _ = writeln!(
+                out,
+                r#"<circle cx="{:.2}" cy="{:.2}" r="{:.2}" fill="{}"/>"#,
+                x,
+                y,
+                radius * (0.2 + 0.4 * size),
+                scheme.food
+            );
+        }
+        if let Some(ant) = cell.ant().and_then(|id| world.ant(id)) {
+            let outline = if ant.carries_food() {
+                format!(
+                    r#" stroke="{}" stroke-width="{:.2}""#,
+                    scheme.food,
+                    radius * 0.15
+                )
+            } else {
+                String::new()
+            };
+            let _ = writeln!(
+                out,
+                r#"<polygon points="{:.2},0 {:.2},{:.2} {:.2},{:.2}" transform="translate({:.2} {:.2}) rotate({})" fill="{}"{}/>"#,
+                radius * 0.6,
+                -radius * 0.4,
+                -radius * 0.45,
+                -radius * 0.4,
+                radius * 0.45,
+                x,
+                y,
+                60 * ant.direction() as u32,
+                scheme.teams[ant.color().index()],
+                outline
+            );
+        }
+    }
+    if let Some(color) = &scheme.trail {
+        for (from, to, opacity) in trails.segments() {
+            let (x1, y1) = center(from, cell_size);
+            let (x2, y2) = center(to, cell_size);
+            let _ = writeln!(
+                out,
+                r#"<line x1="{:.2}" y1="{:.2}" x2="{:.2}" y2="{:.2}" stroke="{}" stroke-width="{:.2}" stroke-opacity="{:.2}" stroke-linecap="round"/>"#,
+                x1,
+                y1,
+                x2,
+                y2,
+                color,
+                radius * 0.25,
+                opacity
+            );
+        }
+    }
+    out.push_str("</svg>\n");
+    out
+}
+
+fn center(position: Position, cell_size: f64) -> (f64, f64) {
+    let radius = cell_size / 3f64.sqrt();
+    (
+        cell_size * (position.x as f64 + 0.5 + 0.5 * (position.y % 2) as f64),
+        radius * (1.5 * position.y as f64 + 1.0),
+    )
+}
+
+// Renders an SVG from `draw_svg` at its own size.
+#[cfg(feature = "png")]
+pub fn rasterize(svg: &str) -> Result<Vec<u8>, String> {
+    use resvg::{tiny_skia, usvg};
+
+    let tree = usvg::Tree::from_str(svg, &usvg::Options::default()).map_err(|e| e.to_string())?;
+    let size = tree.size().to_int_size();
+    let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height()).ok_or("empty image")?;
+    resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+    pixmap.encode_png().map_err(|e| e.to_string())
+}
+
+// Writes every frame it is given to `frame-00000.svg`, `frame-00001.svg`
+// and so on in a directory, for figures or to stitch into an animation.
+// Wrap it in `EveryNth` to keep only every nth step.
+pub struct ImageRenderer {
+    dir: PathBuf,
+    format: ImageFormat,
+    cell_size: f64,
+    scheme: ColorScheme,
+    trails: CarryTrails,
+    frames: u32,
+    error: Option<io::Error>,
+}
+
+impl ImageRenderer {
+    // The directory is created on the first frame if need be.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        ImageRenderer {
+            dir: dir.into(),
+            format: ImageFormat::Svg,
+            cell_size: 20.0,
+            scheme: ColorScheme::default(),
+            trails: CarryTrails::new(8),
+            frames: 0,
+            error: None,
+        }
+    }
+
+    pub fn with_format(mut self, format: ImageFormat) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn with_cell_size(mut self, cell_size: f64) -> Self {
+        assert!(cell_size > 0.0);
+        self.cell_size = cell_size;
+        self
+    }
+
+    pub fn with_color_scheme(mut self, scheme: ColorScheme) -> Self {
+        self.scheme = scheme;
+        self
+    }
+
+    // How many frames trails last, when the scheme draws them.
+    pub fn with_trail_length(mut self, length: usize) -> Self {
+        self.trails = CarryTrails::new(length);
+        self
+    }
+
+    pub fn frames_written(&self) -> u32 {
+        self.frames
+    }
+
+    // The error that stopped the renderer, if one did. Renderers cannot
+    // fail the step, so after an error later frames are skipped.
+    pub fn error(&self) -> Option<&io::Error> {
+        self.error.as_ref()
+    }
+
+    fn write_frame(&self, world: &World) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let svg =
+            draw_svg_with_trails(world.snapshot(), &self.trails, self.cell_size, &self.scheme);
+        let bytes = match self.format {
+            ImageFormat::Svg => svg.into_bytes(),
+            #[cfg(feature = "png")]
+            ImageFormat::Png => rasterize(&svg).map_err(io::Error::other)?,
+        };
+        let name = format!("frame-{:05}.{}", self.frames, self.format.extension());
+        fs::write(self.dir.join(name), bytes)
+    }
+}
+
+impl Renderer for ImageRenderer {
+    fn render(&mut self, world: &World) {
+        if self.error.is_some() {
+            return;
+        }
+        if self.scheme.trail.is_some() {
+            self.trails.update(world.snapshot());
+        }
+        match self.write_frame(world) {
+            Ok(()) => self.frames += 1,
+            Err(error) => self.error = Some(error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::renderers::EveryNth;
+
+    fn world() -> World {
+        let mut grid = Grid::new(3, 2);
+        *grid.cell_at_mut(Position { x: 0, y: 0 }).unwrap() = Cell::Wall;
+        *grid.cell_at_mut(Position { x: 2, y: 1 }).unwrap() = Cell::new_anthill(Color::Red);
+        let mut world = World::new(grid);
+        world.add_ant(Color::Red, Position { x: 2, y: 1 }).unwrap();
+        world
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bugworld-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn draws_every_cell() {
+        let svg = draw_svg(world().snapshot(), 20.0, &ColorScheme::default());
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(r#"width="70" height="41""#));
+        // Six hexes, an anthill overlay and an ant.
+        assert_eq!(svg.matches("<polygon").count(), 8);
+        assert_eq!(svg.matches(r##"fill="#555555""##).count(), 1);
+        assert!(svg.contains(r##"rotate(0)" fill="#d62728"/>"##));
+    }
+
+    #[test]
+    fn turns_ants_and_outlines_carriers() {
+        let mut world = world();
+        world.grow_food(Position { x: 1, y: 1 }, 1);
+        let porter = world
+            .add_ant(Color::Black, Position { x: 1, y: 1 })
+            .unwrap();
+        world.ant_mut(porter).unwrap().pickup_food().unwrap();
+        world.ant_mut(porter).unwrap().rotate(Direction::Left);
+        let scheme = ColorScheme::default();
+        let svg = draw_svg(world.snapshot(), 20.0, &scheme);
+        let black = format!(
+            r#"rotate(180)" fill="{}" stroke="{}""#,
+            scheme.teams[0], scheme.food
+        );
+        assert!(svg.contains(&black));
+        let red = format!(r#"rotate(0)" fill="{}"/>"#, scheme.teams[1]);
+        assert!(svg.contains(&red));
+    }
+
+    #[test]
+    fn draws_trails_when_the_scheme_has_them() {
+        let mut world = World::new(Grid::new(5, 1));
+        let porter = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world.grow_food(Position { x: 0, y: 0 }, 1);
+        world.ant_mut(porter).unwrap().pickup_food().unwrap();
+        let mut trails = CarryTrails::new(4);
+        for _ in 0..3 {
+            trails.update(world.snapshot());
+            world.ant_mut(porter).unwrap().move_forward().unwrap();
+        }
+        trails.update(world.snapshot());
+        let scheme = ColorScheme {
+            trail: Some("#00aa00".to_string()),
+            ..ColorScheme::default()
+        };
+        let svg = draw_svg_with_trails(world.snapshot(), &trails, 20.0, &scheme);
+        assert_eq!(svg.matches(r##"<line"##).count(), 3);
+        assert!(svg.contains(r##"stroke="#00aa00""##));
+        let plain = draw_svg_with_trails(world.snapshot(), &trails, 20.0, &ColorScheme::default());
+        assert_eq!(
+            plain,
+            draw_svg(world.snapshot(), 20.0, &ColorScheme::default())
+        );
+    }
+
+    #[test]
+    fn writes_every_nth_frame() {
+        let dir = temp_dir("svg-frames");
+        let mut renderer = EveryNth::new(ImageRenderer::new(&dir), 2);
+        let world = world();
+        for _ in 0..5 {
+            renderer.render(&world);
+        }
+        let renderer = renderer.into_inner();
+        assert!(renderer.error().is_none());
+        assert_eq!(renderer.frames_written(), 2);
+        let first = fs::read_to_string(dir.join("frame-00000.svg")).unwrap();
+        assert_eq!(
+            first,
+            draw_svg(world.snapshot(), 20.0, &ColorScheme::default())
+        );
+        assert!(dir.join("frame-00001.svg").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn rasterizes() {
+        let svg = draw_svg(world().snapshot(), 20.0, &ColorScheme::default());
+        let png = rasterize(&svg).unwrap();
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+    }
+}
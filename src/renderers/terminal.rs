@@ -0,0 +1,193 @@
+use std::fmt::Write as _;
+use std::io::{self, Stdout, Write};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::sim::*;
+use crate::world::*;
+
+// Ant colors by team, as ANSI foreground codes; anthills use the matching
+// background. Black ants show blue, as black would vanish on most
+// terminals.
+const TEAM_COLORS: [u8; MAX_TEAMS] = [94, 91, 92, 93, 95, 96, 33, 35];
+
+// Draws the world in an ANSI terminal, laid out as in the map format: walls
+// are `#`, food is its amount (`+` past 9) and ants are an arrow for the
+// way they face, underlined while they carry food. Every frame redraws the whole
+// grid over the one before.
+pub struct TerminalRenderer<W: Write> {
+    out: W,
+    frame_time: Option<Duration>,
+    last_frame: Option<Instant>,
+}
+
+impl TerminalRenderer<Stdout> {
+    pub fn stdout() -> Self {
+        TerminalRenderer::new(io::stdout())
+    }
+}
+
+impl<W: Write> TerminalRenderer<W> {
+    pub fn new(out: W) -> Self {
+        TerminalRenderer {
+            out,
+            frame_time: None,
+            last_frame: None,
+        }
+    }
+
+    // Holds the simulation back so frames come at most `fps` times a
+    // second, slow enough to watch.
+    pub fn with_frame_rate(mut self, fps: u32) -> Self {
+        assert!(fps > 0);
+        self.frame_time = Some(Duration::from_secs(1) / fps);
+        self
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn wait_for_frame(&mut self) {
+        if let (Some(frame_time), Some(last_frame)) = (self.frame_time, self.last_frame) {
+            if let Some(left) = frame_time.checked_sub(last_frame.elapsed()) {
+                thread::sleep(left);
+            }
+        }
+        self.last_frame = Some(Instant::now());
+    }
+}
+
+impl<W: Write> Renderer for TerminalRenderer<W> {
+    // Write errors are dropped; a closed terminal should not stop the
+    // match.
+    fn render(&mut self, world: &World) {
+        // The first frame clears the screen; later ones draw over it.
+        let mut frame = String::from(if self.last_frame.is_none() {
+            "\x1b[2J\x1b[H"
+        } else {
+            "\x1b[H"
+        });
+        self.wait_for_frame();
+        draw(world.snapshot(), &mut frame);
+        let _ = self.out.write_all(frame.as_bytes());
+        let _ = self.out.flush();
+    }
+}
+
+fn direction_glyph(direction: Direction) -> char {
+    match direction {
+        Direction::Right => '→',
+        Direction::DownRight => '↘',
+        Direction::DownLeft => '↙',
+        Direction::Left => '←',
+        Direction::UpLeft => '↖',
+        Direction::UpRight => '↗',
+    }
+}
+
+fn draw(world: WorldSnapshot<'_>, out: &mut String) {
+    for (y, row) in world.rows().enumerate() {
+        if y % 2 == 1 {
+            out.push(' ');
+        }
+        for (x, cell) in row.iter().enumerate() {
+            if x > 0 {
+                out.push(' ');
+            }
+            draw_cell(world, cell, out);
+        }
+        out.push_str("\x1b[K\n");
+    }
+}
+
+fn draw_cell(world: WorldSnapshot<'_>, cell: &Cell, out: &mut String) {
+    if let Cell::Wall = cell {
+        out.push('#');
+        return;
+    }
+    let mut codes = Vec::new();
+    if let Some(color) = cell.anthill() {
+        codes.push(TEAM_COLORS[color.index()] + 10);
+    }
+    let glyph = match cell.ant().and_then(|id| world.ant(id)) {
+        Some(ant) => {
+            codes.push(TEAM_COLORS[ant.color().index()]);
+            // Underlined rather than bold, which many terminals only show
+            // as a brighter color.
+            if ant.carries_food() {
+                codes.push(4);
+            }
+            direction_glyph(ant.direction())
+        }
+        None if cell.food() > 9 => '+',
+        None if cell.has_food() => char::from_digit(cell.food(), 10).unwrap(),
+        None => '.',
+    };
+    if codes.is_empty() {
+        out.push(glyph);
+        return;
+    }
+    let codes: Vec<_> = codes.iter().map(u8::to_string).collect();
+    let _ = write!(out, "\x1b[{}m{}\x1b[0m", codes.join(";"), glyph);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_the_grid() {
+        let mut grid = Grid::new(3, 2);
+        *grid.cell_at_mut(Position { x: 0, y: 0 }).unwrap() = Cell::Wall;
+        *grid.cell_at_mut(Position { x: 2, y: 1 }).unwrap() = Cell::new_anthill(Color::Red);
+        *grid.cell_at_mut(Position { x: 1, y: 0 }).unwrap() = Cell::FreeCell {
+            ant_id: None,
+            food: 12,
+            markers: Markers::default(),
+            anthill: None,
+        };
+        let mut world = World::new(grid);
+        world.add_ant(Color::Red, Position { x: 2, y: 1 }).unwrap();
+        world
+            .add_ant(Color::Black, Position { x: 0, y: 1 })
+            .unwrap();
+
+        let mut renderer = TerminalRenderer::new(Vec::new());
+        renderer.render(&world);
+        renderer.render(&world);
+        let out = String::from_utf8(renderer.into_inner()).unwrap();
+        let frame = "# + .\x1b[K\n \x1b[94m→\x1b[0m . \x1b[101;91m→\x1b[0m\x1b[K\n";
+        assert_eq!(out, format!("\x1b[2J\x1b[H{}\x1b[H{}", frame, frame));
+    }
+
+    #[test]
+    fn marks_facing_and_carrying() {
+        let mut world = World::new(Grid::new(2, 1));
+        world.grow_food(Position { x: 0, y: 0 }, 1);
+        let porter = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let idler = world
+            .add_ant(Color::Black, Position { x: 1, y: 0 })
+            .unwrap();
+        world.ant_mut(porter).unwrap().pickup_food().unwrap();
+        world.ant_mut(porter).unwrap().rotate(Direction::UpLeft);
+        world.ant_mut(idler).unwrap().rotate(Direction::DownLeft);
+
+        let mut frame = String::new();
+        draw(world.snapshot(), &mut frame);
+        assert_eq!(frame, "\x1b[91;4m↖\x1b[0m \x1b[94m↙\x1b[0m\x1b[K\n");
+        let glyphs: String = Direction::all().map(direction_glyph).collect();
+        assert_eq!(glyphs, "→↘↙←↖↗");
+    }
+
+    #[test]
+    fn frame_rate() {
+        let world = World::new(Grid::new(1, 1));
+        let mut renderer = TerminalRenderer::new(io::sink()).with_frame_rate(100);
+        let start = Instant::now();
+        for _ in 0..3 {
+            renderer.render(&world);
+        }
+        assert!(start.elapsed() >= Duration::from_millis(20));
+    }
+}
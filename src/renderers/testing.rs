@@ -0,0 +1,211 @@
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::asm::*;
+use crate::delta::*;
+use crate::mapgen::*;
+use crate::sim::*;
+use crate::testing::*;
+use crate::world::*;
+
+// Helpers for testing renderers without a display.
+
+// One call the simulator made: the world as it was rendered and the diff
+// that came with it, if any.
+#[derive(Clone)]
+pub struct Frame {
+    pub world: World,
+    pub delta: Option<WorldDelta>,
+}
+
+// Remembers every frame it is given. Clones share the recording, so keep
+// one to look at after handing the other to a simulator.
+#[derive(Clone, Default)]
+pub struct RecordingRenderer {
+    frames: Rc<RefCell<Vec<Frame>>>,
+}
+
+impl RecordingRenderer {
+    pub fn new() -> Self {
+        RecordingRenderer::default()
+    }
+
+    pub fn frames(&self) -> Ref<'_, [Frame]> {
+        Ref::map(self.frames.borrow(), Vec::as_slice)
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.borrow().len()
+    }
+
+    pub fn last_world(&self) -> Option<World> {
+        self.frames.borrow().last().map(|frame| frame.world.clone())
+    }
+
+    pub fn clear(&self) {
+        self.frames.borrow_mut().clear();
+    }
+
+    #[track_caller]
+    pub fn assert_frame_count(&self, count: usize) {
+        assert_eq!(self.frame_count(), count, "wrong number of frames");
+    }
+
+    #[track_caller]
+    pub fn assert_last_world(&self, world: &World) {
+        let last = self.last_world().expect("nothing was rendered");
+        assert_world_eq(&last, world);
+    }
+
+    // Checks that every diff names all the cells and ants that changed
+    // since the frame before it, which is what renderers that only redraw
+    // the diff rely on.
+    #[track_caller]
+    pub fn assert_diffs_complete(&self) {
+        for (index, pair) in self.frames().windows(2).enumerate() {
+            let Some(delta) = &pair[1].delta else {
+                continue;
+            };
+            if let Some(missing) = missing_from_diff(&pair[0].world, &pair[1].world, delta) {
+                panic!("diff of frame {} misses {}", index + 1, missing);
+            }
+        }
+    }
+}
+
+impl Renderer for RecordingRenderer {
+    fn render(&mut self, world: &World) {
+        self.frames.borrow_mut().push(Frame {
+            world: world.clone(),
+            delta: None,
+        });
+    }
+
+    fn render_diff(&mut self, world: &World, delta: &WorldDelta) {
+        self.frames.borrow_mut().push(Frame {
+            world: world.clone(),
+            delta: Some(delta.clone()),
+        });
+    }
+}
+
+fn missing_from_diff(before: &World, after: &World, delta: &WorldDelta) -> Option<String> {
+    let (grid, old) = (after.grid(), before.grid());
+    for y in 0..grid.height() as i32 {
+        for x in 0..grid.width() as i32 {
+            let position = Position { x, y };
+            if grid.cell_at(position) != old.cell_at(position) && !delta.has_cell(position) {
+                return Some(format!("cell ({}, {})", x, y));
+            }
+        }
+    }
+    let max_id = before.ant_ids().chain(after.ant_ids()).max();
+    for id in max_id.map_or(0..0, |max_id| 0..max_id + 1) {
+        if describe_ant(before, id) != describe_ant(after, id) && !delta.has_ant(id) {
+            return Some(format!("ant {}", id));
+        }
+    }
+    None
+}
+
+// Passes frames to both renderers.
+struct Tee(Box<dyn Renderer>, RecordingRenderer);
+
+impl Renderer for Tee {
+    fn render(&mut self, world: &World) {
+        self.0.render(world);
+        self.1.render(world);
+    }
+
+    fn render_diff(&mut self, world: &World, delta: &WorldDelta) {
+        self.0.render_diff(world, delta);
+        self.1.render_diff(world, delta);
+    }
+}
+
+const FORAGER: &str = "
+    start:   PickUpFood home look
+    look:    Flip 3 turn walk
+    turn:    Turn Left walk
+    walk:    Move start turn
+    home:    Sense Here Home drop wander
+    wander:  Mark 0 step
+    step:    Flip 2 left forward
+    left:    Turn Right forward
+    forward: Move home home
+    drop:    DropFood start start
+";
+
+// Runs `renderer` through a short generated match in which ants move,
+// carry food and leave markers, checking along the way that the diffs
+// it gets are complete. Returns the final world.
+pub fn smoke_test(renderer: Box<dyn Renderer>, steps: u32) -> World {
+    let world = MapGenerator::new(24, 14).generate(1);
+    let program = parse_program(FORAGER).unwrap();
+    let programs = HashMap::from([(Color::Red, program.clone()), (Color::Black, program)]);
+    let recorder = RecordingRenderer::new();
+    let tee = Tee(renderer, recorder.clone());
+    let mut simulator = Simulator::new(world, programs, Box::new(tee), 1).unwrap();
+    simulator.run_for(steps);
+    recorder.assert_frame_count(steps as usize);
+    recorder.assert_diffs_complete();
+    recorder.assert_last_world(simulator.world());
+    simulator.world().clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::renderers::*;
+    use crate::shared::*;
+
+    #[test]
+    fn built_in_renderers() {
+        smoke_test(Box::new(NullRenderer), 50);
+
+        let every = EveryNth::new(RecordingRenderer::new(), 5);
+        smoke_test(Box::new(every), 50);
+
+        let shared = SharedWorld::new(World::new(Grid::new(1, 1)));
+        let world = smoke_test(Box::new(shared.clone()), 50);
+        assert_world_eq(&shared.to_world(), &world);
+
+        let (channel, receiver) = frame_channel(4, DropPolicy::DropOldest);
+        let world = smoke_test(Box::new(channel), 50);
+        let mut last = None;
+        while let Some(frame) = receiver.recv() {
+            last = Some(frame);
+        }
+        assert_world_eq(&last.unwrap(), &world);
+    }
+
+    #[test]
+    fn records_frames() {
+        let recorder = RecordingRenderer::new();
+        let world = smoke_test(Box::new(recorder.clone()), 20);
+        recorder.assert_frame_count(20);
+        recorder.assert_last_world(&world);
+        assert!(recorder.frames().iter().all(|frame| frame.delta.is_some()));
+        recorder.clear();
+        recorder.assert_frame_count(0);
+    }
+
+    #[test]
+    #[should_panic(expected = "diff of frame 1 misses cell (1, 0)")]
+    fn incomplete_diff() {
+        let mut before = World::new(Grid::new(3, 1));
+        before.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let mut after = before.clone();
+        after.ant_mut(0).unwrap().move_forward().unwrap();
+
+        let mut recorder = RecordingRenderer::new();
+        recorder.render(&before);
+        let mut delta = WorldDelta::new();
+        delta.mark_cell(Position { x: 0, y: 0 });
+        delta.mark_ant(0);
+        recorder.render_diff(&after, &delta);
+        recorder.assert_diffs_complete();
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::world::*;
+
+// An annotation for renderers to draw over the world: short trails behind
+// ants carrying food, which show where the food flows. Fed every frame, it
+// keeps the last `length` cells each carrying ant stood on; putting the
+// food down ends the trail, and so does wrapping around a torus.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CarryTrails {
+    length: usize,
+    trails: BTreeMap<AntId, VecDeque<Position>>,
+}
+
+impl CarryTrails {
+    pub fn new(length: usize) -> Self {
+        CarryTrails {
+            length,
+            trails: BTreeMap::new(),
+        }
+    }
+
+    pub fn update(&mut self, world: WorldSnapshot<'_>) {
+        // Frames can be some steps apart, but never half the map.
+        let wrapped = |from: Position, to: Position| {
+            from.x.abs_diff(to.x) as usize * 2 > world.width()
+                || from.y.abs_diff(to.y) as usize * 2 > world.height()
+        };
+        let mut trails = BTreeMap::new();
+        for ant in world.ants().filter(|ant| ant.carries_food()) {
+            let mut trail = self.trails.remove(&ant.id()).unwrap_or_default();
+            if trail
+                .back()
+                .is_some_and(|&last| wrapped(last, ant.position()))
+            {
+                trail.clear();
+            }
+            if trail.back() != Some(&ant.position()) {
+                trail.push_back(ant.position());
+            }
+            while trail.len() > self.length {
+                trail.pop_front();
+            }
+            trails.insert(ant.id(), trail);
+        }
+        self.trails = trails;
+    }
+
+    // Oldest cell first, ending where the ant stands.
+    pub fn trail(&self, ant: AntId) -> Option<&VecDeque<Position>> {
+        self.trails.get(&ant)
+    }
+
+    // Every step of every trail with how opaque to draw it, fading from
+    // nearly 1 at the ant to nothing at the trail's full length.
+    pub fn segments(&self) -> impl Iterator<Item = (Position, Position, f64)> + '_ {
+        self.trails.values().flat_map(move |trail| {
+            let age = self.length - trail.len();
+            trail
+                .iter()
+                .zip(trail.iter().skip(1))
+                .enumerate()
+                .map(move |(i, (&from, &to))| (from, to, (age + i + 1) as f64 / self.length as f64))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_carrying_ants() {
+        let mut world = World::new(Grid::new(6, 1));
+        let porter = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world
+            .add_ant(Color::Black, Position { x: 5, y: 0 })
+            .unwrap();
+        world.grow_food(Position { x: 0, y: 0 }, 1);
+        world.ant_mut(porter).unwrap().pickup_food().unwrap();
+        let mut trails = CarryTrails::new(3);
+        for x in 1..5 {
+            trails.update(world.snapshot());
+            world.ant_mut(porter).unwrap().move_forward().unwrap();
+            assert_eq!(world.ant(porter).unwrap().position().x, x);
+        }
+        trails.update(world.snapshot());
+        let cells: Vec<_> = trails.trail(porter).unwrap().iter().map(|p| p.x).collect();
+        assert_eq!(cells, vec![2, 3, 4]);
+        assert_eq!(trails.trail(1), None);
+        let segments: Vec<_> = trails.segments().collect();
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[1].0.x, 3);
+        assert!(segments[0].2 < segments[1].2 && segments[1].2 < 1.0);
+
+        world.ant_mut(porter).unwrap().drop_food().unwrap();
+        trails.update(world.snapshot());
+        assert_eq!(trails.trail(porter), None);
+    }
+}
@@ -0,0 +1,206 @@
+use std::fmt;
+use std::fs;
+
+use crate::asm::*;
+use crate::data::*;
+use crate::map::*;
+use crate::remote::*;
+use crate::rng::*;
+use crate::rules::*;
+use crate::testing::*;
+use crate::world::*;
+
+// A live world to poke at one command at a time, for `bugworld repl`:
+//
+//     load <map file>        replace the world with the map
+//     ant <color> <x> <y>    add an ant and print its id
+//     act <ant> <action>     take an action, named as remote brains do
+//     exec <ant> <instr>     run one instruction, targets being indices
+//     show <ant>             describe the ant
+//     print                  the world as a map
+//
+// Nothing else happens: there are no rounds, so no combat, spawning or
+// regrowth.
+pub struct Repl {
+    world: World,
+    rules: RuleSet,
+    rng: Rng,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplError {
+    UnknownCommand(String),
+    Usage(&'static str),
+    Io(String),
+    Map(MapError),
+    Asm(AsmError),
+    World(WorldError),
+    Action(ActionError),
+    NoSuchAnt(AntId),
+}
+
+impl fmt::Display for ReplError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplError::UnknownCommand(command) => write!(f, "unknown command `{}`", command),
+            ReplError::Usage(usage) => write!(f, "usage: {}", usage),
+            ReplError::Io(error) => write!(f, "{}", error),
+            ReplError::Map(error) => write!(f, "{}", error),
+            ReplError::Asm(error) => write!(f, "{}", error),
+            ReplError::World(error) => write!(f, "{}", error),
+            ReplError::Action(error) => write!(f, "{}", error),
+            ReplError::NoSuchAnt(ant) => write!(f, "no ant {}", ant),
+        }
+    }
+}
+
+impl std::error::Error for ReplError {}
+
+pub const REPL_HELP: &str = "\
+load <map file>        replace the world with the map
+ant <color> <x> <y>    add an ant and print its id
+act <ant> <action>     Move, Turn <direction>, PickUpFood, DropFood, Eat,
+                       Mark <n> or Unmark <n>
+exec <ant> <instr>     run one instruction, e.g. `Sense Ahead Food 1 2`
+show <ant>             describe the ant
+print                  the world as a map";
+
+impl Repl {
+    pub fn new(world: World) -> Self {
+        Repl {
+            world,
+            rules: RuleSet::default(),
+            rng: Rng::new(0),
+        }
+    }
+
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    // Runs one line and returns what to print, if anything. Blank lines
+    // do nothing.
+    pub fn run(&mut self, line: &str) -> Result<String, ReplError> {
+        let words: Vec<_> = line.split_whitespace().collect();
+        match words[..] {
+            [] => Ok(String::new()),
+            ["load", path] => {
+                let map = fs::read_to_string(path)
+                    .map_err(|error| ReplError::Io(format!("{}: {}", path, error)))?;
+                self.world = map.parse().map_err(ReplError::Map)?;
+                Ok(String::new())
+            }
+            ["load", ..] => Err(ReplError::Usage("load <map file>")),
+            ["ant", color, x, y] => {
+                let usage = || ReplError::Usage("ant <color> <x> <y>");
+                let color = color.parse().map_err(|()| usage())?;
+                let x = x.parse().map_err(|_| usage())?;
+                let y = y.parse().map_err(|_| usage())?;
+                let id = (self.world)
+                    .add_ant(color, Position { x, y })
+                    .map_err(ReplError::World)?;
+                Ok(id.to_string())
+            }
+            ["ant", ..] => Err(ReplError::Usage("ant <color> <x> <y>")),
+            ["act", ant, ..] => {
+                let id = self.ant_id(ant)?;
+                let action = words[2..].join(" ");
+                let usage = ReplError::Usage("act <ant> <action>");
+                // Passing is for brains; here it would do nothing.
+                let action = parse_reply(&action).ok().flatten().ok_or(usage)?;
+                (self.world)
+                    .apply(id, action, &self.rules)
+                    .map_err(ReplError::Action)?;
+                Ok(String::new())
+            }
+            ["exec", ant, ..] => {
+                let id = self.ant_id(ant)?;
+                self.exec(id, &words[2..].join(" "))
+            }
+            ["show", ant] => {
+                let id = self.ant_id(ant)?;
+                describe_ant(&self.world, id).ok_or(ReplError::NoSuchAnt(id))
+            }
+            ["print"] => Ok(self.world.to_map_string()),
+            [command, ..] => Err(ReplError::UnknownCommand(command.to_string())),
+        }
+    }
+
+    fn ant_id(&self, word: &str) -> Result<AntId, ReplError> {
+        let id = word
+            .parse()
+            .map_err(|_| ReplError::Usage("<ant> is an ant id"))?;
+        match self.world.ant(id) {
+            Some(_) => Ok(id),
+            None => Err(ReplError::NoSuchAnt(id)),
+        }
+    }
+
+    // Prints where the ant goes next and the action that took effect.
+    fn exec(&mut self, id: AntId, source: &str) -> Result<String, ReplError> {
+        let program = parse_program(source).map_err(ReplError::Asm)?;
+        let [instr] = program[..] else {
+            return Err(ReplError::Usage("exec <ant> <instr>"));
+        };
+        let mut ant = self.world.ant_mut(id).unwrap();
+        let mut ctx = EvalContext {
+            rules: &self.rules,
+            census: SwarmCensus::default(),
+            rng: &mut self.rng,
+            scent: None,
+        };
+        let command = instr.command(&mut ant, &mut ctx);
+        let (next_instr, action) =
+            command.resolve(|action| self.world.apply(id, action, &self.rules).is_ok());
+        self.world
+            .ant_mut(id)
+            .unwrap()
+            .update_instr_pointer(next_instr);
+        Ok(match action {
+            Some(action) => format!("{:?}, next {}", action, next_instr),
+            None => format!("next {}", next_instr),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drives_a_world() {
+        let mut repl = Repl::new(World::new(Grid::new(3, 1)));
+        assert_eq!(repl.run("ant Red 0 0"), Ok("0".to_string()));
+        assert_eq!(repl.run("act 0 Move"), Ok(String::new()));
+        assert_eq!(repl.run("print"), Ok(". r .\n".to_string()));
+        assert_eq!(
+            repl.run("exec 0 Sense Ahead Food 1 2"),
+            Ok("next 2".to_string())
+        );
+        assert_eq!(repl.run("exec 0 Move 1 2"), Ok("Move, next 1".to_string()));
+        assert_eq!(repl.run("exec 0 Move 1 2"), Ok("next 2".to_string()));
+        assert!(repl.run("show 0").unwrap().starts_with("Red at (2, 0)"));
+        assert_eq!(repl.run(""), Ok(String::new()));
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        let mut repl = Repl::new(World::new(Grid::new(3, 1)));
+        assert!(matches!(repl.run("fly"), Err(ReplError::UnknownCommand(_))));
+        assert!(matches!(
+            repl.run("act 0 Move"),
+            Err(ReplError::NoSuchAnt(0))
+        ));
+        assert!(matches!(repl.run("ant Blue 0 0"), Err(ReplError::Usage(_))));
+        repl.run("ant Black 0 0").unwrap();
+        assert!(matches!(repl.run("act 0 Pass"), Err(ReplError::Usage(_))));
+        assert!(repl.run("act 0 Turn Left").is_ok());
+        assert!(matches!(repl.run("exec 0 Move 7"), Err(ReplError::Asm(_))));
+        assert!(matches!(repl.run("ant Red 0 0"), Err(ReplError::World(_))));
+    }
+}
@@ -0,0 +1,564 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use crate::asm::*;
+use crate::data::*;
+use crate::rules::*;
+use crate::snapshot::*;
+use crate::victory::*;
+use crate::world::*;
+
+// `step` counts from the start of the recording. Ants that neither acted
+// nor jumped anywhere new get no entry. `registers` and `call_stack` are
+// only there when the turn changed them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ReplayEntry {
+    pub step: u32,
+    pub ant: AntId,
+    pub action: Option<Action>,
+    pub next_instr: InstrIdx,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub registers: Option<Registers>,
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, skip_serializing_if = "Option::is_none")
+    )]
+    pub call_stack: Option<CallStack>,
+}
+
+pub type ProgramHash = u64;
+
+// FNV-1a over the program's canonical text: one instruction per line with
+// absolute targets, so the labels it was written with do not matter.
+pub fn program_hash(program: &Program) -> ProgramHash {
+    fnv1a(
+        program
+            .iter()
+            .flat_map(|instr| format!("{}\n", instr).into_bytes()),
+    )
+}
+
+// Everything needed to reproduce a run without the rng: the world the
+// recording started from, the rules it ran under and what every ant did.
+// The programs are kept for reference only; each distinct one is stored
+// once under its hash, so self-play replays carry a single copy. Colors
+// played by a brain have no program and are listed as such.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Replay {
+    initial: World,
+    rules: RuleSet,
+    steps: u32,
+    entries: Vec<ReplayEntry>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    programs: BTreeMap<ProgramHash, Program>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    swarms: BTreeMap<Color, ProgramHash>,
+    // Castes past the first with a program of their own.
+    #[cfg_attr(feature = "serde", serde(default))]
+    castes: BTreeMap<Color, BTreeMap<Caste, ProgramHash>>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    brains: BTreeSet<Color>,
+    // Food that grew back, which takes the rng to work out.
+    #[cfg_attr(feature = "serde", serde(default))]
+    growth: Vec<FoodGrowth>,
+    // For reference, like the programs: replaying the entries kills the
+    // same ants.
+    #[cfg_attr(feature = "serde", serde(default))]
+    deaths: Vec<DeathRecord>,
+    // Only kept under an rng audit, and not needed to replay.
+    #[cfg_attr(feature = "serde", serde(default))]
+    draws: Vec<RngDraw>,
+}
+
+// Food added to a spawner cell at the end of `step`, see `RegrowthRule`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FoodGrowth {
+    pub step: u32,
+    pub position: Position,
+    pub amount: u32,
+}
+
+// What a random number was drawn for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DrawPurpose {
+    // The ant's `Flip`, which succeeds on a zero.
+    Flip(AntId),
+    // A swap in the shuffled turn order of the round.
+    Schedule,
+    // Whether the spawner at the position grows food, below the rule's
+    // chance.
+    Regrowth(Position),
+}
+
+// One draw of `value` in `0..bound`, see `Simulator::with_rng_audit`.
+// Rounds are the match's, as for deaths.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RngDraw {
+    pub round: u32,
+    pub purpose: DrawPurpose,
+    pub bound: u32,
+    pub value: u32,
+}
+
+impl Replay {
+    pub fn new(initial: World, rules: RuleSet) -> Self {
+        Replay {
+            initial,
+            rules,
+            steps: 0,
+            entries: Vec::new(),
+            programs: BTreeMap::new(),
+            swarms: BTreeMap::new(),
+            castes: BTreeMap::new(),
+            brains: BTreeSet::new(),
+            growth: Vec::new(),
+            deaths: Vec::new(),
+            draws: Vec::new(),
+        }
+    }
+
+    pub fn initial(&self) -> &World {
+        &self.initial
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    pub fn entries(&self) -> &[ReplayEntry] {
+        &self.entries
+    }
+
+    pub fn growth(&self) -> &[FoodGrowth] {
+        &self.growth
+    }
+
+    // Rounds are the match's, not counted from the start of the recording.
+    pub fn deaths(&self) -> &[DeathRecord] {
+        &self.deaths
+    }
+
+    pub fn draws(&self) -> &[RngDraw] {
+        &self.draws
+    }
+
+    pub fn program(&self, color: Color) -> Option<&Program> {
+        self.swarms.get(&color).map(|hash| &self.programs[hash])
+    }
+
+    pub fn program_hash(&self, color: Color) -> Option<ProgramHash> {
+        self.swarms.get(&color).copied()
+    }
+
+    // The program `color`'s ants of `caste` ran: their own, or the color's
+    // when the caste had none.
+    pub fn caste_program(&self, color: Color, caste: Caste) -> Option<&Program> {
+        match self
+            .castes
+            .get(&color)
+            .and_then(|castes| castes.get(&caste))
+        {
+            Some(hash) => Some(&self.programs[hash]),
+            None => self.program(color),
+        }
+    }
+
+    // Whether a brain played `color` in place of a program.
+    pub fn is_brain(&self, color: Color) -> bool {
+        self.brains.contains(&color)
+    }
+
+    // Distinct programs, by hash.
+    pub fn programs(&self) -> impl Iterator<Item = (ProgramHash, &Program)> {
+        self.programs.iter().map(|(&hash, program)| (hash, program))
+    }
+
+    pub(crate) fn add_program(
+        &mut self,
+        color: Color,
+        caste: Caste,
+        program: &Program,
+    ) -> ProgramHash {
+        let hash = program_hash(program);
+        self.programs.entry(hash).or_insert_with(|| program.clone());
+        match caste {
+            0 => self.swarms.insert(color, hash),
+            _ => self.castes.entry(color).or_default().insert(caste, hash),
+        };
+        hash
+    }
+
+    pub(crate) fn add_brain(&mut self, color: Color) {
+        self.brains.insert(color);
+    }
+
+    pub(crate) fn record(
+        &mut self,
+        ant: AntId,
+        action: Option<Action>,
+        next_instr: InstrIdx,
+        registers: Option<Registers>,
+        call_stack: Option<CallStack>,
+    ) {
+        self.entries.push(ReplayEntry {
+            step: self.steps,
+            ant,
+            action,
+            next_instr,
+            registers,
+            call_stack,
+        });
+    }
+
+    pub(crate) fn record_growth(&mut self, position: Position, amount: u32) {
+        self.growth.push(FoodGrowth {
+            step: self.steps,
+            position,
+            amount,
+        });
+    }
+
+    pub(crate) fn record_death(&mut self, death: DeathRecord) {
+        self.deaths.push(death);
+    }
+
+    pub(crate) fn record_draw(&mut self, draw: RngDraw) {
+        self.draws.push(draw);
+    }
+
+    pub(crate) fn finish_step(&mut self) {
+        self.steps += 1;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayError {
+    NoSuchAnt { step: u32, ant: AntId },
+    Rejected { step: u32, error: ActionError },
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReplayError::NoSuchAnt { step, ant } => {
+                write!(f, "step {}: ant {} is not in the world", step, ant)
+            }
+            ReplayError::Rejected { step, error } => write!(f, "step {}: {}", step, error),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ReplayError::NoSuchAnt { .. } => None,
+            ReplayError::Rejected { error, .. } => Some(error),
+        }
+    }
+}
+
+pub struct ReplayPlayer<'a> {
+    replay: &'a Replay,
+    world: World,
+    step: u32,
+    next_entry: usize,
+    next_growth: usize,
+}
+
+impl<'a> ReplayPlayer<'a> {
+    pub fn new(replay: &'a Replay) -> Self {
+        ReplayPlayer {
+            replay,
+            world: replay.initial.clone(),
+            step: 0,
+            next_entry: 0,
+            next_growth: 0,
+        }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn step(&self) -> u32 {
+        self.step
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.step >= self.replay.steps
+    }
+
+    // Applies one recorded step. Returns `false` once the replay is over.
+    pub fn advance(&mut self) -> Result<bool, ReplayError> {
+        if self.is_finished() {
+            return Ok(false);
+        }
+        // Ants rest at their turn, before anything else can happen to them.
+        self.world.take_rests();
+        let entries = &self.replay.entries[self.next_entry..];
+        let count = entries
+            .iter()
+            .take_while(|entry| entry.step == self.step)
+            .count();
+        for entry in &entries[..count] {
+            self.apply(entry)?;
+        }
+        if let Some(metabolism) = self.replay.rules.metabolism {
+            self.world.metabolize(metabolism);
+        }
+        if let Some(rule) = self.replay.rules.spawning {
+            self.world.spawn(rule);
+        }
+        let growth = &self.replay.growth[self.next_growth..];
+        let grown = growth.iter().take_while(|growth| growth.step == self.step);
+        for growth in grown {
+            self.world.grow_food(growth.position, growth.amount);
+            self.next_growth += 1;
+        }
+        self.next_entry += count;
+        self.step += 1;
+        Ok(true)
+    }
+
+    pub fn run_to_end(&mut self) -> Result<(), ReplayError> {
+        while self.advance()? {}
+        Ok(())
+    }
+
+    fn apply(&mut self, entry: &ReplayEntry) -> Result<(), ReplayError> {
+        let rules = &self.replay.rules;
+        if !self.world.is_alive(entry.ant) {
+            return Err(ReplayError::NoSuchAnt {
+                step: entry.step,
+                ant: entry.ant,
+            });
+        }
+        if let Some(action) = entry.action {
+            self.world
+                .apply(entry.ant, action, rules)
+                .map_err(|error| ReplayError::Rejected {
+                    step: entry.step,
+                    error,
+                })?;
+        }
+        let mut ant = self
+            .world
+            .ant_mut(entry.ant)
+            .ok_or(ReplayError::NoSuchAnt {
+                step: entry.step,
+                ant: entry.ant,
+            })?;
+        ant.update_instr_pointer(entry.next_instr);
+        if let Some(registers) = entry.registers {
+            ant.set_registers(registers);
+        }
+        if let Some(call_stack) = entry.call_stack {
+            *ant.call_stack_mut() = call_stack;
+        }
+        let position = ant.position();
+        if let (Some(Action::Move), Some(rule)) = (entry.action, rules.combat) {
+            self.world.resolve_combat(position, rule);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::renderers::NullRenderer;
+    use crate::sim::*;
+    use crate::testing::*;
+
+    const WANDERER: &str = "
+        start: Flip 3 turn move
+        turn:  Turn Left mark
+        mark:  Mark 1 start
+        move:  Move pick start
+        pick:  PickUpFood start drop
+        drop:  DropFood start
+    ";
+
+    fn simulator(world: World, red: Program, black: Program) -> Simulator {
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, red);
+        programs.insert(Color::Black, black);
+        Simulator::new(world, programs, Box::new(NullRenderer), 7).unwrap()
+    }
+
+    #[test]
+    fn reproduces_run() {
+        let world: World = "\
+# # # # # # # #
+ # r 5 . 9 b . #
+# . 3 + - 2 . #
+ # b . 7 . r . #
+# # # # # # # #
+"
+        .parse()
+        .unwrap();
+        let program = parse_program(WANDERER).unwrap();
+        let mut simulator = simulator(world, program.clone(), program);
+        for _ in 0..5 {
+            simulator.step();
+        }
+        simulator.start_recording();
+        for _ in 0..100 {
+            simulator.step();
+        }
+        let replay = simulator.take_replay().unwrap();
+        assert_eq!(replay.steps(), 100);
+        assert!(replay.entries().iter().any(|entry| entry.action.is_some()));
+
+        let mut player = ReplayPlayer::new(&replay);
+        player.run_to_end().unwrap();
+        assert!(player.is_finished());
+        assert_eq!(player.step(), 100);
+        assert_world_eq(player.world(), simulator.world());
+        assert_eq!(player.advance(), Ok(false));
+    }
+
+    #[test]
+    fn shares_identical_programs() {
+        let world: World = "\
+# # # #
+ # r b #
+# # # #
+"
+        .parse()
+        .unwrap();
+        let wanderer = parse_program(WANDERER).unwrap();
+        let mut selfplay = simulator(world.clone(), wanderer.clone(), wanderer.clone());
+        selfplay.start_recording();
+        let replay = selfplay.replay().unwrap();
+        assert_eq!(replay.programs().count(), 1);
+        assert_eq!(replay.program(Color::Red), Some(&wanderer));
+        assert_eq!(
+            replay.program_hash(Color::Black),
+            Some(program_hash(&wanderer))
+        );
+
+        let idler = parse_program("start: Turn Left start").unwrap();
+        let mut mixed = simulator(world, wanderer, idler.clone());
+        mixed.start_recording();
+        let replay = mixed.replay().unwrap();
+        assert_eq!(replay.programs().count(), 2);
+        assert_eq!(replay.program(Color::Black), Some(&idler));
+    }
+
+    #[test]
+    fn records_castes_and_brains() {
+        let world: World = "\
+# # # #
+ # r b #
+# # # #
+"
+        .parse()
+        .unwrap();
+        let wanderer = parse_program(WANDERER).unwrap();
+        let soldier = parse_program("start: Turn Left start").unwrap();
+        let replies = std::io::Cursor::new("Move\n");
+        let brain = crate::remote::RemoteBrain::new(replies, std::io::sink());
+        let mut simulator = Simulator::headless(world, HashMap::new(), 0)
+            .unwrap()
+            .with_remote_brain(Color::Black, brain)
+            .with_brain(
+                Color::Red,
+                Box::new(crate::brain::AsmBrain::new(wanderer.clone()).unwrap()),
+            )
+            .with_caste(Color::Red, 2, soldier.clone())
+            .unwrap();
+        simulator.start_recording();
+
+        let replay = simulator.replay().unwrap();
+        assert_eq!(replay.program(Color::Red), Some(&wanderer));
+        assert_eq!(replay.caste_program(Color::Red, 2), Some(&soldier));
+        assert_eq!(replay.caste_program(Color::Red, 1), Some(&wanderer));
+        assert!(!replay.is_brain(Color::Red));
+        assert!(replay.is_brain(Color::Black));
+        assert_eq!(replay.program(Color::Black), None);
+        assert_eq!(replay.caste_program(Color::Black, 0), None);
+    }
+
+    #[test]
+    fn hash_ignores_labels() {
+        let a = parse_program("start: Move start other\nother: Turn Left start").unwrap();
+        let b = parse_program("x: Move x y\ny: Turn Left x").unwrap();
+        let c = parse_program("x: Move x y\ny: Turn Right x").unwrap();
+        assert_eq!(program_hash(&a), program_hash(&b));
+        assert_ne!(program_hash(&a), program_hash(&c));
+    }
+
+    #[test]
+    fn replays_combat() {
+        let mut world = World::new(Grid::with_wall_border(10, 10));
+        let trap = Position { x: 5, y: 5 };
+        let red = world
+            .add_ant(Color::Red, trap.translate(Direction::Left))
+            .unwrap();
+        for d in [1, 2, 4, 5, 0] {
+            let position = trap.translate(Direction::try_from(d).unwrap());
+            world.add_ant(Color::Black, position).unwrap();
+        }
+        let red_program = parse_program("start: Move start start").unwrap();
+        let black_program = parse_program("start: Turn Left start").unwrap();
+        let mut simulator = simulator(world, red_program, black_program);
+        simulator.start_recording();
+        simulator.step();
+
+        let replay = simulator.replay().unwrap();
+        let mut player = ReplayPlayer::new(replay);
+        assert_eq!(player.advance(), Ok(true));
+        assert!(!player.world().is_alive(red));
+        assert_world_eq(player.world(), simulator.world());
+    }
+
+    #[test]
+    fn desync() {
+        let mut world = World::new(Grid::with_wall_border(4, 4));
+        let id = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+        let mut replay = Replay::new(world, RuleSet::classic());
+        replay.record(id, Some(Action::DropFood), 0, None, None);
+        replay.finish_step();
+        replay.record(id + 1, None, 0, None, None);
+        replay.finish_step();
+
+        let mut player = ReplayPlayer::new(&replay);
+        assert_eq!(
+            player.advance(),
+            Err(ReplayError::Rejected {
+                step: 0,
+                error: ActionError {
+                    ant: id,
+                    position: Position { x: 1, y: 1 },
+                    action: Action::DropFood,
+                    error: WorldError::AntHasNoFood,
+                },
+            })
+        );
+        let mut replay = Replay::new(player.world().clone(), RuleSet::classic());
+        replay.record(id + 1, None, 0, None, None);
+        replay.finish_step();
+        assert_eq!(
+            ReplayPlayer::new(&replay).advance(),
+            Err(ReplayError::NoSuchAnt {
+                step: 0,
+                ant: id + 1
+            })
+        );
+    }
+}
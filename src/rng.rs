@@ -0,0 +1,217 @@
+use crate::snapshot::*;
+use crate::world::*;
+
+// SplitMix64: tiny, fast and fully determined by the seed, which is all the
+// simulator needs to keep runs reproducible.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Rng { state: seed }
+    }
+
+    // A generator keyed by `seed` and `keys`. Every distinct key list gets
+    // an independent stream; the draw count is the generator's state.
+    pub fn stream(seed: u64, keys: &[u64]) -> Self {
+        let state = keys
+            .iter()
+            .fold(mix(seed), |state, &key| mix(state ^ mix(key)));
+        Rng::new(state)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    // Uniform value in `0..n`. Panics if `n` is zero.
+    pub fn below(&mut self, n: u32) -> u32 {
+        assert!(n > 0);
+        (((self.next_u64() >> 32) * n as u64) >> 32) as u32
+    }
+}
+
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RngStreams {
+    // Every ant draws from its own stream derived from (seed, color, ant id),
+    // so adding or removing ants leaves the other ants' draws unchanged.
+    #[default]
+    PerAnt,
+    // All ants share one stream in turn order, as older versions did.
+    Shared,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchRng {
+    seed: u64,
+    streams: RngStreams,
+    shared: Rng,
+    // By ant id, made on the ant's first draw.
+    per_ant: Vec<Option<Rng>>,
+}
+
+impl MatchRng {
+    pub fn new(seed: u64, streams: RngStreams) -> Self {
+        MatchRng {
+            seed,
+            streams,
+            shared: Rng::new(seed),
+            per_ant: Vec::new(),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn streams(&self) -> RngStreams {
+        self.streams
+    }
+
+    pub fn shared(&mut self) -> &mut Rng {
+        &mut self.shared
+    }
+
+    // A stream for shuffling the turn order of `round`, separate from the
+    // ants' own draws.
+    pub fn schedule(&self, round: u32) -> Rng {
+        Rng::stream(self.seed, &[round as u64])
+    }
+
+    // A stream for regrowing food at the end of `round`. Ant streams are
+    // keyed by a team index, which never gets this high.
+    pub fn regrowth(&self, round: u32) -> Rng {
+        Rng::stream(self.seed, &[u64::MAX, round as u64])
+    }
+
+    pub fn for_ant(&mut self, color: Color, id: AntId) -> &mut Rng {
+        let seed = self.seed;
+        match self.streams {
+            RngStreams::Shared => &mut self.shared,
+            RngStreams::PerAnt => {
+                if self.per_ant.len() <= id {
+                    self.per_ant.resize(id + 1, None);
+                }
+                self.per_ant[id]
+                    .get_or_insert_with(|| Rng::stream(seed, &[color.index() as u64, id as u64]))
+            }
+        }
+    }
+}
+
+impl MatchRng {
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        out.u64(self.seed);
+        out.bool(self.streams == RngStreams::Shared);
+        out.u64(self.shared.state);
+        out.usize(self.per_ant.iter().flatten().count());
+        for (id, rng) in self.per_ant.iter().enumerate() {
+            if let Some(rng) = rng {
+                out.usize(id);
+                out.u64(rng.state);
+            }
+        }
+    }
+
+    // Streams are only kept for ants the world has had, fewer than `ants`.
+    pub(crate) fn decode(input: &mut Decoder, ants: usize) -> Result<MatchRng, SnapshotError> {
+        let seed = input.u64()?;
+        let streams = if input.bool()? {
+            RngStreams::Shared
+        } else {
+            RngStreams::PerAnt
+        };
+        let mut rng = MatchRng::new(seed, streams);
+        rng.shared = Rng::new(input.u64()?);
+        for _ in 0..input.count()? {
+            let id = input.usize()?;
+            if id >= ants {
+                return Err(SnapshotError::Corrupt);
+            }
+            if rng.per_ant.len() <= id {
+                rng.per_ant.resize(id + 1, None);
+            }
+            rng.per_ant[id] = Some(Rng::new(input.u64()?));
+        }
+        Ok(rng)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        for _ in 0..100 {
+            assert_eq!(a.next_u64(), b.next_u64());
+        }
+    }
+
+    #[test]
+    fn different_seeds_differ() {
+        let mut a = Rng::new(1);
+        let mut b = Rng::new(2);
+        assert_ne!(a.next_u64(), b.next_u64());
+    }
+
+    #[test]
+    fn below_in_range() {
+        let mut rng = Rng::new(7);
+        let mut seen = [false; 6];
+        for _ in 0..1000 {
+            let value = rng.below(6);
+            assert!(value < 6);
+            seen[value as usize] = true;
+        }
+        assert!(seen.iter().all(|&s| s));
+        assert_eq!(rng.below(1), 0);
+    }
+
+    #[test]
+    fn streams_are_independent() {
+        let mut a = Rng::stream(5, &[0, 1]);
+        let mut b = Rng::stream(5, &[0, 2]);
+        let mut c = Rng::stream(5, &[1, 1]);
+        let first = a.next_u64();
+        assert_ne!(first, b.next_u64());
+        assert_ne!(first, c.next_u64());
+        assert_eq!(Rng::stream(5, &[0, 1]).next_u64(), first);
+    }
+
+    #[test]
+    fn per_ant_streams() {
+        let mut rng = MatchRng::new(9, RngStreams::PerAnt);
+        let draws: Vec<_> = (0..5)
+            .map(|_| rng.for_ant(Color::Red, 3).next_u64())
+            .collect();
+
+        let mut other = MatchRng::new(9, RngStreams::PerAnt);
+        let mut interleaved = Vec::new();
+        for _ in 0..5 {
+            other.for_ant(Color::Black, 0).next_u64();
+            interleaved.push(other.for_ant(Color::Red, 3).next_u64());
+        }
+        assert_eq!(draws, interleaved);
+    }
+
+    #[test]
+    fn shared_stream() {
+        let mut rng = MatchRng::new(9, RngStreams::Shared);
+        let first = rng.for_ant(Color::Red, 3).next_u64();
+        let second = rng.for_ant(Color::Black, 0).next_u64();
+        let mut plain = Rng::new(9);
+        assert_eq!((first, second), (plain.next_u64(), plain.next_u64()));
+    }
+}
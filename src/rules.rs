@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use crate::asm::*;
+use crate::scent::*;
+use crate::world::*;
+
+// Every round an ant gains `budget` ticks of energy and runs instructions
+// for as long as it can pay for the next one; what is left over carries to
+// the next round, so an instruction dearer than the budget just takes
+// several rounds. Costs start at one tick each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CostTable {
+    budget: u32,
+    costs: [u32; InstrKind::COUNT],
+}
+
+impl CostTable {
+    pub fn new(budget: u32) -> Self {
+        assert!(budget > 0, "energy budget must be positive");
+        CostTable {
+            budget,
+            costs: [1; InstrKind::COUNT],
+        }
+    }
+
+    // Free instructions would let an ant loop forever within a round.
+    pub fn with_cost(mut self, kind: InstrKind, cost: u32) -> Self {
+        assert!(cost > 0, "instruction cost must be positive");
+        self.costs[usize::from(kind.opcode())] = cost;
+        self
+    }
+
+    pub fn budget(&self) -> u32 {
+        self.budget
+    }
+
+    pub fn cost(&self, kind: InstrKind) -> u32 {
+        self.costs[usize::from(kind.opcode())]
+    }
+}
+
+// Turns an ant sits out after an action takes effect, as in the original
+// game where moving keeps an ant busy for a while. Only the kinds of
+// instruction that act are looked up, for program and brain ants alike;
+// every action starts out with no rest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RestTable {
+    rests: [u32; InstrKind::COUNT],
+}
+
+impl RestTable {
+    pub fn new() -> Self {
+        RestTable::default()
+    }
+
+    pub fn with_rest(mut self, kind: InstrKind, turns: u32) -> Self {
+        self.rests[usize::from(kind.opcode())] = turns;
+        self
+    }
+
+    pub fn rest(&self, kind: InstrKind) -> u32 {
+        self.rests[usize::from(kind.opcode())]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RuleSet {
+    // Lets ants query coarse global information about the swarms
+    // (`Instr::SenseSwarm`). Not part of the classic game.
+    pub swarm_sensing: bool,
+    // Maximum amount of food a single cell may hold after a drop and what
+    // happens to food that does not fit. `None` means unlimited.
+    pub food_cap: Option<FoodCap>,
+    // Kills ants surrounded by enemies after every move. `None` disables
+    // combat entirely.
+    pub combat: Option<CombatRule>,
+    // Swarms with a cost table run on an energy budget; the rest execute
+    // exactly one instruction per ant per round, unless
+    // `instruction_budget` says otherwise.
+    pub costs: BTreeMap<Color, CostTable>,
+    // Lets an ant run up to this many instructions a round, its turn
+    // ending early on the first one that acts on the world
+    // (`InstrKind::is_physical`), so chains of branches cost no rounds.
+    // `None` keeps one instruction a round.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub instruction_budget: Option<u32>,
+    // Ants that have to eat or starve. `None` keeps them fed forever.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub metabolism: Option<Metabolism>,
+    // Lets anthills turn stored food into new ants. `None` keeps the
+    // swarms at the size they started with.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub spawning: Option<SpawnRule>,
+    // Makes ants rest after acting. `None` lets them act every turn.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub resting: Option<RestTable>,
+    // Food that grows back on spawner cells. `None` leaves what is eaten
+    // gone for good.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub regrowth: Option<RegrowthRule>,
+    // Scent given off by food and anthills for `Instr::SenseScent` to
+    // follow. `None` leaves the air clear.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub scent: Option<ScentRule>,
+    // What ants can sense of the other teams' markers, by `FoeMarker` or
+    // in a brain's observation.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub foe_markers: MarkerVisibility,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MarkerVisibility {
+    // That some other team marked a cell, but not which marker or team.
+    #[default]
+    Aggregated,
+    // Nothing; `FoeMarker` never holds.
+    Private,
+}
+
+impl RuleSet {
+    pub fn classic() -> Self {
+        RuleSet {
+            swarm_sensing: false,
+            food_cap: None,
+            combat: Some(CombatRule::default()),
+            costs: BTreeMap::new(),
+            instruction_budget: None,
+            metabolism: None,
+            spawning: None,
+            resting: None,
+            regrowth: None,
+            scent: None,
+            foe_markers: MarkerVisibility::Aggregated,
+        }
+    }
+
+    pub fn extended() -> Self {
+        RuleSet {
+            swarm_sensing: true,
+            food_cap: None,
+            combat: Some(CombatRule::default()),
+            costs: BTreeMap::new(),
+            instruction_budget: None,
+            metabolism: None,
+            spawning: None,
+            resting: None,
+            regrowth: None,
+            scent: None,
+            foe_markers: MarkerVisibility::Aggregated,
+        }
+    }
+}
+
+impl Default for RuleSet {
+    fn default() -> Self {
+        RuleSet::classic()
+    }
+}
@@ -0,0 +1,270 @@
+use std::mem;
+
+use crate::snapshot::*;
+use crate::world::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum Scent {
+    Food,
+    // Each team smells only its own anthills.
+    Home,
+}
+
+// Every round each cell with food gives off `food` and each anthill cell
+// `home`, on top of what is there. Then every free cell passes on
+// `diffusion` thousandths of its scent, a sixth to each neighbour, keeping
+// the shares meant for walls and the edge, and loses `decay` thousandths
+// of what it ends up with. Strengths are whole numbers, rounded down.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ScentRule {
+    pub food: u32,
+    pub home: u32,
+    pub diffusion: u32,
+    pub decay: u32,
+}
+
+impl Default for ScentRule {
+    fn default() -> Self {
+        ScentRule {
+            food: 1000,
+            home: 1000,
+            diffusion: 600,
+            decay: 50,
+        }
+    }
+}
+
+// The strength of every scent on every cell. Updating costs a pass over
+// the grid per scent that is present, with no allocations once every
+// team's home scent has appeared.
+#[derive(Debug, Clone)]
+pub struct ScentField {
+    layout: Layout,
+    food: Vec<u32>,
+    // By team, in the order their anthills were first smelled.
+    homes: Vec<(Color, Vec<u32>)>,
+    // Room for `update` to work in.
+    free: Vec<bool>,
+    scratch: Vec<u32>,
+}
+
+impl ScentField {
+    // No scent anywhere yet.
+    pub fn new(grid: &Grid) -> Self {
+        let cells = grid.width() * grid.height();
+        ScentField {
+            layout: Layout {
+                width: grid.width(),
+                height: grid.height(),
+                topology: grid.topology(),
+            },
+            food: vec![0; cells],
+            homes: Vec::new(),
+            free: vec![false; cells],
+            scratch: vec![0; cells],
+        }
+    }
+
+    // What an ant of `color` smells at `position`; nothing off the edge.
+    pub fn strength(&self, scent: Scent, color: Color, position: Position) -> u32 {
+        let Some(index) = self.layout.index(position) else {
+            return 0;
+        };
+        match scent {
+            Scent::Food => self.food[index],
+            Scent::Home => self
+                .homes
+                .iter()
+                .find(|(home, _)| *home == color)
+                .map_or(0, |(_, values)| values[index]),
+        }
+    }
+
+    // One round of emission, diffusion and decay over `grid`, which has to
+    // be the size the field was made for.
+    pub fn update(&mut self, grid: &Grid, rule: &ScentRule) {
+        assert_eq!(
+            (grid.width(), grid.height()),
+            (self.layout.width, self.layout.height),
+            "the grid changed size"
+        );
+        for (index, (_, cell)) in grid.iter_cells().enumerate() {
+            self.free[index] = *cell != Cell::Wall;
+            if cell.has_food() {
+                self.food[index] = self.food[index].saturating_add(rule.food);
+            }
+            if let Some(color) = cell.anthill() {
+                let home = match self.homes.iter().position(|(home, _)| *home == color) {
+                    Some(home) => home,
+                    None => {
+                        self.homes.push((color, vec![0; self.free.len()]));
+                        self.homes.len() - 1
+                    }
+                };
+                let values = &mut self.homes[home].1;
+                values[index] = values[index].saturating_add(rule.home);
+            }
+        }
+        spread(&self.food, &mut self.scratch, &self.free, self.layout, rule);
+        mem::swap(&mut self.food, &mut self.scratch);
+        for (_, values) in &mut self.homes {
+            spread(values, &mut self.scratch, &self.free, self.layout, rule);
+            mem::swap(values, &mut self.scratch);
+        }
+    }
+
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        for &value in &self.food {
+            out.u32(value);
+        }
+        out.usize(self.homes.len());
+        for (color, values) in &self.homes {
+            out.color(*color);
+            for &value in values {
+                out.u32(value);
+            }
+        }
+    }
+
+    pub(crate) fn decode(input: &mut Decoder, grid: &Grid) -> Result<Self, SnapshotError> {
+        let mut field = ScentField::new(grid);
+        for value in &mut field.food {
+            *value = input.u32()?;
+        }
+        for _ in 0..input.count()? {
+            let color = input.color()?;
+            let mut values = vec![0; field.food.len()];
+            for value in &mut values {
+                *value = input.u32()?;
+            }
+            field.homes.push((color, values));
+        }
+        Ok(field)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    width: usize,
+    height: usize,
+    topology: Topology,
+}
+
+impl Layout {
+    fn index(self, position: Position) -> Option<usize> {
+        let (width, height) = (self.width as i32, self.height as i32);
+        let inside = (0..width).contains(&position.x) && (0..height).contains(&position.y);
+        let (x, y) = match self.topology {
+            _ if inside => (position.x, position.y),
+            Topology::Torus if width > 0 && height > 0 => {
+                (position.x.rem_euclid(width), position.y.rem_euclid(height))
+            }
+            _ => return None,
+        };
+        Some(y as usize * self.width + x as usize)
+    }
+
+    // In direction order; away from the edges it is plain arithmetic.
+    fn neighbours(self, index: usize) -> [Option<usize>; 6] {
+        let (x, y) = (index % self.width, index / self.width);
+        if (1..self.width - 1).contains(&x) && (1..self.height - 1).contains(&y) {
+            let width = self.width;
+            return [
+                Some(index + 1),
+                Some(index + width),
+                Some(index + width - 1),
+                Some(index - 1),
+                Some(index - width),
+                Some(index - width + 1),
+            ];
+        }
+        let position = Position {
+            x: x as i32,
+            y: y as i32,
+        };
+        let mut neighbours = [None; 6];
+        for (slot, direction) in neighbours.iter_mut().zip(Direction::all()) {
+            *slot = self.index(position.translate(direction));
+        }
+        neighbours
+    }
+}
+
+// One round of diffusion and decay of `values` into `out`, see `ScentRule`.
+fn spread(values: &[u32], out: &mut [u32], free: &[bool], layout: Layout, rule: &ScentRule) {
+    let diffusion = u64::from(rule.diffusion.min(1000));
+    let kept = u64::from(1000 - rule.decay.min(1000));
+    for (index, slot) in out.iter_mut().enumerate() {
+        if !free[index] {
+            *slot = 0;
+            continue;
+        }
+        let (mut incoming, mut open) = (0, 0);
+        for neighbour in layout.neighbours(index).into_iter().flatten() {
+            if free[neighbour] {
+                incoming += u64::from(values[neighbour]);
+                open += 1;
+            }
+        }
+        let own = u64::from(values[index]);
+        let value = (own * (6000 - diffusion * open) + incoming * diffusion) / 6000;
+        *slot = (value * kept / 1000).min(u64::from(u32::MAX)) as u32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spreads_and_decays() {
+        let mut grid = Grid::new(9, 3);
+        let food = Position { x: 1, y: 1 };
+        grid.cell_at_mut(food).unwrap().try_drop_food().unwrap();
+        *grid.cell_at_mut(Position { x: 7, y: 1 }).unwrap() = Cell::new_anthill(Color::Red);
+        *grid.cell_at_mut(Position { x: 4, y: 0 }).unwrap() = Cell::Wall;
+        let mut field = ScentField::new(&grid);
+        let rule = ScentRule::default();
+        for _ in 0..20 {
+            field.update(&grid, &rule);
+        }
+
+        // Weaker and weaker away from the food, and none in walls.
+        let along: Vec<_> = (1..7)
+            .map(|x| field.strength(Scent::Food, Color::Red, Position { x, y: 1 }))
+            .collect();
+        assert!(
+            along.windows(2).all(|pair| pair[0] > pair[1]),
+            "{:?}",
+            along
+        );
+        assert_eq!(
+            field.strength(Scent::Food, Color::Red, Position { x: 4, y: 0 }),
+            0
+        );
+        assert_eq!(
+            field.strength(Scent::Food, Color::Red, Position { x: 9, y: 1 }),
+            0
+        );
+
+        let home = Position { x: 7, y: 1 };
+        assert!(field.strength(Scent::Home, Color::Red, home) > 0);
+        assert_eq!(field.strength(Scent::Home, Color::Black, home), 0);
+
+        // Without a source the scent fades away.
+        grid.cell_at_mut(food).unwrap().try_pickup_food().unwrap();
+        let before = field.strength(Scent::Food, Color::Red, food);
+        field.update(&grid, &rule);
+        assert!(field.strength(Scent::Food, Color::Red, food) < before);
+
+        let mut out = Encoder::bare();
+        field.encode(&mut out);
+        let bytes = out.into_bytes();
+        let copy = ScentField::decode(&mut Decoder::bare(&bytes), &grid).unwrap();
+        assert_eq!(copy.food, field.food);
+        assert_eq!(copy.homes, field.homes);
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::sim::*;
+use crate::world::*;
+
+// A world behind a lock, for handing to server and viewer threads. Clones
+// share the same world. The read methods mirror `World`'s and hold the
+// lock only for the call; take a `read_guard` to look at several things in
+// one consistent state.
+//
+// As a renderer it copies every frame in, so other threads can follow a
+// simulation without touching the simulator.
+#[derive(Clone)]
+pub struct SharedWorld {
+    world: Arc<RwLock<World>>,
+}
+
+impl SharedWorld {
+    pub fn new(world: World) -> Self {
+        SharedWorld {
+            world: Arc::new(RwLock::new(world)),
+        }
+    }
+
+    pub fn read_guard(&self) -> RwLockReadGuard<'_, World> {
+        self.world.read().unwrap()
+    }
+
+    pub fn write_guard(&self) -> RwLockWriteGuard<'_, World> {
+        self.world.write().unwrap()
+    }
+
+    pub fn read<T>(&self, f: impl FnOnce(&World) -> T) -> T {
+        f(&self.read_guard())
+    }
+
+    pub fn replace(&self, world: World) -> World {
+        std::mem::replace(&mut self.write_guard(), world)
+    }
+
+    pub fn to_world(&self) -> World {
+        self.read_guard().clone()
+    }
+
+    pub fn width(&self) -> usize {
+        self.read_guard().grid().width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.read_guard().grid().height()
+    }
+
+    pub fn cell_at(&self, position: Position) -> Option<Cell> {
+        self.read_guard().grid().cell_at(position).cloned()
+    }
+
+    pub fn ant_at(&self, position: Position) -> Option<AntId> {
+        self.read_guard().grid().ant_at(position)
+    }
+
+    pub fn is_alive(&self, id: AntId) -> bool {
+        self.read_guard().is_alive(id)
+    }
+
+    pub fn ant_position(&self, id: AntId) -> Option<Position> {
+        let world = self.read_guard();
+        world.ant(id).map(|ant| ant.position())
+    }
+
+    pub fn swarm_size(&self, color: Color) -> usize {
+        self.read_guard().swarm_size(color)
+    }
+
+    pub fn food_in_anthill(&self, color: Color) -> u32 {
+        self.read_guard().food_in_anthill(color)
+    }
+
+    pub fn food_on_ground(&self) -> u32 {
+        self.read_guard().food_on_ground()
+    }
+}
+
+impl Renderer for SharedWorld {
+    fn render(&mut self, world: &World) {
+        self.write_guard().clone_from(world);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+    use std::thread;
+
+    use crate::asm::*;
+
+    #[test]
+    fn guards() {
+        let shared = SharedWorld::new(World::new(Grid::new(4, 3)));
+        let id = shared
+            .write_guard()
+            .add_ant(Color::Red, Position { x: 1, y: 1 })
+            .unwrap();
+        assert_eq!((shared.width(), shared.height()), (4, 3));
+        assert_eq!(shared.ant_at(Position { x: 1, y: 1 }), Some(id));
+        assert_eq!(shared.ant_position(id), Some(Position { x: 1, y: 1 }));
+        assert_eq!(shared.ant_position(id + 1), None);
+        assert_eq!(shared.swarm_size(Color::Red), 1);
+        assert!(shared.cell_at(Position { x: 1, y: 1 }).unwrap().has_ant());
+
+        let old = shared.replace(World::new(Grid::new(2, 2)));
+        assert_eq!(old.swarm_size(Color::Red), 1);
+        assert_eq!(shared.read(|world| world.grid().width()), 2);
+    }
+
+    #[test]
+    fn follows_simulator_from_another_thread() {
+        let mut world = World::new(Grid::new(10, 1));
+        let id = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let shared = SharedWorld::new(world.clone());
+        let mut programs = HashMap::new();
+        programs.insert(
+            Color::Red,
+            parse_program("start: Move start start").unwrap(),
+        );
+        let mut simulator = Simulator::new(world, programs, Box::new(shared.clone()), 0).unwrap();
+        simulator.run_for(4);
+
+        let viewer = {
+            let shared = shared.clone();
+            thread::spawn(move || shared.ant_position(id))
+        };
+        assert_eq!(viewer.join().unwrap(), Some(Position { x: 4, y: 0 }));
+        assert!(shared.to_world() == *simulator.world());
+    }
+}
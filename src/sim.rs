@@ -1,33 +1,524 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::mem;
+use std::ops::RangeInclusive;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use crate::asm::*;
+use crate::brain::*;
+use crate::data::*;
+use crate::delta::*;
+use crate::history::*;
+use crate::observation::*;
+use crate::observer::*;
+use crate::profile::*;
+use crate::remote::*;
+use crate::replay::*;
+use crate::rng::*;
+use crate::rules::*;
+use crate::scent::*;
+use crate::snapshot::*;
+use crate::stats::*;
+use crate::trace::*;
+use crate::victory::*;
 use crate::world::*;
 
+// Ticks a slow frame of `Simulator::run_realtime` makes up for; a frame
+// later than that drops the rest, so one stall does not leave the run
+// racing to catch up for long after.
+const MAX_CATCH_UP: u32 = 4;
+
 pub trait Renderer {
     fn render(&mut self, world: &World);
+
+    // Called after every step with what the step touched. Renderers that
+    // can redraw only part of the screen override this; the rest get a
+    // full render.
+    fn render_diff(&mut self, world: &World, delta: &WorldDelta) {
+        let _ = delta;
+        self.render(world);
+    }
+}
+
+// Totals since the start of the match. Without a cost table every
+// instruction counts as one tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct InterpreterStats {
+    pub instructions: u64,
+    pub ticks: u64,
+    // Turns ants spent resting, see `RestTable`.
+    pub rested: u64,
+}
+
+// What a step needs besides the interpreter itself.
+struct StepEnv<'a> {
+    world: &'a mut World,
+    rules: &'a RuleSet,
+    scent: Option<&'a ScentField>,
+    rng: &'a mut MatchRng,
+    round: u32,
+    replay: Option<&'a mut Replay>,
+    rng_audit: bool,
+    stats: Option<&'a mut StatsSeries>,
+    tracer: Option<&'a mut Tracer>,
+    profiler: Option<&'a mut Profiler>,
+    observers: &'a mut [Box<dyn Observer>],
+    deaths: &'a mut Vec<DeathRecord>,
+    // `None` when nobody is going to look at it.
+    delta: Option<&'a mut WorldDelta>,
+}
+
+// What one ant did with its turn.
+struct AntTurn {
+    ant_id: AntId,
+    old_position: Position,
+    old_instr: InstrIdx,
+    next_instr: InstrIdx,
+    old_registers: Registers,
+    old_call_stack: CallStack,
+    action: Option<Action>,
+    // Where food the ant dropped spilled to.
+    spilled: Vec<Position>,
+    // It tried to move and could not.
+    blocked: bool,
 }
 
 struct Interpreter {
-    program: Program,
+    // One program per caste; an empty one leaves the caste on caste 0's.
+    castes: Vec<Program>,
     color: Color,
+    energy: HashMap<AntId, u32>,
+    stats: InterpreterStats,
+    // Decides for the swarm instead of the program when set.
+    brain: Option<Box<dyn Brain>>,
 }
 
 impl Interpreter {
-    fn step_brains(&mut self, world: &mut World) {
-        let ant_ids: Vec<_> = world.swarm_ids(self.color).collect();
-        for ant_id in ant_ids {
-            let mut ant = world.ant_mut(ant_id);
-            let instr = self.program[ant.instr_pointer()];
-            let next_instr = instr.eval(&mut ant);
-            ant.update_instr_pointer(next_instr)
+    fn new(program: Program, color: Color) -> Self {
+        Interpreter {
+            castes: vec![program],
+            color,
+            energy: HashMap::new(),
+            stats: InterpreterStats::default(),
+            brain: None,
+        }
+    }
+
+    fn program(&self, caste: Caste) -> &Program {
+        &self.castes[usize::from(self.running_caste(caste))]
+    }
+
+    // Whether `ant` points into the program it runs. Brains keep no
+    // instruction pointer, so any will do for them.
+    fn can_run(&self, ant: Ant<'_>) -> bool {
+        self.brain.is_some() || ant.instr_pointer() < self.program(ant.caste()).len()
+    }
+
+    // The caste whose program ants of `caste` run.
+    fn running_caste(&self, caste: Caste) -> Caste {
+        match self.castes.get(usize::from(caste)) {
+            Some(program) if !program.is_empty() => caste,
+            _ => 0,
+        }
+    }
+
+    // Runs one ant for a round: a single instruction, or as many as its
+    // energy pays for under a cost table. A resting ant does nothing, and
+    // an ant sent to rest stops there.
+    fn run_ant(&mut self, env: &mut StepEnv, census: SwarmCensus, ant_id: AntId) {
+        let Some(mut ant) = env.world.ant_mut(ant_id) else {
+            return;
+        };
+        if ant.take_rest() {
+            self.stats.rested += 1;
+            return;
+        }
+        if self.brain.is_some() {
+            self.ask_brain(env, census, ant_id);
+            return;
+        }
+        let Some(costs) = env.rules.costs.get(&self.color).copied() else {
+            match env.rules.instruction_budget {
+                Some(budget) => self.run_ant_to_action(env, census, ant_id, budget),
+                None => self.step_ant(env, census, ant_id, 1),
+            }
+            return;
+        };
+        let mut energy = self.energy.remove(&ant_id).unwrap_or(0) + costs.budget();
+        while let Some(ant) = env.world.ant(ant_id) {
+            let instr = self.program(ant.caste())[ant.instr_pointer()];
+            let cost = costs.cost(instr.kind());
+            if cost > energy {
+                self.energy.insert(ant_id, energy);
+                break;
+            }
+            energy -= cost;
+            self.step_ant(env, census, ant_id, cost);
+            if env.world.ant(ant_id).is_some_and(|ant| ant.resting() > 0) {
+                self.energy.insert(ant_id, energy);
+                break;
+            }
+        }
+    }
+
+    // Runs instructions until one is physical or `budget` have run, so an
+    // endless loop of branches still ends the turn. One always runs.
+    fn run_ant_to_action(
+        &mut self,
+        env: &mut StepEnv,
+        census: SwarmCensus,
+        ant_id: AntId,
+        budget: u32,
+    ) {
+        for _ in 0..budget.max(1) {
+            let Some(ant) = env.world.ant(ant_id) else {
+                break;
+            };
+            let kind = self.program(ant.caste())[ant.instr_pointer()].kind();
+            self.step_ant(env, census, ant_id, 1);
+            let resting = env.world.ant(ant_id).is_none_or(|ant| ant.resting() > 0);
+            if kind.is_physical() || resting {
+                break;
+            }
+        }
+    }
+
+    fn step_ant(&mut self, env: &mut StepEnv, census: SwarmCensus, ant_id: AntId, cost: u32) {
+        self.stats.instructions += 1;
+        self.stats.ticks += u64::from(cost);
+        let mut ctx = EvalContext {
+            rules: env.rules,
+            census,
+            rng: env.rng.for_ant(self.color, ant_id),
+            scent: env.scent,
+        };
+        let mut ant = env.world.ant_mut(ant_id).unwrap();
+        let old_position = ant.position();
+        let old_instr = ant.instr_pointer();
+        let old_registers = ant.registers();
+        let old_call_stack = ant.call_stack();
+        let caste = self.running_caste(ant.caste());
+        let instr = self.castes[usize::from(caste)][old_instr];
+        // Drawing again from a copy gives the same number.
+        let flip = match instr {
+            Instr::Flip { n, .. } if n > 0 && env.rng_audit => Some((n, ctx.rng.clone().below(n))),
+            _ => None,
+        };
+        let command = instr.command(&mut ant, &mut ctx);
+        let mut spilled = Vec::new();
+        let (next_instr, action) = command.resolve(|action| {
+            env.world
+                .apply(ant_id, action, env.rules)
+                .map(|cells| spilled = cells)
+                .is_ok()
+        });
+        let mut ant = env.world.ant_mut(ant_id).unwrap();
+        ant.update_instr_pointer(next_instr);
+        if let (Some((bound, value)), Some(replay)) = (flip, env.replay.as_deref_mut()) {
+            replay.record_draw(RngDraw {
+                round: env.round,
+                purpose: DrawPurpose::Flip(ant_id),
+                bound,
+                value,
+            });
+        }
+        if let Some(profiler) = env.profiler.as_deref_mut() {
+            let outcome = Outcome::of(instr, next_instr, action);
+            profiler.record(self.color, caste, old_instr, outcome);
+        }
+        if let Some(tracer) = env.tracer.as_deref_mut().filter(|t| t.traces(ant_id)) {
+            tracer.record(&TraceEntry {
+                round: env.round,
+                ant: ant_id,
+                instr_pointer: old_instr,
+                instr,
+                outcome: Outcome::of(instr, next_instr, action),
+                next_instr,
+                position: ant.position(),
+                direction: ant.direction(),
+            });
+        }
+        let turn = AntTurn {
+            ant_id,
+            old_position,
+            old_instr,
+            next_instr,
+            old_registers,
+            old_call_stack,
+            action,
+            spilled,
+            blocked: matches!(instr, Instr::Move { .. }) && action.is_none(),
+        };
+        self.finish_turn(env, turn);
+    }
+
+    // A brain decides one action a round, whatever the costs. Actions the
+    // world refuses count as doing nothing.
+    fn ask_brain(&mut self, env: &mut StepEnv, census: SwarmCensus, ant_id: AntId) {
+        self.stats.instructions += 1;
+        self.stats.ticks += 1;
+        let mut observation = Observation::of(env.world, ant_id, env.round).unwrap();
+        observation.census = env.rules.swarm_sensing.then_some(census);
+        if env.rules.foe_markers == MarkerVisibility::Private {
+            observation.hide_foe_markers();
+        }
+        let brain = self.brain.as_mut().unwrap();
+        let wanted = brain.decide(env.world.ant(ant_id).unwrap(), &observation);
+        let old_position = env.world.ant(ant_id).unwrap().position();
+        let mut spilled = Vec::new();
+        let action = wanted.filter(|&action| {
+            env.world
+                .apply(ant_id, action, env.rules)
+                .map(|cells| spilled = cells)
+                .is_ok()
+        });
+        let ant = env.world.ant(ant_id).unwrap();
+        let instr_pointer = ant.instr_pointer();
+        if let Some(wanted) = wanted {
+            brain.outcome(ant_id, wanted, action.is_some());
+        }
+        let turn = AntTurn {
+            ant_id,
+            old_position,
+            old_instr: instr_pointer,
+            next_instr: instr_pointer,
+            old_registers: ant.registers(),
+            old_call_stack: ant.call_stack(),
+            action,
+            spilled,
+            blocked: wanted == Some(Action::Move) && action.is_none(),
+        };
+        self.finish_turn(env, turn);
+    }
+
+    // Everything that follows from a turn: recording, events and combat.
+    fn finish_turn(&mut self, env: &mut StepEnv, turn: AntTurn) {
+        let AntTurn {
+            ant_id,
+            old_position,
+            old_instr,
+            next_instr,
+            old_registers,
+            old_call_stack,
+            action,
+            spilled,
+            blocked,
+        } = turn;
+        let ant = env.world.ant(ant_id).unwrap();
+        let new_position = ant.position();
+        let registers = Some(ant.registers()).filter(|&registers| registers != old_registers);
+        let call_stack = Some(ant.call_stack()).filter(|&stack| stack != old_call_stack);
+        let changed = action.is_some()
+            || next_instr != old_instr
+            || registers.is_some()
+            || call_stack.is_some();
+        if let Some(replay) = env.replay.as_deref_mut() {
+            if changed {
+                replay.record(ant_id, action, next_instr, registers, call_stack);
+            }
+        }
+        env.world.record_occupancy(ant_id, env.round, action);
+        if blocked {
+            if let Some(stats) = env.stats.as_deref_mut() {
+                stats.count_blocked(self.color);
+            }
+        }
+        if let Some(delta) = env.delta.as_deref_mut() {
+            if changed {
+                delta.mark_ant(ant_id);
+            }
+            match action {
+                Some(Action::Move) => {
+                    delta.mark_cell(old_position);
+                    delta.mark_cell(new_position);
+                }
+                Some(_) => delta.mark_cell(new_position),
+                None => {}
+            }
+            for &position in &spilled {
+                delta.mark_cell(position);
+            }
+        }
+        if !env.observers.is_empty() {
+            let event = match action {
+                Some(Action::Move) => Some(Event::AntMoved {
+                    ant: ant_id,
+                    from: old_position,
+                    to: new_position,
+                }),
+                Some(Action::Turn(direction)) => Some(Event::AntTurned {
+                    ant: ant_id,
+                    direction,
+                }),
+                Some(Action::PickUpFood) => Some(Event::FoodPickedUp {
+                    ant: ant_id,
+                    position: new_position,
+                }),
+                Some(Action::DropFood) => Some(Event::FoodDropped {
+                    ant: ant_id,
+                    position: new_position,
+                }),
+                None if blocked => Some(Event::AntBlocked {
+                    ant: ant_id,
+                    position: new_position,
+                }),
+                _ => None,
+            };
+            if let Some(event) = event {
+                emit(env.observers, event);
+            }
+        }
+        if new_position != old_position {
+            if let Some(rule) = env.rules.combat {
+                for (killed, food_dropped) in env.world.resolve_combat(new_position, rule) {
+                    let position = env.world.dead_ant(killed).unwrap().position();
+                    if let Some(delta) = env.delta.as_deref_mut() {
+                        delta.mark_ant(killed);
+                        delta.mark_cell(position);
+                    }
+                    let death = DeathRecord {
+                        ant: killed,
+                        round: env.round,
+                        cause: DeathCause::Combat,
+                        position,
+                        food_dropped,
+                    };
+                    if let Some(replay) = env.replay.as_deref_mut() {
+                        replay.record_death(death);
+                    }
+                    env.deaths.push(death);
+                    emit(
+                        env.observers,
+                        Event::AntKilled {
+                            ant: killed,
+                            position,
+                        },
+                    );
+                }
+            }
         }
     }
 }
 
+fn emit(observers: &mut [Box<dyn Observer>], event: Event) {
+    for observer in observers {
+        observer.on_event(event);
+    }
+}
+
+// The order in which ants act within a round.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TurnOrder {
+    // Each swarm in turn, in color order. Simple, but the first color
+    // always moves first.
+    #[default]
+    ByColor,
+    // All ants by id, whatever their color.
+    ById,
+    // One ant of each swarm in turn, each swarm in id order; once a swarm
+    // runs out the rest go one after another.
+    Alternating,
+    // A fresh permutation of all ants every round, drawn from the match
+    // seed.
+    Shuffled,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SimulatorError {
+    InvalidProgram {
+        color: Color,
+        errors: Vec<ProgramError>,
+    },
+    InvalidCaste(Caste),
+    // Castes are only for colors run by a program of their own.
+    NoProgram(Color),
+}
+
+impl fmt::Display for SimulatorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SimulatorError::InvalidProgram { color, errors } => write!(
+                f,
+                "invalid program for {:?}: {}",
+                color,
+                describe_errors(errors)
+            ),
+            SimulatorError::InvalidCaste(caste) => write!(f, "no such caste: {}", caste),
+            SimulatorError::NoProgram(color) => write!(f, "{:?} has no program for castes", color),
+        }
+    }
+}
+
+impl std::error::Error for SimulatorError {}
+
 pub struct Simulator {
     world: World,
     interpreters: Vec<Interpreter>,
-    renderer: Box<dyn Renderer>,
+    renderer: Option<Box<dyn Renderer>>,
+    rules: RuleSet,
+    rng: MatchRng,
+    // Made on the first round played under a `ScentRule`.
+    scent: Option<ScentField>,
+    round: u32,
+    victory_condition: Option<VictoryCondition>,
+    victory: Option<Victory>,
+    end_conditions: Vec<EndCondition>,
+    result: Option<MatchResult>,
+    turn_order: TurnOrder,
+    replay: Option<Replay>,
+    rng_audit: bool,
+    step_stats: Option<StatsSeries>,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    state_hashes: Option<Vec<u64>>,
+    history: Option<History>,
+    observers: Vec<Box<dyn Observer>>,
+    deaths: Vec<DeathRecord>,
+    delta: WorldDelta,
+    // Set while a round is played one turn at a time.
+    in_progress: Option<RoundInProgress>,
+    spare_round: RoundInProgress,
+}
+
+// An interpreter's counters and energy, as kept in snapshots.
+type InterpreterState = (InterpreterStats, HashMap<AntId, u32>);
+
+// How far the match has got, as kept in snapshots.
+struct Progress {
+    rng: MatchRng,
+    scent: Option<ScentField>,
+    round: u32,
+    victory: Option<Victory>,
+    result: Option<MatchResult>,
+}
+
+impl Progress {
+    fn decode(input: &mut Decoder, world: &World) -> Result<Progress, SnapshotError> {
+        Ok(Progress {
+            rng: MatchRng::decode(input, world.ants_ever())?,
+            scent: match input.bool()? {
+                true => Some(ScentField::decode(input, world.grid())?),
+                false => None,
+            },
+            round: input.u32()?,
+            victory: Victory::decode(input)?,
+            result: MatchResult::decode(input)?,
+        })
+    }
+}
+
+// Finished rounds hand theirs on to the next one as `Simulator::spare_round`,
+// so once the buffers have grown a round allocates nothing.
+#[derive(Default)]
+struct RoundInProgress {
+    schedule: Vec<(usize, AntId)>,
+    next: usize,
+    // Taken when the swarm's first ant acts, so with `ByColor` a swarm sees
+    // the effects of the swarms that went before it.
+    censuses: Vec<Option<SwarmCensus>>,
+    // Room for `schedule` to work in.
+    scratch: Vec<(usize, AntId)>,
 }
 
 impl Simulator {
@@ -35,22 +526,2745 @@ impl Simulator {
         world: World,
         programs: HashMap<Color, Program>,
         renderer: Box<dyn Renderer>,
-    ) -> Self {
-        let interpreters = programs
+        seed: u64,
+    ) -> Result<Self, SimulatorError> {
+        Simulator::headless(world, programs, seed)
+            .map(|simulator| simulator.with_renderer(renderer))
+    }
+
+    // A simulator that renders nothing, for batch runs and tests.
+    pub fn headless(
+        world: World,
+        programs: HashMap<Color, Program>,
+        seed: u64,
+    ) -> Result<Self, SimulatorError> {
+        let mut colors: Vec<_> = programs.keys().copied().collect();
+        colors.sort();
+        for color in colors {
+            if let Err(errors) = validate(&programs[&color]) {
+                let errors: Vec<_> = errors.into_iter().filter(|e| e.is_fatal()).collect();
+                if !errors.is_empty() {
+                    return Err(SimulatorError::InvalidProgram { color, errors });
+                }
+            }
+        }
+        let mut interpreters: Vec<_> = programs
             .into_iter()
-            .map(|(color, program)| Interpreter { program, color })
+            .map(|(color, program)| Interpreter::new(program, color))
             .collect();
-        Self {
+        interpreters.sort_by_key(|interpreter| interpreter.color);
+        Ok(Self {
             world,
             interpreters,
-            renderer,
+            renderer: None,
+            rules: RuleSet::default(),
+            rng: MatchRng::new(seed, RngStreams::default()),
+            scent: None,
+            round: 0,
+            victory_condition: None,
+            victory: None,
+            end_conditions: Vec::new(),
+            result: None,
+            turn_order: TurnOrder::default(),
+            replay: None,
+            rng_audit: false,
+            step_stats: None,
+            tracer: None,
+            profiler: None,
+            state_hashes: None,
+            history: None,
+            observers: Vec::new(),
+            deaths: Vec::new(),
+            delta: WorldDelta::new(),
+            in_progress: None,
+            spare_round: RoundInProgress::default(),
+        })
+    }
+
+    // Continues from a world taken out of another match (or built by hand)
+    // with a new set of brains; `remap` picks each ant's starting
+    // instruction in its new program.
+    pub fn warm_start(
+        mut world: World,
+        programs: HashMap<Color, Program>,
+        renderer: Box<dyn Renderer>,
+        seed: u64,
+        remap: impl FnMut(Ant<'_>) -> InstrIdx,
+    ) -> Result<Self, SimulatorError> {
+        world.remap_instr_pointers(remap);
+        Simulator::new(world, programs, renderer, seed)
+    }
+
+    // Hands `color`'s ants to `brain`, in place of its program if it had
+    // one. A brain that is a program underneath becomes the color's program.
+    pub fn with_brain(mut self, color: Color, brain: Box<dyn Brain>) -> Self {
+        if let Some(program) = brain.program() {
+            let interpreter = Interpreter::new(program.clone(), color);
+            self.interpreters.retain(|i| i.color != color);
+            self.interpreters.push(interpreter);
+            self.interpreters
+                .sort_by_key(|interpreter| interpreter.color);
+            return self;
+        }
+        if !self.interpreters.iter().any(|i| i.color == color) {
+            self.interpreters
+                .push(Interpreter::new(Program::new(), color));
+            self.interpreters
+                .sort_by_key(|interpreter| interpreter.color);
+        }
+        let interpreter = self.interpreters.iter_mut().find(|i| i.color == color);
+        interpreter.unwrap().brain = Some(brain);
+        self
+    }
+
+    // Runs `program` for `color`'s ants of `caste` in place of the color's
+    // own program, which castes without one keep running.
+    pub fn with_caste(
+        mut self,
+        color: Color,
+        caste: Caste,
+        program: Program,
+    ) -> Result<Self, SimulatorError> {
+        if usize::from(caste) >= MAX_CASTES {
+            return Err(SimulatorError::InvalidCaste(caste));
+        }
+        if let Err(errors) = validate(&program) {
+            let errors: Vec<_> = errors.into_iter().filter(|e| e.is_fatal()).collect();
+            if !errors.is_empty() {
+                return Err(SimulatorError::InvalidProgram { color, errors });
+            }
+        }
+        let interpreter = self
+            .interpreters
+            .iter_mut()
+            .find(|interpreter| interpreter.color == color && !interpreter.castes[0].is_empty())
+            .ok_or(SimulatorError::NoProgram(color))?;
+        let caste = usize::from(caste);
+        if interpreter.castes.len() <= caste {
+            interpreter.castes.resize(caste + 1, Program::new());
+        }
+        interpreter.castes[caste] = program;
+        Ok(self)
+    }
+
+    pub fn with_remote_brain(self, color: Color, brain: RemoteBrain) -> Self {
+        self.with_brain(color, Box::new(brain))
+    }
+
+    // Why `color`'s brain stopped deciding, if it has one that did.
+    pub fn brain_failure(&self, color: Color) -> Option<String> {
+        self.interpreters
+            .iter()
+            .find(|interpreter| interpreter.color == color)
+            .and_then(|interpreter| interpreter.brain.as_ref()?.failure())
+    }
+
+    pub fn with_renderer(mut self, renderer: Box<dyn Renderer>) -> Self {
+        self.renderer = Some(renderer);
+        self
+    }
+
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn with_rng_streams(mut self, streams: RngStreams) -> Self {
+        self.rng = MatchRng::new(self.rng.seed(), streams);
+        self
+    }
+
+    // Replays also log every random number drawn, see `Replay::draws`.
+    pub fn with_rng_audit(mut self) -> Self {
+        self.rng_audit = true;
+        self
+    }
+
+    pub fn with_turn_order(mut self, turn_order: TurnOrder) -> Self {
+        self.turn_order = turn_order;
+        self
+    }
+
+    pub fn with_victory_condition(mut self, condition: VictoryCondition) -> Self {
+        self.victory_condition = Some(condition);
+        self
+    }
+
+    // The match ends as soon as any end condition holds or the victory
+    // condition decides it.
+    pub fn with_end_condition(mut self, condition: EndCondition) -> Self {
+        self.end_conditions.push(condition);
+        self
+    }
+
+    // Observers are notified in the order they were added.
+    pub fn add_observer(&mut self, observer: Box<dyn Observer>) {
+        self.observers.push(observer);
+    }
+
+    // Starts recording a replay from the current world; any replay recorded
+    // so far is discarded.
+    pub fn start_recording(&mut self) {
+        let mut replay = Replay::new(self.world.clone(), self.rules.clone());
+        for interpreter in &self.interpreters {
+            if interpreter.brain.is_some() {
+                replay.add_brain(interpreter.color);
+                continue;
+            }
+            for caste in 0..interpreter.castes.len() as Caste {
+                if interpreter.running_caste(caste) == caste {
+                    replay.add_program(interpreter.color, caste, interpreter.program(caste));
+                }
+            }
+        }
+        self.replay = Some(replay);
+    }
+
+    pub fn replay(&self) -> Option<&Replay> {
+        self.replay.as_ref()
+    }
+
+    // Stops recording.
+    pub fn take_replay(&mut self) -> Option<Replay> {
+        self.replay.take()
+    }
+
+    // Starts collecting per-step statistics; anything collected so far is
+    // discarded.
+    pub fn start_collecting_stats(&mut self) {
+        self.step_stats = Some(StatsSeries::new());
+    }
+
+    // As `start_collecting_stats`, with the swarm metrics of every step.
+    pub fn start_collecting_swarm_metrics(&mut self) {
+        self.step_stats = Some(StatsSeries::new().with_swarm_metrics());
+    }
+
+    pub fn step_stats(&self) -> Option<&StatsSeries> {
+        self.step_stats.as_ref()
+    }
+
+    // Stops collecting.
+    pub fn take_step_stats(&mut self) -> Option<StatsSeries> {
+        self.step_stats.take()
+    }
+
+    // Starts keeping `World::state_hash` after every step; any hashes kept
+    // so far are discarded.
+    pub fn start_hashing(&mut self) {
+        self.state_hashes = Some(Vec::new());
+    }
+
+    pub fn state_hashes(&self) -> Option<&[u64]> {
+        self.state_hashes.as_deref()
+    }
+
+    // Stops hashing.
+    pub fn take_state_hashes(&mut self) -> Option<Vec<u64>> {
+        self.state_hashes.take()
+    }
+
+    // Replaces any tracer set before.
+    pub fn start_tracing(&mut self, tracer: Tracer) {
+        self.tracer = Some(tracer);
+    }
+
+    // Stops tracing.
+    pub fn take_tracer(&mut self) -> Option<Tracer> {
+        self.tracer.take()
+    }
+
+    // Starts counting how often each instruction of every program runs and
+    // which way it branches; any counts so far are discarded. Programs
+    // added after this are not counted.
+    pub fn start_profiling(&mut self) {
+        let programs = self.interpreters.iter().flat_map(|interpreter| {
+            (interpreter.castes.iter().enumerate())
+                .filter(|(_, program)| !program.is_empty())
+                .map(|(caste, program)| (interpreter.color, caste as Caste, program.clone()))
+        });
+        self.profiler = Some(Profiler::new(programs));
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // Stops profiling.
+    pub fn take_profiler(&mut self) -> Option<Profiler> {
+        self.profiler.take()
+    }
+
+    // Starts keeping the rounds played from here on for `seek` and
+    // `step_back`; any history kept so far is discarded. Call it between
+    // rounds.
+    pub fn start_history(&mut self, config: HistoryConfig) {
+        self.history = Some(History::new(
+            config,
+            self.round,
+            self.snapshot(),
+            self.world.clone(),
+        ));
+    }
+
+    pub fn stop_history(&mut self) {
+        self.history = None;
+    }
+
+    // The rounds `seek` can go to.
+    pub fn history_rounds(&self) -> Option<RangeInclusive<u32>> {
+        self.history.as_ref().map(History::rounds)
+    }
+
+    // Puts the match back as it was after `round`, which may be before or
+    // after the current one. As with `restore`, a replay being recorded is
+    // dropped; collected stats and state hashes are left alone. Stepping on
+    // from an earlier round forgets the rounds that came after it.
+    pub fn seek(&mut self, round: u32) -> Result<(), HistoryError> {
+        let mut history = self.history.take().ok_or(HistoryError::NotRecording)?;
+        let result = history.frames_to(round).and_then(|(key, patches)| {
+            self.load(key).map_err(HistoryError::Snapshot)?;
+            for patch in patches {
+                self.apply_patch(patch).map_err(HistoryError::Snapshot)?;
+            }
+            Ok(())
+        });
+        if result.is_ok() {
+            history.set_base(self.world.clone());
+            if let Some(renderer) = &mut self.renderer {
+                renderer.render(&self.world);
+            }
+        }
+        self.history = Some(history);
+        result
+    }
+
+    // Back to the start of the round under way, or else to the round
+    // before.
+    pub fn step_back(&mut self) -> Result<(), HistoryError> {
+        if self.history.is_none() {
+            return Err(HistoryError::NotRecording);
+        }
+        if self.in_progress.is_some() {
+            return self.seek(self.round);
+        }
+        let round = self.round.checked_sub(1).ok_or(HistoryError::AtStart)?;
+        self.seek(round)
+    }
+
+    pub fn rules(&self) -> &RuleSet {
+        &self.rules
+    }
+
+    // `None` until a round has been played under a `ScentRule`.
+    pub fn scent(&self) -> Option<&ScentField> {
+        self.scent.as_ref()
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn round(&self) -> u32 {
+        self.round
+    }
+
+    pub fn score(&self, color: Color) -> u32 {
+        self.world.food_in_anthill(color)
+    }
+
+    pub fn stats(&self, color: Color) -> Option<InterpreterStats> {
+        self.interpreters
+            .iter()
+            .find(|interpreter| interpreter.color == color)
+            .map(|interpreter| interpreter.stats)
+    }
+
+    pub fn victory(&self) -> Option<Victory> {
+        self.victory
+    }
+
+    // Set once the match is over and kept from then on. Stepping past the
+    // end is allowed but does not change the result.
+    pub fn result(&self) -> Option<MatchResult> {
+        self.result
+    }
+
+    // Every ant that died so far, in the order they died.
+    pub fn deaths(&self) -> &[DeathRecord] {
+        &self.deaths
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.result.is_some()
+    }
+
+    // A compact checkpoint of everything that changes as the match runs:
+    // the world, the rng, the round, how the match stands and the
+    // interpreters' energy and counters. Restoring it into a simulator set
+    // up with the same programs and rules continues the run exactly as this
+    // one would, as long as it was taken between rounds.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut out = Encoder::new();
+        self.encode_interpreters(&mut out);
+        self.world.encode(&mut out);
+        self.encode_progress(&mut out);
+        out.into_bytes()
+    }
+
+    // Leaves the simulator untouched if the snapshot is damaged or was
+    // taken with other programs. A replay being recorded is dropped, as it
+    // no longer leads up to the current world, and history starts over from
+    // the restored round.
+    pub fn restore(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        self.load(bytes)?;
+        if let Some(history) = &self.history {
+            let config = history.config();
+            self.start_history(config);
         }
+        Ok(())
     }
 
+    fn load(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let mut input = Decoder::new(bytes)?;
+        let interpreters = self.decode_interpreters(&mut input)?;
+        let world = World::decode(&mut input)?;
+        let progress = Progress::decode(&mut input, &world)?;
+        input.finish()?;
+        let programs_fit = world.ants().all(|ant| {
+            self.interpreters
+                .iter()
+                .find(|interpreter| interpreter.color == ant.color())
+                .is_none_or(|interpreter| interpreter.can_run(ant))
+        });
+        if !programs_fit {
+            return Err(SnapshotError::Corrupt);
+        }
+
+        self.set_interpreters(interpreters);
+        self.world = world.with_rules(*self.world.rules());
+        self.set_progress(progress);
+        self.replay = None;
+        self.delta.clear();
+        self.in_progress = None;
+        Ok(())
+    }
+
+    // A step's worth of history: the interpreters and progress in full,
+    // and the world as a patch against the round before.
+    fn encode_patch(&self, base: &World) -> Vec<u8> {
+        let mut out = Encoder::bare();
+        self.encode_interpreters(&mut out);
+        self.world.encode_patch(base, &mut out);
+        self.encode_progress(&mut out);
+        out.into_bytes()
+    }
+
+    fn apply_patch(&mut self, bytes: &[u8]) -> Result<(), SnapshotError> {
+        let mut input = Decoder::bare(bytes);
+        let interpreters = self.decode_interpreters(&mut input)?;
+        self.world.apply_patch(&mut input)?;
+        let progress = Progress::decode(&mut input, &self.world)?;
+        input.finish()?;
+        self.set_interpreters(interpreters);
+        self.set_progress(progress);
+        Ok(())
+    }
+
+    fn encode_interpreters(&self, out: &mut Encoder) {
+        out.usize(self.interpreters.len());
+        for interpreter in &self.interpreters {
+            out.color(interpreter.color);
+            out.usize(interpreter.castes.len());
+            for program in &interpreter.castes {
+                out.u64(program_hash(program));
+            }
+            out.u64(interpreter.stats.instructions);
+            out.u64(interpreter.stats.ticks);
+            out.u64(interpreter.stats.rested);
+            let mut energy: Vec<_> = interpreter.energy.iter().collect();
+            energy.sort();
+            out.usize(energy.len());
+            for (&id, &ticks) in energy {
+                out.usize(id);
+                out.u32(ticks);
+            }
+        }
+    }
+
+    fn decode_interpreters(
+        &self,
+        input: &mut Decoder,
+    ) -> Result<Vec<InterpreterState>, SnapshotError> {
+        if input.usize()? != self.interpreters.len() {
+            return Err(SnapshotError::Corrupt);
+        }
+        let mut interpreters = Vec::new();
+        for interpreter in &self.interpreters {
+            let color = input.color()?;
+            if color != interpreter.color {
+                return Err(SnapshotError::ProgramMismatch(color));
+            }
+            if input.usize()? != interpreter.castes.len() {
+                return Err(SnapshotError::ProgramMismatch(color));
+            }
+            for program in &interpreter.castes {
+                if input.u64()? != program_hash(program) {
+                    return Err(SnapshotError::ProgramMismatch(color));
+                }
+            }
+            let stats = InterpreterStats {
+                instructions: input.u64()?,
+                ticks: input.u64()?,
+                rested: input.u64()?,
+            };
+            let mut energy = HashMap::new();
+            for _ in 0..input.count()? {
+                energy.insert(input.usize()?, input.u32()?);
+            }
+            interpreters.push((stats, energy));
+        }
+        Ok(interpreters)
+    }
+
+    fn set_interpreters(&mut self, states: Vec<InterpreterState>) {
+        for (interpreter, (stats, energy)) in self.interpreters.iter_mut().zip(states) {
+            interpreter.stats = stats;
+            interpreter.energy = energy;
+        }
+    }
+
+    fn encode_progress(&self, out: &mut Encoder) {
+        self.rng.encode(out);
+        out.bool(self.scent.is_some());
+        if let Some(scent) = &self.scent {
+            scent.encode(out);
+        }
+        out.u32(self.round);
+        Victory::encode(self.victory, out);
+        MatchResult::encode(self.result, out);
+    }
+
+    fn set_progress(&mut self, progress: Progress) {
+        self.rng = progress.rng;
+        self.scent = progress.scent;
+        self.round = progress.round;
+        // Deaths are not kept in snapshots; forget those yet to happen.
+        self.deaths.retain(|death| death.round < progress.round);
+        self.victory = progress.victory;
+        self.result = progress.result;
+    }
+
+    // Pairs of interpreter index and ant id, in the order the ants act.
+    fn schedule(&mut self, schedule: &mut Vec<(usize, AntId)>, scratch: &mut Vec<(usize, AntId)>) {
+        // By color, each swarm in id order. Swarms are kept that way, so
+        // the sorts only check.
+        schedule.clear();
+        let mut ends = [0; MAX_TEAMS];
+        for (index, interpreter) in self.interpreters.iter().enumerate() {
+            let start = schedule.len();
+            schedule.extend(
+                self.world
+                    .swarm_ids(interpreter.color)
+                    .map(|id| (index, id)),
+            );
+            schedule[start..].sort_unstable_by_key(|&(_, id)| id);
+            ends[index] = schedule.len();
+        }
+        match self.turn_order {
+            TurnOrder::ByColor => {}
+            TurnOrder::ById => schedule.sort_unstable_by_key(|&(_, id)| id),
+            TurnOrder::Alternating => {
+                scratch.clear();
+                scratch.append(schedule);
+                let ends = &ends[..self.interpreters.len()];
+                let starts = |index: usize| if index == 0 { 0 } else { ends[index - 1] };
+                let longest = (0..ends.len())
+                    .map(|index| ends[index] - starts(index))
+                    .max()
+                    .unwrap_or(0);
+                for i in 0..longest {
+                    for (index, &end) in ends.iter().enumerate() {
+                        if starts(index) + i < end {
+                            schedule.push(scratch[starts(index) + i]);
+                        }
+                    }
+                }
+            }
+            TurnOrder::Shuffled => {
+                schedule.sort_unstable_by_key(|&(_, id)| id);
+                let mut rng = self.rng.schedule(self.round);
+                let mut audit = self.replay.as_mut().filter(|_| self.rng_audit);
+                for i in (1..schedule.len()).rev() {
+                    let j = rng.below(i as u32 + 1);
+                    if let Some(replay) = audit.as_deref_mut() {
+                        replay.record_draw(RngDraw {
+                            round: self.round,
+                            purpose: DrawPurpose::Schedule,
+                            bound: i as u32 + 1,
+                            value: j,
+                        });
+                    }
+                    schedule.swap(i, j as usize);
+                }
+            }
+        }
+    }
+
+    // Plays the rest of the round, all of it unless a debugger stopped
+    // partway through.
     pub fn step(&mut self) {
-        for interpreter in &mut self.interpreters {
-            interpreter.step_brains(&mut self.world);
+        while let Some((index, ant_id)) = self.next_turn() {
+            self.play_turn(index, ant_id);
+        }
+        self.finish_round();
+    }
+
+    // Plays the next ant's turn and, after the last one, the end of the
+    // round. Returns the ant, or `None` if nobody was left to act.
+    pub(crate) fn step_turn(&mut self) -> Option<AntId> {
+        let turn = self.next_turn();
+        if let Some((index, ant_id)) = turn {
+            self.play_turn(index, ant_id);
+        }
+        if self.next_turn().is_none() {
+            self.finish_round();
+        }
+        turn.map(|(_, ant_id)| ant_id)
+    }
+
+    // The interpreter and ant whose turn is next, starting a round if none
+    // is under way.
+    fn next_turn(&mut self) -> Option<(usize, AntId)> {
+        if self.in_progress.is_none() {
+            self.delta.clear();
+            let mut round = mem::take(&mut self.spare_round);
+            self.schedule(&mut round.schedule, &mut round.scratch);
+            round.next = 0;
+            round.censuses.clear();
+            round.censuses.resize(self.interpreters.len(), None);
+            self.in_progress = Some(round);
+        }
+        let round = self.in_progress.as_mut().unwrap();
+        while let Some(&(index, ant_id)) = round.schedule.get(round.next) {
+            if self.world.is_alive(ant_id) {
+                return Some((index, ant_id));
+            }
+            round.next += 1;
+        }
+        None
+    }
+
+    fn play_turn(&mut self, index: usize, ant_id: AntId) {
+        let round = self.in_progress.as_mut().unwrap();
+        round.next += 1;
+        let mut env = StepEnv {
+            world: &mut self.world,
+            rules: &self.rules,
+            scent: self.scent.as_ref(),
+            rng: &mut self.rng,
+            round: self.round,
+            replay: self.replay.as_mut(),
+            rng_audit: self.rng_audit,
+            stats: self.step_stats.as_mut(),
+            tracer: self.tracer.as_mut(),
+            profiler: self.profiler.as_mut(),
+            observers: &mut self.observers,
+            deaths: &mut self.deaths,
+            delta: self.renderer.is_some().then_some(&mut self.delta),
+        };
+        let interpreter = &mut self.interpreters[index];
+        // Counting scans the whole grid, so it waits until someone can
+        // sense the result.
+        let census = if env.rules.swarm_sensing {
+            *round.censuses[index]
+                .get_or_insert_with(|| SwarmCensus::of(env.world, interpreter.color))
+        } else {
+            SwarmCensus::default()
+        };
+        interpreter.run_ant(&mut env, census, ant_id);
+    }
+
+    fn finish_round(&mut self) {
+        if let Some(round) = self.in_progress.take() {
+            self.spare_round = round;
+        }
+        if let Some(metabolism) = self.rules.metabolism {
+            for (ant_id, food_dropped) in self.world.metabolize(metabolism) {
+                let ant = self.world.dead_ant(ant_id).unwrap();
+                let position = ant.position();
+                let death = DeathRecord {
+                    ant: ant_id,
+                    round: self.round,
+                    cause: DeathCause::Starvation,
+                    position,
+                    food_dropped,
+                };
+                if let Some(replay) = &mut self.replay {
+                    replay.record_death(death);
+                }
+                self.deaths.push(death);
+                if let Some(stats) = &mut self.step_stats {
+                    stats.count_starved(ant.color());
+                }
+                if self.renderer.is_some() {
+                    self.delta.mark_ant(ant_id);
+                    self.delta.mark_cell(position);
+                }
+                emit(
+                    &mut self.observers,
+                    Event::AntStarved {
+                        ant: ant_id,
+                        position,
+                    },
+                );
+            }
+        }
+        if let Some(rule) = self.rules.spawning {
+            for (ant_id, emptied) in self.world.spawn(rule) {
+                let position = self.world.ant(ant_id).unwrap().position();
+                if self.renderer.is_some() {
+                    self.delta.mark_ant(ant_id);
+                    self.delta.mark_cell(position);
+                    for position in emptied {
+                        self.delta.mark_cell(position);
+                    }
+                }
+                emit(
+                    &mut self.observers,
+                    Event::AntSpawned {
+                        ant: ant_id,
+                        position,
+                    },
+                );
+            }
+        }
+        if let Some(rule) = &self.rules.regrowth {
+            if rule.is_due(self.round + 1) {
+                let mut rng = self.rng.regrowth(self.round);
+                if let Some(replay) = self.replay.as_mut().filter(|_| self.rng_audit) {
+                    // `regrow` draws once for every spawner, in order.
+                    let mut audit = rng.clone();
+                    for &position in &rule.spawners {
+                        replay.record_draw(RngDraw {
+                            round: self.round,
+                            purpose: DrawPurpose::Regrowth(position),
+                            bound: 100,
+                            value: audit.below(100),
+                        });
+                    }
+                }
+                for (position, amount) in self.world.regrow(rule, &mut rng) {
+                    if let Some(replay) = &mut self.replay {
+                        replay.record_growth(position, amount);
+                    }
+                    if self.renderer.is_some() {
+                        self.delta.mark_cell(position);
+                    }
+                }
+            }
+        }
+        if let Some(rule) = &self.rules.scent {
+            let grid = self.world.grid();
+            let scent = self.scent.get_or_insert_with(|| ScentField::new(grid));
+            scent.update(grid, rule);
+        }
+        if let Some(replay) = &mut self.replay {
+            replay.finish_step();
+        }
+        self.round += 1;
+        if let Some(stats) = &mut self.step_stats {
+            stats.finish_step(&self.world, self.round);
+        }
+        if let Some(hashes) = &mut self.state_hashes {
+            hashes.push(self.world.state_hash());
+        }
+        if self.victory.is_none() {
+            self.victory = self
+                .victory_condition
+                .as_ref()
+                .and_then(|condition| condition.evaluate(&self.world, self.round));
+        }
+        if self.result.is_none() {
+            let reason = if self.victory.is_some() {
+                Some(EndReason::Victory)
+            } else {
+                self.end_conditions
+                    .iter()
+                    .find(|condition| condition.holds(&self.world, self.round))
+                    .map(|&condition| EndReason::Condition(condition))
+            };
+            self.result = reason
+                .map(|reason| MatchResult::new(&self.world, self.round, self.victory, reason));
+        }
+        if let Some(mut history) = self.history.take() {
+            let key = history.needs_key();
+            let bytes = if key {
+                self.snapshot()
+            } else {
+                self.encode_patch(history.base())
+            };
+            history.push_round(self.round, key, bytes, self.world.clone());
+            self.history = Some(history);
+        }
+        emit(
+            &mut self.observers,
+            Event::StepCompleted { round: self.round },
+        );
+        if let Some(renderer) = &mut self.renderer {
+            renderer.render_diff(&self.world, &self.delta);
+        }
+    }
+
+    pub fn run_for(&mut self, steps: u32) {
+        for _ in 0..steps {
+            self.step();
+        }
+    }
+
+    // Steps until `done` holds, checking it before every step. Returns the
+    // number of steps taken.
+    pub fn run_until(&mut self, mut done: impl FnMut(&Simulator) -> bool) -> u32 {
+        let mut steps = 0;
+        while !done(self) {
+            self.step();
+            steps += 1;
+        }
+        steps
+    }
+
+    // Steps `tick_hz` times a second of wall-clock time until `until` holds
+    // or the match is over, checking `until` before every step. A frame
+    // that starts late takes the ticks it missed in one go and renders
+    // once, in full, at the end. Returns the number of steps taken.
+    pub fn run_realtime(&mut self, tick_hz: u32, mut until: impl FnMut(&Simulator) -> bool) -> u32 {
+        assert!(tick_hz > 0, "tick rate must be positive");
+        let tick = Duration::from_secs(1) / tick_hz;
+        let mut next_tick = Instant::now();
+        let mut steps = 0;
+        while !until(self) && !self.is_over() {
+            if let Some(wait) = next_tick.checked_duration_since(Instant::now()) {
+                thread::sleep(wait);
+            }
+            let missed = Instant::now()
+                .saturating_duration_since(next_tick)
+                .as_nanos()
+                / tick.as_nanos();
+            let frame = 1 + missed.min(u128::from(MAX_CATCH_UP)) as u32;
+            next_tick = if missed > u128::from(MAX_CATCH_UP) {
+                Instant::now() + tick
+            } else {
+                next_tick + tick * frame
+            };
+
+            let renderer = if frame > 1 {
+                self.renderer.take()
+            } else {
+                None
+            };
+            self.step();
+            steps += 1;
+            for _ in 1..frame {
+                if until(self) || self.is_over() {
+                    break;
+                }
+                self.step();
+                steps += 1;
+            }
+            if let Some(mut renderer) = renderer {
+                renderer.render(&self.world);
+                self.renderer = Some(renderer);
+            }
+        }
+        steps
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Breakpoint {
+    // Any ant about to run this instruction of its swarm's program.
+    Instr(InstrIdx),
+    Ant(AntId),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DebugStop {
+    // The ant is due to act next and has not yet.
+    Breakpoint { breakpoint: Breakpoint, ant: AntId },
+    MatchOver,
+    RoundLimit,
+}
+
+// An ant as the debugger sees it, with the cells around it as they are
+// rather than as the ant would sense them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AntInspection {
+    pub id: AntId,
+    pub color: Color,
+    pub position: Position,
+    pub direction: Direction,
+    pub carried_food: u32,
+    pub hunger: u32,
+    pub registers: Registers,
+    pub call_stack: CallStack,
+    pub instr_pointer: InstrIdx,
+    // `None` for brains other than a program.
+    pub instr: Option<Instr>,
+    pub here: Cell,
+    // Indexed like `Observation::neighbours`; `None` off the edge of a
+    // bounded map.
+    pub neighbours: [Option<Cell>; 6],
+}
+
+// Runs a simulator one ant at a time and stops before ants that hit a
+// breakpoint. Rounds played with the simulator's own `step` in between
+// finish the round under way first.
+pub struct Debugger {
+    simulator: Simulator,
+    breakpoints: BTreeSet<Breakpoint>,
+}
+
+impl Debugger {
+    pub fn new(simulator: Simulator) -> Self {
+        Debugger {
+            simulator,
+            breakpoints: BTreeSet::new(),
+        }
+    }
+
+    pub fn simulator(&self) -> &Simulator {
+        &self.simulator
+    }
+
+    pub fn simulator_mut(&mut self) -> &mut Simulator {
+        &mut self.simulator
+    }
+
+    pub fn into_simulator(self) -> Simulator {
+        self.simulator
+    }
+
+    // Returns `false` if it was already set.
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        self.breakpoints.insert(breakpoint)
+    }
+
+    pub fn remove_breakpoint(&mut self, breakpoint: Breakpoint) -> bool {
+        self.breakpoints.remove(&breakpoint)
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = Breakpoint> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // The ant due to act next, if any ant is left to.
+    pub fn next_ant(&mut self) -> Option<AntId> {
+        self.simulator.next_turn().map(|(_, ant_id)| ant_id)
+    }
+
+    // Plays one ant's turn, finishing the round if it was the last.
+    // Returns the ant that acted.
+    pub fn step_ant(&mut self) -> Option<AntId> {
+        self.simulator.step_turn()
+    }
+
+    // Plays turns until the next ant would hit a breakpoint, the match is
+    // over or `max_rounds` more rounds have been finished. The ant due
+    // first is let through, so continuing from a breakpoint moves on.
+    pub fn continue_until_break(&mut self, max_rounds: u32) -> DebugStop {
+        let last_round = self.simulator.round().saturating_add(max_rounds);
+        let mut first = true;
+        loop {
+            if self.simulator.is_over() {
+                return DebugStop::MatchOver;
+            }
+            if self.simulator.round() >= last_round {
+                return DebugStop::RoundLimit;
+            }
+            if let Some(ant) = self.next_ant() {
+                let hit = self.breakpoint_for(ant);
+                if let (Some(breakpoint), false) = (hit, first) {
+                    return DebugStop::Breakpoint { breakpoint, ant };
+                }
+            }
+            first = false;
+            self.step_ant();
+        }
+    }
+
+    fn breakpoint_for(&self, ant_id: AntId) -> Option<Breakpoint> {
+        let ant = self.simulator.world.ant(ant_id)?;
+        [
+            Breakpoint::Ant(ant_id),
+            Breakpoint::Instr(ant.instr_pointer()),
+        ]
+        .into_iter()
+        .find(|breakpoint| self.breakpoints.contains(breakpoint))
+    }
+
+    pub fn inspect(&self, ant_id: AntId) -> Option<AntInspection> {
+        let world = self.simulator.world();
+        let ant = world.ant(ant_id)?;
+        let grid = world.grid();
+        let program = self
+            .simulator
+            .interpreters
+            .iter()
+            .find(|interpreter| interpreter.color == ant.color() && interpreter.brain.is_none())
+            .map(|interpreter| interpreter.program(ant.caste()));
+        let mut neighbours: [Option<Cell>; 6] = Default::default();
+        for (direction, cell) in grid.neighbors(ant.position()) {
+            neighbours[u32::from(direction) as usize] = Some(cell.clone());
+        }
+        Some(AntInspection {
+            id: ant_id,
+            color: ant.color(),
+            position: ant.position(),
+            direction: ant.direction(),
+            carried_food: ant.carried_food(),
+            hunger: ant.hunger(),
+            registers: ant.registers(),
+            call_stack: ant.call_stack(),
+            instr_pointer: ant.instr_pointer(),
+            instr: program.and_then(|program| program.get(ant.instr_pointer()).copied()),
+            here: grid.cell_at(ant.position())?.clone(),
+            neighbours,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::renderers::NullRenderer;
+
+    #[test]
+    fn victory_is_evaluated_each_round() {
+        let mut world = World::new(Grid::new(10, 10));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world
+            .add_ant(Color::Black, Position { x: 5, y: 5 })
+            .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(
+            Color::Red,
+            vec![Instr::DropFood {
+                success_instr: 0,
+                fail_instr: 0,
+            }],
+        );
+        programs.insert(
+            Color::Black,
+            vec![Instr::DropFood {
+                success_instr: 0,
+                fail_instr: 0,
+            }],
+        );
+        let mut simulator = Simulator::new(world, programs, Box::new(NullRenderer), 0)
+            .unwrap()
+            .with_victory_condition(VictoryCondition::Rounds(3));
+
+        simulator.step();
+        simulator.step();
+        assert_eq!(simulator.round(), 2);
+        assert_eq!(simulator.victory(), None);
+        simulator.step();
+        assert_eq!(simulator.victory(), Some(Victory::Draw));
+        assert_eq!(simulator.result().unwrap().reason, EndReason::Victory);
+    }
+
+    #[test]
+    fn end_conditions() {
+        let mut grid = Grid::new(4, 1);
+        *grid.cell_at_mut(Position { x: 0, y: 0 }).unwrap() = Cell::new_anthill(Color::Red);
+        grid.cell_at_mut(Position { x: 1, y: 0 })
+            .unwrap()
+            .try_drop_food()
+            .unwrap();
+        let mut world = World::new(grid);
+        let id = world.add_ant(Color::Red, Position { x: 1, y: 0 }).unwrap();
+        world.ant_mut(id).unwrap().rotate(Direction::Left);
+        world
+            .add_ant(Color::Black, Position { x: 3, y: 0 })
+            .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(
+            Color::Red,
+            parse_program("pick: PickUpFood move pick\nmove: Move drop move\ndrop: DropFood drop")
+                .unwrap(),
+        );
+        programs.insert(
+            Color::Black,
+            parse_program("start: Turn Left start").unwrap(),
+        );
+        let simulator = || {
+            Simulator::headless(world.clone(), programs.clone(), 0)
+                .unwrap()
+                .with_end_condition(EndCondition::MaxRounds(10))
+        };
+
+        let mut collected = simulator().with_end_condition(EndCondition::AllFoodCollected);
+        collected.run_until(Simulator::is_over);
+        assert_eq!(
+            collected.result(),
+            Some(MatchResult {
+                outcome: Victory::Winner(Color::Red),
+                scores: [0, 1, 0, 0, 0, 0, 0, 0],
+                round: 3,
+                reason: EndReason::Condition(EndCondition::AllFoodCollected),
+            })
+        );
+        collected.run_for(20);
+        assert_eq!(collected.result().unwrap().round, 3);
+
+        let mut timed_out = simulator();
+        assert_eq!(timed_out.run_until(Simulator::is_over), 10);
+        assert_eq!(
+            timed_out.result().unwrap().reason,
+            EndReason::Condition(EndCondition::MaxRounds(10))
+        );
+    }
+
+    fn snapshot_simulator() -> Simulator {
+        let mut world = World::new(Grid::new_torus(8, 8));
+        for x in 0..4 {
+            world.add_ant(Color::Red, Position { x, y: 0 }).unwrap();
+            world.add_ant(Color::Black, Position { x, y: 4 }).unwrap();
+        }
+        let program = parse_program(
+            "
+            start: Flip 3 turn move
+            turn:  Turn Right start
+            move:  Move mark start
+            mark:  Mark 2 start
+            ",
+        )
+        .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program.clone());
+        programs.insert(Color::Black, program);
+        let mut rules = RuleSet::classic();
+        rules
+            .costs
+            .insert(Color::Red, CostTable::new(3).with_cost(InstrKind::Move, 2));
+        Simulator::headless(world, programs, 11)
+            .unwrap()
+            .with_rules(rules)
+            .with_end_condition(EndCondition::MaxRounds(35))
+    }
+
+    #[test]
+    fn snapshot_resumes_identically() {
+        let mut original = snapshot_simulator();
+        original.run_for(20);
+        let snapshot = original.snapshot();
+        original.run_for(30);
+
+        let mut resumed = snapshot_simulator();
+        resumed.restore(&snapshot).unwrap();
+        assert_eq!(resumed.round(), 20);
+        resumed.run_for(30);
+        assert!(resumed.world() == original.world());
+        assert_eq!(resumed.result(), original.result());
+        assert_eq!(resumed.stats(Color::Red), original.stats(Color::Red));
+        assert_eq!(resumed.snapshot(), original.snapshot());
+    }
+
+    #[test]
+    fn snapshot_rejects_bad_input() {
+        let mut simulator = snapshot_simulator();
+        simulator.run_for(5);
+        let snapshot = simulator.snapshot();
+        let before = simulator.snapshot();
+
+        let mut other = HashMap::new();
+        other.insert(
+            Color::Red,
+            parse_program("start: Move start start").unwrap(),
+        );
+        other.insert(
+            Color::Black,
+            parse_program("start: Move start start").unwrap(),
+        );
+        let mut stranger = Simulator::headless(simulator.world().clone(), other, 0).unwrap();
+        assert_eq!(
+            stranger.restore(&snapshot),
+            Err(SnapshotError::ProgramMismatch(Color::Black))
+        );
+
+        assert_eq!(
+            simulator.restore(&snapshot[..snapshot.len() - 1]),
+            Err(SnapshotError::Truncated)
+        );
+        let mut longer = snapshot.clone();
+        longer.push(0);
+        assert_eq!(
+            simulator.restore(&longer),
+            Err(SnapshotError::TrailingBytes)
+        );
+        assert_eq!(
+            simulator.restore(b"hello"),
+            Err(SnapshotError::NotASnapshot)
+        );
+        assert_eq!(simulator.snapshot(), before);
+    }
+
+    fn flip_world(seed: u64) -> World {
+        let mut world = World::new(Grid::new(10, 10));
+        for x in 0..5 {
+            world.add_ant(Color::Red, Position { x, y: 0 }).unwrap();
+            world.add_ant(Color::Black, Position { x, y: 9 }).unwrap();
+        }
+        let program = vec![
+            Instr::Flip {
+                n: 2,
+                success_instr: 1,
+                fail_instr: 2,
+            },
+            Instr::Move {
+                success_instr: 0,
+                fail_instr: 0,
+            },
+            Instr::Turn {
+                direction: TurnDirection::Right,
+                next_instr: 0,
+            },
+        ];
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program.clone());
+        programs.insert(Color::Black, program);
+        let mut simulator = Simulator::new(world, programs, Box::new(NullRenderer), seed).unwrap();
+        for _ in 0..50 {
+            simulator.step();
+        }
+        simulator.world().clone()
+    }
+
+    #[test]
+    fn seeded_runs_are_reproducible() {
+        assert!(flip_world(1) == flip_world(1));
+        assert!(flip_world(1) != flip_world(2));
+        assert_eq!(flip_world(1).state_hash(), flip_world(1).state_hash());
+        assert_ne!(flip_world(1).state_hash(), flip_world(2).state_hash());
+    }
+
+    #[test]
+    fn warm_start_with_new_brains() {
+        let mut world = World::new(Grid::new(10, 10));
+        let id = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world.ant_mut(id).unwrap().update_instr_pointer(5);
+        let program = vec![
+            Instr::Move {
+                success_instr: 1,
+                fail_instr: 1,
+            },
+            Instr::DropFood {
+                success_instr: 1,
+                fail_instr: 1,
+            },
+        ];
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program);
+
+        let mut simulator =
+            Simulator::warm_start(world, programs, Box::new(NullRenderer), 0, |_| 0).unwrap();
+        assert_eq!(simulator.world().ant(id).unwrap().instr_pointer(), 0);
+        simulator.step();
+        assert_eq!(
+            simulator.world().ant(id).unwrap().position(),
+            Position { x: 1, y: 0 }
+        );
+        assert_eq!(simulator.world().ant(id).unwrap().instr_pointer(), 1);
+    }
+
+    #[test]
+    fn moving_into_encirclement_kills() {
+        let mut world = World::new(Grid::new(10, 10));
+        let trap = Position { x: 5, y: 5 };
+        let red = world
+            .add_ant(Color::Red, trap.translate(Direction::Left))
+            .unwrap();
+        for d in [1, 2, 4, 5, 0] {
+            let position = trap.translate(Direction::try_from(d).unwrap());
+            world.add_ant(Color::Black, position).unwrap();
+        }
+        let mut programs = HashMap::new();
+        programs.insert(
+            Color::Red,
+            vec![Instr::Move {
+                success_instr: 0,
+                fail_instr: 0,
+            }],
+        );
+        let mut simulator = Simulator::new(world, programs, Box::new(NullRenderer), 0).unwrap();
+
+        simulator.step();
+        assert!(!simulator.world().dead_ant(red).unwrap().is_alive());
+        assert_eq!(simulator.world().grid().ant_at(trap), None);
+        assert_eq!(simulator.world().grid().cell_at(trap).unwrap().food(), 3);
+    }
+
+    #[test]
+    fn rejects_invalid_programs() {
+        let world = World::new(Grid::new(10, 10));
+        let mut programs = HashMap::new();
+        programs.insert(
+            Color::Red,
+            vec![Instr::DropFood {
+                success_instr: 3,
+                fail_instr: 3,
+            }],
+        );
+        programs.insert(Color::Black, vec![]);
+
+        let result = Simulator::new(world.clone(), programs.clone(), Box::new(NullRenderer), 0);
+        assert_eq!(
+            result.err(),
+            Some(SimulatorError::InvalidProgram {
+                color: Color::Black,
+                errors: vec![ProgramError::Empty],
+            })
+        );
+        programs.insert(
+            Color::Black,
+            vec![Instr::DropFood {
+                success_instr: 0,
+                fail_instr: 0,
+            }],
+        );
+        let result = Simulator::new(world.clone(), programs.clone(), Box::new(NullRenderer), 0);
+        assert_eq!(
+            result.err(),
+            Some(SimulatorError::InvalidProgram {
+                color: Color::Red,
+                errors: vec![ProgramError::TargetOutOfBounds {
+                    instr: 0,
+                    target: 3
+                }],
+            })
+        );
+
+        programs.insert(
+            Color::Red,
+            vec![
+                Instr::DropFood {
+                    success_instr: 0,
+                    fail_instr: 0,
+                },
+                Instr::DropFood {
+                    success_instr: 0,
+                    fail_instr: 0,
+                },
+            ],
+        );
+        assert!(Simulator::new(world, programs, Box::new(NullRenderer), 0).is_ok());
+    }
+
+    fn wanderer_trail(extra_ant: bool, streams: RngStreams) -> Vec<(Position, Direction)> {
+        let mut world = World::new(Grid::new(30, 30));
+        let id = world
+            .add_ant(Color::Red, Position { x: 15, y: 15 })
+            .unwrap();
+        if extra_ant {
+            world.add_ant(Color::Red, Position { x: 2, y: 2 }).unwrap();
+        }
+        let program = parse_program(
+            "
+            start: Flip 2 move turn
+            move:  Move start start
+            turn:  Turn Left start
+            ",
+        )
+        .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program);
+        let mut simulator = Simulator::new(world, programs, Box::new(NullRenderer), 17)
+            .unwrap()
+            .with_rng_streams(streams);
+        (0..40)
+            .map(|_| {
+                simulator.step();
+                let ant = simulator.world().ant(id).unwrap();
+                (ant.position(), ant.direction())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn per_ant_streams_ignore_other_ants() {
+        assert_eq!(
+            wanderer_trail(false, RngStreams::PerAnt),
+            wanderer_trail(true, RngStreams::PerAnt)
+        );
+    }
+
+    #[test]
+    fn shared_stream_is_shifted_by_other_ants() {
+        assert_ne!(
+            wanderer_trail(false, RngStreams::Shared),
+            wanderer_trail(true, RngStreams::Shared)
+        );
+    }
+
+    #[test]
+    fn observers_see_events() {
+        let world: World = "# # # # #\n # r 1 . #\n# # # # #\n".parse().unwrap();
+        let program = parse_program(
+            "
+                  Move 1 1
+                  PickUpFood 2 2
+                  Move 3 3
+                  Move 4 4
+                  Turn Left 5
+            drop: DropFood drop
+            ",
+        )
+        .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program);
+        let mut simulator = Simulator::new(world, programs, Box::new(NullRenderer), 0).unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        simulator.add_observer(Box::new(move |event| sink.borrow_mut().push(event)));
+        for _ in 0..7 {
+            simulator.step();
+        }
+
+        let ant = 0;
+        let at = |x| Position { x, y: 1 };
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                Event::AntMoved {
+                    ant,
+                    from: at(1),
+                    to: at(2)
+                },
+                Event::StepCompleted { round: 1 },
+                Event::FoodPickedUp {
+                    ant,
+                    position: at(2)
+                },
+                Event::StepCompleted { round: 2 },
+                Event::AntMoved {
+                    ant,
+                    from: at(2),
+                    to: at(3)
+                },
+                Event::StepCompleted { round: 3 },
+                Event::AntBlocked {
+                    ant,
+                    position: at(3)
+                },
+                Event::StepCompleted { round: 4 },
+                Event::AntTurned {
+                    ant,
+                    direction: TurnDirection::Left.apply_to(Direction::Right)
+                },
+                Event::StepCompleted { round: 5 },
+                Event::FoodDropped {
+                    ant,
+                    position: at(3)
+                },
+                Event::StepCompleted { round: 6 },
+                Event::StepCompleted { round: 7 },
+            ]
+        );
+    }
+
+    #[test]
+    fn observers_see_kills() {
+        let mut world = World::new(Grid::new(10, 10));
+        let trap = Position { x: 5, y: 5 };
+        let red = world
+            .add_ant(Color::Red, trap.translate(Direction::Left))
+            .unwrap();
+        for d in [1, 2, 4, 5, 0] {
+            let position = trap.translate(Direction::try_from(d).unwrap());
+            world.add_ant(Color::Black, position).unwrap();
+        }
+        let mut programs = HashMap::new();
+        programs.insert(
+            Color::Red,
+            parse_program("start: Move start start").unwrap(),
+        );
+        let mut simulator = Simulator::new(world, programs, Box::new(NullRenderer), 0).unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        simulator.add_observer(Box::new(move |event| sink.borrow_mut().push(event)));
+        let before = simulator.snapshot();
+
+        simulator.step();
+        assert!(events.borrow().contains(&Event::AntKilled {
+            ant: red,
+            position: trap
+        }));
+        assert_eq!(
+            simulator.deaths(),
+            [DeathRecord {
+                ant: red,
+                round: 0,
+                cause: DeathCause::Combat,
+                position: trap,
+                food_dropped: 3,
+            }]
+        );
+        simulator.restore(&before).unwrap();
+        assert!(simulator.deaths().is_empty());
+    }
+
+    fn costed_simulator(costs: CostTable) -> Simulator {
+        let mut world = World::new(Grid::new(20, 20));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world
+            .add_ant(Color::Black, Position { x: 0, y: 10 })
+            .unwrap();
+        let program = parse_program(
+            "
+            start: Sense Here Home move move
+            move:  Move start start
+            ",
+        )
+        .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program.clone());
+        programs.insert(Color::Black, program);
+        let mut rules = RuleSet::classic();
+        rules.costs.insert(Color::Red, costs);
+        Simulator::new(world, programs, Box::new(NullRenderer), 0)
+            .unwrap()
+            .with_rules(rules)
+    }
+
+    #[test]
+    fn cheap_instructions_share_a_round() {
+        let costs = CostTable::new(3).with_cost(InstrKind::Move, 2);
+        let mut simulator = costed_simulator(costs);
+        for _ in 0..4 {
+            simulator.step();
+        }
+        assert_eq!(
+            simulator.world().ant(0).unwrap().position(),
+            Position { x: 4, y: 0 }
+        );
+        assert_eq!(
+            simulator.world().ant(1).unwrap().position(),
+            Position { x: 2, y: 10 }
+        );
+        assert_eq!(
+            simulator.stats(Color::Red),
+            Some(InterpreterStats {
+                instructions: 8,
+                ticks: 12,
+                rested: 0
+            })
+        );
+        assert_eq!(
+            simulator.stats(Color::Black),
+            Some(InterpreterStats {
+                instructions: 4,
+                ticks: 4,
+                rested: 0
+            })
+        );
+    }
+
+    #[test]
+    fn realtime() {
+        let mut world = World::new(Grid::new_torus(4, 1));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let program = parse_program("start: Move start start").unwrap();
+        let recorder = crate::renderers::testing::RecordingRenderer::new();
+        let mut simulator = Simulator::headless(world, HashMap::from([(Color::Red, program)]), 0)
+            .unwrap()
+            .with_renderer(Box::new(recorder.clone()));
+
+        let start = Instant::now();
+        assert_eq!(simulator.run_realtime(50, |s| s.round() == 5), 5);
+        assert!(start.elapsed() >= Duration::from_millis(80));
+        recorder.assert_frame_count(5);
+
+        // A stall at round 8 is made up for with a frame of several steps.
+        recorder.clear();
+        let steps = simulator.run_realtime(200, |s| {
+            if s.round() == 8 {
+                thread::sleep(Duration::from_millis(15));
+            }
+            s.round() == 20
+        });
+        assert_eq!(steps, 15);
+        assert!(recorder.frame_count() < 15);
+        assert!(recorder.frames().iter().any(|frame| frame.delta.is_none()));
+        recorder.assert_last_world(simulator.world());
+    }
+
+    #[test]
+    fn ants_follow_scent() {
+        let mut grid = Grid::with_wall_border(12, 3);
+        for _ in 0..3 {
+            grid.cell_at_mut(Position { x: 10, y: 1 })
+                .unwrap()
+                .try_drop_food()
+                .unwrap();
+        }
+        let mut world = World::new(grid);
+        let ant = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+        let program = parse_program(
+            "
+            sniff: SenseScent Ahead Food go turn
+            turn:  Turn Left sniff
+            go:    Move sniff sniff
+            ",
+        )
+        .unwrap();
+        let programs = HashMap::from([(Color::Red, program)]);
+        let rules = RuleSet {
+            scent: Some(ScentRule::default()),
+            ..RuleSet::default()
+        };
+        let mut simulator = Simulator::headless(world, programs.clone(), 0)
+            .unwrap()
+            .with_rules(rules.clone());
+        // The scent needs a while to reach across the map.
+        simulator.run_for(150);
+        assert_eq!(
+            simulator.world().ant(ant).unwrap().position(),
+            Position { x: 10, y: 1 }
+        );
+
+        let snapshot = simulator.snapshot();
+        simulator.run_for(5);
+        let mut restored = Simulator::headless(World::new(Grid::new(1, 1)), programs, 0)
+            .unwrap()
+            .with_rules(rules);
+        restored.restore(&snapshot).unwrap();
+        restored.run_for(5);
+        crate::testing::assert_world_eq(restored.world(), simulator.world());
+    }
+
+    #[test]
+    fn instruction_budget() {
+        let mut world = World::new(Grid::new(10, 1));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let program = parse_program(
+            "
+            start: Flip 1 check check
+            check: Sense Ahead Food start move
+            move:  Move start start
+            ",
+        )
+        .unwrap();
+        let programs = HashMap::from([(Color::Red, program.clone())]);
+        let rules = RuleSet {
+            instruction_budget: Some(8),
+            ..RuleSet::default()
+        };
+        let mut simulator = Simulator::headless(world.clone(), programs, 0)
+            .unwrap()
+            .with_rules(rules.clone());
+        simulator.run_for(2);
+        assert_eq!(simulator.world().ant(0).unwrap().position().x, 2);
+        assert_eq!(simulator.stats(Color::Red).unwrap().instructions, 6);
+
+        // A loop of branches ends the turn once the budget runs out.
+        let spin = parse_program("start: Flip 1 start start").unwrap();
+        let mut simulator = Simulator::headless(world, HashMap::from([(Color::Red, spin)]), 0)
+            .unwrap()
+            .with_rules(rules);
+        simulator.step();
+        assert_eq!(simulator.stats(Color::Red).unwrap().instructions, 8);
+    }
+
+    #[test]
+    fn resting_after_actions() {
+        let mut world = World::new(Grid::new(10, 1));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let program = parse_program("start: Move start start").unwrap();
+        let rules = RuleSet {
+            resting: Some(RestTable::new().with_rest(InstrKind::Move, 2)),
+            ..RuleSet::default()
+        };
+        let mut simulator = Simulator::headless(world, HashMap::from([(Color::Red, program)]), 0)
+            .unwrap()
+            .with_rules(rules);
+        simulator.start_recording();
+        simulator.start_hashing();
+        let mut positions = Vec::new();
+        for _ in 0..7 {
+            simulator.step();
+            positions.push(simulator.world().ant(0).unwrap().position().x);
+        }
+        assert_eq!(positions, vec![1, 1, 1, 2, 2, 2, 3]);
+        assert_eq!(simulator.world().ant(0).unwrap().resting(), 2);
+        assert_eq!(
+            simulator.stats(Color::Red),
+            Some(InterpreterStats {
+                instructions: 3,
+                ticks: 3,
+                rested: 4
+            })
+        );
+
+        let mut player = ReplayPlayer::new(simulator.replay().unwrap());
+        for &hash in simulator.state_hashes().unwrap() {
+            player.advance().unwrap();
+            assert_eq!(player.world().state_hash(), hash);
+        }
+    }
+
+    #[test]
+    fn food_regrows() {
+        let mut world = World::new(Grid::new(4, 1));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let program = parse_program("start: Turn Left start").unwrap();
+        let spawner = Position { x: 2, y: 0 };
+        let rules = RuleSet {
+            regrowth: Some(RegrowthRule {
+                spawners: vec![spawner],
+                interval: 3,
+                amount: 2,
+                cap: 5,
+                chance: 100,
+            }),
+            ..RuleSet::default()
+        };
+        let mut simulator = Simulator::headless(world, HashMap::from([(Color::Red, program)]), 0)
+            .unwrap()
+            .with_rules(rules);
+        simulator.start_recording();
+        let mut food = Vec::new();
+        for _ in 0..9 {
+            simulator.step();
+            food.push(simulator.world().grid().cell_at(spawner).unwrap().food());
+        }
+        assert_eq!(food, vec![0, 0, 2, 2, 2, 4, 4, 4, 5]);
+
+        let mut player = ReplayPlayer::new(simulator.replay().unwrap());
+        player.run_to_end().unwrap();
+        crate::testing::assert_world_eq(player.world(), simulator.world());
+    }
+
+    #[test]
+    fn rng_audit_logs_every_draw() {
+        let mut world = World::new(Grid::new(4, 1));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world.add_ant(Color::Red, Position { x: 1, y: 0 }).unwrap();
+        let program = parse_program(
+            "start: Flip 3 left right\n\
+             left: Turn Left start\n\
+             right: Turn Right start",
+        )
+        .unwrap();
+        let rules = RuleSet {
+            regrowth: Some(RegrowthRule {
+                spawners: vec![Position { x: 3, y: 0 }],
+                interval: 1,
+                amount: 1,
+                cap: 5,
+                chance: 50,
+            }),
+            ..RuleSet::default()
+        };
+        let simulator = || {
+            Simulator::headless(
+                world.clone(),
+                HashMap::from([(Color::Red, program.clone())]),
+                7,
+            )
+            .unwrap()
+            .with_rules(rules.clone())
+            .with_turn_order(TurnOrder::Shuffled)
+        };
+        let mut audited = simulator().with_rng_audit();
+        let mut plain = simulator();
+        for simulator in [&mut audited, &mut plain] {
+            simulator.start_recording();
+            for _ in 0..4 {
+                simulator.step();
+            }
+        }
+        assert!(plain.replay().unwrap().draws().is_empty());
+
+        let replay = audited.replay().unwrap();
+        let draws = replay.draws();
+        assert!(draws.iter().all(|draw| draw.value < draw.bound));
+        let count = |purpose: fn(DrawPurpose) -> bool| {
+            draws.iter().filter(|draw| purpose(draw.purpose)).count()
+        };
+        assert_eq!(count(|purpose| matches!(purpose, DrawPurpose::Flip(_))), 4);
+        assert_eq!(count(|purpose| purpose == DrawPurpose::Schedule), 4);
+        assert_eq!(
+            count(|purpose| matches!(purpose, DrawPurpose::Regrowth(_))),
+            4
+        );
+        for draw in draws {
+            if let DrawPurpose::Flip(ant) = draw.purpose {
+                let entry = replay
+                    .entries()
+                    .iter()
+                    .find(|entry| entry.step == draw.round && entry.ant == ant)
+                    .unwrap();
+                assert_eq!(entry.next_instr, if draw.value == 0 { 1 } else { 2 });
+            }
+        }
+        assert_eq!(audited.world().state_hash(), plain.world().state_hash());
+    }
+
+    #[test]
+    fn expensive_instructions_save_up() {
+        let costs = CostTable::new(2).with_cost(InstrKind::Move, 5);
+        let mut simulator = costed_simulator(costs);
+        // 2 ticks a round: Sense in the first, Move in the third.
+        let mut positions = Vec::new();
+        for _ in 0..5 {
+            simulator.step();
+            positions.push(simulator.world().ant(0).unwrap().position().x);
+        }
+        assert_eq!(positions, vec![0, 0, 1, 1, 1]);
+        assert_eq!(
+            simulator.stats(Color::Red),
+            Some(InterpreterStats {
+                instructions: 3,
+                ticks: 7,
+                rested: 0
+            })
+        );
+    }
+
+    struct DiffLog(Rc<RefCell<Vec<WorldDelta>>>);
+
+    impl Renderer for DiffLog {
+        fn render(&mut self, _world: &World) {}
+
+        fn render_diff(&mut self, _world: &World, delta: &WorldDelta) {
+            self.0.borrow_mut().push(delta.clone());
+        }
+    }
+
+    #[test]
+    fn castes_run_their_own_programs() {
+        let mut world = World::new(Grid::new(4, 1));
+        let worker = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let soldier = world
+            .add_ant_to_caste(Color::Red, Position { x: 2, y: 0 }, 2)
+            .unwrap();
+        let programs =
+            HashMap::from([(Color::Red, parse_program("start: Turn Left start").unwrap())]);
+        let mut simulator = Simulator::headless(world, programs, 0)
+            .unwrap()
+            .with_caste(
+                Color::Red,
+                2,
+                parse_program("start: Turn Right start").unwrap(),
+            )
+            .unwrap();
+        simulator.step();
+        let world = simulator.world();
+        let (left, right) = (
+            world.ant(worker).unwrap().direction(),
+            world.ant(soldier).unwrap().direction(),
+        );
+        assert_eq!(left.rotated(2), right);
+
+        let invalid = simulator.with_caste(Color::Red, 1, vec![]);
+        assert!(matches!(
+            invalid,
+            Err(SimulatorError::InvalidProgram {
+                color: Color::Red,
+                ..
+            })
+        ));
+
+        let programs = HashMap::from([(
+            Color::Red,
+            parse_program("start: Move start start").unwrap(),
+        )]);
+        let simulator =
+            || Simulator::headless(World::new(Grid::new(4, 1)), programs.clone(), 0).unwrap();
+        let program = parse_program("start: Turn Right start").unwrap();
+        assert!(matches!(
+            simulator().with_caste(Color::Red, MAX_CASTES as Caste, program.clone()),
+            Err(SimulatorError::InvalidCaste(_))
+        ));
+        assert!(matches!(
+            simulator().with_caste(Color::Black, 1, program),
+            Err(SimulatorError::NoProgram(Color::Black))
+        ));
+    }
+
+    #[test]
+    fn anthills_spawn_ants() {
+        let mut grid = Grid::with_wall_border(7, 3);
+        for x in 3..6 {
+            *grid.cell_at_mut(Position { x, y: 1 }).unwrap() = Cell::new_anthill(Color::Red);
+        }
+        for _ in 0..5 {
+            grid.cell_at_mut(Position { x: 5, y: 1 })
+                .unwrap()
+                .try_drop_food()
+                .unwrap();
+        }
+        let mut world = World::new(grid);
+        let first = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+        let program = parse_program("start: Turn Left start").unwrap();
+        let rules = RuleSet {
+            spawning: Some(SpawnRule::new(2)),
+            ..RuleSet::default()
+        };
+        let recorder = crate::renderers::testing::RecordingRenderer::new();
+        let mut simulator = Simulator::new(
+            world,
+            HashMap::from([(Color::Red, program)]),
+            Box::new(recorder.clone()),
+            0,
+        )
+        .unwrap()
+        .with_rules(rules);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = events.clone();
+        simulator.add_observer(Box::new(move |event| log.borrow_mut().push(event)));
+        simulator.start_recording();
+
+        simulator.step();
+        let world = simulator.world();
+        assert_eq!(world.swarm_size(Color::Red), 3);
+        assert_eq!(world.food_in_anthill(Color::Red), 1);
+        simulator.run_for(3);
+        assert_eq!(simulator.world().swarm_size(Color::Red), 3);
+        assert_eq!(simulator.stats(Color::Red).unwrap().instructions, 1 + 3 * 3);
+        let spawned: Vec<_> = events
+            .borrow()
+            .iter()
+            .filter_map(|event| match event {
+                Event::AntSpawned { ant, .. } => Some(*ant),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(spawned, [first + 1, first + 2]);
+        recorder.assert_diffs_complete();
+
+        let mut player = ReplayPlayer::new(simulator.replay().unwrap());
+        player.run_to_end().unwrap();
+        crate::testing::assert_world_eq(player.world(), simulator.world());
+    }
+
+    #[test]
+    fn renderers_get_touched_cells() {
+        let mut world = World::new(Grid::new(10, 10));
+        let walker = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let marker = world.add_ant(Color::Red, Position { x: 0, y: 5 }).unwrap();
+        world.ant_mut(marker).unwrap().rotate(Direction::Left);
+        let idler = world
+            .add_ant(Color::Black, Position { x: 5, y: 5 })
+            .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(
+            Color::Red,
+            parse_program(
+                "
+                start: Direction Right move mark
+                move:  Move start start
+                mark:  Mark 0 start
+                ",
+            )
+            .unwrap(),
+        );
+        programs.insert(
+            Color::Black,
+            parse_program("start: Sense Here Food start start").unwrap(),
+        );
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let renderer = DiffLog(frames.clone());
+        let mut simulator = Simulator::new(world, programs, Box::new(renderer), 0).unwrap();
+        simulator.step();
+        simulator.step();
+
+        let frames = frames.borrow();
+        assert_eq!(frames.len(), 2);
+        // Branching only moves the instruction pointers.
+        assert_eq!(frames[0].cells().count(), 0);
+        assert!(frames[0].has_ant(walker) && frames[0].has_ant(marker));
+        assert!(!frames[0].has_ant(idler));
+        let mut cells: Vec<_> = frames[1].cells().collect();
+        cells.sort_by_key(|position| (position.y, position.x));
+        assert_eq!(
+            cells,
+            vec![
+                Position { x: 0, y: 0 },
+                Position { x: 1, y: 0 },
+                Position { x: 0, y: 5 },
+            ]
+        );
+    }
+
+    #[test]
+    fn renderers_get_only_the_cells_food_went_to() {
+        let mut grid = Grid::new(10, 10);
+        for x in 0..4 {
+            *grid.cell_at_mut(Position { x, y: 0 }).unwrap() = Cell::new_anthill(Color::Red);
+        }
+        let mut world = World::new(grid);
+        world.grow_food(Position { x: 0, y: 0 }, 2);
+        let here = Position { x: 5, y: 5 };
+        let neighbours: Vec<_> = world.grid().ring(here, 1).collect();
+        for position in neighbours {
+            world.grow_food(position, 1);
+        }
+        world.grow_food(here, 2);
+        let porter = world.add_ant(Color::Red, here).unwrap();
+        world.ant_mut(porter).unwrap().pickup_food().unwrap();
+        let program = parse_program("start: DropFood start start").unwrap();
+        let rules = RuleSet {
+            food_cap: Some(FoodCap {
+                limit: 1,
+                overflow: FoodOverflow::Nearest,
+            }),
+            spawning: Some(SpawnRule::new(2)),
+            ..RuleSet::default()
+        };
+        let frames = Rc::new(RefCell::new(Vec::new()));
+        let renderer = DiffLog(frames.clone());
+        let mut simulator = Simulator::new(
+            world,
+            HashMap::from([(Color::Red, program)]),
+            Box::new(renderer),
+            0,
+        )
+        .unwrap()
+        .with_rules(rules);
+        simulator.step();
+
+        let spilled = simulator.world().grid().ring(here, 2).next().unwrap();
+        assert_eq!(simulator.world().grid().cell_at(spilled).unwrap().food(), 1);
+        assert_eq!(simulator.world().swarm_size(Color::Red), 2);
+        let mut cells: Vec<_> = frames.borrow()[0].cells().collect();
+        cells.sort_by_key(|position| (position.y, position.x));
+        let mut expected = vec![Position { x: 0, y: 0 }, here, spilled];
+        expected.sort_by_key(|position| (position.y, position.x));
+        assert_eq!(cells, expected);
+    }
+
+    #[test]
+    fn headless_runs() {
+        let mut world = World::new(Grid::new(10, 1));
+        let id = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(
+            Color::Red,
+            parse_program("start: Move start start").unwrap(),
+        );
+        let mut simulator = Simulator::headless(world, programs, 0).unwrap();
+
+        simulator.run_for(3);
+        assert_eq!(simulator.round(), 3);
+        assert_eq!(
+            simulator.world().ant(id).unwrap().position(),
+            Position { x: 3, y: 0 }
+        );
+        let steps =
+            simulator.run_until(|simulator| simulator.world().ant(id).unwrap().position().x == 7);
+        assert_eq!(steps, 4);
+        assert_eq!(simulator.run_until(|simulator| simulator.round() >= 5), 0);
+        assert_eq!(simulator.round(), 7);
+    }
+
+    #[test]
+    fn ants_starve_without_food() {
+        let mut grid = Grid::new(6, 1);
+        grid.cell_at_mut(Position { x: 5, y: 0 })
+            .unwrap()
+            .try_drop_food()
+            .unwrap();
+        let mut world = World::new(grid);
+        let idler = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let forager = world
+            .add_ant(Color::Black, Position { x: 4, y: 0 })
+            .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, parse_program("start: Turn Left start").unwrap());
+        programs.insert(
+            Color::Black,
+            parse_program(
+                "
+                start: Move eat eat
+                eat:   Eat start start
+                ",
+            )
+            .unwrap(),
+        );
+        let rules = RuleSet {
+            metabolism: Some(Metabolism {
+                max_energy: 6,
+                per_round: 1,
+                per_action: 1,
+                per_food: 6,
+                eat_at_home: false,
+            }),
+            ..RuleSet::default()
+        };
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let log = events.clone();
+        let mut simulator = Simulator::headless(world, programs, 0)
+            .unwrap()
+            .with_rules(rules);
+        simulator.add_observer(Box::new(move |event| log.borrow_mut().push(event)));
+        simulator.start_collecting_stats();
+        simulator.start_recording();
+
+        // Turning costs the idler two a round.
+        simulator.run_for(2);
+        assert!(simulator.world().is_alive(idler));
+        simulator.step();
+        assert!(!simulator.world().is_alive(idler));
+        assert!(events.borrow().contains(&Event::AntStarved {
+            ant: idler,
+            position: Position { x: 0, y: 0 },
+        }));
+        let stats = simulator.step_stats().unwrap().steps();
+        assert_eq!((stats[1].red.starved, stats[2].red.starved), (0, 1));
+
+        // The forager found food and ate it on the way.
+        assert!(simulator.world().is_alive(forager));
+        assert_eq!(simulator.world().food_on_ground(), 0);
+        simulator.run_for(2);
+        assert!(simulator.world().is_alive(forager));
+        simulator.step();
+        assert!(!simulator.world().is_alive(forager));
+        let deaths: Vec<_> = (simulator.deaths().iter())
+            .map(|death| (death.ant, death.round, death.cause))
+            .collect();
+        assert_eq!(
+            deaths,
+            [
+                (idler, 2, DeathCause::Starvation),
+                (forager, 5, DeathCause::Starvation)
+            ]
+        );
+        assert_eq!(simulator.replay().unwrap().deaths(), simulator.deaths());
+
+        let mut player = ReplayPlayer::new(simulator.replay().unwrap());
+        player.run_to_end().unwrap();
+        crate::testing::assert_world_eq(player.world(), simulator.world());
+    }
+
+    #[test]
+    fn registers_count_moves() {
+        let mut world = World::new(Grid::new(10, 1));
+        let id = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let program = parse_program(
+            "
+                   Set r0 3 check
+            check: JumpIfZero r0 done walk
+            walk:  Move count count
+            count: Dec r0 check
+            done:  Turn Left done
+            ",
+        )
+        .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program);
+        let mut simulator = Simulator::headless(world, programs, 0).unwrap();
+        simulator.start_recording();
+        simulator.run_for(20);
+        assert_eq!(
+            simulator.world().ant(id).unwrap().position(),
+            Position { x: 3, y: 0 }
+        );
+
+        let mut player = ReplayPlayer::new(simulator.replay().unwrap());
+        player.run_to_end().unwrap();
+        crate::testing::assert_world_eq(player.world(), simulator.world());
+    }
+
+    #[test]
+    fn subroutines() {
+        let mut world = World::new(Grid::new(10, 1));
+        let id = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let program = parse_program(
+            "
+                  Call step back
+            back: Call step done
+            done: Turn Left done
+            step: Move ret ret
+            ret:  Return
+            ",
+        )
+        .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program);
+        let mut simulator = Simulator::headless(world, programs, 0).unwrap();
+        simulator.start_recording();
+        simulator.run_for(20);
+        let ant = simulator.world().ant(id).unwrap();
+        assert_eq!(ant.position(), Position { x: 2, y: 0 });
+        assert!(ant.call_stack().is_empty());
+
+        let mut player = ReplayPlayer::new(simulator.replay().unwrap());
+        player.run_to_end().unwrap();
+        crate::testing::assert_world_eq(player.world(), simulator.world());
+    }
+
+    mod history {
+        use super::*;
+
+        fn wanderers() -> Simulator {
+            let mut world = World::new(Grid::new(10, 10));
+            for x in 0..5 {
+                world.add_ant(Color::Red, Position { x, y: 0 }).unwrap();
+            }
+            let program = parse_program(
+                "
+                start: Flip 3 move turn
+                move:  Move mark start
+                mark:  Mark 0 start
+                turn:  Turn Right start
+                ",
+            )
+            .unwrap();
+            let mut programs = HashMap::new();
+            programs.insert(Color::Red, program);
+            Simulator::headless(world, programs, 7).unwrap()
+        }
+
+        fn config(keyframe_interval: u32) -> HistoryConfig {
+            HistoryConfig {
+                keyframe_interval,
+                ..HistoryConfig::default()
+            }
+        }
+
+        #[test]
+        fn seek_both_ways() {
+            let mut simulator = wanderers();
+            let start = simulator.world().state_hash();
+            simulator.start_history(config(8));
+            simulator.start_hashing();
+            simulator.run_for(50);
+            let hashes = simulator.take_state_hashes().unwrap();
+            assert_eq!(simulator.history_rounds(), Some(0..=50));
+
+            for round in [33, 0, 7, 8, 9, 50, 16] {
+                simulator.seek(round).unwrap();
+                assert_eq!(simulator.round(), round);
+                let expected = round.checked_sub(1).map_or(start, |r| hashes[r as usize]);
+                assert_eq!(simulator.world().state_hash(), expected);
+            }
+            assert_eq!(simulator.seek(51), Err(HistoryError::NotKept(51)));
+        }
+
+        #[test]
+        fn stepping_after_a_seek_replaces_the_future() {
+            let mut simulator = wanderers();
+            simulator.start_history(config(4));
+            simulator.run_for(30);
+            let expected = simulator.snapshot();
+
+            simulator.seek(10).unwrap();
+            simulator.run_for(5);
+            assert_eq!(simulator.history_rounds(), Some(0..=15));
+            simulator.run_for(15);
+            assert_eq!(simulator.snapshot(), expected);
+        }
+
+        #[test]
+        fn step_back() {
+            let mut simulator = wanderers();
+            assert_eq!(simulator.step_back(), Err(HistoryError::NotRecording));
+            simulator.start_history(HistoryConfig::default());
+            assert_eq!(simulator.step_back(), Err(HistoryError::AtStart));
+            simulator.run_for(2);
+            let after_one = {
+                let mut other = wanderers();
+                other.step();
+                other.snapshot()
+            };
+            simulator.step_back().unwrap();
+            assert_eq!(simulator.snapshot(), after_one);
+
+            // Partway through a round it goes back to the round's start.
+            simulator.step_turn();
+            simulator.step_back().unwrap();
+            assert_eq!(simulator.snapshot(), after_one);
+        }
+
+        #[test]
+        fn step_back_with_a_brain() {
+            let mut world = World::new(Grid::new(10, 1));
+            world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+            let replies = std::io::Cursor::new("Move\nMove\n");
+            let mut simulator = Simulator::headless(world, HashMap::new(), 0)
+                .unwrap()
+                .with_remote_brain(Color::Red, RemoteBrain::new(replies, std::io::sink()));
+            simulator.start_history(HistoryConfig::default());
+            simulator.run_for(2);
+            simulator.step_back().unwrap();
+            assert_eq!(simulator.round(), 1);
+            assert_eq!(
+                simulator.world().ant(0).unwrap().position(),
+                Position { x: 1, y: 0 }
+            );
+        }
+
+        #[test]
+        fn budget_drops_the_oldest_rounds() {
+            let mut simulator = wanderers();
+            simulator.start_history(HistoryConfig {
+                keyframe_interval: 4,
+                memory_budget: 1,
+            });
+            simulator.run_for(18);
+            assert_eq!(simulator.history_rounds(), Some(16..=18));
+            assert_eq!(simulator.seek(3), Err(HistoryError::NotKept(3)));
+            simulator.seek(17).unwrap();
+        }
+    }
+
+    mod debugger {
+        use super::*;
+
+        // Two red ants and a black one walking right, in a tunnel.
+        fn simulator() -> Simulator {
+            let mut world = World::new(Grid::new(10, 3));
+            for y in 0..2 {
+                world.add_ant(Color::Red, Position { x: 0, y }).unwrap();
+            }
+            world
+                .add_ant(Color::Black, Position { x: 0, y: 2 })
+                .unwrap();
+            let program = parse_program(
+                "
+                start: Move turn turn
+                turn:  Turn Right back
+                back:  Turn Left start
+                ",
+            )
+            .unwrap();
+            let mut programs = HashMap::new();
+            programs.insert(Color::Red, program.clone());
+            programs.insert(Color::Black, program);
+            Simulator::headless(world, programs, 0).unwrap()
+        }
+
+        #[test]
+        fn steps_one_ant_at_a_time() {
+            let mut debugger = Debugger::new(simulator());
+            // Black goes first.
+            assert_eq!(debugger.next_ant(), Some(2));
+            assert_eq!(debugger.step_ant(), Some(2));
+            assert_eq!(debugger.step_ant(), Some(0));
+            let world = debugger.simulator().world();
+            assert_eq!(world.ant(0).unwrap().position(), Position { x: 1, y: 0 });
+            assert_eq!(world.ant(1).unwrap().position(), Position { x: 0, y: 1 });
+            assert_eq!(debugger.simulator().round(), 0);
+
+            assert_eq!(debugger.step_ant(), Some(1));
+            assert_eq!(debugger.simulator().round(), 1);
+
+            // Stepping the simulator finishes the round under way.
+            debugger.step_ant();
+            debugger.simulator_mut().step();
+            let mut plain = simulator();
+            plain.run_for(2);
+            assert!(debugger.simulator().world() == plain.world());
+        }
+
+        #[test]
+        fn breakpoints() {
+            let mut debugger = Debugger::new(simulator());
+            assert!(debugger.add_breakpoint(Breakpoint::Ant(1)));
+            assert!(!debugger.add_breakpoint(Breakpoint::Ant(1)));
+            assert_eq!(
+                debugger.continue_until_break(10),
+                DebugStop::Breakpoint {
+                    breakpoint: Breakpoint::Ant(1),
+                    ant: 1,
+                }
+            );
+            assert_eq!(
+                debugger.simulator().world().ant(0).unwrap().instr_pointer(),
+                1
+            );
+            assert_eq!(
+                debugger.simulator().world().ant(1).unwrap().instr_pointer(),
+                0
+            );
+            // Continuing lets the ant through, up to its next turn.
+            assert_eq!(
+                debugger.continue_until_break(10),
+                DebugStop::Breakpoint {
+                    breakpoint: Breakpoint::Ant(1),
+                    ant: 1,
+                }
+            );
+            assert_eq!(debugger.simulator().round(), 1);
+
+            debugger.clear_breakpoints();
+            debugger.add_breakpoint(Breakpoint::Instr(2));
+            assert_eq!(
+                debugger.continue_until_break(10),
+                DebugStop::Breakpoint {
+                    breakpoint: Breakpoint::Instr(2),
+                    ant: 2,
+                }
+            );
+            assert!(debugger.remove_breakpoint(Breakpoint::Instr(2)));
+            assert_eq!(debugger.continue_until_break(3), DebugStop::RoundLimit);
+            assert_eq!(debugger.simulator().round(), 5);
+        }
+
+        #[test]
+        fn inspect() {
+            let mut debugger = Debugger::new(simulator());
+            debugger.step_ant();
+            debugger.step_ant();
+            let ant = debugger.inspect(0).unwrap();
+            assert_eq!(ant.position, Position { x: 1, y: 0 });
+            assert_eq!(
+                ant.instr,
+                Some(Instr::Turn {
+                    direction: TurnDirection::Right,
+                    next_instr: 2,
+                })
+            );
+            assert_eq!(
+                ant.neighbours[u32::from(Direction::Left) as usize],
+                Some(Cell::default())
+            );
+            assert_eq!(ant.neighbours[u32::from(Direction::UpLeft) as usize], None);
+            let below = ant.neighbours[u32::from(Direction::DownLeft) as usize].clone();
+            assert_eq!(below.unwrap().ant(), Some(1));
+            assert!(debugger.inspect(7).is_none());
+        }
+    }
+
+    #[test]
+    fn tracing() {
+        let mut world = World::new(Grid::new(3, 2));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let traced = world.add_ant(Color::Red, Position { x: 0, y: 1 }).unwrap();
+        let program = parse_program("start: Move start turn\nturn: Turn Left start").unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program);
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut simulator = Simulator::headless(world, programs, 0).unwrap();
+        simulator.start_tracing(Tracer::new(Box::new(trace.clone())).with_ants([traced]));
+        simulator.run_for(3);
+
+        let trace = trace.borrow();
+        assert_eq!(trace.len(), 3);
+        assert!(trace.iter().all(|entry| entry.ant == traced));
+        let outcomes: Vec<_> = trace.iter().map(|entry| entry.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [Outcome::Success, Outcome::Success, Outcome::Fail]
+        );
+        assert_eq!(trace[2].round, 2);
+        assert_eq!(trace[2].next_instr, 1);
+        assert_eq!(trace[2].position, Position { x: 2, y: 1 });
+        assert!(simulator.take_tracer().is_some());
+    }
+
+    #[test]
+    fn state_hashes_match_the_replay() {
+        let mut world = World::new(Grid::new(10, 10));
+        for x in 0..5 {
+            world.add_ant(Color::Red, Position { x, y: 0 }).unwrap();
+        }
+        let program = parse_program(
+            "start: Flip 2 move turn\nmove: Move start start\nturn: Turn Right start",
+        )
+        .unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program);
+        let mut simulator = Simulator::headless(world, programs, 7).unwrap();
+        simulator.start_hashing();
+        simulator.start_recording();
+        simulator.run_for(20);
+        let hashes = simulator.take_state_hashes().unwrap();
+        assert_eq!(hashes.len(), 20);
+        assert_eq!(hashes[19], simulator.world().state_hash());
+
+        let mut player = ReplayPlayer::new(simulator.replay().unwrap());
+        for &hash in &hashes {
+            player.advance().unwrap();
+            assert_eq!(player.world().state_hash(), hash);
+        }
+        assert!(simulator.state_hashes().is_none());
+    }
+
+    #[test]
+    fn remote_brain() {
+        let mut world = World::new(Grid::new(10, 1));
+        let id = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let replies = std::io::Cursor::new("Move\nTurn DownRight\nMove\nMark 9\n");
+        let brain = RemoteBrain::new(replies, std::io::sink());
+        let mut simulator = Simulator::headless(world, HashMap::new(), 0)
+            .unwrap()
+            .with_remote_brain(Color::Red, brain);
+        simulator.start_collecting_stats();
+
+        simulator.run_for(4);
+        let ant = simulator.world().ant(id).unwrap();
+        assert_eq!(ant.position(), Position { x: 1, y: 0 });
+        assert_eq!(ant.direction(), Direction::DownRight);
+        let stats = simulator.step_stats().unwrap().steps();
+        assert_eq!(stats[2].red.blocked_moves, 1);
+        assert_eq!(simulator.brain_failure(Color::Red), None);
+
+        simulator.step();
+        assert_eq!(
+            simulator.brain_failure(Color::Red).as_deref(),
+            Some("Disconnected")
+        );
+    }
+
+    #[test]
+    fn snapshots_with_a_brain() {
+        let mut world = World::new(Grid::new(10, 1));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world
+            .add_ant(Color::Black, Position { x: 9, y: 0 })
+            .unwrap();
+        let program = parse_program("start: Turn Left start").unwrap();
+        let replies = std::io::Cursor::new("Move\nMove\nMove\n");
+        let mut simulator = Simulator::headless(world, HashMap::from([(Color::Black, program)]), 0)
+            .unwrap()
+            .with_remote_brain(Color::Red, RemoteBrain::new(replies, std::io::sink()));
+        simulator.run_for(2);
+        let snapshot = simulator.snapshot();
+        simulator.step();
+        assert_eq!(
+            simulator.world().ant(0).unwrap().position(),
+            Position { x: 3, y: 0 }
+        );
+
+        simulator.restore(&snapshot).unwrap();
+        assert_eq!(simulator.round(), 2);
+        assert_eq!(
+            simulator.world().ant(0).unwrap().position(),
+            Position { x: 2, y: 0 }
+        );
+    }
+
+    #[test]
+    fn asm_brain_matches_interpreter() {
+        let world = crate::mapgen::MapGenerator::new(24, 14).generate(3);
+        let program = parse_program(
+            "
+            start:  PickUpFood home search
+            search: Sense Ahead Food go look
+            look:   Sense Ahead Wall turn go
+            turn:   Turn Left start
+            go:     Move start turn
+            home:   Sense Here Home drop walk
+            walk:   Mark 1 step
+            step:   Move home spin
+            spin:   Turn Right home
+            drop:   DropFood start start
+            ",
+        )
+        .unwrap();
+        let programs = HashMap::from([
+            (Color::Red, program.clone()),
+            (Color::Black, program.clone()),
+        ]);
+        // Costs and a budget apply to the program whichever way it came in.
+        let mut rules = RuleSet {
+            instruction_budget: Some(4),
+            ..RuleSet::classic()
+        };
+        rules
+            .costs
+            .insert(Color::Red, CostTable::new(3).with_cost(InstrKind::Move, 2));
+        let mut native = Simulator::headless(world.clone(), programs, 0)
+            .unwrap()
+            .with_rules(rules.clone());
+        let brain = |program: &Program| Box::new(AsmBrain::new(program.clone()).unwrap());
+        let mut brains = Simulator::headless(world.clone(), HashMap::new(), 0)
+            .unwrap()
+            .with_rules(rules)
+            .with_brain(Color::Red, brain(&program))
+            .with_brain(Color::Black, brain(&program));
+
+        native.run_for(60);
+        brains.run_for(60);
+        assert!(native.world().grid() != world.grid());
+        assert!(native.world().grid() == brains.world().grid());
+        assert_eq!(native.score(Color::Red), brains.score(Color::Red));
+        let positions = |simulator: &Simulator| -> Vec<_> {
+            let world = simulator.world();
+            world
+                .ants()
+                .map(|ant| (ant.position(), ant.direction()))
+                .collect()
+        };
+        assert_eq!(positions(&native), positions(&brains));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn remote_brain_process() {
+        let mut world = World::new(Grid::new(10, 1));
+        let id = world
+            .add_ant(Color::Black, Position { x: 0, y: 0 })
+            .unwrap();
+        let mut command = std::process::Command::new("sh");
+        command.args(["-c", "read hello; while read line; do echo Move; done"]);
+        let brain = RemoteBrain::spawn(&mut command).unwrap();
+        let mut simulator = Simulator::headless(world, HashMap::new(), 0)
+            .unwrap()
+            .with_remote_brain(Color::Black, brain);
+
+        simulator.run_for(3);
+        assert_eq!(
+            simulator.world().ant(id).unwrap().position(),
+            Position { x: 3, y: 0 }
+        );
+    }
+
+    #[test]
+    fn occupancy_history() {
+        let mut world = World::new(Grid::new(3, 1));
+        let id = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world.track_history(2);
+        let mut programs = HashMap::new();
+        programs.insert(
+            Color::Red,
+            parse_program("start: Move start start").unwrap(),
+        );
+        let mut simulator = Simulator::headless(world, programs, 0).unwrap();
+        simulator.run_for(5);
+
+        let grid = simulator.world().grid();
+        let at = |x| grid.history_at(Position { x, y: 0 }).collect::<Vec<_>>();
+        assert!(at(0).is_empty());
+        assert_eq!(
+            at(1),
+            vec![Occupancy {
+                round: 0,
+                ant: id,
+                action: Some(Action::Move),
+            }]
+        );
+        // Blocked by the edge from round 2 on; only the last two are kept.
+        assert_eq!(
+            at(2),
+            vec![
+                Occupancy {
+                    round: 3,
+                    ant: id,
+                    action: None,
+                },
+                Occupancy {
+                    round: 4,
+                    ant: id,
+                    action: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn collects_step_stats() {
+        let mut world = World::new(Grid::new(3, 1));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(
+            Color::Red,
+            parse_program("start: Move start start").unwrap(),
+        );
+        let mut simulator = Simulator::headless(world, programs, 0).unwrap();
+        simulator.run_for(1);
+        simulator.start_collecting_stats();
+        simulator.run_for(3);
+
+        let stats = simulator.take_step_stats().unwrap();
+        let rounds: Vec<_> = stats.steps().iter().map(|step| step.round).collect();
+        assert_eq!(rounds, vec![2, 3, 4]);
+        let blocked: Vec<_> = stats
+            .steps()
+            .iter()
+            .map(|step| step.red.blocked_moves)
+            .collect();
+        assert_eq!(blocked, vec![0, 1, 1]);
+        assert!(stats.steps().iter().all(|step| step.red.living_ants == 1));
+        simulator.step();
+        assert!(simulator.step_stats().is_none());
+    }
+
+    fn turn_orders(turn_order: TurnOrder, rounds: u32) -> Vec<Vec<AntId>> {
+        let mut world = World::new(Grid::new(10, 10));
+        for (x, color) in [
+            Color::Red,
+            Color::Black,
+            Color::Red,
+            Color::Red,
+            Color::Black,
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            world
+                .add_ant(color, Position { x: x as i32, y: 0 })
+                .unwrap();
+        }
+        let program = parse_program("start: Turn Left start").unwrap();
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program.clone());
+        programs.insert(Color::Black, program);
+        let mut simulator = Simulator::headless(world, programs, 3)
+            .unwrap()
+            .with_turn_order(turn_order);
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        simulator.add_observer(Box::new(move |event| sink.borrow_mut().push(event)));
+        simulator.run_for(rounds);
+
+        let events = events.borrow();
+        events
+            .split(|event| matches!(event, Event::StepCompleted { .. }))
+            .take(rounds as usize)
+            .map(|round| {
+                round
+                    .iter()
+                    .map(|event| match event {
+                        Event::AntTurned { ant, .. } => *ant,
+                        _ => unreachable!(),
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn turn_orders_are_explicit() {
+        assert_eq!(
+            turn_orders(TurnOrder::ByColor, 1),
+            vec![vec![1, 4, 0, 2, 3]]
+        );
+        assert_eq!(turn_orders(TurnOrder::ById, 1), vec![vec![0, 1, 2, 3, 4]]);
+        assert_eq!(
+            turn_orders(TurnOrder::Alternating, 1),
+            vec![vec![1, 0, 4, 2, 3]]
+        );
+    }
+
+    #[test]
+    fn shuffled_turn_order_is_seeded() {
+        let orders = turn_orders(TurnOrder::Shuffled, 3);
+        assert_eq!(orders, turn_orders(TurnOrder::Shuffled, 3));
+        for order in &orders {
+            let mut sorted = order.clone();
+            sorted.sort();
+            assert_eq!(sorted, vec![0, 1, 2, 3, 4]);
         }
-        self.renderer.render(&self.world);
+        assert!(orders[0] != orders[1] || orders[1] != orders[2]);
     }
 }
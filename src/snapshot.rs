@@ -0,0 +1,266 @@
+use std::fmt;
+
+use crate::world::*;
+
+// Checkpoints of a running simulation, see `Simulator::snapshot`. A
+// snapshot is the magic bytes and a little-endian version, followed by the
+// state in a fixed order with every integer as a LEB128 varint (zigzag
+// encoded when it may be negative).
+pub const SNAPSHOT_MAGIC: [u8; 4] = *b"BWSN";
+pub const SNAPSHOT_VERSION: u16 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotError {
+    NotASnapshot,
+    UnsupportedVersion(u16),
+    Truncated,
+    // A value out of range or state that does not hang together.
+    Corrupt,
+    TrailingBytes,
+    // The simulator restoring the snapshot runs a different program for
+    // `color` than the one that took it.
+    ProgramMismatch(Color),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SnapshotError::NotASnapshot => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(version) => {
+                write!(f, "unsupported snapshot version {}", version)
+            }
+            SnapshotError::Truncated => write!(f, "the snapshot is cut short"),
+            SnapshotError::Corrupt => write!(f, "the snapshot is corrupt"),
+            SnapshotError::TrailingBytes => write!(f, "trailing bytes after the snapshot"),
+            SnapshotError::ProgramMismatch(color) => {
+                write!(
+                    f,
+                    "the snapshot was taken with another program for {:?}",
+                    color
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+// 64-bit FNV-1a: simple, and the same on every platform.
+pub(crate) fn fnv1a(bytes: impl IntoIterator<Item = u8>) -> u64 {
+    bytes.into_iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub(crate) struct Encoder {
+    bytes: Vec<u8>,
+}
+
+impl Encoder {
+    pub(crate) fn new() -> Self {
+        let mut bytes = SNAPSHOT_MAGIC.to_vec();
+        bytes.extend_from_slice(&SNAPSHOT_VERSION.to_le_bytes());
+        Encoder { bytes }
+    }
+
+    // Without the snapshot header, for other formats built from the same
+    // pieces.
+    pub(crate) fn bare() -> Self {
+        Encoder { bytes: Vec::new() }
+    }
+
+    pub(crate) fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    pub(crate) fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub(crate) fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    pub(crate) fn u64(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.u8(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.u8(value as u8);
+    }
+
+    pub(crate) fn u32(&mut self, value: u32) {
+        self.u64(value.into());
+    }
+
+    pub(crate) fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    pub(crate) fn i32(&mut self, value: i32) {
+        self.u32(((value << 1) ^ (value >> 31)) as u32);
+    }
+
+    pub(crate) fn color(&mut self, color: Color) {
+        self.usize(color.index());
+    }
+
+    pub(crate) fn direction(&mut self, direction: Direction) {
+        self.u32(direction.into());
+    }
+
+    pub(crate) fn position(&mut self, position: Position) {
+        self.i32(position.x);
+        self.i32(position.y);
+    }
+}
+
+pub(crate) struct Decoder<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub(crate) fn new(bytes: &'a [u8]) -> Result<Self, SnapshotError> {
+        let header = SNAPSHOT_MAGIC.len() + 2;
+        if bytes.len() < header || bytes[..4] != SNAPSHOT_MAGIC {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        Ok(Decoder {
+            bytes: &bytes[header..],
+        })
+    }
+
+    pub(crate) fn bare(bytes: &'a [u8]) -> Self {
+        Decoder { bytes }
+    }
+
+    // A count of items that each take at least a byte, checked against
+    // what is left so corrupt input cannot ask for huge allocations.
+    pub(crate) fn count(&mut self) -> Result<usize, SnapshotError> {
+        let count = self.usize()?;
+        if count > self.bytes.len() {
+            return Err(SnapshotError::Truncated);
+        }
+        Ok(count)
+    }
+
+    pub(crate) fn finish(self) -> Result<(), SnapshotError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(SnapshotError::TrailingBytes)
+        }
+    }
+
+    pub(crate) fn u8(&mut self) -> Result<u8, SnapshotError> {
+        let (&first, rest) = self.bytes.split_first().ok_or(SnapshotError::Truncated)?;
+        self.bytes = rest;
+        Ok(first)
+    }
+
+    pub(crate) fn bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(SnapshotError::Corrupt),
+        }
+    }
+
+    pub(crate) fn u64(&mut self) -> Result<u64, SnapshotError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(SnapshotError::Corrupt)
+    }
+
+    pub(crate) fn u32(&mut self) -> Result<u32, SnapshotError> {
+        self.u64()?.try_into().map_err(|_| SnapshotError::Corrupt)
+    }
+
+    pub(crate) fn usize(&mut self) -> Result<usize, SnapshotError> {
+        self.u64()?.try_into().map_err(|_| SnapshotError::Corrupt)
+    }
+
+    pub(crate) fn i32(&mut self) -> Result<i32, SnapshotError> {
+        let value = self.u32()?;
+        Ok((value >> 1) as i32 ^ -((value & 1) as i32))
+    }
+
+    pub(crate) fn color(&mut self) -> Result<Color, SnapshotError> {
+        Color::try_team(self.usize()?).ok_or(SnapshotError::Corrupt)
+    }
+
+    pub(crate) fn direction(&mut self) -> Result<Direction, SnapshotError> {
+        Direction::try_from(self.u32()?).map_err(|_| SnapshotError::Corrupt)
+    }
+
+    pub(crate) fn position(&mut self) -> Result<Position, SnapshotError> {
+        Ok(Position {
+            x: self.i32()?,
+            y: self.i32()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut out = Encoder::new();
+        for value in [0, 1, 127, 128, 300, u64::MAX] {
+            out.u64(value);
+        }
+        for value in [0, -1, 1, i32::MIN, i32::MAX] {
+            out.i32(value);
+        }
+        out.color(Color::Red);
+        out.direction(Direction::UpLeft);
+        let bytes = out.into_bytes();
+
+        let mut input = Decoder::new(&bytes).unwrap();
+        for value in [0, 1, 127, 128, 300, u64::MAX] {
+            assert_eq!(input.u64(), Ok(value));
+        }
+        for value in [0, -1, 1, i32::MIN, i32::MAX] {
+            assert_eq!(input.i32(), Ok(value));
+        }
+        assert_eq!(input.color(), Ok(Color::Red));
+        assert_eq!(input.direction(), Ok(Direction::UpLeft));
+        assert_eq!(input.u8(), Err(SnapshotError::Truncated));
+        assert_eq!(input.finish(), Ok(()));
+    }
+
+    #[test]
+    fn header() {
+        assert!(matches!(
+            Decoder::new(b"BWS"),
+            Err(SnapshotError::NotASnapshot)
+        ));
+        assert!(matches!(
+            Decoder::new(b"BWSN\x01\x00"),
+            Err(SnapshotError::UnsupportedVersion(1))
+        ));
+        let mut out = Encoder::new();
+        out.u8(0xff);
+        let bytes = out.into_bytes();
+        assert!(matches!(
+            Decoder::new(&bytes).unwrap().u64(),
+            Err(SnapshotError::Truncated)
+        ));
+        assert!(matches!(
+            Decoder::new(&bytes).unwrap().finish(),
+            Err(SnapshotError::TrailingBytes)
+        ));
+    }
+}
@@ -0,0 +1,392 @@
+use std::fmt::Write;
+
+use crate::world::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct SwarmStats {
+    pub living_ants: u32,
+    pub food_carried: u32,
+    pub food_in_anthill: u32,
+    // `Move` instructions that found the way blocked during the step.
+    pub blocked_moves: u32,
+    // Ants that starved at the end of the step.
+    pub starved: u32,
+}
+
+// The state of the match after a step; `round` is the number of rounds
+// played so far.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepStats {
+    pub round: u32,
+    pub food_on_ground: u32,
+    pub red: SwarmStats,
+    pub black: SwarmStats,
+}
+
+impl StepStats {
+    // Everything but the blocked moves and starved ants, which only the
+    // simulator sees.
+    pub fn measure(world: &World, round: u32) -> Self {
+        let swarm = |color| SwarmStats {
+            living_ants: world.swarm_size(color) as u32,
+            food_carried: world.swarm(color).map(|ant| ant.carried_food()).sum(),
+            food_in_anthill: world.food_in_anthill(color),
+            blocked_moves: 0,
+            starved: 0,
+        };
+        StepStats {
+            round,
+            food_on_ground: world.food_on_ground(),
+            red: swarm(Color::Red),
+            black: swarm(Color::Black),
+        }
+    }
+
+    // Only Black and Red are measured; other teams have no stats.
+    pub fn swarm(&self, color: Color) -> Option<&SwarmStats> {
+        match color {
+            Color::Red => Some(&self.red),
+            Color::Black => Some(&self.black),
+            _ => None,
+        }
+    }
+
+    fn swarm_mut(&mut self, color: Color) -> Option<&mut SwarmStats> {
+        match color {
+            Color::Red => Some(&mut self.red),
+            Color::Black => Some(&mut self.black),
+            _ => None,
+        }
+    }
+}
+
+// How a swarm is spread over the map. Distances are hex steps, ignoring
+// walls and the wrap of a torus.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SwarmMetrics {
+    // Mean distance between two of the swarm's ants.
+    pub dispersion: f64,
+    // Mean over the ants with at least two neighbouring friends of how
+    // many pairs of those friends neighbour each other too.
+    pub clustering: f64,
+    // `anthill_distances[d]` ants stand `d` steps from the nearest cell of
+    // their own anthill. Empty when the swarm has no anthill.
+    pub anthill_distances: Vec<u32>,
+}
+
+impl SwarmMetrics {
+    pub fn measure(world: &World, color: Color) -> Self {
+        let positions: Vec<_> = world.swarm(color).map(|ant| ant.position()).collect();
+
+        let mut total = 0u64;
+        for (i, a) in positions.iter().enumerate() {
+            for b in &positions[i + 1..] {
+                total += u64::from(a.distance(*b));
+            }
+        }
+        let pairs = positions.len() * positions.len().saturating_sub(1) / 2;
+        let dispersion = if pairs == 0 {
+            0.0
+        } else {
+            total as f64 / pairs as f64
+        };
+
+        let grid = world.grid();
+        let friend = |position: Position| {
+            grid.ant_at(position)
+                .and_then(|id| world.ant(id))
+                .is_some_and(|ant| ant.color() == color)
+        };
+        let mut coefficients = Vec::new();
+        for &position in &positions {
+            let friends: Vec<_> = grid.ring(position, 1).filter(|&p| friend(p)).collect();
+            if friends.len() < 2 {
+                continue;
+            }
+            let mut linked = 0;
+            for (i, a) in friends.iter().enumerate() {
+                linked += friends[i + 1..]
+                    .iter()
+                    .filter(|b| a.distance(**b) == 1)
+                    .count();
+            }
+            let possible = friends.len() * (friends.len() - 1) / 2;
+            coefficients.push(linked as f64 / possible as f64);
+        }
+        let clustering = if coefficients.is_empty() {
+            0.0
+        } else {
+            coefficients.iter().sum::<f64>() / coefficients.len() as f64
+        };
+
+        let (width, height) = (grid.width() as i32, grid.height() as i32);
+        let anthill: Vec<_> = (0..height)
+            .flat_map(|y| (0..width).map(move |x| Position { x, y }))
+            .filter(|&p| grid.cell_at(p).unwrap().anthill() == Some(color))
+            .collect();
+        let mut anthill_distances = Vec::new();
+        if !anthill.is_empty() {
+            for position in &positions {
+                let distance = anthill.iter().map(|p| p.distance(*position)).min().unwrap();
+                let distance = distance as usize;
+                if anthill_distances.len() <= distance {
+                    anthill_distances.resize(distance + 1, 0);
+                }
+                anthill_distances[distance] += 1;
+            }
+        }
+
+        SwarmMetrics {
+            dispersion,
+            clustering,
+            anthill_distances,
+        }
+    }
+
+    pub fn mean_anthill_distance(&self) -> f64 {
+        let ants: u32 = self.anthill_distances.iter().sum();
+        if ants == 0 {
+            return 0.0;
+        }
+        let total: u64 = (0u64..)
+            .zip(&self.anthill_distances)
+            .map(|(distance, &count)| distance * u64::from(count))
+            .sum();
+        total as f64 / f64::from(ants)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepMetrics {
+    pub round: u32,
+    pub red: SwarmMetrics,
+    pub black: SwarmMetrics,
+}
+
+impl StepMetrics {
+    pub fn measure(world: &World, round: u32) -> Self {
+        StepMetrics {
+            round,
+            red: SwarmMetrics::measure(world, Color::Red),
+            black: SwarmMetrics::measure(world, Color::Black),
+        }
+    }
+
+    // Only Black and Red are measured; other teams have no metrics.
+    pub fn swarm(&self, color: Color) -> Option<&SwarmMetrics> {
+        match color {
+            Color::Red => Some(&self.red),
+            Color::Black => Some(&self.black),
+            _ => None,
+        }
+    }
+}
+
+// One row per step, in the order the steps were played. Swarm metrics cost
+// time quadratic in the swarm size, so they are only taken when asked for.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StatsSeries {
+    steps: Vec<StepStats>,
+    blocked: [u32; MAX_TEAMS],
+    starved: [u32; MAX_TEAMS],
+    metrics: Option<Vec<StepMetrics>>,
+}
+
+const CSV_HEADER: &str = "round,food_on_ground,\
+red_living_ants,red_food_carried,red_food_in_anthill,red_blocked_moves,red_starved,\
+black_living_ants,black_food_carried,black_food_in_anthill,black_blocked_moves,black_starved";
+
+const METRICS_CSV_HEADER: &str = "round,\
+red_dispersion,red_clustering,red_mean_anthill_distance,red_max_anthill_distance,\
+black_dispersion,black_clustering,black_mean_anthill_distance,black_max_anthill_distance";
+
+impl StatsSeries {
+    pub fn new() -> Self {
+        StatsSeries::default()
+    }
+
+    pub fn with_swarm_metrics(mut self) -> Self {
+        self.metrics.get_or_insert_with(Vec::new);
+        self
+    }
+
+    pub fn steps(&self) -> &[StepStats] {
+        &self.steps
+    }
+
+    pub fn last(&self) -> Option<&StepStats> {
+        self.steps.last()
+    }
+
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{}", CSV_HEADER).unwrap();
+        for step in &self.steps {
+            write!(out, "{},{}", step.round, step.food_on_ground).unwrap();
+            for swarm in [&step.red, &step.black] {
+                write!(
+                    out,
+                    ",{},{},{},{},{}",
+                    swarm.living_ants,
+                    swarm.food_carried,
+                    swarm.food_in_anthill,
+                    swarm.blocked_moves,
+                    swarm.starved
+                )
+                .unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    // Empty unless the series was made `with_swarm_metrics`.
+    pub fn metrics(&self) -> &[StepMetrics] {
+        self.metrics.as_deref().unwrap_or_default()
+    }
+
+    pub fn metrics_to_csv(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "{}", METRICS_CSV_HEADER).unwrap();
+        for step in self.metrics() {
+            write!(out, "{}", step.round).unwrap();
+            for swarm in [&step.red, &step.black] {
+                write!(
+                    out,
+                    ",{:.3},{:.3},{:.3},{}",
+                    swarm.dispersion,
+                    swarm.clustering,
+                    swarm.mean_anthill_distance(),
+                    swarm.anthill_distances.len().saturating_sub(1)
+                )
+                .unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    pub(crate) fn count_blocked(&mut self, color: Color) {
+        self.blocked[color.index()] += 1;
+    }
+
+    pub(crate) fn count_starved(&mut self, color: Color) {
+        self.starved[color.index()] += 1;
+    }
+
+    pub(crate) fn finish_step(&mut self, world: &World, round: u32) {
+        let mut step = StepStats::measure(world, round);
+        for color in [Color::Red, Color::Black] {
+            let swarm = step.swarm_mut(color).unwrap();
+            swarm.blocked_moves = self.blocked[color.index()];
+            swarm.starved = self.starved[color.index()];
+        }
+        self.blocked = [0; MAX_TEAMS];
+        self.starved = [0; MAX_TEAMS];
+        self.steps.push(step);
+        if let Some(metrics) = &mut self.metrics {
+            metrics.push(StepMetrics::measure(world, round));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "\
+# # # # # #
+ # r 2 + b #
+# # # # # #
+";
+
+    #[test]
+    fn measure() {
+        let mut world: World = MAP.parse().unwrap();
+        let red = world.swarm_ids(Color::Red).next().unwrap();
+        world.ant_mut(red).unwrap().move_forward().unwrap();
+        world.ant_mut(red).unwrap().pickup_food().unwrap();
+
+        let stats = StepStats::measure(&world, 4);
+        assert_eq!(stats.round, 4);
+        assert_eq!(stats.food_on_ground, 1);
+        assert_eq!(
+            stats.swarm(Color::Red),
+            Some(&SwarmStats {
+                living_ants: 1,
+                food_carried: 1,
+                food_in_anthill: 0,
+                blocked_moves: 0,
+                starved: 0,
+            })
+        );
+        assert_eq!(stats.black.food_carried, 0);
+        assert_eq!(stats.swarm(Color::team(2)), None);
+    }
+
+    #[test]
+    fn csv() {
+        let world: World = MAP.parse().unwrap();
+        let mut series = StatsSeries::new();
+        series.count_blocked(Color::Black);
+        series.count_blocked(Color::Black);
+        series.finish_step(&world, 1);
+        series.finish_step(&world, 2);
+        assert_eq!(series.steps().len(), 2);
+        assert_eq!(series.last().unwrap().black.blocked_moves, 0);
+
+        let csv = series.to_csv();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].split(',').count(), 12);
+        assert_eq!(lines[1], "1,2,1,0,0,0,0,1,0,0,2,0");
+        assert_eq!(lines[2], "2,2,1,0,0,0,0,1,0,0,0,0");
+    }
+
+    #[test]
+    fn swarm_metrics() {
+        let mut world = World::new(Grid::new(8, 8));
+        for position in [(1, 1), (2, 1), (1, 2), (6, 6)] {
+            let (x, y) = position;
+            world.add_ant(Color::Red, Position { x, y }).unwrap();
+        }
+        world
+            .add_ant(Color::Black, Position { x: 4, y: 4 })
+            .unwrap();
+
+        let red = SwarmMetrics::measure(&world, Color::Red);
+        // Pairs: 1, 1, 1 within the triangle and 10, 9, 9 to the straggler.
+        assert_eq!(red.dispersion, 31.0 / 6.0);
+        // The triangle is fully linked; the straggler has no friends.
+        assert_eq!(red.clustering, 1.0);
+        assert!(red.anthill_distances.is_empty());
+
+        let black = SwarmMetrics::measure(&world, Color::Black);
+        assert_eq!((black.dispersion, black.clustering), (0.0, 0.0));
+    }
+
+    #[test]
+    fn anthill_distances() {
+        let mut world: World = MAP.parse().unwrap();
+        let red = world.swarm_ids(Color::Red).next().unwrap();
+        world.ant_mut(red).unwrap().move_forward().unwrap();
+
+        let mut series = StatsSeries::new().with_swarm_metrics();
+        series.finish_step(&world, 1);
+        let step = &series.metrics()[0];
+        assert_eq!(step.red.anthill_distances, [0, 1]);
+        assert_eq!(step.red.mean_anthill_distance(), 1.0);
+        assert!(step
+            .swarm(Color::Black)
+            .unwrap()
+            .anthill_distances
+            .is_empty());
+        assert!(step.swarm(Color::team(5)).is_none());
+
+        let csv = series.metrics_to_csv();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[0].split(',').count(), 9);
+        assert_eq!(lines[1], "1,0.000,0.000,1.000,1,0.000,0.000,0.000,0");
+        assert!(StatsSeries::new().metrics().is_empty());
+    }
+}
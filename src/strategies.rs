@@ -0,0 +1,240 @@
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use crate::asm::*;
+use crate::scent::*;
+use crate::world::*;
+
+// Proptest strategies for worlds and programs, to fuzz the simulator with
+// inputs that shrink to something readable when a property fails. Sizes
+// stay small enough to simulate many cases quickly.
+pub const MAX_SIDE: usize = 16;
+pub const MAX_ANTS: usize = 32;
+pub const MAX_PROGRAM_LEN: usize = 32;
+
+pub fn color() -> impl Strategy<Value = Color> {
+    (0..MAX_TEAMS).prop_map(Color::team)
+}
+
+pub fn direction() -> impl Strategy<Value = Direction> {
+    (0..6u32).prop_map(|n| Direction::try_from(n).unwrap())
+}
+
+// Walls, or free cells with up to 9 food, some markers and maybe an
+// anthill. Never an ant; `world` puts those in.
+pub fn cell() -> impl Strategy<Value = Cell> {
+    let markers = vec((color(), 0..MARKERS_PER_COLOR), 0..4).prop_map(|set| {
+        let mut markers = Markers::default();
+        for (color, marker) in set {
+            markers.set(color, marker);
+        }
+        markers
+    });
+    prop_oneof![
+        1 => Just(Cell::Wall),
+        3 => (0..=9u32, markers, proptest::option::weighted(0.2, color())).prop_map(
+            |(food, markers, anthill)| Cell::FreeCell {
+                ant_id: None,
+                food,
+                markers,
+                anthill,
+            }
+        ),
+    ]
+}
+
+// A grid of any cells, torus or not, with ants of any team wherever they
+// found room.
+pub fn world() -> impl Strategy<Value = World> {
+    (1..=MAX_SIDE, 1..=MAX_SIDE, any::<bool>()).prop_flat_map(|(width, height, torus)| {
+        let ant = (color(), 0..width as i32, 0..height as i32, direction());
+        let ants = vec(ant, 0..=MAX_ANTS.min(width * height));
+        (vec(cell(), width * height), ants).prop_map(move |(cells, ants)| {
+            let mut grid = if torus {
+                Grid::new_torus(width, height)
+            } else {
+                Grid::new(width, height)
+            };
+            for (i, cell) in cells.into_iter().enumerate() {
+                let position = Position {
+                    x: (i % width) as i32,
+                    y: (i / width) as i32,
+                };
+                *grid.cell_at_mut(position).unwrap() = cell;
+            }
+            let mut world = World::new(grid);
+            for (color, x, y, direction) in ants {
+                if let Ok(id) = world.add_ant(color, Position { x, y }) {
+                    world.ant_mut(id).unwrap().rotate(direction);
+                }
+            }
+            world
+        })
+    })
+}
+
+// Any instruction, jumping only within a program of `len`.
+pub fn instr(len: usize) -> impl Strategy<Value = Instr> {
+    let target = 0..len;
+    let branches = (target.clone(), target.clone());
+    let marker = 0..MARKERS_PER_COLOR;
+    let register = 0..REGISTERS as Register;
+    let sense_condition = prop_oneof![
+        prop::sample::select(SENSE_CONDITIONS.map(|(_, condition)| condition).to_vec()),
+        marker.clone().prop_map(SenseCondition::Marker),
+    ];
+    let swarm_condition = prop_oneof![
+        (0..50usize).prop_map(SwarmCondition::SizeBelow),
+        Just(SwarmCondition::Outnumbered),
+        Just(SwarmCondition::ScoreAhead),
+    ];
+    prop_oneof![
+        (
+            prop::sample::select(TURN_DIRECTIONS.map(|(_, turn)| turn).to_vec()),
+            target.clone()
+        )
+            .prop_map(|(direction, next_instr)| Instr::Turn {
+                direction,
+                next_instr
+            }),
+        branches
+            .clone()
+            .prop_map(|(success_instr, fail_instr)| Instr::Move {
+                success_instr,
+                fail_instr
+            }),
+        (direction(), branches.clone()).prop_map(|(direction, (success_instr, fail_instr))| {
+            Instr::Direction {
+                direction,
+                success_instr,
+                fail_instr,
+            }
+        }),
+        branches
+            .clone()
+            .prop_map(|(success_instr, fail_instr)| Instr::PickUpFood {
+                success_instr,
+                fail_instr
+            }),
+        branches
+            .clone()
+            .prop_map(|(success_instr, fail_instr)| Instr::DropFood {
+                success_instr,
+                fail_instr
+            }),
+        (marker.clone(), target.clone())
+            .prop_map(|(marker, next_instr)| Instr::Mark { marker, next_instr }),
+        (marker, target.clone())
+            .prop_map(|(marker, next_instr)| Instr::Unmark { marker, next_instr }),
+        (
+            prop::sample::select(SENSE_DIRECTIONS.map(|(_, sense_dir)| sense_dir).to_vec()),
+            sense_condition,
+            branches.clone()
+        )
+            .prop_map(
+                |(sense_dir, condition, (success_instr, fail_instr))| Instr::Sense {
+                    sense_dir,
+                    condition,
+                    success_instr,
+                    fail_instr,
+                }
+            ),
+        (swarm_condition, branches.clone()).prop_map(|(condition, (success_instr, fail_instr))| {
+            Instr::SenseSwarm {
+                condition,
+                success_instr,
+                fail_instr,
+            }
+        }),
+        (
+            prop::sample::select(SENSE_DIRECTIONS.map(|(_, sense_dir)| sense_dir).to_vec()),
+            prop::sample::select(vec![Scent::Food, Scent::Home]),
+            branches.clone()
+        )
+            .prop_map(|(sense_dir, scent, (success_instr, fail_instr))| {
+                Instr::SenseScent {
+                    sense_dir,
+                    scent,
+                    success_instr,
+                    fail_instr,
+                }
+            }),
+        (0..=10u32, branches.clone()).prop_map(|(n, (success_instr, fail_instr))| Instr::Flip {
+            n,
+            success_instr,
+            fail_instr,
+        }),
+        branches
+            .clone()
+            .prop_map(|(success_instr, fail_instr)| Instr::Eat {
+                success_instr,
+                fail_instr
+            }),
+        (register.clone(), any::<u8>(), target.clone()).prop_map(
+            |(register, value, next_instr)| Instr::Set {
+                register,
+                value,
+                next_instr,
+            }
+        ),
+        (register.clone(), any::<u8>(), target.clone()).prop_map(
+            |(register, value, next_instr)| Instr::Add {
+                register,
+                value,
+                next_instr,
+            }
+        ),
+        (register.clone(), target.clone()).prop_map(|(register, next_instr)| Instr::Dec {
+            register,
+            next_instr
+        }),
+        (register, branches).prop_map(|(register, (success_instr, fail_instr))| {
+            Instr::JumpIfZero {
+                register,
+                success_instr,
+                fail_instr,
+            }
+        }),
+        (target.clone(), target)
+            .prop_map(|(target, next_instr)| Instr::Call { target, next_instr }),
+        Just(Instr::Return),
+    ]
+}
+
+// Programs the simulator accepts: never empty, and every target in range.
+pub fn program() -> impl Strategy<Value = Program> {
+    (1..=MAX_PROGRAM_LEN).prop_flat_map(|len| vec(instr(len), len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sim::*;
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(64))]
+
+        #[test]
+        fn worlds_hold_together(world in world()) {
+            prop_assert_eq!(world.check_invariants(), Ok(()));
+        }
+
+        #[test]
+        fn steps_keep_invariants(
+            world in world(),
+            programs in vec(program(), MAX_TEAMS),
+            seed in any::<u64>(),
+        ) {
+            let programs = world
+                .teams()
+                .into_iter()
+                .map(|color| (color, programs[color.index()].clone()))
+                .collect();
+            let mut simulator = Simulator::headless(world, programs, seed).unwrap();
+            for _ in 0..20 {
+                simulator.step();
+                prop_assert_eq!(simulator.world().check_invariants(), Ok(()));
+            }
+        }
+    }
+}
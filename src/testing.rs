@@ -0,0 +1,178 @@
+use std::fmt::Write;
+
+use crate::world::*;
+
+// Describes how two worlds differ, or returns `None` if they are equal: a
+// map of the grid with differing cells marked `*`, followed by the
+// differing cells and ants.
+pub fn world_diff(left: &World, right: &World) -> Option<String> {
+    if left == right {
+        return None;
+    }
+    let mut out = String::new();
+    let (lg, rg) = (left.grid(), right.grid());
+    if (lg.width(), lg.height()) != (rg.width(), rg.height()) {
+        writeln!(
+            out,
+            "grid size: {}x{} != {}x{}",
+            lg.width(),
+            lg.height(),
+            rg.width(),
+            rg.height()
+        )
+        .unwrap();
+    } else {
+        write_cell_diff(&mut out, lg, rg);
+    }
+    write_ant_diff(&mut out, left, right);
+    if out.is_empty() {
+        out.push_str("worlds differ only in swarm bookkeeping\n");
+    }
+    Some(out)
+}
+
+fn write_cell_diff(out: &mut String, left: &Grid, right: &Grid) {
+    let positions = (0..left.height() as i32)
+        .flat_map(|y| (0..left.width() as i32).map(move |x| Position { x, y }));
+    let differing: Vec<_> = positions
+        .filter(|&position| left.cell_at(position) != right.cell_at(position))
+        .collect();
+    if differing.is_empty() {
+        return;
+    }
+    for y in 0..left.height() as i32 {
+        // Each row is shifted right by its index, so hex neighbours line up
+        // in axial coordinates.
+        let mut row = " ".repeat(y as usize);
+        for x in 0..left.width() as i32 {
+            let mark = if differing.contains(&Position { x, y }) {
+                '*'
+            } else {
+                '.'
+            };
+            row.push(mark);
+            row.push(' ');
+        }
+        writeln!(out, "{}", row.trim_end()).unwrap();
+    }
+    for position in differing {
+        writeln!(
+            out,
+            "cell ({}, {}): {:?} != {:?}",
+            position.x,
+            position.y,
+            left.cell_at(position).unwrap(),
+            right.cell_at(position).unwrap()
+        )
+        .unwrap();
+    }
+}
+
+pub(crate) fn describe_ant(world: &World, id: AntId) -> Option<String> {
+    let ant = world.ant(id)?;
+    Some(format!(
+        "{:?} at ({}, {}) facing {:?}, instr {}, food {}, registers {:?}, calls {:?}",
+        ant.color(),
+        ant.position().x,
+        ant.position().y,
+        ant.direction(),
+        ant.instr_pointer(),
+        ant.carried_food(),
+        ant.registers(),
+        ant.call_stack().frames()
+    ))
+}
+
+fn write_ant_diff(out: &mut String, left: &World, right: &World) {
+    let max_id = left.ant_ids().chain(right.ant_ids()).max();
+    for id in max_id.map_or(0..0, |max_id| 0..max_id + 1) {
+        let (l, r) = (describe_ant(left, id), describe_ant(right, id));
+        if l != r {
+            let none = || "none".to_string();
+            writeln!(
+                out,
+                "ant {}: {} != {}",
+                id,
+                l.unwrap_or_else(none),
+                r.unwrap_or_else(none)
+            )
+            .unwrap();
+        }
+    }
+}
+
+#[track_caller]
+pub fn assert_world_eq(left: &World, right: &World) {
+    if let Some(diff) = world_diff(left, right) {
+        panic!("worlds are not equal:\n{}", diff);
+    }
+}
+
+#[macro_export]
+macro_rules! assert_world_eq {
+    ($left:expr, $right:expr $(,)?) => {
+        $crate::testing::assert_world_eq(&$left, &$right)
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world() -> World {
+        let mut world = World::new(Grid::new(3, 2));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world
+    }
+
+    #[test]
+    fn equal_worlds() {
+        assert_eq!(world_diff(&world(), &world()), None);
+        assert_world_eq!(world(), world());
+    }
+
+    #[test]
+    fn differing_cell_and_ant() {
+        let left = world();
+        let mut right = world();
+        right.ant_mut(0).unwrap().move_forward().unwrap();
+
+        let diff = world_diff(&left, &right).unwrap();
+        assert!(diff.starts_with("* * .\n . . .\n"));
+        assert!(diff.contains("cell (1, 0): "));
+        assert!(diff.contains(
+            "ant 0: Red at (0, 0) facing Right, instr 0, food 0, registers [0, 0, 0, 0], \
+             calls [] != Red at (1, 0)"
+        ));
+    }
+
+    #[test]
+    fn missing_ant() {
+        let left = world();
+        let mut right = world();
+        right
+            .add_ant(Color::Black, Position { x: 2, y: 1 })
+            .unwrap();
+
+        let diff = world_diff(&left, &right).unwrap();
+        assert!(diff.contains("ant 1: none != Black at (2, 1)"));
+    }
+
+    #[test]
+    fn grid_size() {
+        let left = World::new(Grid::new(3, 2));
+        let right = World::new(Grid::new(2, 3));
+        assert_eq!(
+            world_diff(&left, &right).unwrap(),
+            "grid size: 3x2 != 2x3\n"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "worlds are not equal")]
+    fn assert_panics() {
+        let mut right = world();
+        right.ant_mut(0).unwrap().rotate(Direction::Left);
+        assert_world_eq!(world(), right);
+    }
+}
@@ -0,0 +1,531 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::asm::*;
+use crate::rng::*;
+use crate::rules::*;
+use crate::sim::*;
+use crate::victory::*;
+use crate::world::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entrant {
+    pub name: String,
+    pub program: Program,
+}
+
+// One game: `red` and `black` index the entrants, `map` the maps. `leg`
+// counts the times the map has been played through, see `Adaptive`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchRecord {
+    pub map: usize,
+    pub leg: u32,
+    pub red: usize,
+    pub black: usize,
+    pub red_score: u32,
+    pub black_score: u32,
+    pub outcome: Victory,
+    pub rounds: u32,
+}
+
+impl MatchRecord {
+    fn entrant(&self, color: Color) -> usize {
+        if color == Color::Red {
+            self.red
+        } else {
+            self.black
+        }
+    }
+
+    fn score(&self, color: Color) -> u32 {
+        if color == Color::Red {
+            self.red_score
+        } else {
+            self.black_score
+        }
+    }
+
+    // What the game was worth to `entrant`, if it played.
+    fn points(&self, entrant: usize) -> Option<u32> {
+        let color = [Color::Red, Color::Black]
+            .into_iter()
+            .find(|&color| self.entrant(color) == entrant)?;
+        Some(match self.outcome {
+            Victory::Winner(winner) if winner == color => 3,
+            Victory::Winner(_) => 0,
+            Victory::Draw => 1,
+        })
+    }
+}
+
+// Plays extra legs, every pairing once more on one map, while neighbours in
+// the standings are too close to call: their points per game less than `z`
+// standard errors apart. Each leg goes to the map where most of those
+// neighbours are still undecided, so maps that already separate them are
+// not played again. At most `max_legs` legs are added.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adaptive {
+    // 1.96 for 95% confidence.
+    pub z: f64,
+    pub max_legs: u32,
+}
+
+impl Default for Adaptive {
+    fn default() -> Self {
+        Adaptive {
+            z: 1.96,
+            max_legs: 10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Standing {
+    pub entrant: usize,
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+    pub food_for: u32,
+    pub food_against: u32,
+}
+
+impl Standing {
+    // Three points for a win, one for a draw.
+    pub fn points(&self) -> u32 {
+        3 * self.wins + self.draws
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TournamentResult {
+    pub matches: Vec<MatchRecord>,
+    // Best first: by points, then food difference, then food scored.
+    pub standings: Vec<Standing>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TournamentError {
+    NotEnoughEntrants,
+    NoMaps,
+    InvalidProgram {
+        entrant: usize,
+        errors: Vec<ProgramError>,
+    },
+}
+
+impl fmt::Display for TournamentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TournamentError::NotEnoughEntrants => write!(f, "a tournament needs two entrants"),
+            TournamentError::NoMaps => write!(f, "a tournament needs a map"),
+            TournamentError::InvalidProgram { entrant, errors } => write!(
+                f,
+                "entrant {} has an invalid program: {}",
+                entrant,
+                describe_errors(errors)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for TournamentError {}
+
+// Every pair of entrants meets on every map twice, once with each color,
+// and more often under `Adaptive` scheduling. A game lasts `rounds` rounds
+// unless the victory condition settles it earlier; undecided games go to
+// the swarm with more food at home.
+pub struct Tournament {
+    entrants: Vec<Entrant>,
+    maps: Vec<World>,
+    rounds: u32,
+    seed: u64,
+    rules: RuleSet,
+    victory_condition: Option<VictoryCondition>,
+    adaptive: Option<Adaptive>,
+}
+
+impl Tournament {
+    pub fn new(entrants: Vec<Entrant>, maps: Vec<World>, rounds: u32) -> Self {
+        Tournament {
+            entrants,
+            maps,
+            rounds,
+            seed: 0,
+            rules: RuleSet::default(),
+            victory_condition: None,
+            adaptive: None,
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn with_victory_condition(mut self, condition: VictoryCondition) -> Self {
+        self.victory_condition = Some(condition);
+        self
+    }
+
+    pub fn with_adaptive(mut self, adaptive: Adaptive) -> Self {
+        self.adaptive = Some(adaptive);
+        self
+    }
+
+    pub fn entrants(&self) -> &[Entrant] {
+        &self.entrants
+    }
+
+    // Pairs of (red, black) entrant indices in the order they are played.
+    pub fn pairings(&self) -> Vec<(usize, usize)> {
+        let n = self.entrants.len();
+        (0..n)
+            .flat_map(|a| (a + 1..n).flat_map(move |b| [(a, b), (b, a)]))
+            .collect()
+    }
+
+    pub fn run(&self) -> Result<TournamentResult, TournamentError> {
+        if self.entrants.len() < 2 {
+            return Err(TournamentError::NotEnoughEntrants);
+        }
+        if self.maps.is_empty() {
+            return Err(TournamentError::NoMaps);
+        }
+        for (entrant, Entrant { program, .. }) in self.entrants.iter().enumerate() {
+            if let Err(errors) = validate(program) {
+                let errors: Vec<_> = errors.into_iter().filter(|e| e.is_fatal()).collect();
+                if !errors.is_empty() {
+                    return Err(TournamentError::InvalidProgram { entrant, errors });
+                }
+            }
+        }
+
+        let mut matches = Vec::new();
+        let mut legs = vec![0; self.maps.len()];
+        for map in 0..self.maps.len() {
+            self.play_leg(map, &mut legs, &mut matches);
+        }
+        let mut standings = self.standings(&matches);
+        if let Some(adaptive) = self.adaptive {
+            for _ in 0..adaptive.max_legs {
+                let Some(map) = self.undecided_map(&standings, &matches, &legs, adaptive.z) else {
+                    break;
+                };
+                self.play_leg(map, &mut legs, &mut matches);
+                standings = self.standings(&matches);
+            }
+        }
+        Ok(TournamentResult { matches, standings })
+    }
+
+    fn play_leg(&self, map: usize, legs: &mut [u32], matches: &mut Vec<MatchRecord>) {
+        for (red, black) in self.pairings() {
+            matches.push(self.play(map, legs[map], red, black));
+        }
+        legs[map] += 1;
+    }
+
+    // The map to play another leg on, `None` once the standings are
+    // decided.
+    fn undecided_map(
+        &self,
+        standings: &[Standing],
+        matches: &[MatchRecord],
+        legs: &[u32],
+        z: f64,
+    ) -> Option<usize> {
+        let close: Vec<_> = standings
+            .windows(2)
+            .map(|pair| (pair[0].entrant, pair[1].entrant))
+            .filter(|&(a, b)| undecided(matches.iter(), a, b, z))
+            .collect();
+        if close.is_empty() {
+            return None;
+        }
+        (0..self.maps.len()).min_by_key(|&map| {
+            let on_map = || matches.iter().filter(move |record| record.map == map);
+            let open = close
+                .iter()
+                .filter(|&&(a, b)| undecided(on_map(), a, b, z))
+                .count();
+            (Reverse(open), legs[map], map)
+        })
+    }
+
+    fn play(&self, map: usize, leg: u32, red: usize, black: usize) -> MatchRecord {
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, self.entrants[red].program.clone());
+        programs.insert(Color::Black, self.entrants[black].program.clone());
+        // The first leg keeps the seeds of tournaments without extra legs.
+        let mut keys = vec![map as u64, red as u64, black as u64];
+        if leg > 0 {
+            keys.push(u64::from(leg));
+        }
+        let seed = Rng::stream(self.seed, &keys).next_u64();
+        let mut simulator = Simulator::headless(self.maps[map].clone(), programs, seed)
+            .expect("programs are validated up front")
+            .with_rules(self.rules.clone())
+            .with_end_condition(EndCondition::MaxRounds(self.rounds));
+        if let Some(condition) = &self.victory_condition {
+            simulator = simulator.with_victory_condition(condition.clone());
+        }
+        simulator.run_until(Simulator::is_over);
+
+        let result = simulator.result().unwrap();
+        MatchRecord {
+            map,
+            leg,
+            red,
+            black,
+            red_score: result.score(Color::Red),
+            black_score: result.score(Color::Black),
+            outcome: result.outcome,
+            rounds: result.round,
+        }
+    }
+
+    fn standings(&self, matches: &[MatchRecord]) -> Vec<Standing> {
+        let mut standings: Vec<_> = (0..self.entrants.len())
+            .map(|entrant| Standing {
+                entrant,
+                wins: 0,
+                draws: 0,
+                losses: 0,
+                food_for: 0,
+                food_against: 0,
+            })
+            .collect();
+        for record in matches {
+            for color in [Color::Red, Color::Black] {
+                let standing = &mut standings[record.entrant(color)];
+                standing.food_for += record.score(color);
+                standing.food_against += record.score(color.enemy());
+                match record.outcome {
+                    Victory::Draw => standing.draws += 1,
+                    Victory::Winner(winner) if winner == color => standing.wins += 1,
+                    Victory::Winner(_) => standing.losses += 1,
+                }
+            }
+        }
+        standings.sort_by_key(|standing| {
+            let difference = standing.food_for as i64 - standing.food_against as i64;
+            (
+                Reverse(standing.points()),
+                Reverse(difference),
+                Reverse(standing.food_for),
+                standing.entrant,
+            )
+        });
+        standings
+    }
+}
+
+// Whether `a` and `b` are within `z` standard errors of each other in
+// points per game. Games that always end the same way are decided, ties
+// included: playing more would not change them.
+fn undecided<'a>(
+    records: impl Iterator<Item = &'a MatchRecord> + Clone,
+    a: usize,
+    b: usize,
+    z: f64,
+) -> bool {
+    let (mean_a, variance_a) = points_per_game(records.clone(), a);
+    let (mean_b, variance_b) = points_per_game(records, b);
+    let error = (variance_a + variance_b).sqrt();
+    error > 0.0 && (mean_a - mean_b).abs() <= z * error
+}
+
+// The mean and the variance of that mean.
+fn points_per_game<'a>(
+    records: impl Iterator<Item = &'a MatchRecord>,
+    entrant: usize,
+) -> (f64, f64) {
+    let points: Vec<_> = records
+        .filter_map(|record| record.points(entrant))
+        .map(f64::from)
+        .collect();
+    let n = points.len() as f64;
+    if points.len() < 2 {
+        return (points.iter().sum::<f64>() / n.max(1.0), 0.0);
+    }
+    let mean = points.iter().sum::<f64>() / n;
+    let variance = points.iter().map(|p| (p - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance / n)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Walks ahead until blocked, picks up the food it is standing on, then
+    // turns round and walks back until blocked again to drop it.
+    const FORAGER: &str = "
+        out:  Move out pick
+        pick: PickUpFood turn turn
+        turn: Turn Left t2
+        t2:   Turn Left t3
+        t3:   Turn Left home
+        home: Move home drop
+        drop: DropFood drop
+    ";
+
+    const IDLER: &str = "start: Turn Left start";
+
+    const MAP: &str = "\
+# # # # # # #
+ # + r 4 b - #
+# # # # # # #
+";
+
+    fn entrant(name: &str, source: &str) -> Entrant {
+        Entrant {
+            name: name.to_string(),
+            program: parse_program(source).unwrap(),
+        }
+    }
+
+    fn maps() -> Vec<World> {
+        let mut world: World = MAP.parse().unwrap();
+        // Both ants face the food in the middle.
+        let black = world.swarm_ids(Color::Black).next().unwrap();
+        world.ant_mut(black).unwrap().rotate(Direction::Left);
+        vec![world]
+    }
+
+    #[test]
+    fn pairings_swap_colors() {
+        let tournament = Tournament::new(
+            vec![
+                entrant("a", IDLER),
+                entrant("b", IDLER),
+                entrant("c", IDLER),
+            ],
+            maps(),
+            10,
+        );
+        assert_eq!(
+            tournament.pairings(),
+            vec![(0, 1), (1, 0), (0, 2), (2, 0), (1, 2), (2, 1)]
+        );
+    }
+
+    #[test]
+    fn forager_beats_idler() {
+        let tournament = Tournament::new(
+            vec![entrant("idler", IDLER), entrant("forager", FORAGER)],
+            maps(),
+            20,
+        );
+        let result = tournament.run().unwrap();
+        assert_eq!(result.matches.len(), 2);
+        for record in &result.matches {
+            let forager = if record.red == 1 {
+                Color::Red
+            } else {
+                Color::Black
+            };
+            assert_eq!(record.outcome, Victory::Winner(forager));
+            assert_eq!(record.rounds, 20);
+            assert_eq!(record.score(forager), 1);
+        }
+        assert_eq!(
+            result.standings,
+            vec![
+                Standing {
+                    entrant: 1,
+                    wins: 2,
+                    draws: 0,
+                    losses: 0,
+                    food_for: 2,
+                    food_against: 0,
+                },
+                Standing {
+                    entrant: 0,
+                    wins: 0,
+                    draws: 0,
+                    losses: 2,
+                    food_for: 0,
+                    food_against: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn adaptive_legs_go_to_undecided_maps() {
+        // The forager, dawdling at random.
+        let dawdler = FORAGER.replace(
+            "out:  Move out pick",
+            "out:  Flip 2 go out\ngo: Move out pick",
+        );
+        let entrants = vec![entrant("a", &dawdler), entrant("b", &dawdler)];
+        let mut settled: World = MAP.parse().unwrap();
+        for id in settled.ant_ids().collect::<Vec<_>>() {
+            settled.remove_ant(id).unwrap();
+        }
+        let mut pool = maps();
+        pool.push(settled);
+        let tournament = Tournament::new(entrants, pool, 20).with_adaptive(Adaptive {
+            z: 3.0,
+            max_legs: 4,
+        });
+        let result = tournament.run().unwrap();
+        assert_eq!(result, tournament.run().unwrap());
+        let legs = |map| {
+            let on_map = result.matches.iter().filter(|record| record.map == map);
+            on_map.map(|record| record.leg + 1).max().unwrap()
+        };
+        // Nothing happens without ants, so only the first map gets more.
+        assert_eq!(legs(1), 1);
+        assert!(legs(0) > 1);
+        assert_eq!(result.matches.len() as u32, 2 * (legs(0) + legs(1)));
+
+        let decided = Tournament::new(
+            vec![entrant("idler", IDLER), entrant("forager", FORAGER)],
+            maps(),
+            20,
+        )
+        .with_adaptive(Adaptive::default());
+        assert_eq!(decided.run().unwrap().matches.len(), 2);
+    }
+
+    #[test]
+    fn victory_ends_games_early() {
+        let tournament =
+            Tournament::new(vec![entrant("a", IDLER), entrant("b", IDLER)], maps(), 100)
+                .with_victory_condition(VictoryCondition::Rounds(5));
+        let result = tournament.run().unwrap();
+        assert!(result.matches.iter().all(|record| record.rounds == 5));
+        assert!(result
+            .matches
+            .iter()
+            .all(|record| record.outcome == Victory::Draw));
+        assert_eq!(result.standings[0].points(), 2);
+    }
+
+    #[test]
+    fn rejects_bad_setups() {
+        let one = Tournament::new(vec![entrant("a", IDLER)], maps(), 1);
+        assert_eq!(one.run().err(), Some(TournamentError::NotEnoughEntrants));
+        let no_maps = Tournament::new(vec![entrant("a", IDLER), entrant("b", IDLER)], vec![], 1);
+        assert_eq!(no_maps.run().err(), Some(TournamentError::NoMaps));
+        let broken = Entrant {
+            name: "broken".to_string(),
+            program: vec![],
+        };
+        let tournament = Tournament::new(vec![entrant("a", IDLER), broken], maps(), 1);
+        assert_eq!(
+            tournament.run().err(),
+            Some(TournamentError::InvalidProgram {
+                entrant: 1,
+                errors: vec![ProgramError::Empty],
+            })
+        );
+    }
+}
@@ -0,0 +1,208 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::io::Write;
+use std::rc::Rc;
+
+use crate::asm::*;
+use crate::data::*;
+use crate::world::*;
+
+// Which way an instruction went.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Outcome {
+    Success,
+    Fail,
+    // Instructions with a single way on.
+    Next,
+}
+
+impl Outcome {
+    // Actions count as a success when the world allowed them, whichever
+    // target that leads to.
+    pub fn of(instr: Instr, next_instr: InstrIdx, action: Option<Action>) -> Self {
+        let branch = |success_instr| {
+            if next_instr == success_instr {
+                Outcome::Success
+            } else {
+                Outcome::Fail
+            }
+        };
+        match instr {
+            Instr::Move { .. }
+            | Instr::PickUpFood { .. }
+            | Instr::DropFood { .. }
+            | Instr::Eat { .. } => {
+                if action.is_some() {
+                    Outcome::Success
+                } else {
+                    Outcome::Fail
+                }
+            }
+            Instr::Direction { success_instr, .. }
+            | Instr::Sense { success_instr, .. }
+            | Instr::SenseSwarm { success_instr, .. }
+            | Instr::Flip { success_instr, .. }
+            | Instr::SenseScent { success_instr, .. }
+            | Instr::JumpIfZero { success_instr, .. } => branch(success_instr),
+            Instr::Turn { .. }
+            | Instr::Mark { .. }
+            | Instr::Unmark { .. }
+            | Instr::Set { .. }
+            | Instr::Add { .. }
+            | Instr::Dec { .. }
+            | Instr::Call { .. }
+            | Instr::Return => Outcome::Next,
+        }
+    }
+}
+
+// One instruction run by one ant, with where the ant stands after it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TraceEntry {
+    pub round: u32,
+    pub ant: AntId,
+    pub instr_pointer: InstrIdx,
+    pub instr: Instr,
+    pub outcome: Outcome,
+    pub next_instr: InstrIdx,
+    pub position: Position,
+    pub direction: Direction,
+}
+
+impl fmt::Display for TraceEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "round {} ant {} @{}: {} -> {:?} {}, at ({}, {}) facing {:?}",
+            self.round,
+            self.ant,
+            self.instr_pointer,
+            self.instr,
+            self.outcome,
+            self.next_instr,
+            self.position.x,
+            self.position.y,
+            self.direction
+        )
+    }
+}
+
+pub trait TraceSink {
+    fn record(&mut self, entry: &TraceEntry);
+}
+
+impl<F: FnMut(&TraceEntry)> TraceSink for F {
+    fn record(&mut self, entry: &TraceEntry) {
+        self(entry)
+    }
+}
+
+// Keep a clone to read the trace while the simulator holds the sink.
+impl TraceSink for Rc<RefCell<Vec<TraceEntry>>> {
+    fn record(&mut self, entry: &TraceEntry) {
+        self.borrow_mut().push(*entry);
+    }
+}
+
+// One line per entry. Tracing is a debugging aid, so write errors are
+// dropped rather than stopping the match.
+pub struct WriteSink<W: Write>(pub W);
+
+impl<W: Write> TraceSink for WriteSink<W> {
+    fn record(&mut self, entry: &TraceEntry) {
+        let _ = writeln!(self.0, "{}", entry);
+    }
+}
+
+// Logs every entry at trace level, under the `bugworld::trace` target.
+#[cfg(feature = "log")]
+pub struct LogSink;
+
+#[cfg(feature = "log")]
+impl TraceSink for LogSink {
+    fn record(&mut self, entry: &TraceEntry) {
+        log::trace!(target: "bugworld::trace", "{}", entry);
+    }
+}
+
+// Traces the ants that run programs; ants with a brain have no
+// instructions to show.
+pub struct Tracer {
+    // `None` for all of them.
+    ants: Option<BTreeSet<AntId>>,
+    sink: Box<dyn TraceSink>,
+}
+
+impl Tracer {
+    pub fn new(sink: Box<dyn TraceSink>) -> Self {
+        Tracer { ants: None, sink }
+    }
+
+    pub fn with_ants(mut self, ants: impl IntoIterator<Item = AntId>) -> Self {
+        self.ants = Some(ants.into_iter().collect());
+        self
+    }
+
+    pub fn traces(&self, ant: AntId) -> bool {
+        self.ants.as_ref().is_none_or(|ants| ants.contains(&ant))
+    }
+
+    pub fn into_sink(self) -> Box<dyn TraceSink> {
+        self.sink
+    }
+
+    pub(crate) fn record(&mut self, entry: &TraceEntry) {
+        self.sink.record(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn outcomes() {
+        let program = parse_program(
+            "
+            Move 1 1
+            Sense Ahead Food 2 2
+            Flip 1 0 1
+            Turn Left 0
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            Outcome::of(program[0], 1, Some(Action::Move)),
+            Outcome::Success
+        );
+        assert_eq!(Outcome::of(program[0], 1, None), Outcome::Fail);
+        assert_eq!(Outcome::of(program[1], 2, None), Outcome::Success);
+        assert_eq!(Outcome::of(program[2], 1, None), Outcome::Fail);
+        assert_eq!(Outcome::of(program[3], 0, None), Outcome::Next);
+    }
+
+    #[test]
+    fn writes_lines() {
+        let mut sink = WriteSink(Vec::new());
+        sink.record(&TraceEntry {
+            round: 3,
+            ant: 1,
+            instr_pointer: 0,
+            instr: Instr::Move {
+                success_instr: 1,
+                fail_instr: 2,
+            },
+            outcome: Outcome::Fail,
+            next_instr: 2,
+            position: Position { x: 4, y: 0 },
+            direction: Direction::Left,
+        });
+        assert_eq!(
+            String::from_utf8(sink.0).unwrap(),
+            "round 3 ant 1 @0: Move 1 2 -> Fail 2, at (4, 0) facing Left\n"
+        );
+    }
+}
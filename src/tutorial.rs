@@ -0,0 +1,226 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::asm::*;
+use crate::sim::*;
+use crate::world::*;
+
+// A tiny scenario for a single red ant and the condition that solves it.
+// Ants start facing right.
+pub struct Lesson {
+    pub name: &'static str,
+    pub task: &'static str,
+    pub rounds: u32,
+    map: &'static str,
+    setup: fn(&mut Grid),
+    goal: fn(&World) -> bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LessonError {
+    InvalidProgram(Vec<ProgramError>),
+    OutOfRounds,
+}
+
+impl fmt::Display for LessonError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LessonError::InvalidProgram(errors) => {
+                write!(f, "invalid program: {}", describe_errors(errors))
+            }
+            LessonError::OutOfRounds => write!(f, "the goal was not reached in time"),
+        }
+    }
+}
+
+impl std::error::Error for LessonError {}
+
+impl Lesson {
+    pub fn world(&self) -> World {
+        let mut grid: Grid = self.map.parse().unwrap();
+        (self.setup)(&mut grid);
+        let ants: World = self.map.parse().unwrap();
+        let mut world = World::new(grid);
+        for ant in ants.ants() {
+            world.add_ant(ant.color(), ant.position()).unwrap();
+        }
+        world
+    }
+
+    pub fn is_solved(&self, world: &World) -> bool {
+        (self.goal)(world)
+    }
+
+    // Runs `program` on the lesson and returns the number of rounds it took
+    // to reach the goal.
+    pub fn check(&self, program: &Program) -> Result<u32, LessonError> {
+        let mut programs = HashMap::new();
+        programs.insert(Color::Red, program.clone());
+        let mut simulator =
+            Simulator::headless(self.world(), programs, 0).map_err(|error| match error {
+                SimulatorError::InvalidProgram { errors, .. } => {
+                    LessonError::InvalidProgram(errors)
+                }
+                // Only castes are refused otherwise.
+                error => unreachable!("{}", error),
+            })?;
+        simulator.run_until(|simulator| {
+            self.is_solved(simulator.world()) || simulator.round() >= self.rounds
+        });
+        if self.is_solved(simulator.world()) {
+            Ok(simulator.round())
+        } else {
+            Err(LessonError::OutOfRounds)
+        }
+    }
+}
+
+fn no_setup(_grid: &mut Grid) {}
+
+fn stands_on_food(world: &World) -> bool {
+    world
+        .swarm(Color::Red)
+        .any(|ant| world.grid().cell_at(ant.position()).unwrap().has_food())
+}
+
+fn food_at_home(world: &World) -> bool {
+    world.food_in_anthill(Color::Red) > 0
+}
+
+fn carries_food(world: &World) -> bool {
+    world.swarm(Color::Red).any(|ant| ant.carries_food())
+}
+
+// From the ant's start to the food, which is marked as well.
+const TRAIL: [Position; 6] = [
+    Position { x: 2, y: 1 },
+    Position { x: 3, y: 1 },
+    Position { x: 4, y: 1 },
+    Position { x: 4, y: 2 },
+    Position { x: 4, y: 3 },
+    Position { x: 5, y: 3 },
+];
+
+fn lay_trail(grid: &mut Grid) {
+    for position in TRAIL.into_iter().chain([Position { x: 6, y: 3 }]) {
+        let cell = grid.cell_at_mut(position).unwrap();
+        cell.try_set_marker(Color::Red, 0).unwrap();
+    }
+}
+
+// In the order they are meant to be taken.
+pub fn lessons() -> Vec<Lesson> {
+    vec![
+        Lesson {
+            name: "first-steps",
+            task: "Walk to the food.",
+            rounds: 10,
+            map: "\
+# # # # # #
+ # r . . 3 #
+# # # # # #
+",
+            setup: no_setup,
+            goal: stands_on_food,
+        },
+        Lesson {
+            name: "bring-it-home",
+            task: "Pick up the food and drop it on your anthill.",
+            rounds: 30,
+            map: "\
+# # # # # #
+ # + r . 3 #
+# # # # # #
+",
+            setup: no_setup,
+            goal: food_at_home,
+        },
+        Lesson {
+            name: "follow-the-trail",
+            task: "Marker 0 leads to the food. Follow it and pick the food up.",
+            rounds: 60,
+            map: "\
+# # # # # # # #
+ # r . . . . . #
+# . . . . . . #
+ # . . . . . 5 #
+# # # # # # # #
+",
+            setup: lay_trail,
+            goal: carries_food,
+        },
+    ]
+}
+
+pub fn lesson(name: &str) -> Option<Lesson> {
+    lessons().into_iter().find(|lesson| lesson.name == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(name: &str, source: &str) -> Result<u32, LessonError> {
+        lesson(name).unwrap().check(&parse_program(source).unwrap())
+    }
+
+    const IDLER: &str = "start: Turn Left start";
+
+    #[test]
+    fn first_steps() {
+        assert_eq!(check("first-steps", "start: Move start start"), Ok(3));
+        assert_eq!(check("first-steps", IDLER), Err(LessonError::OutOfRounds));
+    }
+
+    #[test]
+    fn bring_it_home() {
+        let forager = "
+            out:  Move out pick
+            pick: PickUpFood turn out
+            turn: Turn Left t2
+            t2:   Turn Left t3
+            t3:   Turn Left home
+            home: Move home drop
+            drop: DropFood drop
+        ";
+        assert!(check("bring-it-home", forager).is_ok());
+        assert_eq!(check("bring-it-home", IDLER), Err(LessonError::OutOfRounds));
+    }
+
+    #[test]
+    fn follow_the_trail() {
+        let tracker = "
+            start: PickUpFood done look
+            look:  Sense Ahead Marker 0 go turn
+            go:    Unmark 0 move
+            move:  Move start start
+            turn:  Turn Right look
+            done:  Turn Left done
+        ";
+        assert!(check("follow-the-trail", tracker).is_ok());
+        let world = lesson("follow-the-trail").unwrap().world();
+        for position in TRAIL {
+            assert!(world
+                .grid()
+                .cell_at(position)
+                .unwrap()
+                .has_marker(Color::Red, 0));
+        }
+    }
+
+    #[test]
+    fn invalid_programs() {
+        let lesson = lesson("first-steps").unwrap();
+        assert_eq!(
+            lesson.check(&vec![]),
+            Err(LessonError::InvalidProgram(vec![ProgramError::Empty]))
+        );
+    }
+
+    #[test]
+    fn lessons_start_unsolved() {
+        for lesson in lessons() {
+            assert!(!lesson.is_solved(&lesson.world()), "{}", lesson.name);
+        }
+    }
+}
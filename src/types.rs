@@ -0,0 +1,58 @@
+//! Plain data types shared with code built outside this crate (frontends,
+//! bots, tools). Everything re-exported here keeps its shape and encoding
+//! across engine refactors; changing any of it is a breaking release. The
+//! instruction set, its conditions and the actions ants take grow with the
+//! game, so those enums are `#[non_exhaustive]`: new variants are not
+//! breaking, and code outside the crate has to match them with a wildcard
+//! arm.
+//!
+//! The text formats come with their parsers: maps, programs, the remote
+//! brain protocol and replays.
+
+pub use crate::asm::{
+    disassemble, parse_program, AsmError, AsmErrorKind, Instr, Program, SenseCondition,
+    SwarmCondition, TurnDirection,
+};
+pub use crate::data::Action;
+pub use crate::map::{parse_grid, parse_world, Borders, MapError, MapErrorKind};
+pub use crate::observation::{CellView, Observation};
+pub use crate::remote::{format_observation, parse_reply, RemoteError, REMOTE_VERSION};
+pub use crate::replay::{
+    program_hash, DrawPurpose, ProgramHash, Replay, ReplayEntry, ReplayError, RngDraw,
+};
+pub use crate::world::{
+    AntId, Color, Direction, InstrIdx, Marker, Position, SenseDirection, MARKERS_PER_COLOR,
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direction_encoding_is_stable() {
+        let directions = [
+            (Direction::Right, 0),
+            (Direction::DownRight, 1),
+            (Direction::DownLeft, 2),
+            (Direction::Left, 3),
+            (Direction::UpLeft, 4),
+            (Direction::UpRight, 5),
+        ];
+        for (direction, code) in directions {
+            assert_eq!(u32::from(direction), code);
+            assert_eq!(Direction::try_from(code), Ok(direction));
+        }
+        assert_eq!(Direction::try_from(6), Err(()));
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let world = parse_world("+ . 2\n . - r\n", Borders::Open).unwrap();
+        let again = parse_world(&world.to_map_string(), Borders::Open).unwrap();
+        assert_eq!(again.to_map_string(), world.to_map_string());
+
+        let program = parse_program("start: Move start start").unwrap();
+        assert_eq!(parse_program(&disassemble(&program)), Ok(program));
+        assert_eq!(parse_reply("Move\n"), Ok(Some(Action::Move)));
+    }
+}
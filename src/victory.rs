@@ -0,0 +1,363 @@
+use crate::snapshot::*;
+use crate::world::*;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum VictoryCondition {
+    FoodScore(u32),
+    AllEnemiesDead,
+    Rounds(u32),
+    And(Vec<VictoryCondition>),
+    Or(Vec<VictoryCondition>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Victory {
+    Winner(Color),
+    Draw,
+}
+
+impl VictoryCondition {
+    pub fn holds(&self, world: &World, round: u32, color: Color) -> bool {
+        match self {
+            VictoryCondition::FoodScore(n) => world.food_in_anthill(color) >= *n,
+            VictoryCondition::AllEnemiesDead => {
+                world.foes(color).all(|foe| world.swarm_size(foe) == 0)
+            }
+            VictoryCondition::Rounds(n) => round >= *n,
+            VictoryCondition::And(conditions) => conditions
+                .iter()
+                .all(|condition| condition.holds(world, round, color)),
+            VictoryCondition::Or(conditions) => conditions
+                .iter()
+                .any(|condition| condition.holds(world, round, color)),
+        }
+    }
+
+    // A team wins when it alone meets the condition; if several do at once
+    // the match is drawn.
+    pub fn evaluate(&self, world: &World, round: u32) -> Option<Victory> {
+        let teams = world.teams();
+        let mut winners = teams
+            .into_iter()
+            .filter(|&color| self.holds(world, round, color));
+        match (winners.next(), winners.next()) {
+            (Some(color), None) => Some(Victory::Winner(color)),
+            (Some(_), Some(_)) => Some(Victory::Draw),
+            (None, _) => None,
+        }
+    }
+}
+
+// Ends a match without deciding it; the swarm with the most food at home
+// wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EndCondition {
+    MaxRounds(u32),
+    // No food left on the ground or in any ant's jaws.
+    AllFoodCollected,
+    // Some swarm has no living ants.
+    SwarmEliminated,
+}
+
+impl EndCondition {
+    pub fn holds(&self, world: &World, round: u32) -> bool {
+        match self {
+            EndCondition::MaxRounds(n) => round >= *n,
+            EndCondition::AllFoodCollected => {
+                world.food_on_ground() == 0 && !world.ants().any(|ant| ant.carries_food())
+            }
+            EndCondition::SwarmEliminated => world
+                .teams()
+                .into_iter()
+                .any(|color| world.swarm_size(color) == 0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndReason {
+    Victory,
+    Condition(EndCondition),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DeathCause {
+    // Surrounded by enemies under a `CombatRule`.
+    Combat,
+    // Out of energy under a `Metabolism`.
+    Starvation,
+}
+
+// An ant that died in `round`, counting from 0, leaving `food_dropped` on
+// the cell it died on. The simulator keeps one for every death, next to
+// the match result, see `Simulator::deaths`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct DeathRecord {
+    pub ant: AntId,
+    pub round: u32,
+    pub cause: DeathCause,
+    pub position: Position,
+    pub food_dropped: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchResult {
+    pub outcome: Victory,
+    // Food at home, indexed by `Color::index`.
+    pub scores: [u32; MAX_TEAMS],
+    // Rounds played when the match ended.
+    pub round: u32,
+    pub reason: EndReason,
+}
+
+impl MatchResult {
+    // Decides the match by score unless a victory condition already did; a
+    // tie for the top score is a draw.
+    pub fn new(world: &World, round: u32, victory: Option<Victory>, reason: EndReason) -> Self {
+        let mut scores = [0; MAX_TEAMS];
+        let teams = world.teams();
+        for &color in &teams {
+            scores[color.index()] = world.food_in_anthill(color);
+        }
+        let outcome = victory.unwrap_or_else(|| {
+            let best = teams.iter().map(|color| scores[color.index()]).max();
+            let mut leaders = teams
+                .iter()
+                .filter(|color| Some(scores[color.index()]) == best);
+            match (leaders.next(), leaders.next()) {
+                (Some(&color), None) => Victory::Winner(color),
+                _ => Victory::Draw,
+            }
+        });
+        MatchResult {
+            outcome,
+            scores,
+            round,
+            reason,
+        }
+    }
+
+    pub fn winner(&self) -> Option<Color> {
+        match self.outcome {
+            Victory::Winner(color) => Some(color),
+            Victory::Draw => None,
+        }
+    }
+
+    pub fn score(&self, color: Color) -> u32 {
+        self.scores[color.index()]
+    }
+}
+
+impl Victory {
+    pub(crate) fn encode(victory: Option<Victory>, out: &mut Encoder) {
+        match victory {
+            None => out.u8(0),
+            Some(Victory::Draw) => out.u8(1),
+            Some(Victory::Winner(color)) => {
+                out.u8(2);
+                out.color(color);
+            }
+        }
+    }
+
+    pub(crate) fn decode(input: &mut Decoder) -> Result<Option<Victory>, SnapshotError> {
+        match input.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(Victory::Draw)),
+            2 => Ok(Some(Victory::Winner(input.color()?))),
+            _ => Err(SnapshotError::Corrupt),
+        }
+    }
+}
+
+impl MatchResult {
+    pub(crate) fn encode(result: Option<MatchResult>, out: &mut Encoder) {
+        let Some(result) = result else {
+            return out.bool(false);
+        };
+        out.bool(true);
+        Victory::encode(Some(result.outcome), out);
+        let teams = result.scores.iter().rposition(|&score| score != 0);
+        let teams = teams.map_or(0, |last| last + 1);
+        out.usize(teams);
+        for &score in &result.scores[..teams] {
+            out.u32(score);
+        }
+        out.u32(result.round);
+        match result.reason {
+            EndReason::Victory => out.u8(0),
+            EndReason::Condition(EndCondition::MaxRounds(n)) => {
+                out.u8(1);
+                out.u32(n);
+            }
+            EndReason::Condition(EndCondition::AllFoodCollected) => out.u8(2),
+            EndReason::Condition(EndCondition::SwarmEliminated) => out.u8(3),
+        }
+    }
+
+    pub(crate) fn decode(input: &mut Decoder) -> Result<Option<MatchResult>, SnapshotError> {
+        if !input.bool()? {
+            return Ok(None);
+        }
+        let outcome = Victory::decode(input)?.ok_or(SnapshotError::Corrupt)?;
+        let mut scores = [0; MAX_TEAMS];
+        let teams = input.count()?;
+        if teams > MAX_TEAMS {
+            return Err(SnapshotError::Corrupt);
+        }
+        for score in &mut scores[..teams] {
+            *score = input.u32()?;
+        }
+        let round = input.u32()?;
+        let reason = match input.u8()? {
+            0 => EndReason::Victory,
+            1 => EndReason::Condition(EndCondition::MaxRounds(input.u32()?)),
+            2 => EndReason::Condition(EndCondition::AllFoodCollected),
+            3 => EndReason::Condition(EndCondition::SwarmEliminated),
+            _ => return Err(SnapshotError::Corrupt),
+        };
+        Ok(Some(MatchResult {
+            outcome,
+            scores,
+            round,
+            reason,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world_with_red_ant() -> World {
+        let mut world = World::new(Grid::new(10, 10));
+        world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+        world
+    }
+
+    #[test]
+    fn food_score() {
+        let mut grid = Grid::new(10, 10);
+        let home = Position { x: 2, y: 2 };
+        *grid.cell_at_mut(home).unwrap() = Cell::new_anthill(Color::Black);
+        grid.cell_at_mut(home).unwrap().try_drop_food().unwrap();
+        grid.cell_at_mut(home).unwrap().try_drop_food().unwrap();
+        let world = World::new(grid);
+
+        assert_eq!(VictoryCondition::FoodScore(3).evaluate(&world, 0), None);
+        assert_eq!(
+            VictoryCondition::FoodScore(2).evaluate(&world, 0),
+            Some(Victory::Winner(Color::Black))
+        );
+    }
+
+    #[test]
+    fn all_enemies_dead() {
+        let world = world_with_red_ant();
+        let condition = VictoryCondition::AllEnemiesDead;
+        assert!(condition.holds(&world, 0, Color::Red));
+        assert!(!condition.holds(&world, 0, Color::Black));
+        assert_eq!(
+            condition.evaluate(&world, 0),
+            Some(Victory::Winner(Color::Red))
+        );
+    }
+
+    #[test]
+    fn rounds() {
+        let world = world_with_red_ant();
+        let condition = VictoryCondition::Rounds(10);
+        assert_eq!(condition.evaluate(&world, 9), None);
+        assert_eq!(condition.evaluate(&world, 10), Some(Victory::Draw));
+    }
+
+    #[test]
+    fn and() {
+        let world = world_with_red_ant();
+        let condition = VictoryCondition::And(vec![
+            VictoryCondition::AllEnemiesDead,
+            VictoryCondition::Rounds(5),
+        ]);
+        assert_eq!(condition.evaluate(&world, 4), None);
+        assert_eq!(
+            condition.evaluate(&world, 5),
+            Some(Victory::Winner(Color::Red))
+        );
+    }
+
+    #[test]
+    fn or() {
+        let world = World::new(Grid::new(10, 10));
+        let condition = VictoryCondition::Or(vec![
+            VictoryCondition::Rounds(5),
+            VictoryCondition::And(vec![]),
+        ]);
+        assert_eq!(condition.evaluate(&world, 0), Some(Victory::Draw));
+        assert_eq!(VictoryCondition::Or(vec![]).evaluate(&world, 0), None);
+    }
+
+    #[test]
+    fn end_conditions() {
+        let mut grid = Grid::new(10, 10);
+        let food = Position { x: 3, y: 3 };
+        grid.cell_at_mut(food).unwrap().try_drop_food().unwrap();
+        let mut world = World::new(grid);
+        let id = world.add_ant(Color::Red, food).unwrap();
+
+        assert!(!EndCondition::MaxRounds(3).holds(&world, 2));
+        assert!(EndCondition::MaxRounds(3).holds(&world, 3));
+        assert!(EndCondition::SwarmEliminated.holds(&world, 0));
+        assert!(!EndCondition::AllFoodCollected.holds(&world, 0));
+        world.ant_mut(id).unwrap().pickup_food().unwrap();
+        assert!(!EndCondition::AllFoodCollected.holds(&world, 0));
+        let empty = World::new(Grid::new(10, 10));
+        assert!(EndCondition::AllFoodCollected.holds(&empty, 0));
+    }
+
+    #[test]
+    fn match_result_by_score() {
+        let mut grid = Grid::new(10, 10);
+        let home = Position { x: 2, y: 2 };
+        *grid.cell_at_mut(home).unwrap() = Cell::new_anthill(Color::Black);
+        grid.cell_at_mut(home).unwrap().try_drop_food().unwrap();
+        let world = World::new(grid);
+        let reason = EndReason::Condition(EndCondition::MaxRounds(7));
+
+        let result = MatchResult::new(&world, 7, None, reason);
+        assert_eq!(result.winner(), Some(Color::Black));
+        assert_eq!(
+            (result.score(Color::Red), result.score(Color::Black)),
+            (0, 1)
+        );
+        let decided = MatchResult::new(&world, 7, Some(Victory::Draw), EndReason::Victory);
+        assert_eq!(decided.winner(), None);
+    }
+
+    #[test]
+    fn free_for_all() {
+        let green = Color::team(2);
+        let mut grid = Grid::new(10, 10);
+        let home = Position { x: 2, y: 2 };
+        *grid.cell_at_mut(home).unwrap() = Cell::new_anthill(green);
+        grid.cell_at_mut(home).unwrap().try_drop_food().unwrap();
+        let mut world = World::new(grid);
+        world.add_ant(Color::Red, Position { x: 5, y: 5 }).unwrap();
+        world.add_ant(green, Position { x: 7, y: 7 }).unwrap();
+
+        assert!(!VictoryCondition::AllEnemiesDead.holds(&world, 0, Color::Red));
+        assert_eq!(
+            VictoryCondition::FoodScore(1).evaluate(&world, 0),
+            Some(Victory::Winner(green))
+        );
+
+        let reason = EndReason::Condition(EndCondition::MaxRounds(7));
+        let result = MatchResult::new(&world, 7, None, reason);
+        assert_eq!(result.winner(), Some(green));
+        assert_eq!(result.score(green), 1);
+    }
+}
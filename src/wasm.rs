@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+use wasm_bindgen::prelude::*;
+
+use crate::asm::*;
+use crate::sim::*;
+use crate::world::*;
+
+// The simulator as seen from JavaScript: built from text, stepped, and read
+// back as flat arrays that arrive as typed arrays on the JS side.
+//
+// `cells()` has `CELL_STRIDE` numbers per cell, row by row:
+//   kind (0 free, 1 wall, 2 + color for an anthill), food,
+//   red markers (bit n set for marker n), black markers.
+// `ants()` has `ANT_STRIDE` numbers per living ant:
+//   id, color (0 red, 1 black, n for team n above), x, y,
+//   direction (0 right, clockwise), carried food.
+#[wasm_bindgen]
+pub struct WasmSimulator {
+    simulator: Simulator,
+}
+
+pub const CELL_STRIDE: usize = 4;
+pub const ANT_STRIDE: usize = 6;
+
+fn color_code(color: Color) -> u32 {
+    match color {
+        Color::Red => 0,
+        Color::Black => 1,
+        _ => color.index() as u32,
+    }
+}
+
+fn marker_bits(cell: &Cell, color: Color) -> u32 {
+    (0..MARKERS_PER_COLOR)
+        .filter(|&marker| cell.has_marker(color, marker))
+        .map(|marker| 1 << marker)
+        .sum()
+}
+
+#[wasm_bindgen]
+impl WasmSimulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new(map: &str, red: &str, black: &str, seed: u32) -> Result<WasmSimulator, String> {
+        let world: World = map.parse().map_err(|error| format!("map: {:?}", error))?;
+        let mut programs = HashMap::new();
+        for (color, source) in [(Color::Red, red), (Color::Black, black)] {
+            let program = parse_program(source)
+                .map_err(|error| format!("{:?} program: {:?}", color, error))?;
+            programs.insert(color, program);
+        }
+        let simulator = Simulator::headless(world, programs, seed.into())
+            .map_err(|error| format!("{:?}", error))?;
+        Ok(WasmSimulator { simulator })
+    }
+
+    pub fn step(&mut self) {
+        self.simulator.step();
+    }
+
+    pub fn run(&mut self, steps: u32) {
+        self.simulator.run_for(steps);
+    }
+
+    pub fn round(&self) -> u32 {
+        self.simulator.round()
+    }
+
+    pub fn width(&self) -> usize {
+        self.simulator.world().grid().width()
+    }
+
+    pub fn height(&self) -> usize {
+        self.simulator.world().grid().height()
+    }
+
+    pub fn red_score(&self) -> u32 {
+        self.simulator.score(Color::Red)
+    }
+
+    pub fn black_score(&self) -> u32 {
+        self.simulator.score(Color::Black)
+    }
+
+    pub fn cell_stride() -> usize {
+        CELL_STRIDE
+    }
+
+    pub fn ant_stride() -> usize {
+        ANT_STRIDE
+    }
+
+    pub fn cells(&self) -> Vec<u32> {
+        let grid = self.simulator.world().grid();
+        let mut cells = Vec::with_capacity(grid.width() * grid.height() * CELL_STRIDE);
+        for y in 0..grid.height() as i32 {
+            for x in 0..grid.width() as i32 {
+                let cell = grid.cell_at(Position { x, y }).unwrap();
+                let kind = match (cell, cell.anthill()) {
+                    (Cell::Wall, _) => 1,
+                    (_, Some(color)) => 2 + color_code(color),
+                    (_, None) => 0,
+                };
+                cells.extend([
+                    kind,
+                    cell.food(),
+                    marker_bits(cell, Color::Red),
+                    marker_bits(cell, Color::Black),
+                ]);
+            }
+        }
+        cells
+    }
+
+    pub fn ants(&self) -> Vec<i32> {
+        let world = self.simulator.world();
+        let mut ants = Vec::with_capacity(world.ant_ids().count() * ANT_STRIDE);
+        for ant in world.ants() {
+            ants.extend([
+                ant.id() as i32,
+                color_code(ant.color()) as i32,
+                ant.position().x,
+                ant.position().y,
+                u32::from(ant.direction()) as i32,
+                ant.carried_food() as i32,
+            ]);
+        }
+        ants
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAP: &str = "\
+# # # # # #
+ # r 2 + b #
+# # # # # #
+";
+
+    #[test]
+    fn flat_views() {
+        let mut simulator =
+            WasmSimulator::new(MAP, "start: Move start start", "start: Mark 1 start", 3).unwrap();
+        assert_eq!((simulator.width(), simulator.height()), (6, 3));
+        simulator.step();
+        assert_eq!(simulator.round(), 1);
+
+        let cells = simulator.cells();
+        assert_eq!(cells.len(), 6 * 3 * CELL_STRIDE);
+        let cell = |x: usize, y: usize| &cells[(y * 6 + x) * CELL_STRIDE..][..CELL_STRIDE];
+        assert_eq!(cell(0, 0), [1, 0, 0, 0]);
+        assert_eq!(cell(2, 1), [0, 2, 0, 0]);
+        assert_eq!(cell(3, 1), [2, 0, 0, 0]);
+        assert_eq!(cell(4, 1), [0, 0, 0, 1 << 1]);
+
+        let ants = simulator.ants();
+        assert_eq!(ants.len(), 2 * ANT_STRIDE);
+        assert_eq!(&ants[..ANT_STRIDE], [0, 0, 2, 1, 0, 0]);
+        assert_eq!(&ants[ANT_STRIDE..], [1, 1, 4, 1, 0, 0]);
+    }
+
+    #[test]
+    fn rejects_bad_input() {
+        assert!(WasmSimulator::new("#", "", "", 0).is_err());
+        let error = WasmSimulator::new(MAP, "start: Move start start", "nonsense", 0).err();
+        assert!(error.unwrap().starts_with("Black program"));
+    }
+}
@@ -1,6 +1,15 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::data::Action;
+use crate::hex;
+use crate::rng::Rng;
+use crate::snapshot::*;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub enum Direction {
     #[default]
     Right,
@@ -11,9 +20,27 @@ pub enum Direction {
     UpRight,
 }
 
-impl Into<u32> for Direction {
-    fn into(self) -> u32 {
-        match self {
+impl Direction {
+    // Right first, then clockwise.
+    pub fn all() -> impl Iterator<Item = Direction> {
+        (0..6).map(|code| Direction::try_from(code).unwrap())
+    }
+
+    // Turned clockwise by `turns` sixths of a full turn; negative turns go
+    // anticlockwise.
+    pub fn rotated(self, turns: i32) -> Direction {
+        let code = (u32::from(self) as i32 + turns).rem_euclid(6);
+        Direction::try_from(code as u32).unwrap()
+    }
+
+    pub fn opposite(self) -> Direction {
+        self.rotated(3)
+    }
+}
+
+impl From<Direction> for u32 {
+    fn from(direction: Direction) -> u32 {
+        match direction {
             Direction::Right => 0,
             Direction::DownRight => 1,
             Direction::DownLeft => 2,
@@ -40,13 +67,21 @@ impl TryFrom<u32> for Direction {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     pub x: i32,
     pub y: i32,
 }
 
 impl Position {
+    // Steps between the two cells on an unbounded hex grid.
+    pub fn distance(&self, other: Position) -> u32 {
+        let dx = self.x - other.x;
+        let dy = self.y - other.y;
+        (dx.unsigned_abs() + dy.unsigned_abs() + (dx + dy).unsigned_abs()) / 2
+    }
+
     pub fn translate(&self, direction: Direction) -> Self {
         match direction {
             Direction::Right => Position {
@@ -75,24 +110,156 @@ impl Position {
             },
         }
     }
+
+    pub fn translate_by(&self, direction: Direction, distance: i32) -> Self {
+        let step = Position::default().translate(direction);
+        Position {
+            x: self.x + step.x * distance,
+            y: self.y + step.y * distance,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum SenseDirection {
+    Here,
+    Ahead,
+    LeftAhead,
+    RightAhead,
+}
+
+impl SenseDirection {
+    pub fn apply_to(self, position: Position, facing: Direction) -> Position {
+        match self {
+            SenseDirection::Here => position,
+            SenseDirection::Ahead => position.translate(facing),
+            SenseDirection::LeftAhead => position.translate(facing.rotated(-1)),
+            SenseDirection::RightAhead => position.translate(facing.rotated(1)),
+        }
+    }
 }
 
 pub type AntId = usize;
 pub type InstrIdx = usize;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-pub enum Color {
-    Black,
-    Red,
+// A team. Black and Red are the two classic ones, and matches can have up
+// to `MAX_TEAMS` of them, each other team's ants being foes.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Color(u8);
+
+pub type TeamId = Color;
+
+pub const MAX_TEAMS: usize = 8;
+
+#[allow(non_upper_case_globals)]
+impl Color {
+    pub const Black: Color = Color(0);
+    pub const Red: Color = Color(1);
+
+    pub fn team(index: usize) -> Color {
+        Color::try_team(index).expect("team index out of range")
+    }
+
+    pub fn try_team(index: usize) -> Option<Color> {
+        (index < MAX_TEAMS).then_some(Color(index as u8))
+    }
+
+    pub fn index(self) -> usize {
+        usize::from(self.0)
+    }
+
+    pub fn all() -> impl Iterator<Item = Color> {
+        (0..MAX_TEAMS).map(Color::team)
+    }
+
+    // The other side of a two-team match.
+    pub fn enemy(self) -> Color {
+        match self {
+            Color::Black => Color::Red,
+            Color::Red => Color::Black,
+            _ => panic!("{:?} has no single enemy", self),
+        }
+    }
+}
+
+impl fmt::Debug for Color {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Color::Black => write!(f, "Black"),
+            Color::Red => write!(f, "Red"),
+            Color(index) => write!(f, "Team{}", index),
+        }
+    }
+}
+
+impl FromStr for Color {
+    type Err = ();
+
+    fn from_str(name: &str) -> Result<Self, ()> {
+        match name {
+            "Black" => Ok(Color::Black),
+            "Red" => Ok(Color::Red),
+            _ => name
+                .strip_prefix("Team")
+                .and_then(|index| index.parse().ok())
+                .and_then(Color::try_team)
+                .ok_or(()),
+        }
+    }
+}
+
+// By name, as the two-variant enum this used to be was.
+#[cfg(feature = "serde")]
+impl serde::Serialize for Color {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&format_args!("{:?}", self))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Color {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        name.parse()
+            .map_err(|()| serde::de::Error::custom(format!("unknown team {:?}", name)))
+    }
+}
+
+// Fuzzing sticks to the two classic teams.
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for Color {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(if u.arbitrary()? {
+            Color::Red
+        } else {
+            Color::Black
+        })
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-struct AntData {
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub(crate) struct AntData {
     color: Color,
     direction: Direction,
     position: Position,
     instr_pointer: InstrIdx,
-    carries_food: bool,
+    carried_food: u32,
+    alive: bool,
+    // Energy used up since the ant last ate, see `Metabolism`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    hunger: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    registers: Registers,
+    #[cfg_attr(feature = "serde", serde(default))]
+    call_stack: CallStack,
+    // Turns left to sit out, see `RestTable`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    resting: u32,
+    #[cfg_attr(feature = "serde", serde(default))]
+    caste: Caste,
 }
 
 impl AntData {
@@ -102,15 +269,188 @@ impl AntData {
             position,
             direction: Direction::default(),
             instr_pointer: 0,
-            carries_food: false,
+            carried_food: 0,
+            alive: true,
+            hunger: 0,
+            registers: Registers::default(),
+            call_stack: CallStack::default(),
+            resting: 0,
+            caste: 0,
+        }
+    }
+}
+
+pub type Marker = u8;
+
+pub const MARKERS_PER_COLOR: Marker = 6;
+
+// Scratch memory a program keeps per ant, see `Instr::Set`.
+pub type Register = u8;
+
+pub const REGISTERS: usize = 4;
+
+pub type Registers = [u8; REGISTERS];
+
+pub const CALL_STACK_DEPTH: usize = 8;
+
+// Which of its swarm's programs an ant runs, see `Simulator::with_caste`.
+// Ants start out in caste 0.
+pub type Caste = u8;
+
+pub const MAX_CASTES: usize = 4;
+
+// Return addresses of `Instr::Call`, innermost last. A call on a full
+// stack forgets the outermost one, so runaway recursion keeps going
+// instead of wedging the ant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "Vec<InstrIdx>", try_from = "Vec<InstrIdx>")
+)]
+pub struct CallStack {
+    frames: [InstrIdx; CALL_STACK_DEPTH],
+    depth: usize,
+}
+
+impl CallStack {
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.depth == 0
+    }
+
+    pub fn frames(&self) -> &[InstrIdx] {
+        &self.frames[..self.depth]
+    }
+
+    // Returns `false` if the outermost frame had to go.
+    pub fn push(&mut self, return_instr: InstrIdx) -> bool {
+        let fits = self.depth < CALL_STACK_DEPTH;
+        if !fits {
+            self.frames.rotate_left(1);
+            self.depth -= 1;
+        }
+        self.frames[self.depth] = return_instr;
+        self.depth += 1;
+        fits
+    }
+
+    pub fn pop(&mut self) -> Option<InstrIdx> {
+        if self.depth == 0 {
+            return None;
+        }
+        self.depth -= 1;
+        Some(self.frames[self.depth])
+    }
+}
+
+impl From<CallStack> for Vec<InstrIdx> {
+    fn from(stack: CallStack) -> Self {
+        stack.frames().to_vec()
+    }
+}
+
+impl TryFrom<Vec<InstrIdx>> for CallStack {
+    type Error = String;
+
+    fn try_from(frames: Vec<InstrIdx>) -> Result<Self, String> {
+        if frames.len() > CALL_STACK_DEPTH {
+            return Err(format!("call stack deeper than {}", CALL_STACK_DEPTH));
+        }
+        let mut stack = CallStack::default();
+        for frame in frames {
+            stack.push(frame);
         }
+        Ok(stack)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Markers {
+    #[cfg_attr(feature = "serde", serde(with = "marker_bits"))]
+    bits: [u8; MAX_TEAMS],
+}
+
+impl Markers {
+    pub fn has(&self, color: Color, marker: Marker) -> bool {
+        marker < MARKERS_PER_COLOR && self.bits[color.index()] & (1 << marker) != 0
+    }
+
+    pub fn has_any(&self, color: Color) -> bool {
+        self.bits[color.index()] != 0
+    }
+
+    // Whether any team but `color` left a marker here.
+    pub fn has_foreign(&self, color: Color) -> bool {
+        Color::all().any(|other| other != color && self.has_any(other))
+    }
+
+    pub fn set(&mut self, color: Color, marker: Marker) {
+        assert!(marker < MARKERS_PER_COLOR);
+        self.bits[color.index()] |= 1 << marker;
+    }
+
+    pub fn clear(&mut self, color: Color, marker: Marker) {
+        assert!(marker < MARKERS_PER_COLOR);
+        self.bits[color.index()] &= !(1 << marker);
+    }
+
+    // The bits of the teams up to the last with any marker, and at least
+    // Black's and Red's.
+    pub(crate) fn used_bits(&self) -> &[u8] {
+        let used = self
+            .bits
+            .iter()
+            .rposition(|&bits| bits != 0)
+            .map_or(0, |i| i + 1);
+        &self.bits[..used.max(2)]
+    }
+
+    pub(crate) fn from_bits(bits: &[u8]) -> Option<Markers> {
+        let mut markers = Markers::default();
+        markers.bits.get_mut(..bits.len())?.copy_from_slice(bits);
+        Some(markers)
+    }
+}
+
+// Written as a list no longer than it has to be, so two-team worlds look
+// the same as before there were more teams.
+#[cfg(feature = "serde")]
+mod marker_bits {
+    use super::*;
+
+    pub fn serialize<S: serde::Serializer>(
+        bits: &[u8; MAX_TEAMS],
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let markers = Markers { bits: *bits };
+        serde::Serialize::serialize(markers.used_bits(), serializer)
+    }
+
+    pub fn deserialize<'de, D: serde::Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<[u8; MAX_TEAMS], D::Error> {
+        let bits: Vec<u8> = serde::Deserialize::deserialize(deserializer)?;
+        Markers::from_bits(&bits)
+            .map(|markers| markers.bits)
+            .ok_or_else(|| serde::de::Error::custom("too many teams"))
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Cell {
     Wall,
-    FreeCell { ant_id: Option<AntId>, food: u32 },
+    FreeCell {
+        ant_id: Option<AntId>,
+        food: u32,
+        markers: Markers,
+        anthill: Option<Color>,
+    },
 }
 
 impl Default for Cell {
@@ -118,6 +458,8 @@ impl Default for Cell {
         Cell::FreeCell {
             ant_id: None,
             food: 0,
+            markers: Markers::default(),
+            anthill: None,
         }
     }
 }
@@ -127,9 +469,41 @@ pub enum CellError {
     Occupied,
     Wall,
     NoFood,
+    Full,
+    InvalidMarker,
+}
+
+impl fmt::Display for CellError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CellError::Occupied => write!(f, "the cell is occupied"),
+            CellError::Wall => write!(f, "the cell is a wall"),
+            CellError::NoFood => write!(f, "the cell has no food"),
+            CellError::Full => write!(f, "the cell holds all the food it can"),
+            CellError::InvalidMarker => write!(f, "no such marker"),
+        }
+    }
 }
 
+impl std::error::Error for CellError {}
+
 impl Cell {
+    pub fn new_anthill(color: Color) -> Self {
+        Cell::FreeCell {
+            ant_id: None,
+            food: 0,
+            markers: Markers::default(),
+            anthill: Some(color),
+        }
+    }
+
+    pub fn anthill(&self) -> Option<Color> {
+        match self {
+            Cell::FreeCell { anthill, .. } => *anthill,
+            _ => None,
+        }
+    }
+
     pub fn clear_ant(&mut self) -> Option<AntId> {
         if let Cell::FreeCell { ref mut ant_id, .. } = self {
             let old_ant_id = *ant_id;
@@ -209,160 +583,701 @@ impl Cell {
                 food: ref mut food_ref,
                 ..
             } => {
-                *food_ref += 1;
+                *food_ref = food_ref.checked_add(1).ok_or(CellError::Full)?;
                 Ok(())
             }
         }
     }
 
-    pub fn free_to_move(&self) -> bool {
+    pub fn try_drop_food_capped(&mut self, limit: u32) -> Result<(), CellError> {
+        if self.food() >= limit {
+            return match self {
+                Cell::Wall => Err(CellError::Wall),
+                _ => Err(CellError::Full),
+            };
+        }
+        self.try_drop_food()
+    }
+
+    pub fn markers(&self) -> Markers {
         match self {
-            Cell::Wall => false,
-            Cell::FreeCell { ant_id, .. } => ant_id.is_none(),
+            Cell::FreeCell { markers, .. } => *markers,
+            _ => Markers::default(),
         }
     }
-}
 
-#[derive(Clone, PartialEq, Eq)]
-pub struct Grid {
-    cells: Vec<Vec<Cell>>,
-    width: usize,
-    height: usize,
-}
+    pub fn has_marker(&self, color: Color, marker: Marker) -> bool {
+        self.markers().has(color, marker)
+    }
 
-impl Grid {
-    pub fn new(width: usize, height: usize) -> Self {
-        let row = vec![Cell::default(); width];
-        let cells = vec![row; height];
-        Grid {
-            cells,
-            width,
-            height,
-        }
+    pub fn has_any_marker(&self, color: Color) -> bool {
+        self.markers().has_any(color)
     }
 
-    pub fn width(&self) -> usize {
-        self.width
+    pub fn has_foreign_marker(&self, color: Color) -> bool {
+        self.markers().has_foreign(color)
     }
 
-    pub fn height(&self) -> usize {
-        self.height
+    pub fn try_set_marker(&mut self, color: Color, marker: Marker) -> Result<(), CellError> {
+        self.update_markers(marker, |markers| markers.set(color, marker))
     }
 
-    pub fn cell_at(&self, position: Position) -> Option<&Cell> {
-        if !self.in_bounds(position) {
-            return None;
-        }
-        Some(&self.cells[position.y as usize][position.x as usize])
+    pub fn try_clear_marker(&mut self, color: Color, marker: Marker) -> Result<(), CellError> {
+        self.update_markers(marker, |markers| markers.clear(color, marker))
     }
 
-    pub fn cell_at_mut(&mut self, position: Position) -> Option<&mut Cell> {
-        if !self.in_bounds(position) {
-            return None;
+    fn update_markers(
+        &mut self,
+        marker: Marker,
+        update: impl FnOnce(&mut Markers),
+    ) -> Result<(), CellError> {
+        match self {
+            Cell::Wall => Err(CellError::Wall),
+            Cell::FreeCell { .. } if marker >= MARKERS_PER_COLOR => Err(CellError::InvalidMarker),
+            Cell::FreeCell { markers, .. } => {
+                update(markers);
+                Ok(())
+            }
         }
-        Some(&mut self.cells[position.y as usize][position.x as usize])
     }
 
-    pub fn ant_at(&self, position: Position) -> Option<AntId> {
-        self.cell_at(position).and_then(|cell| match cell {
-            Cell::FreeCell {
-                ant_id: Some(ant_id),
-                ..
-            } => Some(*ant_id),
-            _ => None,
-        })
+    pub fn free_to_move(&self) -> bool {
+        match self {
+            Cell::Wall => false,
+            Cell::FreeCell { ant_id, .. } => ant_id.is_none(),
+        }
     }
+}
 
-    pub fn in_bounds(&self, position: Position) -> bool {
-        position.y >= 0
-            && position.x >= 0
-            && position.y < self.height as i32
-            && position.x < self.width as i32
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+// Where food goes when the cell an ant drops it on is full. A drop that
+// finds no place leaves the food with the ant and takes the fail branch of
+// `DropFood`.
+pub enum FoodOverflow {
+    // Nowhere.
+    Fail,
+    // Onto a neighbour.
+    Spill,
+    // Onto the nearest cell with room, however far: the drop only fails if
+    // every free cell is full.
+    Nearest,
 }
 
-#[derive(Clone, PartialEq, Eq)]
-pub struct World {
-    ants: Vec<AntData>,
-    swarms: HashMap<Color, Vec<AntId>>,
-    grid: Grid,
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FoodCap {
+    pub limit: u32,
+    pub overflow: FoodOverflow,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum WorldError {
-    OutOfBounds,
-    Wall,
-    Occupied,
-    CellHasNoFood,
-    AntHasNoFood,
-    AntCarriesFood,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CombatRule {
+    // An ant with at least this many enemies on adjacent cells dies.
+    pub enemies_to_kill: usize,
+    // Food left on the cell of a killed ant, on top of what it carried.
+    pub food_bonus: u32,
 }
 
-impl From<CellError> for WorldError {
-    fn from(value: CellError) -> Self {
-        match value {
-            CellError::Wall => WorldError::Wall,
-            CellError::Occupied => WorldError::Occupied,
-            CellError::NoFood => WorldError::CellHasNoFood,
+impl Default for CombatRule {
+    fn default() -> Self {
+        CombatRule {
+            enemies_to_kill: 5,
+            food_bonus: 3,
         }
     }
 }
 
-#[derive(Debug, Clone, Copy)]
-pub struct Ant<'a> {
-    id: AntId,
-
-    data: &'a AntData,
+// Ants that burn energy: every round costs `per_round` and every action
+// that takes effect `per_action` on top. Eating a unit of food, carried or
+// from the ant's cell, gives back `per_food`, and an ant that has used up
+// `max_energy` since it was full starves. Unrelated to the instruction
+// budget of a `CostTable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Metabolism {
+    pub max_energy: u32,
+    pub per_round: u32,
+    pub per_action: u32,
+    pub per_food: u32,
+    // Hungry ants on their own anthill eat from the food stored there at
+    // the end of every round.
+    pub eat_at_home: bool,
 }
 
-impl Ant<'_> {
-    pub fn id(&self) -> AntId {
-        self.id
+impl Metabolism {
+    pub fn energy(&self, ant: Ant<'_>) -> u32 {
+        self.max_energy.saturating_sub(ant.hunger())
     }
+}
 
-    pub fn position(&self) -> Position {
-        self.data.position
+impl Default for Metabolism {
+    fn default() -> Self {
+        Metabolism {
+            max_energy: 500,
+            per_round: 1,
+            per_action: 1,
+            per_food: 100,
+            eat_at_home: true,
+        }
     }
+}
 
-    pub fn direction(&self) -> Direction {
-        self.data.direction
-    }
+// Anthills breed: `food_per_ant` units of food stored in a team's anthill
+// buy a new ant on a free cell of it, starting at instruction 0. The new
+// ant joins the caste furthest below its share of the living swarm, going
+// by `caste_shares`; the lowest caste wins ties, and with no shares at all
+// every ant is caste 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpawnRule {
+    pub food_per_ant: u32,
+    #[cfg_attr(feature = "serde", serde(default = "SpawnRule::caste_zero"))]
+    pub caste_shares: [u32; MAX_CASTES],
+}
 
-    pub fn color(&self) -> Color {
-        self.data.color
+impl SpawnRule {
+    // Every new ant in caste 0.
+    pub fn new(food_per_ant: u32) -> Self {
+        SpawnRule {
+            food_per_ant,
+            caste_shares: SpawnRule::caste_zero(),
+        }
     }
 
-    pub fn carries_food(&self) -> bool {
-        self.data.carries_food
+    pub fn with_caste_shares(mut self, shares: [u32; MAX_CASTES]) -> Self {
+        self.caste_shares = shares;
+        self
     }
 
-    pub fn instr_pointer(&self) -> usize {
-        self.data.instr_pointer
+    fn caste_zero() -> [u32; MAX_CASTES] {
+        let mut shares = [0; MAX_CASTES];
+        shares[0] = 1;
+        shares
     }
-}
 
-impl PartialEq for Ant<'_> {
-    fn eq(&self, other: &Self) -> bool {
-        self.id == other.id
+    fn caste_for(&self, counts: [u32; MAX_CASTES]) -> Caste {
+        // `count / share` is smallest for the caste furthest behind.
+        let behind = |a: usize, b: usize| {
+            u64::from(counts[a]) * u64::from(self.caste_shares[b])
+                < u64::from(counts[b]) * u64::from(self.caste_shares[a])
+        };
+        let mut best: Option<usize> = None;
+        for caste in (0..MAX_CASTES).filter(|&caste| self.caste_shares[caste] > 0) {
+            if best.is_none_or(|best| behind(caste, best)) {
+                best = Some(caste);
+            }
+        }
+        best.unwrap_or(0) as Caste
     }
 }
 
-impl Eq for Ant<'_> {}
+// Limits the world keeps to by itself, whichever simulator runs it; the
+// rules of play proper are in `RuleSet`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct WorldRules {
+    // The most food a cell holds. Drops onto a full cell fail, or spill
+    // under a `FoodCap`; food left by the dead and regrowth is lost past
+    // it. Food a map starts with is not checked.
+    pub max_food: u32,
+    // Turns the edge of the grid into walls, torus or not.
+    pub wall_border: bool,
+    // The most food an ant carries at once.
+    pub carry_capacity: u32,
+}
 
-pub struct AntMut<'a> {
-    id: AntId,
+impl Default for WorldRules {
+    fn default() -> Self {
+        WorldRules {
+            max_food: u32::MAX,
+            wall_border: false,
+            carry_capacity: 1,
+        }
+    }
+}
 
-    grid: &'a mut Grid,
-    data: &'a mut AntData,
+// Food sources that grow back. Every `interval` rounds each spawner gains
+// `amount` food with a `chance` in 100, but never past `cap`; spawners on
+// walls or anthills stay bare. An `interval` of zero never regrows.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RegrowthRule {
+    pub spawners: Vec<Position>,
+    pub interval: u32,
+    pub amount: u32,
+    pub cap: u32,
+    pub chance: u32,
 }
 
-impl<'a> AntMut<'a> {
-    pub fn id(&self) -> AntId {
-        self.id
+impl RegrowthRule {
+    // Whether spawners grow at the end of `round`, counting from 1.
+    pub fn is_due(&self, round: u32) -> bool {
+        self.interval > 0 && round.is_multiple_of(self.interval)
     }
+}
 
-    pub fn position(&self) -> Position {
-        self.data.position
+// What lies past the edge of the grid: nothing, or the opposite edge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Topology {
+    #[default]
+    Bounded,
+    Torus,
+}
+
+// An ant standing on a cell at the end of its turn in `round` (counted
+// from zero), and what it did there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Occupancy {
+    pub round: u32,
+    pub ant: AntId,
+    pub action: Option<Action>,
+}
+
+// The last `capacity` occupancies of every cell, oldest first.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct History {
+    capacity: usize,
+    cells: HashMap<Position, VecDeque<Occupancy>>,
+}
+
+// Cells are stored row by row in one vector, `y * width + x`.
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(into = "grid_rows::Rows", try_from = "grid_rows::Rows")
+)]
+pub struct Grid {
+    cells: Vec<Cell>,
+    width: usize,
+    height: usize,
+    topology: Topology,
+    // Analytics only, so it is left out of save files.
+    history: Option<History>,
+}
+
+// Save files keep the grid as a list of rows.
+#[cfg(feature = "serde")]
+mod grid_rows {
+    use super::*;
+
+    #[derive(serde::Serialize, serde::Deserialize)]
+    pub struct Rows {
+        cells: Vec<Vec<Cell>>,
+        width: usize,
+        height: usize,
+        #[serde(default)]
+        topology: Topology,
+    }
+
+    impl From<Grid> for Rows {
+        fn from(grid: Grid) -> Self {
+            let cells = if grid.width == 0 {
+                vec![Vec::new(); grid.height]
+            } else {
+                grid.cells
+                    .chunks(grid.width)
+                    .map(<[Cell]>::to_vec)
+                    .collect()
+            };
+            Rows {
+                cells,
+                width: grid.width,
+                height: grid.height,
+                topology: grid.topology,
+            }
+        }
+    }
+
+    impl TryFrom<Rows> for Grid {
+        type Error = String;
+
+        fn try_from(rows: Rows) -> Result<Self, String> {
+            if rows.cells.len() != rows.height
+                || rows.cells.iter().any(|row| row.len() != rows.width)
+            {
+                return Err(format!(
+                    "rows do not make a {}x{} grid",
+                    rows.width, rows.height
+                ));
+            }
+            Ok(Grid {
+                cells: rows.cells.into_iter().flatten().collect(),
+                width: rows.width,
+                height: rows.height,
+                topology: rows.topology,
+                history: None,
+            })
+        }
+    }
+}
+
+impl Grid {
+    pub fn new(width: usize, height: usize) -> Self {
+        Grid {
+            cells: vec![Cell::default(); width * height],
+            width,
+            height,
+            topology: Topology::Bounded,
+            history: None,
+        }
+    }
+
+    // On a torus every position names some cell: coordinates wrap modulo
+    // the grid size, so ants walking off one edge come back on the other.
+    // Positions stored in the world are always the wrapped ones.
+    pub fn new_torus(width: usize, height: usize) -> Self {
+        Grid {
+            topology: Topology::Torus,
+            ..Grid::new(width, height)
+        }
+    }
+
+    pub fn topology(&self) -> Topology {
+        self.topology
+    }
+
+    // The position inside the grid that `position` refers to, if any.
+    pub fn wrap(&self, position: Position) -> Option<Position> {
+        // Most lookups are inside already; dividing is slow enough to show.
+        if self.in_bounds(position) {
+            return Some(position);
+        }
+        match self.topology {
+            Topology::Bounded => None,
+            Topology::Torus if self.width > 0 && self.height > 0 => Some(Position {
+                x: position.x.rem_euclid(self.width as i32),
+                y: position.y.rem_euclid(self.height as i32),
+            }),
+            Topology::Torus => None,
+        }
+    }
+
+    pub fn neighbour(&self, position: Position, direction: Direction) -> Option<Position> {
+        self.wrap(position.translate(direction))
+    }
+
+    // The cells around `position` with the direction they lie in, starting
+    // from `Right` and going clockwise; cells past the edge are left out.
+    pub fn neighbors(&self, position: Position) -> impl Iterator<Item = (Direction, &Cell)> + '_ {
+        Direction::all().filter_map(move |direction| {
+            let neighbour = self.neighbour(position, direction)?;
+            Some((direction, self.cell_at(neighbour).unwrap()))
+        })
+    }
+
+    // A grid whose outermost ring of cells is wall, so ants can never walk
+    // off the map.
+    pub fn with_wall_border(width: usize, height: usize) -> Self {
+        let mut grid = Grid::new(width, height);
+        grid.seal_borders();
+        grid
+    }
+
+    // Starts keeping the last `capacity` occupancies of every cell,
+    // discarding any history kept so far.
+    pub fn track_history(&mut self, capacity: usize) {
+        assert!(capacity > 0);
+        self.history = Some(History {
+            capacity,
+            cells: HashMap::new(),
+        });
+    }
+
+    pub fn stop_tracking_history(&mut self) {
+        self.history = None;
+    }
+
+    pub fn is_tracking_history(&self) -> bool {
+        self.history.is_some()
+    }
+
+    // Oldest first. Empty when history is not tracked.
+    pub fn history_at(&self, position: Position) -> impl Iterator<Item = Occupancy> + '_ {
+        let events = self.wrap(position).and_then(|position| {
+            self.history
+                .as_ref()
+                .and_then(|history| history.cells.get(&position))
+        });
+        events.into_iter().flatten().copied()
+    }
+
+    pub(crate) fn record_occupancy(&mut self, position: Position, occupancy: Occupancy) {
+        let Some(history) = &mut self.history else {
+            return;
+        };
+        let events = history.cells.entry(position).or_default();
+        if events.len() == history.capacity {
+            events.pop_front();
+        }
+        events.push_back(occupancy);
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    // Index into `cells` of a position inside the grid.
+    fn index(&self, position: Position) -> usize {
+        position.y as usize * self.width + position.x as usize
+    }
+
+    pub fn cell_at(&self, position: Position) -> Option<&Cell> {
+        let position = self.wrap(position)?;
+        Some(&self.cells[self.index(position)])
+    }
+
+    pub fn cell_at_mut(&mut self, position: Position) -> Option<&mut Cell> {
+        let position = self.wrap(position)?;
+        let index = self.index(position);
+        Some(&mut self.cells[index])
+    }
+
+    // Every cell with its position, row by row.
+    pub fn iter_cells(&self) -> impl Iterator<Item = (Position, &Cell)> + '_ {
+        let width = self.width.max(1);
+        self.cells.iter().enumerate().map(move |(i, cell)| {
+            let position = Position {
+                x: (i % width) as i32,
+                y: (i / width) as i32,
+            };
+            (position, cell)
+        })
+    }
+
+    pub(crate) fn row(&self, y: usize) -> &[Cell] {
+        &self.cells[y * self.width..(y + 1) * self.width]
+    }
+
+    pub fn ant_at(&self, position: Position) -> Option<AntId> {
+        self.cell_at(position).and_then(|cell| match cell {
+            Cell::FreeCell {
+                ant_id: Some(ant_id),
+                ..
+            } => Some(*ant_id),
+            _ => None,
+        })
+    }
+
+    // Whether `position` lies inside the grid's rectangle, whatever the
+    // topology.
+    pub fn in_bounds(&self, position: Position) -> bool {
+        position.y >= 0
+            && position.x >= 0
+            && position.y < self.height as i32
+            && position.x < self.width as i32
+    }
+
+    pub fn is_border(&self, position: Position) -> bool {
+        self.in_bounds(position)
+            && (position.x == 0
+                || position.y == 0
+                || position.x == self.width as i32 - 1
+                || position.y == self.height as i32 - 1)
+    }
+
+    pub fn is_sealed(&self) -> bool {
+        self.border()
+            .all(|position| self.cells[self.index(position)] == Cell::Wall)
+    }
+
+    // Turns every border cell into a wall, discarding whatever was there.
+    // Meant for grids that are not yet part of a `World`.
+    pub fn seal_borders(&mut self) {
+        for position in self.border().collect::<Vec<_>>() {
+            let index = self.index(position);
+            self.cells[index] = Cell::Wall;
+        }
+    }
+
+    // Border positions in row-major order.
+    pub fn border(&self) -> impl Iterator<Item = Position> + '_ {
+        (0..self.height as i32)
+            .flat_map(move |y| (0..self.width as i32).map(move |x| Position { x, y }))
+            .filter(move |&position| self.is_border(position))
+    }
+
+    // Cells exactly `radius` steps away from `center`, walked clockwise
+    // starting from the corner in the `UpLeft` direction. On a bounded grid
+    // cells past the edge are left out; on a torus they wrap, and a ring
+    // wider than the torus visits some cells more than once.
+    pub fn ring(&self, center: Position, radius: u32) -> impl Iterator<Item = Position> + '_ {
+        hex::ring(center, radius).filter_map(move |position| self.wrap(position))
+    }
+
+    // Cells within `max_radius` of `center`, ring by ring.
+    pub fn spiral(&self, center: Position, max_radius: u32) -> impl Iterator<Item = Position> + '_ {
+        (0..=max_radius).flat_map(move |radius| self.ring(center, radius))
+    }
+}
+
+#[derive(Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct World {
+    ants: Vec<AntData>,
+    swarms: HashMap<Color, Vec<AntId>>,
+    grid: Grid,
+    #[cfg_attr(feature = "serde", serde(default))]
+    rules: WorldRules,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WorldError {
+    OutOfBounds,
+    NoSuchAnt,
+    Wall,
+    Occupied,
+    CellHasNoFood,
+    AntHasNoFood,
+    AntIsFull,
+    CellFull,
+    InvalidMarker,
+    // Eating with no metabolism, or on a full stomach.
+    NotHungry,
+    InvalidRegister,
+    InvalidCaste,
+}
+
+impl fmt::Display for WorldError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WorldError::OutOfBounds => write!(f, "the position is off the grid"),
+            WorldError::NoSuchAnt => write!(f, "no such ant"),
+            WorldError::Wall => write!(f, "the cell is a wall"),
+            WorldError::Occupied => write!(f, "the cell is occupied"),
+            WorldError::CellHasNoFood => write!(f, "the cell has no food"),
+            WorldError::AntHasNoFood => write!(f, "the ant carries no food"),
+            WorldError::AntIsFull => write!(f, "the ant carries all the food it can"),
+            WorldError::CellFull => write!(f, "the cell holds all the food it can"),
+            WorldError::InvalidMarker => write!(f, "no such marker"),
+            WorldError::NotHungry => write!(f, "the ant is not hungry"),
+            WorldError::InvalidRegister => write!(f, "no such register"),
+            WorldError::InvalidCaste => write!(f, "no such caste"),
+        }
+    }
+}
+
+impl std::error::Error for WorldError {}
+
+impl From<CellError> for WorldError {
+    fn from(value: CellError) -> Self {
+        match value {
+            CellError::Wall => WorldError::Wall,
+            CellError::Occupied => WorldError::Occupied,
+            CellError::NoFood => WorldError::CellHasNoFood,
+            CellError::Full => WorldError::CellFull,
+            CellError::InvalidMarker => WorldError::InvalidMarker,
+        }
+    }
+}
+
+// A way the grid, the ants and the swarms disagree, see
+// `World::check_invariants`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvariantViolation {
+    // A living ant whose cell does not point back at it.
+    AntNotOnCell {
+        id: AntId,
+        position: Position,
+    },
+    AntOnWall {
+        id: AntId,
+        position: Position,
+    },
+    // Two living ants at one position; the cell points at `first`.
+    SharedCell {
+        position: Position,
+        first: AntId,
+        second: AntId,
+    },
+    // A cell pointing at an ant that is dead, unknown or elsewhere.
+    StrayAnt {
+        position: Position,
+        id: AntId,
+    },
+    // A living ant missing from the swarm of its color.
+    NotInSwarm {
+        id: AntId,
+    },
+    // A swarm listing an ant that is dead, unknown, of another color or
+    // listed already.
+    BadSwarmEntry {
+        color: Color,
+        id: AntId,
+    },
+}
+
+impl fmt::Display for InvariantViolation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantViolation::AntNotOnCell { id, position } => {
+                write!(
+                    f,
+                    "ant {} is not on its cell at ({}, {})",
+                    id, position.x, position.y
+                )
+            }
+            InvariantViolation::AntOnWall { id, position } => {
+                write!(
+                    f,
+                    "ant {} stands on the wall at ({}, {})",
+                    id, position.x, position.y
+                )
+            }
+            InvariantViolation::SharedCell {
+                position,
+                first,
+                second,
+            } => write!(
+                f,
+                "ants {} and {} share the cell at ({}, {})",
+                first, second, position.x, position.y
+            ),
+            InvariantViolation::StrayAnt { position, id } => {
+                write!(
+                    f,
+                    "the cell at ({}, {}) holds ant {}, which is not there",
+                    position.x, position.y, id
+                )
+            }
+            InvariantViolation::NotInSwarm { id } => {
+                write!(f, "ant {} is missing from its swarm", id)
+            }
+            InvariantViolation::BadSwarmEntry { color, id } => {
+                write!(
+                    f,
+                    "the {:?} swarm lists ant {}, which is not in it",
+                    color, id
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for InvariantViolation {}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ant<'a> {
+    id: AntId,
+
+    data: &'a AntData,
+}
+
+impl Ant<'_> {
+    pub fn id(&self) -> AntId {
+        self.id
+    }
+
+    pub fn position(&self) -> Position {
+        self.data.position
     }
 
     pub fn direction(&self) -> Direction {
@@ -373,53 +1288,317 @@ impl<'a> AntMut<'a> {
         self.data.color
     }
 
+    pub fn carried_food(&self) -> u32 {
+        self.data.carried_food
+    }
+
     pub fn carries_food(&self) -> bool {
-        self.data.carries_food
+        self.carried_food() > 0
     }
 
     pub fn instr_pointer(&self) -> usize {
         self.data.instr_pointer
     }
 
+    pub fn caste(&self) -> Caste {
+        self.data.caste
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.data.alive
+    }
+
+    // Always zero without a `Metabolism`.
+    pub fn hunger(&self) -> u32 {
+        self.data.hunger
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.data.registers
+    }
+
+    pub fn call_stack(&self) -> CallStack {
+        self.data.call_stack
+    }
+
+    // Always zero without a `RestTable`.
+    pub fn resting(&self) -> u32 {
+        self.data.resting
+    }
+
+    pub fn sensed_position(&self, sense_dir: SenseDirection) -> Position {
+        sense_dir.apply_to(self.data.position, self.data.direction)
+    }
+}
+
+impl PartialEq for Ant<'_> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for Ant<'_> {}
+
+pub struct AntMut<'a> {
+    id: AntId,
+
+    grid: &'a mut Grid,
+    ants: &'a mut [AntData],
+    rules: &'a WorldRules,
+}
+
+impl<'a> AntMut<'a> {
+    fn data(&self) -> &AntData {
+        &self.ants[self.id]
+    }
+
+    fn data_mut(&mut self) -> &mut AntData {
+        &mut self.ants[self.id]
+    }
+
+    pub fn id(&self) -> AntId {
+        self.id
+    }
+
+    pub fn position(&self) -> Position {
+        self.data().position
+    }
+
+    pub fn direction(&self) -> Direction {
+        self.data().direction
+    }
+
+    pub fn color(&self) -> Color {
+        self.data().color
+    }
+
+    pub fn carried_food(&self) -> u32 {
+        self.data().carried_food
+    }
+
+    pub fn carries_food(&self) -> bool {
+        self.carried_food() > 0
+    }
+
+    pub fn instr_pointer(&self) -> usize {
+        self.data().instr_pointer
+    }
+
+    pub fn caste(&self) -> Caste {
+        self.data().caste
+    }
+
+    pub fn registers(&self) -> Registers {
+        self.data().registers
+    }
+
+    // Registers past the last one read as zero.
+    pub fn register(&self, register: Register) -> u8 {
+        let registers = &self.data().registers;
+        registers.get(usize::from(register)).copied().unwrap_or(0)
+    }
+
+    pub fn set_register(&mut self, register: Register, value: u8) -> Result<(), WorldError> {
+        let slot = self
+            .data_mut()
+            .registers
+            .get_mut(usize::from(register))
+            .ok_or(WorldError::InvalidRegister)?;
+        *slot = value;
+        Ok(())
+    }
+
+    pub(crate) fn set_registers(&mut self, registers: Registers) {
+        self.data_mut().registers = registers;
+    }
+
+    pub fn call_stack(&self) -> CallStack {
+        self.data().call_stack
+    }
+
+    pub fn call_stack_mut(&mut self) -> &mut CallStack {
+        &mut self.data_mut().call_stack
+    }
+
+    pub fn sensed_position(&self, sense_dir: SenseDirection) -> Position {
+        let position = sense_dir.apply_to(self.data().position, self.data().direction);
+        self.grid.wrap(position).unwrap_or(position)
+    }
+
+    pub fn sensed_cell(&self, sense_dir: SenseDirection) -> Option<&Cell> {
+        self.grid.cell_at(self.sensed_position(sense_dir))
+    }
+
+    pub fn other(&self, id: AntId) -> Ant<'_> {
+        Ant {
+            id,
+            data: &self.ants[id],
+        }
+    }
+
     pub fn move_forward(&mut self) -> Result<(), WorldError> {
-        let new_position = self.data.position.translate(self.data.direction);
-        let new_cell = self
+        let new_position = self
             .grid
-            .cell_at_mut(new_position)
+            .neighbour(self.data().position, self.data().direction)
             .ok_or(WorldError::OutOfBounds)?;
+        let new_cell = self.grid.cell_at_mut(new_position).unwrap();
         new_cell.try_put_ant(self.id)?;
-        let old_cell = self.grid.cell_at_mut(self.data.position).unwrap();
+        let old_cell = self.grid.cell_at_mut(self.data().position).unwrap();
         old_cell.clear_ant();
-        self.data.position = new_position;
+        self.data_mut().position = new_position;
         Ok(())
     }
 
     pub fn rotate(&mut self, direction: Direction) {
-        self.data.direction = direction;
+        self.data_mut().direction = direction;
     }
 
-    pub fn pickup_food(&mut self) -> Result<(), WorldError> {
-        let cell = self.grid.cell_at_mut(self.data.position).unwrap();
-        if self.data.carries_food {
-            return Err(WorldError::AntCarriesFood);
+    // Eats the food the ant carries or, failing that, a unit from its cell.
+    pub fn eat(&mut self, metabolism: Metabolism) -> Result<(), WorldError> {
+        if self.data().hunger == 0 {
+            return Err(WorldError::NotHungry);
         }
-        cell.try_pickup_food()?;
-        self.data.carries_food = true;
+        if self.data().carried_food > 0 {
+            self.data_mut().carried_food -= 1;
+        } else {
+            let position = self.data().position;
+            self.grid.cell_at_mut(position).unwrap().try_pickup_food()?;
+        }
+        let data = self.data_mut();
+        data.hunger = data.hunger.saturating_sub(metabolism.per_food);
         Ok(())
     }
 
-    pub fn drop_food(&mut self) -> Result<(), WorldError> {
-        if !self.data.carries_food {
+    pub fn resting(&self) -> u32 {
+        self.data().resting
+    }
+
+    // Makes the ant sit out its next `turns` turns.
+    pub fn rest(&mut self, turns: u32) {
+        self.data_mut().resting = turns;
+    }
+
+    // Uses up one turn of rest, if the ant has any left.
+    pub(crate) fn take_rest(&mut self) -> bool {
+        let data = self.data_mut();
+        if data.resting == 0 {
+            return false;
+        }
+        data.resting -= 1;
+        true
+    }
+
+    pub(crate) fn burn(&mut self, energy: u32) {
+        let data = self.data_mut();
+        data.hunger = data.hunger.saturating_add(energy);
+    }
+
+    // Picks up as much food as the cell has and the ant has room for, and
+    // returns how much.
+    pub fn pickup_food(&mut self) -> Result<u32, WorldError> {
+        let room = self
+            .rules
+            .carry_capacity
+            .saturating_sub(self.data().carried_food);
+        if room == 0 {
+            return Err(WorldError::AntIsFull);
+        }
+        let cell = self.grid.cell_at_mut(self.data().position).unwrap();
+        cell.try_pickup_food()?;
+        let mut picked = 1;
+        while picked < room && cell.try_pickup_food().is_ok() {
+            picked += 1;
+        }
+        self.data_mut().carried_food += picked;
+        Ok(picked)
+    }
+
+    // Drops all the food the ant carries, or as much of it as the cell
+    // takes, and returns how much.
+    pub fn drop_food(&mut self) -> Result<u32, WorldError> {
+        let carried = self.data().carried_food;
+        if carried == 0 {
             return Err(WorldError::AntHasNoFood);
         }
-        self.data.carries_food = false;
-        let cell = self.grid.cell_at_mut(self.data.position).unwrap();
-        cell.try_drop_food().unwrap();
-        Ok(())
+        let cell = self.grid.cell_at_mut(self.data().position).unwrap();
+        cell.try_drop_food_capped(self.rules.max_food)?;
+        let mut dropped = 1;
+        while dropped < carried && cell.try_drop_food_capped(self.rules.max_food).is_ok() {
+            dropped += 1;
+        }
+        self.data_mut().carried_food -= dropped;
+        Ok(dropped)
+    }
+
+    // Drops food without letting any cell exceed `cap.limit`. Spilled food
+    // goes to the first cell that can take it: neighbours clockwise starting
+    // from the direction the ant faces, then (for `FoodOverflow::Nearest`)
+    // ever wider rings. Each unit goes its own way, so a load may end up
+    // spread over several cells, or partly kept when there is no more room.
+    // Returns where each unit went.
+    pub fn drop_food_capped(&mut self, cap: FoodCap) -> Result<Vec<Position>, WorldError> {
+        let carried = self.data().carried_food;
+        if carried == 0 {
+            return Err(WorldError::AntHasNoFood);
+        }
+        let cap = FoodCap {
+            limit: cap.limit.min(self.rules.max_food),
+            ..cap
+        };
+        let mut targets = Vec::new();
+        while targets.len() < carried as usize {
+            let target = match self.drop_target(cap) {
+                Ok(target) => target,
+                Err(error) if targets.is_empty() => return Err(error),
+                Err(_) => break,
+            };
+            self.grid
+                .cell_at_mut(target)
+                .unwrap()
+                .try_drop_food_capped(cap.limit)?;
+            targets.push(target);
+        }
+        self.data_mut().carried_food -= targets.len() as u32;
+        Ok(targets)
+    }
+
+    fn drop_target(&self, cap: FoodCap) -> Result<Position, WorldError> {
+        let position = self.data().position;
+        let here = self.grid.cell_at(position).unwrap();
+        if here.food() < cap.limit || cap.overflow == FoodOverflow::Fail {
+            return Ok(position);
+        }
+        let facing = self.data().direction;
+        let neighbours =
+            (0..6).filter_map(|turns| self.grid.neighbour(position, facing.rotated(turns)));
+        let max_radius = match cap.overflow {
+            FoodOverflow::Nearest => (self.grid.width + self.grid.height) as u32,
+            _ => 1,
+        };
+        let farther = (2..=max_radius).flat_map(|radius| self.grid.ring(position, radius));
+        neighbours
+            .chain(farther)
+            .find(|&candidate| match self.grid.cell_at(candidate) {
+                Some(cell @ Cell::FreeCell { .. }) => cell.food() < cap.limit,
+                _ => false,
+            })
+            .ok_or(WorldError::CellFull)
+    }
+
+    pub fn set_marker(&mut self, marker: Marker) -> Result<(), WorldError> {
+        let (color, position) = (self.data().color, self.data().position);
+        let cell = self.grid.cell_at_mut(position).unwrap();
+        Ok(cell.try_set_marker(color, marker)?)
+    }
+
+    pub fn clear_marker(&mut self, marker: Marker) -> Result<(), WorldError> {
+        let (color, position) = (self.data().color, self.data().position);
+        let cell = self.grid.cell_at_mut(position).unwrap();
+        Ok(cell.try_clear_marker(color, marker)?)
     }
 
     pub fn update_instr_pointer(&mut self, new_pointer: usize) {
-        self.data.instr_pointer = new_pointer;
+        self.data_mut().instr_pointer = new_pointer;
     }
 }
 
@@ -431,6 +1610,64 @@ impl PartialEq for AntMut<'_> {
 
 impl Eq for AntMut<'_> {}
 
+// A cheap, copyable read-only view of a world for renderers and encoders:
+// the grid rows and the ant table are borrowed as they are stored.
+#[derive(Clone, Copy)]
+pub struct WorldSnapshot<'a> {
+    grid: &'a Grid,
+    ants: &'a [AntData],
+}
+
+impl<'a> WorldSnapshot<'a> {
+    pub fn width(&self) -> usize {
+        self.grid.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.grid.height
+    }
+
+    pub fn row(&self, y: usize) -> &'a [Cell] {
+        self.grid.row(y)
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [Cell]> {
+        (0..self.grid.height).map(|y| self.grid.row(y))
+    }
+
+    pub fn cell_at(&self, position: Position) -> Option<&'a Cell> {
+        self.grid.cell_at(position)
+    }
+
+    // Row by row, like `Grid::iter_cells`.
+    pub fn cells(&self) -> impl Iterator<Item = (Position, &'a Cell)> {
+        self.grid.iter_cells()
+    }
+
+    pub fn ant(&self, id: AntId) -> Option<Ant<'a>> {
+        self.ants
+            .get(id)
+            .filter(|data| data.alive)
+            .map(|data| Ant { id, data })
+    }
+
+    // See `World::dead_ant`.
+    pub fn dead_ant(&self, id: AntId) -> Option<Ant<'a>> {
+        self.ants
+            .get(id)
+            .filter(|data| !data.alive)
+            .map(|data| Ant { id, data })
+    }
+
+    pub fn ants(&self) -> impl Iterator<Item = Ant<'a>> {
+        self.ants
+            .iter()
+            .enumerate()
+            .filter(|(_, data)| data.alive)
+            .map(|(id, data)| Ant { id, data })
+    }
+}
+
 impl World {
     pub fn new(grid: Grid) -> Self {
         let mut swarms = HashMap::new();
@@ -441,69 +1678,752 @@ impl World {
             ants: Vec::new(),
             swarms,
             grid,
+            rules: WorldRules::default(),
         }
     }
 
+    // With `wall_border`, anything standing on the edge is lost to the
+    // walls, ants included.
+    pub fn with_rules(mut self, rules: WorldRules) -> Self {
+        if rules.wall_border {
+            for position in self.grid.border().collect::<Vec<_>>() {
+                if let Some(id) = self.grid.ant_at(position) {
+                    self.remove_ant(id).unwrap();
+                }
+            }
+            self.grid.seal_borders();
+        }
+        self.rules = rules;
+        self
+    }
+
+    pub fn rules(&self) -> &WorldRules {
+        &self.rules
+    }
+
     pub fn grid(&self) -> &Grid {
         &self.grid
     }
 
+    // See `Grid::track_history`.
+    pub fn track_history(&mut self, capacity: usize) {
+        self.grid.track_history(capacity);
+    }
+
+    pub(crate) fn record_occupancy(&mut self, id: AntId, round: u32, action: Option<Action>) {
+        let position = self.ants[id].position;
+        self.grid.record_occupancy(
+            position,
+            Occupancy {
+                round,
+                ant: id,
+                action,
+            },
+        );
+    }
+
+    pub fn snapshot(&self) -> WorldSnapshot<'_> {
+        WorldSnapshot {
+            grid: &self.grid,
+            ants: &self.ants,
+        }
+    }
+
     pub fn swarm(&self, color: Color) -> impl Iterator<Item = Ant<'_>> {
-        self.swarm_ids(color).map(|id| self.ant(id))
+        self.swarm_ids(color).map(|id| self.any_ant(id))
     }
 
     pub fn swarm_ids(&self, color: Color) -> impl Iterator<Item = AntId> + '_ {
-        self.swarms.get(&color).unwrap().iter().copied()
+        self.swarms.get(&color).into_iter().flatten().copied()
+    }
+
+    pub fn swarm_size(&self, color: Color) -> usize {
+        self.swarms.get(&color).map_or(0, Vec::len)
     }
 
     fn swarm_mut(&mut self, color: Color) -> &mut Vec<AntId> {
-        self.swarms.get_mut(&color).unwrap()
+        self.swarms.entry(color).or_default()
+    }
+
+    // The teams in the match, in order: Black and Red always, and any other
+    // team that has had ants or has an anthill.
+    pub fn teams(&self) -> Vec<Color> {
+        let mut teams = vec![Color::Black, Color::Red];
+        let anthills = self.grid.cells.iter().filter_map(Cell::anthill);
+        for color in self.swarms.keys().copied().chain(anthills) {
+            if !teams.contains(&color) {
+                teams.push(color);
+            }
+        }
+        teams.sort();
+        teams
+    }
+
+    // Every team but `color` that is in the match.
+    pub fn foes(&self, color: Color) -> impl Iterator<Item = Color> {
+        self.teams().into_iter().filter(move |&team| team != color)
     }
 
     pub fn add_ant(&mut self, color: Color, position: Position) -> Result<AntId, WorldError> {
+        self.add_ant_to_caste(color, position, 0)
+    }
+
+    pub fn add_ant_to_caste(
+        &mut self,
+        color: Color,
+        position: Position,
+        caste: Caste,
+    ) -> Result<AntId, WorldError> {
+        if usize::from(caste) >= MAX_CASTES {
+            return Err(WorldError::InvalidCaste);
+        }
         let id = self.ants.len();
-        let cell = self
-            .grid
-            .cell_at_mut(position)
-            .ok_or(WorldError::OutOfBounds)?;
+        let position = self.grid.wrap(position).ok_or(WorldError::OutOfBounds)?;
+        let cell = self.grid.cell_at_mut(position).unwrap();
         cell.try_put_ant(id)?;
-        self.ants.push(AntData::new(color, position));
+        self.ants.push(AntData {
+            caste,
+            ..AntData::new(color, position)
+        });
         self.swarm_mut(color).push(id);
         Ok(id)
     }
 
-    pub fn ant(&self, id: AntId) -> Ant<'_> {
+    // `None` for ids of dead ants, as for ids never handed out.
+    pub fn ant(&self, id: AntId) -> Option<Ant<'_>> {
+        self.is_alive(id).then(|| self.any_ant(id))
+    }
+
+    pub fn ant_mut(&mut self, id: AntId) -> Option<AntMut<'_>> {
+        if !self.is_alive(id) {
+            return None;
+        }
+        Some(AntMut {
+            id,
+            grid: &mut self.grid,
+            ants: &mut self.ants,
+            rules: &self.rules,
+        })
+    }
+
+    // What is left of an ant that died: where it died, its color and so on.
+    pub fn dead_ant(&self, id: AntId) -> Option<Ant<'_>> {
+        let dead = self.ants.get(id).is_some_and(|data| !data.alive);
+        dead.then(|| self.any_ant(id))
+    }
+
+    fn any_ant(&self, id: AntId) -> Ant<'_> {
         Ant {
             id,
             data: &self.ants[id],
         }
     }
 
-    pub fn ant_mut(&mut self, id: AntId) -> AntMut<'_> {
-        AntMut {
-            id,
-            grid: &mut self.grid,
-            data: &mut self.ants[id],
-        }
+    pub fn is_alive(&self, id: AntId) -> bool {
+        self.ants.get(id).is_some_and(|data| data.alive)
     }
 
     pub fn ants(&self) -> impl Iterator<Item = Ant<'_>> {
-        (0..self.ants.len()).map(|id| self.ant(id))
+        self.ant_ids().map(|id| self.any_ant(id))
+    }
+
+    // Ids handed out so far, to dead ants too.
+    pub(crate) fn ants_ever(&self) -> usize {
+        self.ants.len()
+    }
+
+    // The raw state, for `World::diff` and `World::apply_delta`.
+    pub(crate) fn parts(&self) -> (&Grid, &[AntData], &HashMap<Color, Vec<AntId>>) {
+        (&self.grid, &self.ants, &self.swarms)
+    }
+
+    pub(crate) fn parts_mut(
+        &mut self,
+    ) -> (
+        &mut Grid,
+        &mut Vec<AntData>,
+        &mut HashMap<Color, Vec<AntId>>,
+    ) {
+        (&mut self.grid, &mut self.ants, &mut self.swarms)
     }
 
-    pub fn ant_ids(&self) -> impl Iterator<Item = AntId> {
-        0..self.ants.len()
+    pub fn ant_ids(&self) -> impl Iterator<Item = AntId> + '_ {
+        (0..self.ants.len()).filter(|&id| self.ants[id].alive)
     }
 
-    pub fn cell_of(&self, id: AntId) -> &Cell {
-        self.grid.cell_at(self.ant(id).position()).unwrap()
+    // Ids are never reused: a removed ant keeps its slot, marked dead, so a
+    // stale id can't end up pointing at a newer ant.
+    pub fn remove_ant(&mut self, id: AntId) -> Result<(), WorldError> {
+        self.kill_ant(id, 0).map(|_| ())
+    }
+
+    // Returns the food the ant left on its cell.
+    pub fn kill_ant(&mut self, id: AntId, food_bonus: u32) -> Result<u32, WorldError> {
+        if !self.is_alive(id) {
+            return Err(WorldError::NoSuchAnt);
+        }
+        let data = &mut self.ants[id];
+        data.alive = false;
+        let (color, position) = (data.color, data.position);
+        let food = food_bonus + data.carried_food;
+        data.carried_food = 0;
+        data.resting = 0;
+
+        let cell = self.grid.cell_at_mut(position).unwrap();
+        cell.clear_ant();
+        let mut dropped = 0;
+        while dropped < food && cell.try_drop_food_capped(self.rules.max_food).is_ok() {
+            dropped += 1;
+        }
+        self.swarm_mut(color).retain(|&ant_id| ant_id != id);
+        Ok(dropped)
+    }
+
+    // Every living ant uses up a turn of rest, as it would at its turn.
+    pub(crate) fn take_rests(&mut self) {
+        for data in self.ants.iter_mut().filter(|data| data.alive) {
+            data.resting = data.resting.saturating_sub(1);
+        }
+    }
+
+    // The end of a round under `metabolism`: every living ant burns its
+    // round's energy, those on their own anthill eat there if they can,
+    // and those out of energy starve. Returns the starved ants by id, with
+    // the food each left behind.
+    pub fn metabolize(&mut self, metabolism: Metabolism) -> Vec<(AntId, u32)> {
+        let mut starved = Vec::new();
+        for id in 0..self.ants.len() {
+            if !self.ants[id].alive {
+                continue;
+            }
+            let data = &mut self.ants[id];
+            data.hunger = data.hunger.saturating_add(metabolism.per_round);
+            let cell = self.grid.cell_at_mut(data.position).unwrap();
+            if metabolism.eat_at_home
+                && cell.anthill() == Some(data.color)
+                && data.hunger >= metabolism.per_food
+                && cell.try_pickup_food().is_ok()
+            {
+                data.hunger -= metabolism.per_food;
+            }
+            if data.hunger >= metabolism.max_energy {
+                let food = self.kill_ant(id, 0).unwrap();
+                starved.push((id, food));
+            }
+        }
+        starved
+    }
+
+    // The end of a round under `rule`: each team buys as many ants as its
+    // stored food and free anthill cells allow, taking the food from the
+    // anthill's cells in row-major order. Returns the new ants by id, each
+    // with the cells its food came from.
+    pub fn spawn(&mut self, rule: SpawnRule) -> Vec<(AntId, Vec<Position>)> {
+        let mut spawned = Vec::new();
+        for color in self.teams() {
+            let anthill: Vec<_> = self
+                .grid
+                .iter_cells()
+                .filter(|(_, cell)| cell.anthill() == Some(color))
+                .map(|(position, _)| position)
+                .collect();
+            let mut stored: u32 = anthill
+                .iter()
+                .map(|&position| self.grid.cell_at(position).unwrap().food())
+                .sum();
+            while stored >= rule.food_per_ant {
+                let free = anthill
+                    .iter()
+                    .copied()
+                    .find(|&position| !self.grid.cell_at(position).unwrap().has_ant());
+                let Some(free) = free else {
+                    break;
+                };
+                let mut owed = rule.food_per_ant;
+                let mut emptied = Vec::new();
+                for &position in &anthill {
+                    let cell = self.grid.cell_at_mut(position).unwrap();
+                    let before = owed;
+                    while owed > 0 && cell.try_pickup_food().is_ok() {
+                        owed -= 1;
+                    }
+                    if owed < before {
+                        emptied.push(position);
+                    }
+                }
+                stored -= rule.food_per_ant;
+                let mut counts = [0; MAX_CASTES];
+                for ant in self.swarm(color) {
+                    counts[usize::from(ant.caste())] += 1;
+                }
+                let caste = rule.caste_for(counts);
+                let id = self.add_ant_to_caste(color, free, caste).unwrap();
+                spawned.push((id, emptied));
+            }
+        }
+        spawned
+    }
+
+    // One growth under `rule`, spawners in the order the rule lists them.
+    // Returns the food added and where.
+    pub fn regrow(&mut self, rule: &RegrowthRule, rng: &mut Rng) -> Vec<(Position, u32)> {
+        let mut grown = Vec::new();
+        for &position in &rule.spawners {
+            if rng.below(100) >= rule.chance {
+                continue;
+            }
+            let Some(cell) = self.grid.cell_at(position) else {
+                continue;
+            };
+            if matches!(cell, Cell::Wall) || cell.anthill().is_some() {
+                continue;
+            }
+            let cap = rule.cap.min(self.rules.max_food);
+            let amount = rule.amount.min(cap.saturating_sub(cell.food()));
+            if amount > 0 {
+                self.grow_food(position, amount);
+                grown.push((position, amount));
+            }
+        }
+        grown
+    }
+
+    pub(crate) fn grow_food(&mut self, position: Position, amount: u32) {
+        let cell = self.grid.cell_at_mut(position).unwrap();
+        for _ in 0..amount {
+            cell.try_drop_food().unwrap();
+        }
+    }
+
+    // None around a dead ant.
+    pub fn adjacent_enemies(&self, id: AntId) -> usize {
+        let Some(ant) = self.ant(id) else {
+            return 0;
+        };
+        self.grid
+            .ring(ant.position(), 1)
+            .filter_map(|position| self.grid.ant_at(position))
+            .filter(|&other| self.any_ant(other).color() != ant.color())
+            .count()
+    }
+
+    // Applies the combat rule around a cell an ant just entered: the ant
+    // there and then its neighbours, clockwise from `Right`, die if they are
+    // surrounded by enough enemies. Returns the killed ants in that order,
+    // with the food each left behind.
+    pub fn resolve_combat(&mut self, position: Position, rule: CombatRule) -> Vec<(AntId, u32)> {
+        // Found up front, as kills empty cells. This runs after every move,
+        // so it stays off the heap.
+        let mut candidates = [None; 7];
+        let positions = std::iter::once(position).chain(hex::neighbors(position));
+        for (candidate, position) in candidates.iter_mut().zip(positions) {
+            *candidate = self.grid.ant_at(position);
+        }
+        let mut killed = Vec::new();
+        for id in candidates.into_iter().flatten() {
+            // On a tiny torus the same ant can show up more than once.
+            if self.is_alive(id) && self.adjacent_enemies(id) >= rule.enemies_to_kill {
+                let food = self.kill_ant(id, rule.food_bonus).unwrap();
+                killed.push((id, food));
+            }
+        }
+        killed
+    }
+
+    pub fn remap_instr_pointers(&mut self, mut remap: impl FnMut(Ant<'_>) -> InstrIdx) {
+        for id in 0..self.ants.len() {
+            let new_pointer = remap(self.any_ant(id));
+            self.ants[id].instr_pointer = new_pointer;
+        }
+    }
+
+    pub fn reset_instr_pointers(&mut self) {
+        self.remap_instr_pointers(|_| 0);
+    }
+
+    pub fn food_in_anthill(&self, color: Color) -> u32 {
+        self.grid
+            .cells
+            .iter()
+            .filter(|cell| cell.anthill() == Some(color))
+            .map(Cell::food)
+            .sum()
+    }
+
+    // Food lying outside every anthill.
+    pub fn food_on_ground(&self) -> u32 {
+        self.grid
+            .cells
+            .iter()
+            .filter(|cell| cell.anthill().is_none())
+            .map(Cell::food)
+            .sum()
+    }
+
+    // Food on the ground, in anthills and carried by ants.
+    pub fn total_food(&self) -> u32 {
+        let carried: u32 = self.ants().map(|ant| ant.carried_food()).sum();
+        self.grid.cells.iter().map(Cell::food).sum::<u32>() + carried
+    }
+
+    // Living ants of `color`.
+    pub fn ant_count(&self, color: Color) -> usize {
+        self.swarm_size(color)
+    }
+
+    // Cells with food on them, anthills included, in row-major order.
+    pub fn food_positions(&self) -> impl Iterator<Item = Position> + '_ {
+        self.grid
+            .iter_cells()
+            .filter(|(_, cell)| cell.has_food())
+            .map(|(position, _)| position)
+    }
+
+    // Living ants within `radius` steps of `center`, nearest rings first.
+    pub fn ants_in_radius(&self, center: Position, radius: u32) -> impl Iterator<Item = Ant<'_>> {
+        // A torus narrower than the radius reaches some cells twice.
+        let mut seen = HashSet::new();
+        self.grid
+            .spiral(center, radius)
+            .filter_map(|position| self.grid.ant_at(position))
+            .filter(move |&id| seen.insert(id))
+            .map(|id| self.any_ant(id))
+    }
+
+    pub fn cell_of(&self, id: AntId) -> Option<&Cell> {
+        self.grid.cell_at(self.ant(id)?.position())
+    }
+
+    pub fn sensed_cell(&self, id: AntId, sense_dir: SenseDirection) -> Option<&Cell> {
+        self.grid.cell_at(self.ant(id)?.sensed_position(sense_dir))
+    }
+
+    // Everything that is off between the grid, the ants and the swarms, ant
+    // by ant, then cell by cell, then swarm by swarm in color order. A world
+    // only ever changed through its own methods has none.
+    pub fn check_invariants(&self) -> Result<(), Vec<InvariantViolation>> {
+        let mut violations = Vec::new();
+        for (id, ant) in self.ants.iter().enumerate().filter(|(_, ant)| ant.alive) {
+            let position = ant.position;
+            match self.grid.cell_at(position) {
+                Some(Cell::Wall) => violations.push(InvariantViolation::AntOnWall { id, position }),
+                Some(Cell::FreeCell {
+                    ant_id: Some(first),
+                    ..
+                }) if *first != id
+                    && self
+                        .ants
+                        .get(*first)
+                        .is_some_and(|other| other.alive && other.position == position) =>
+                {
+                    violations.push(InvariantViolation::SharedCell {
+                        position,
+                        first: *first,
+                        second: id,
+                    })
+                }
+                Some(Cell::FreeCell { ant_id, .. }) if *ant_id == Some(id) => {}
+                _ => violations.push(InvariantViolation::AntNotOnCell { id, position }),
+            }
+        }
+        for (position, cell) in self.grid.iter_cells() {
+            let Some(id) = cell.ant() else {
+                continue;
+            };
+            let placed = self.ants.get(id);
+            if !placed.is_some_and(|ant| ant.alive && ant.position == position) {
+                violations.push(InvariantViolation::StrayAnt { position, id });
+            }
+        }
+        let mut listed = vec![false; self.ants.len()];
+        let mut colors: Vec<_> = self.swarms.keys().copied().collect();
+        colors.sort();
+        for color in colors {
+            for &id in &self.swarms[&color] {
+                match self.ants.get(id) {
+                    Some(ant) if ant.alive && ant.color == color && !listed[id] => {
+                        listed[id] = true;
+                    }
+                    _ => violations.push(InvariantViolation::BadSwarmEntry { color, id }),
+                }
+            }
+        }
+        violations.extend(
+            (self.ants.iter().enumerate())
+                .filter(|&(id, ant)| ant.alive && !listed[id])
+                .map(|(id, _)| InvariantViolation::NotInSwarm { id }),
+        );
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(violations)
+        }
+    }
+}
+
+impl Cell {
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        match self {
+            Cell::Wall => out.bool(false),
+            Cell::FreeCell {
+                ant_id,
+                food,
+                markers,
+                anthill,
+            } => {
+                out.bool(true);
+                out.usize(ant_id.map_or(0, |id| id + 1));
+                out.u32(*food);
+                let bits = markers.used_bits();
+                out.usize(bits.len());
+                for &bits in bits {
+                    out.u8(bits);
+                }
+                out.usize(anthill.map_or(0, |color| 1 + color.index()));
+            }
+        }
+    }
+
+    pub(crate) fn decode(input: &mut Decoder) -> Result<Cell, SnapshotError> {
+        if !input.bool()? {
+            return Ok(Cell::Wall);
+        }
+        let ant_id = input.usize()?.checked_sub(1);
+        let food = input.u32()?;
+        let bits = (0..input.count()?)
+            .map(|_| input.u8())
+            .collect::<Result<Vec<_>, _>>()?;
+        let markers = Markers::from_bits(&bits).ok_or(SnapshotError::Corrupt)?;
+        let anthill = match input.usize()? {
+            0 => None,
+            team => Some(Color::try_team(team - 1).ok_or(SnapshotError::Corrupt)?),
+        };
+        Ok(Cell::FreeCell {
+            ant_id,
+            food,
+            markers,
+            anthill,
+        })
     }
 }
 
+// Snapshot encoding; occupancy history is not part of it.
+impl World {
+    // A hash of the grid and every ant, instruction pointers included. It
+    // is the same on every platform and in every run, so it can be kept
+    // to compare against later.
+    pub fn state_hash(&self) -> u64 {
+        let mut out = Encoder::bare();
+        self.encode(&mut out);
+        fnv1a(out.into_bytes())
+    }
+
+    pub(crate) fn encode(&self, out: &mut Encoder) {
+        let grid = &self.grid;
+        out.usize(grid.width);
+        out.usize(grid.height);
+        out.usize(grid.width * grid.height);
+        out.bool(grid.topology == Topology::Torus);
+        for cell in grid.cells.iter() {
+            cell.encode(out);
+        }
+        out.usize(self.ants.len());
+        for ant in &self.ants {
+            ant.encode(out);
+        }
+        self.encode_swarms(out);
+    }
+
+    fn encode_swarms(&self, out: &mut Encoder) {
+        let mut colors: Vec<_> = self.swarms.keys().copied().collect();
+        colors.sort();
+        out.usize(colors.len());
+        for color in colors {
+            let swarm = &self.swarms[&color];
+            out.color(color);
+            out.usize(swarm.len());
+            for &id in swarm {
+                out.usize(id);
+            }
+        }
+    }
+
+    pub(crate) fn decode(input: &mut Decoder) -> Result<World, SnapshotError> {
+        let width = input.usize()?;
+        let height = input.usize()?;
+        let cells = width.checked_mul(height).ok_or(SnapshotError::Corrupt)?;
+        if cells > input.count()? {
+            return Err(SnapshotError::Corrupt);
+        }
+        let mut grid = if input.bool()? {
+            Grid::new_torus(width, height)
+        } else {
+            Grid::new(width, height)
+        };
+        for cell in grid.cells.iter_mut() {
+            *cell = Cell::decode(input)?;
+        }
+        let mut world = World::new(grid);
+        for _ in 0..input.count()? {
+            world.ants.push(AntData::decode(input)?);
+        }
+        world.decode_swarms(input)?;
+        if !world.is_consistent() {
+            return Err(SnapshotError::Corrupt);
+        }
+        Ok(world)
+    }
+
+    fn decode_swarms(&mut self, input: &mut Decoder) -> Result<(), SnapshotError> {
+        self.swarms.clear();
+        for _ in 0..input.count()? {
+            let color = input.color()?;
+            let swarm = (0..input.count()?)
+                .map(|_| input.usize())
+                .collect::<Result<_, _>>()?;
+            self.swarms.insert(color, swarm);
+        }
+        Ok(())
+    }
+
+    // What changed since `base`, a world of the same size: the cells and
+    // ants that differ and every swarm in full. Used for step history,
+    // where it is a fraction of a full encoding.
+    pub(crate) fn encode_patch(&self, base: &World, out: &mut Encoder) {
+        let cells: Vec<_> = (self.grid.cells.iter())
+            .zip(base.grid.cells.iter())
+            .enumerate()
+            .filter(|(_, (cell, old))| cell != old)
+            .collect();
+        out.usize(cells.len());
+        for (index, (cell, _)) in cells {
+            out.usize(index);
+            cell.encode(out);
+        }
+        let ants: Vec<_> = (self.ants.iter().enumerate())
+            .filter(|&(id, ant)| base.ants.get(id) != Some(ant))
+            .collect();
+        out.usize(self.ants.len());
+        out.usize(ants.len());
+        for (id, ant) in ants {
+            out.usize(id);
+            ant.encode(out);
+        }
+        self.encode_swarms(out);
+    }
+
+    // Applies a patch taken against this world. The world is left half
+    // patched if the patch turns out to be damaged.
+    pub(crate) fn apply_patch(&mut self, input: &mut Decoder) -> Result<(), SnapshotError> {
+        for _ in 0..input.count()? {
+            let index = input.usize()?;
+            let cell = Cell::decode(input)?;
+            *self
+                .grid
+                .cells
+                .get_mut(index)
+                .ok_or(SnapshotError::Corrupt)? = cell;
+        }
+        let len = input.usize()?;
+        self.ants.truncate(len);
+        for _ in 0..input.count()? {
+            let id = input.usize()?;
+            let ant = AntData::decode(input)?;
+            match id.cmp(&self.ants.len()) {
+                std::cmp::Ordering::Less => self.ants[id] = ant,
+                std::cmp::Ordering::Equal => self.ants.push(ant),
+                std::cmp::Ordering::Greater => return Err(SnapshotError::Corrupt),
+            }
+        }
+        self.decode_swarms(input)?;
+        if self.ants.len() != len || !self.is_consistent() {
+            return Err(SnapshotError::Corrupt);
+        }
+        Ok(())
+    }
+
+    fn is_consistent(&self) -> bool {
+        self.check_invariants().is_ok()
+    }
+}
+
+impl AntData {
+    fn encode(&self, out: &mut Encoder) {
+        out.color(self.color);
+        out.direction(self.direction);
+        out.position(self.position);
+        out.usize(self.instr_pointer);
+        out.u32(self.carried_food);
+        out.bool(self.alive);
+        out.u32(self.hunger);
+        for &value in &self.registers {
+            out.u8(value);
+        }
+        out.usize(self.call_stack.depth());
+        for &frame in self.call_stack.frames() {
+            out.usize(frame);
+        }
+        out.u32(self.resting);
+        out.u8(self.caste);
+    }
+
+    fn decode(input: &mut Decoder) -> Result<AntData, SnapshotError> {
+        Ok(AntData {
+            color: input.color()?,
+            direction: input.direction()?,
+            position: input.position()?,
+            instr_pointer: input.usize()?,
+            carried_food: input.u32()?,
+            alive: input.bool()?,
+            hunger: input.u32()?,
+            registers: [input.u8()?, input.u8()?, input.u8()?, input.u8()?],
+            call_stack: decode_call_stack(input)?,
+            resting: input.u32()?,
+            caste: match input.u8()? {
+                caste if usize::from(caste) < MAX_CASTES => caste,
+                _ => return Err(SnapshotError::Corrupt),
+            },
+        })
+    }
+}
+
+fn decode_call_stack(input: &mut Decoder) -> Result<CallStack, SnapshotError> {
+    let depth = input.usize()?;
+    if depth > CALL_STACK_DEPTH {
+        return Err(SnapshotError::Corrupt);
+    }
+    let mut stack = CallStack::default();
+    for _ in 0..depth {
+        stack.push(input.usize()?);
+    }
+    Ok(stack)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    mod direction {
+        use super::*;
+
+        #[test]
+        fn rotated() {
+            assert_eq!(Direction::Right.rotated(1), Direction::DownRight);
+            assert_eq!(Direction::Right.rotated(-1), Direction::UpRight);
+            assert_eq!(Direction::Left.rotated(13), Direction::UpLeft);
+            assert_eq!(Direction::UpLeft.rotated(-6), Direction::UpLeft);
+            for direction in Direction::all() {
+                assert_eq!(direction.opposite().opposite(), direction);
+                let back = Position::default()
+                    .translate(direction)
+                    .translate(direction.opposite());
+                assert_eq!(back, Position::default());
+            }
+            assert_eq!(Direction::all().count(), 6);
+            assert_eq!(Direction::all().last(), Some(Direction::UpRight));
+        }
+    }
+
     mod cell {
         use super::*;
 
@@ -535,6 +2455,7 @@ mod tests {
         }
 
         #[test]
+        #[allow(clippy::bool_assert_comparison)]
         fn has_ant() {
             let mut cell = Cell::default();
             assert_eq!(cell.has_ant(), false);
@@ -564,6 +2485,42 @@ mod tests {
         }
 
         #[test]
+        fn markers() {
+            let mut cell = Cell::default();
+            assert!(!cell.has_any_marker(Color::Red));
+            assert_eq!(cell.try_set_marker(Color::Red, 3), Ok(()));
+            assert!(cell.has_marker(Color::Red, 3));
+            assert!(!cell.has_marker(Color::Red, 2));
+            assert!(!cell.has_marker(Color::Black, 3));
+            assert!(cell.has_any_marker(Color::Red));
+            assert!(!cell.has_any_marker(Color::Black));
+            assert_eq!(cell.try_clear_marker(Color::Red, 3), Ok(()));
+            assert!(!cell.has_any_marker(Color::Red));
+        }
+
+        #[test]
+        fn invalid_marker() {
+            let mut cell = Cell::default();
+            assert_eq!(
+                cell.try_set_marker(Color::Red, MARKERS_PER_COLOR),
+                Err(CellError::InvalidMarker)
+            );
+            assert_eq!(
+                cell.try_clear_marker(Color::Red, MARKERS_PER_COLOR),
+                Err(CellError::InvalidMarker)
+            );
+            assert!(!cell.has_marker(Color::Red, MARKERS_PER_COLOR));
+        }
+
+        #[test]
+        fn marker_on_wall() {
+            let mut cell = Cell::Wall;
+            assert_eq!(cell.try_set_marker(Color::Red, 0), Err(CellError::Wall));
+            assert!(!cell.has_any_marker(Color::Red));
+        }
+
+        #[test]
+        #[allow(clippy::bool_assert_comparison)]
         fn has_food() {
             let mut cell = Cell::default();
             assert_eq!(cell.has_food(), false);
@@ -585,119 +2542,879 @@ mod tests {
         }
 
         #[test]
-        fn cell_at_mutate() {
-            let mut grid = Grid::new(10, 10);
-            let pos = Position { x: 0, y: 0 };
-            let new_cell = Cell::FreeCell {
-                ant_id: None,
-                food: 5,
-            };
+        fn wall_border() {
+            let grid = Grid::with_wall_border(4, 3);
+            assert!(grid.is_sealed());
+            for y in 0..3 {
+                for x in 0..4 {
+                    let position = Position { x, y };
+                    let expected = if grid.is_border(position) {
+                        Cell::Wall
+                    } else {
+                        Cell::default()
+                    };
+                    assert_eq!(grid.cell_at(position), Some(&expected));
+                }
+            }
+            assert_eq!(grid.border().count(), 10);
+            assert!(!grid.is_border(Position { x: -1, y: 0 }));
+        }
+
+        #[test]
+        fn seal_borders() {
+            let mut grid = Grid::new(3, 3);
+            *grid.cell_at_mut(Position { x: 1, y: 1 }).unwrap() = Cell::new_anthill(Color::Red);
+            *grid.cell_at_mut(Position { x: 2, y: 1 }).unwrap() = Cell::new_anthill(Color::Red);
+            assert!(!grid.is_sealed());
+            grid.seal_borders();
+            assert!(grid.is_sealed());
+            assert_eq!(grid.cell_at(Position { x: 2, y: 1 }), Some(&Cell::Wall));
+            assert_eq!(
+                grid.cell_at(Position { x: 1, y: 1 }),
+                Some(&Cell::new_anthill(Color::Red))
+            );
+        }
+
+        #[test]
+        fn cell_at_mutate() {
+            let mut grid = Grid::new(10, 10);
+            let pos = Position { x: 0, y: 0 };
+            let new_cell = Cell::FreeCell {
+                ant_id: None,
+                food: 5,
+                markers: Markers::default(),
+                anthill: None,
+            };
+
+            let cell = grid.cell_at_mut(pos).unwrap();
+            *cell = new_cell.clone();
+            assert_eq!(grid.cell_at(pos), Some(&new_cell));
+        }
+
+        #[test]
+        fn cell_at() {
+            let grid = Grid::new(10, 10);
+            assert_eq!(
+                grid.cell_at(Position { x: 0, y: 5 }),
+                Some(&Cell::default())
+            );
+            assert_eq!(grid.cell_at(Position { x: -1, y: 0 }), None);
+        }
+
+        #[test]
+        fn cell_at_mut() {
+            let mut grid = Grid::new(10, 10);
+            assert_eq!(
+                grid.cell_at_mut(Position { x: 0, y: 5 }),
+                Some(&mut Cell::default())
+            );
+            assert_eq!(grid.cell_at_mut(Position { x: -1, y: 0 }), None);
+        }
+
+        #[test]
+        fn ant_at() {
+            let mut grid = Grid::new(10, 10);
+            let pos = Position { x: 5, y: 5 };
+            assert_eq!(grid.ant_at(pos), None);
+            grid.cell_at_mut(pos).unwrap().try_put_ant(0).unwrap();
+            assert_eq!(grid.ant_at(pos), Some(0));
+        }
+
+        #[test]
+        fn ring_zero() {
+            let grid = Grid::new(10, 10);
+            let center = Position { x: 5, y: 5 };
+            assert_eq!(grid.ring(center, 0).collect::<Vec<_>>(), vec![center]);
+        }
+
+        #[test]
+        fn ring_one() {
+            let grid = Grid::new(10, 10);
+            let center = Position { x: 5, y: 5 };
+            let ring: Vec<_> = grid.ring(center, 1).collect();
+            assert_eq!(
+                ring,
+                vec![
+                    center.translate(Direction::UpLeft),
+                    center.translate(Direction::UpRight),
+                    center.translate(Direction::Right),
+                    center.translate(Direction::DownRight),
+                    center.translate(Direction::DownLeft),
+                    center.translate(Direction::Left),
+                ]
+            );
+        }
+
+        #[test]
+        fn ring_distance() {
+            let grid = Grid::new(20, 20);
+            let center = Position { x: 10, y: 10 };
+            for radius in 1..5 {
+                let ring: Vec<_> = grid.ring(center, radius).collect();
+                assert_eq!(ring.len(), 6 * radius as usize);
+                assert!(ring.iter().all(|&p| p.distance(center) == radius));
+            }
+        }
+
+        #[test]
+        fn ring_near_edge() {
+            let grid = Grid::new(10, 10);
+            let ring: Vec<_> = grid.ring(Position { x: 0, y: 0 }, 1).collect();
+            assert_eq!(ring, vec![Position { x: 1, y: 0 }, Position { x: 0, y: 1 }]);
+
+            let ring: Vec<_> = grid.ring(Position { x: 9, y: 0 }, 2).collect();
+            assert!(ring.iter().all(|&p| grid.in_bounds(p)));
+            assert_eq!(ring.len(), 5);
+        }
+
+        #[test]
+        fn ring_outside_grid() {
+            let grid = Grid::new(3, 3);
+            assert_eq!(grid.ring(Position { x: 1, y: 1 }, 5).count(), 0);
+        }
+
+        #[test]
+        fn spiral() {
+            let grid = Grid::new(20, 20);
+            let center = Position { x: 10, y: 10 };
+            let spiral: Vec<_> = grid.spiral(center, 3).collect();
+            assert_eq!(spiral.len(), 1 + 6 + 12 + 18);
+            assert_eq!(spiral[0], center);
+            assert!(spiral
+                .windows(2)
+                .all(|w| w[0].distance(center) <= w[1].distance(center)));
+        }
+
+        #[test]
+        fn spiral_near_edge() {
+            let grid = Grid::new(3, 3);
+            let mut spiral: Vec<_> = grid.spiral(Position { x: 0, y: 0 }, 10).collect();
+            assert_eq!(spiral.len(), 9);
+            spiral.sort_by_key(|p| (p.y, p.x));
+            spiral.dedup();
+            assert_eq!(spiral.len(), 9);
+        }
+
+        #[test]
+        #[allow(clippy::bool_assert_comparison)]
+        fn in_bounds() {
+            let grid = Grid::new(10, 15);
+            assert_eq!(grid.in_bounds(Position { x: 0, y: 0 }), true);
+            assert_eq!(grid.in_bounds(Position { x: 9, y: 14 }), true);
+            assert_eq!(grid.in_bounds(Position { x: 8, y: 15 }), false);
+            assert_eq!(grid.in_bounds(Position { x: 10, y: 9 }), false);
+            assert_eq!(grid.in_bounds(Position { x: 0, y: -1 }), false);
+            assert_eq!(grid.in_bounds(Position { x: -1, y: 0 }), false);
+            assert_eq!(grid.in_bounds(Position { x: -4, y: -4 }), false);
+        }
+
+        #[test]
+        fn history_keeps_latest() {
+            let mut grid = Grid::new(2, 2);
+            let position = Position { x: 1, y: 1 };
+            let occupancy = |round| Occupancy {
+                round,
+                ant: 0,
+                action: None,
+            };
+            grid.record_occupancy(position, occupancy(0));
+            assert_eq!(grid.history_at(position).count(), 0);
+
+            grid.track_history(3);
+            for round in 0..5 {
+                grid.record_occupancy(position, occupancy(round));
+            }
+            let rounds: Vec<_> = grid.history_at(position).map(|o| o.round).collect();
+            assert_eq!(rounds, vec![2, 3, 4]);
+            assert_eq!(grid.history_at(Position { x: 0, y: 1 }).count(), 0);
+            assert_eq!(grid.history_at(Position { x: 5, y: 5 }).count(), 0);
+
+            grid.stop_tracking_history();
+            assert!(!grid.is_tracking_history());
+            assert_eq!(grid.history_at(position).count(), 0);
+        }
+
+        #[test]
+        fn torus_wraps() {
+            let mut grid = Grid::new_torus(4, 3);
+            assert_eq!(grid.topology(), Topology::Torus);
+            assert_eq!(
+                grid.wrap(Position { x: -1, y: 3 }),
+                Some(Position { x: 3, y: 0 })
+            );
+            assert_eq!(Grid::new(4, 3).wrap(Position { x: -1, y: 0 }), None);
+
+            *grid.cell_at_mut(Position { x: 4, y: -3 }).unwrap() = Cell::Wall;
+            assert!(matches!(
+                grid.cell_at(Position { x: 0, y: 0 }),
+                Some(Cell::Wall)
+            ));
+        }
+
+        #[test]
+        fn ring_on_torus() {
+            let grid = Grid::new_torus(10, 10);
+            let mut ring: Vec<_> = grid.ring(Position { x: 0, y: 0 }, 1).collect();
+            ring.sort_by_key(|p| (p.y, p.x));
+            assert_eq!(
+                ring,
+                vec![
+                    Position { x: 1, y: 0 },
+                    Position { x: 9, y: 0 },
+                    Position { x: 0, y: 1 },
+                    Position { x: 9, y: 1 },
+                    Position { x: 0, y: 9 },
+                    Position { x: 1, y: 9 },
+                ]
+            );
+        }
+
+        #[test]
+        fn neighbors() {
+            let mut grid = Grid::new(3, 3);
+            *grid.cell_at_mut(Position { x: 1, y: 0 }).unwrap() = Cell::Wall;
+            let around: Vec<_> = grid.neighbors(Position { x: 1, y: 1 }).collect();
+            assert_eq!(around.len(), 6);
+            assert_eq!(around[4], (Direction::UpLeft, &Cell::Wall));
+
+            let corner: Vec<_> = grid
+                .neighbors(Position { x: 0, y: 0 })
+                .map(|(direction, _)| direction)
+                .collect();
+            assert_eq!(corner, [Direction::Right, Direction::DownRight]);
+            let torus = Grid::new_torus(3, 3);
+            assert_eq!(torus.neighbors(Position { x: 0, y: 0 }).count(), 6);
+        }
+
+        #[test]
+        fn iter_cells() {
+            let mut grid = Grid::new(3, 2);
+            *grid.cell_at_mut(Position { x: 2, y: 0 }).unwrap() = Cell::Wall;
+            *grid.cell_at_mut(Position { x: 0, y: 1 }).unwrap() = Cell::new_anthill(Color::Red);
+
+            let cells: Vec<_> = grid.iter_cells().collect();
+            assert_eq!(cells.len(), 6);
+            for (position, cell) in &cells {
+                assert_eq!(grid.cell_at(*position), Some(*cell));
+            }
+            assert_eq!(cells[2], (Position { x: 2, y: 0 }, &Cell::Wall));
+            assert_eq!(cells[3].0, Position { x: 0, y: 1 });
+            assert_eq!(cells[3].1.anthill(), Some(Color::Red));
+            assert_eq!(Grid::new(0, 4).iter_cells().count(), 0);
+        }
+    }
+
+    mod world {
+        use super::*;
+
+        #[test]
+        fn new() {
+            let world = World::new(Grid::new(10, 15));
+            assert_eq!(world.grid().width(), 10);
+            assert_eq!(world.grid().height(), 15);
+            assert_eq!(world.swarm(Color::Red).next(), None);
+            assert_eq!(world.swarm(Color::Black).next(), None);
+        }
+
+        #[test]
+        fn add_ant() {
+            let mut world = World::new(Grid::new(10, 15));
+
+            let pos = Position { x: 5, y: 5 };
+
+            let add_result = world.add_ant(Color::Red, pos);
+            assert!(add_result.is_ok());
+            let id = add_result.unwrap();
+
+            assert_eq!(world.ant(id).unwrap().id(), id);
+            assert_eq!(world.swarm(Color::Red).next().map(|ant| ant.id()), Some(id));
+            assert_eq!(world.swarm(Color::Black).next(), None);
+            assert_eq!(world.grid().ant_at(pos), Some(id));
+        }
+
+        #[test]
+        fn add_ant_into_wall() {
+            let blocked_pos = Position { x: 6, y: 7 };
+            let mut grid = Grid::new(10, 15);
+            *grid.cell_at_mut(blocked_pos).unwrap() = Cell::Wall;
+
+            let mut world = World::new(grid);
+            assert_eq!(
+                world.add_ant(Color::Red, blocked_pos),
+                Err(WorldError::Wall)
+            );
+        }
+
+        #[test]
+        fn add_ant_into_occupied() {
+            let mut world = World::new(Grid::new(10, 15));
+
+            let pos = Position { x: 6, y: 7 };
+            assert!(world.add_ant(Color::Red, pos).is_ok());
+            assert_eq!(world.add_ant(Color::Red, pos), Err(WorldError::Occupied));
+        }
+
+        #[test]
+        fn rotate_ant() {
+            let mut world = World::new(Grid::new(10, 15));
+
+            let id = world.add_ant(Color::Red, Position { x: 6, y: 7 }).unwrap();
+            world.ant_mut(id).unwrap().rotate(Direction::DownRight);
+
+            assert_eq!(world.ant(id).unwrap().direction(), Direction::DownRight);
+        }
+
+        fn surround(world: &mut World, center: Position, color: Color, count: u32) {
+            for d in 0..count {
+                let position = center.translate(Direction::try_from(d).unwrap());
+                world.add_ant(color, position).unwrap();
+            }
+        }
+
+        #[test]
+        fn kill_ant() {
+            let mut grid = Grid::new(10, 15);
+            let pos = Position { x: 6, y: 7 };
+            grid.cell_at_mut(pos).unwrap().try_drop_food().unwrap();
+            let mut world = World::new(grid);
+            let id = world.add_ant(Color::Red, pos).unwrap();
+            world.ant_mut(id).unwrap().pickup_food().unwrap();
+
+            assert_eq!(world.kill_ant(id, 3), Ok(4));
+            assert!(!world.dead_ant(id).unwrap().is_alive());
+            assert_eq!(world.grid().ant_at(pos), None);
+            assert_eq!(world.grid().cell_at(pos).unwrap().food(), 4);
+            assert_eq!(world.swarm_size(Color::Red), 0);
+
+            assert_eq!(world.kill_ant(id, 3), Err(WorldError::NoSuchAnt));
+            assert_eq!(world.grid().cell_at(pos).unwrap().food(), 4);
+        }
+
+        #[test]
+        fn rules() {
+            let rules = WorldRules {
+                max_food: 2,
+                wall_border: true,
+                carry_capacity: 1,
+            };
+            let mut world = World::new(Grid::new(5, 4));
+            let edge = world.add_ant(Color::Red, Position { x: 0, y: 1 }).unwrap();
+            let id = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+            let mut world = world.with_rules(rules);
+            assert_eq!(world.rules(), &rules);
+            assert!(!world.dead_ant(edge).unwrap().is_alive());
+            assert_eq!(
+                world.grid().cell_at(Position { x: 0, y: 1 }),
+                Some(&Cell::Wall)
+            );
+
+            let here = Position { x: 1, y: 1 };
+            world.grow_food(here, 2);
+            let mut ant = world.ant_mut(id).unwrap();
+            ant.pickup_food().unwrap();
+            ant.drop_food().unwrap();
+            ant.pickup_food().unwrap();
+            world.grow_food(here, 1);
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food(),
+                Err(WorldError::CellFull)
+            );
+            let cap = FoodCap {
+                limit: 5,
+                overflow: FoodOverflow::Spill,
+            };
+            world.ant_mut(id).unwrap().drop_food_capped(cap).unwrap();
+            assert_eq!(world.grid().cell_at(here).unwrap().food(), 2);
+            assert_eq!(world.total_food(), 3);
+
+            world.kill_ant(id, 3).unwrap();
+            assert_eq!(world.grid().cell_at(here).unwrap().food(), 2);
+        }
+
+        #[test]
+        fn carry_capacity() {
+            let here = Position { x: 1, y: 1 };
+            let mut world = World::new(Grid::new(3, 3)).with_rules(WorldRules {
+                max_food: 2,
+                carry_capacity: 3,
+                ..WorldRules::default()
+            });
+            let id = world.add_ant(Color::Red, here).unwrap();
+            world.grow_food(here, 2);
+            assert_eq!(world.ant_mut(id).unwrap().pickup_food(), Ok(2));
+            world.grow_food(here, 2);
+            assert_eq!(world.ant_mut(id).unwrap().pickup_food(), Ok(1));
+            assert_eq!(world.ant(id).unwrap().carried_food(), 3);
+            assert_eq!(
+                world.ant_mut(id).unwrap().pickup_food(),
+                Err(WorldError::AntIsFull)
+            );
+            assert_eq!(world.total_food(), 4);
+
+            // The cell takes one more; the rest stays with the ant.
+            assert_eq!(world.ant_mut(id).unwrap().drop_food(), Ok(1));
+            assert_eq!(world.ant(id).unwrap().carried_food(), 2);
+            let cap = FoodCap {
+                limit: 2,
+                overflow: FoodOverflow::Spill,
+            };
+            let ahead = world.grid().neighbour(here, Direction::Right).unwrap();
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food_capped(cap),
+                Ok(vec![ahead, ahead])
+            );
+            assert!(!world.ant(id).unwrap().carries_food());
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food(),
+                Err(WorldError::AntHasNoFood)
+            );
+            assert_eq!(world.grid().cell_at(ahead).unwrap().food(), 2);
+        }
+
+        #[test]
+        fn invariants() {
+            let mut world = World::new(Grid::new(4, 4));
+            let a = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+            let b = world
+                .add_ant(Color::Black, Position { x: 2, y: 1 })
+                .unwrap();
+            let c = world.add_ant(Color::Red, Position { x: 3, y: 3 }).unwrap();
+            world.kill_ant(c, 0).unwrap();
+            assert_eq!(world.check_invariants(), Ok(()));
+
+            world.ants[b].position = Position { x: 1, y: 1 };
+            *world.grid.cell_at_mut(Position { x: 0, y: 2 }).unwrap() = Cell::Wall;
+            world.ants[c].alive = true;
+            world.ants[c].position = Position { x: 0, y: 2 };
+            world.swarms.get_mut(&Color::Red).unwrap().push(a);
+            assert_eq!(
+                world.check_invariants(),
+                Err(vec![
+                    InvariantViolation::SharedCell {
+                        position: Position { x: 1, y: 1 },
+                        first: a,
+                        second: b,
+                    },
+                    InvariantViolation::AntOnWall {
+                        id: c,
+                        position: Position { x: 0, y: 2 },
+                    },
+                    InvariantViolation::StrayAnt {
+                        position: Position { x: 2, y: 1 },
+                        id: b,
+                    },
+                    InvariantViolation::BadSwarmEntry {
+                        color: Color::Red,
+                        id: a,
+                    },
+                    InvariantViolation::NotInSwarm { id: c },
+                ])
+            );
+        }
+
+        #[test]
+        fn remove_ant() {
+            let mut world = World::new(Grid::new(10, 15));
+            let pos = Position { x: 6, y: 7 };
+            let first = world.add_ant(Color::Red, pos).unwrap();
+            let second = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+
+            assert_eq!(world.remove_ant(first), Ok(()));
+            assert_eq!(world.grid().ant_at(pos), None);
+            assert_eq!(world.grid().cell_at(pos).unwrap().food(), 0);
+            assert_eq!(world.ant_ids().collect::<Vec<_>>(), vec![second]);
+            assert_eq!(
+                world.ants().map(|ant| ant.id()).collect::<Vec<_>>(),
+                vec![second]
+            );
+            assert_eq!(
+                world.swarm_ids(Color::Red).collect::<Vec<_>>(),
+                vec![second]
+            );
+        }
+
+        #[test]
+        fn stale_ids_are_rejected() {
+            let mut world = World::new(Grid::new(10, 15));
+            let pos = Position { x: 6, y: 7 };
+            let stale = world.add_ant(Color::Red, pos).unwrap();
+            world.remove_ant(stale).unwrap();
+            let fresh = world.add_ant(Color::Black, pos).unwrap();
+
+            assert_ne!(stale, fresh);
+            assert!(!world.is_alive(stale));
+            assert!(world.ant(stale).is_none());
+            assert!(world.ant_mut(stale).is_none());
+            assert_eq!(world.remove_ant(stale), Err(WorldError::NoSuchAnt));
+            assert_eq!(world.ant(fresh).map(|ant| ant.color()), Some(Color::Black));
+            assert!(world.ant(fresh + 1).is_none());
+            assert_eq!(world.remove_ant(fresh + 1), Err(WorldError::NoSuchAnt));
+            assert_eq!(
+                world.dead_ant(stale).map(|ant| ant.color()),
+                Some(Color::Red)
+            );
+            assert!(world.dead_ant(fresh).is_none());
+        }
+
+        #[test]
+        fn resolve_combat_kills_surrounded() {
+            let mut world = World::new(Grid::new(10, 15));
+            let pos = Position { x: 6, y: 7 };
+            let id = world.add_ant(Color::Red, pos).unwrap();
+            surround(&mut world, pos, Color::Black, 5);
+
+            assert_eq!(world.adjacent_enemies(id), 5);
+            assert_eq!(
+                world.resolve_combat(pos, CombatRule::default()),
+                vec![(id, 3)]
+            );
+            assert!(!world.dead_ant(id).unwrap().is_alive());
+            assert_eq!(world.grid().cell_at(pos).unwrap().food(), 3);
+            assert_eq!(world.swarm_size(Color::Black), 5);
+        }
+
+        #[test]
+        fn resolve_combat_spares_four() {
+            let mut world = World::new(Grid::new(10, 15));
+            let pos = Position { x: 6, y: 7 };
+            let id = world.add_ant(Color::Red, pos).unwrap();
+            surround(&mut world, pos, Color::Black, 4);
 
-            let cell = grid.cell_at_mut(pos).unwrap();
-            *cell = new_cell.clone();
-            assert_eq!(grid.cell_at(pos), Some(&new_cell));
+            assert_eq!(
+                world.resolve_combat(pos, CombatRule::default()),
+                Vec::<(AntId, u32)>::new()
+            );
+            assert!(world.ant(id).unwrap().is_alive());
         }
 
         #[test]
-        fn cell_at() {
-            let grid = Grid::new(10, 10);
+        fn resolve_combat_checks_neighbours() {
+            let mut world = World::new(Grid::new(10, 15));
+            let pos = Position { x: 6, y: 7 };
+            let victim = world.add_ant(Color::Red, pos).unwrap();
+            surround(&mut world, pos, Color::Black, 4);
+            let attacker = pos.translate(Direction::UpRight);
+            world.add_ant(Color::Black, attacker).unwrap();
+
             assert_eq!(
-                grid.cell_at(Position { x: 0, y: 5 }),
-                Some(&Cell::default())
+                world.resolve_combat(attacker, CombatRule::default()),
+                vec![(victim, 3)]
             );
-            assert_eq!(grid.cell_at(Position { x: -1, y: 0 }), None);
         }
 
         #[test]
-        fn cell_at_mut() {
-            let mut grid = Grid::new(10, 10);
+        fn metabolism() {
+            let metabolism = Metabolism {
+                max_energy: 10,
+                per_round: 3,
+                per_action: 1,
+                per_food: 4,
+                eat_at_home: true,
+            };
+            let mut grid = Grid::new(6, 2);
+            let home = Position { x: 4, y: 0 };
+            let table = Position { x: 1, y: 1 };
+            *grid.cell_at_mut(home).unwrap() = Cell::new_anthill(Color::Red);
+            grid.cell_at_mut(home).unwrap().try_drop_food().unwrap();
+            grid.cell_at_mut(table).unwrap().try_drop_food().unwrap();
+            grid.cell_at_mut(table).unwrap().try_drop_food().unwrap();
+            let mut world = World::new(grid);
+            let wanderer = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+            let homebody = world.add_ant(Color::Red, home).unwrap();
+            let eater = world.add_ant(Color::Black, table).unwrap();
             assert_eq!(
-                grid.cell_at_mut(Position { x: 0, y: 5 }),
-                Some(&mut Cell::default())
+                world.ant_mut(eater).unwrap().eat(metabolism),
+                Err(WorldError::NotHungry)
             );
-            assert_eq!(grid.cell_at_mut(Position { x: -1, y: 0 }), None);
+
+            assert!(world.metabolize(metabolism).is_empty());
+            assert_eq!(world.ant(wanderer).unwrap().hunger(), 3);
+            assert_eq!(metabolism.energy(world.ant(wanderer).unwrap()), 7);
+            world.ant_mut(eater).unwrap().eat(metabolism).unwrap();
+            assert_eq!(world.ant(eater).unwrap().hunger(), 0);
+            assert_eq!(world.cell_of(eater).unwrap().food(), 1);
+
+            assert!(world.metabolize(metabolism).is_empty());
+            // Six used up, so the homebody eats what is stored at home.
+            assert_eq!(world.ant(homebody).unwrap().hunger(), 2);
+            assert_eq!(world.food_in_anthill(Color::Red), 0);
+            world.ant_mut(eater).unwrap().pickup_food().unwrap();
+            world.ant_mut(eater).unwrap().eat(metabolism).unwrap();
+            assert!(!world.ant(eater).unwrap().carries_food());
+
+            assert!(world.metabolize(metabolism).is_empty());
+            assert_eq!(
+                world.ant_mut(eater).unwrap().eat(metabolism),
+                Err(WorldError::CellHasNoFood)
+            );
+            assert_eq!(world.metabolize(metabolism), [(wanderer, 0)]);
+            assert!(!world.is_alive(wanderer));
+            assert_eq!(world.swarm_size(Color::Red), 1);
         }
 
         #[test]
-        fn ant_at() {
-            let mut grid = Grid::new(10, 10);
-            let pos = Position { x: 5, y: 5 };
-            assert_eq!(grid.ant_at(pos), None);
-            grid.cell_at_mut(pos).unwrap().try_put_ant(0).unwrap();
-            assert_eq!(grid.ant_at(pos), Some(0));
+        fn spawn() {
+            let mut grid = Grid::new(5, 2);
+            for x in 1..4 {
+                *grid.cell_at_mut(Position { x, y: 0 }).unwrap() = Cell::new_anthill(Color::Red);
+            }
+            for _ in 0..3 {
+                grid.cell_at_mut(Position { x: 2, y: 0 })
+                    .unwrap()
+                    .try_drop_food()
+                    .unwrap();
+            }
+            grid.cell_at_mut(Position { x: 3, y: 0 })
+                .unwrap()
+                .try_drop_food()
+                .unwrap();
+            let mut world = World::new(grid);
+            let first = world.add_ant(Color::Red, Position { x: 1, y: 0 }).unwrap();
+            let rule = SpawnRule::new(2);
+
+            let (spawned, emptied): (Vec<_>, Vec<_>) = world.spawn(rule).into_iter().unzip();
+            assert_eq!(spawned, [first + 1, first + 2]);
+            let (x2, x3) = (Position { x: 2, y: 0 }, Position { x: 3, y: 0 });
+            assert_eq!(emptied, [vec![x2], vec![x2, x3]]);
+            assert_eq!(
+                world.ant(spawned[0]).unwrap().position(),
+                Position { x: 2, y: 0 }
+            );
+            assert_eq!(
+                world.ant(spawned[1]).unwrap().position(),
+                Position { x: 3, y: 0 }
+            );
+            assert_eq!(world.ant(spawned[1]).unwrap().instr_pointer(), 0);
+            assert_eq!(world.swarm_size(Color::Red), 3);
+            assert_eq!(world.food_in_anthill(Color::Red), 0);
+            assert_eq!(world.swarm_size(Color::Black), 0);
+
+            // No room left, even for ants that cost nothing.
+            assert!(world.spawn(SpawnRule::new(0)).is_empty());
         }
 
         #[test]
-        fn in_bounds() {
-            let grid = Grid::new(10, 15);
-            assert_eq!(grid.in_bounds(Position { x: 0, y: 0 }), true);
-            assert_eq!(grid.in_bounds(Position { x: 9, y: 14 }), true);
-            assert_eq!(grid.in_bounds(Position { x: 8, y: 15 }), false);
-            assert_eq!(grid.in_bounds(Position { x: 10, y: 9 }), false);
-            assert_eq!(grid.in_bounds(Position { x: 0, y: -1 }), false);
-            assert_eq!(grid.in_bounds(Position { x: -1, y: 0 }), false);
-            assert_eq!(grid.in_bounds(Position { x: -4, y: -4 }), false);
+        fn spawn_castes() {
+            let mut grid = Grid::new(6, 1);
+            for x in 0..6 {
+                *grid.cell_at_mut(Position { x, y: 0 }).unwrap() = Cell::new_anthill(Color::Red);
+            }
+            for _ in 0..10 {
+                grid.cell_at_mut(Position { x: 0, y: 0 })
+                    .unwrap()
+                    .try_drop_food()
+                    .unwrap();
+            }
+            let mut world = World::new(grid);
+            let rule = SpawnRule::new(1).with_caste_shares([1, 2, 0, 0]);
+            let castes: Vec<_> = world
+                .spawn(rule)
+                .into_iter()
+                .map(|(id, _)| world.ant(id).unwrap().caste())
+                .collect();
+            assert_eq!(castes, [0, 1, 1, 0, 1, 1]);
+
+            let mut world = World::new(Grid::new(2, 1));
+            assert_eq!(
+                world.add_ant_to_caste(Color::Red, Position { x: 0, y: 0 }, MAX_CASTES as Caste),
+                Err(WorldError::InvalidCaste)
+            );
+            let id = world
+                .add_ant_to_caste(Color::Red, Position { x: 0, y: 0 }, 3)
+                .unwrap();
+            assert_eq!(world.ant(id).unwrap().caste(), 3);
         }
-    }
-
-    mod world {
-        use super::*;
 
         #[test]
-        fn new() {
-            let world = World::new(Grid::new(10, 15));
-            assert_eq!(world.grid().width(), 10);
-            assert_eq!(world.grid().height(), 15);
-            assert_eq!(world.swarm(Color::Red).next(), None);
-            assert_eq!(world.swarm(Color::Black).next(), None);
+        fn food_in_anthill() {
+            let mut grid = Grid::new(10, 15);
+            let home = Position { x: 6, y: 7 };
+            let food = home.translate(Direction::Left);
+            *grid.cell_at_mut(home).unwrap() = Cell::new_anthill(Color::Red);
+            *grid.cell_at_mut(home.translate(Direction::Right)).unwrap() =
+                Cell::new_anthill(Color::Red);
+            *grid.cell_at_mut(food).unwrap() = Cell::FreeCell {
+                ant_id: None,
+                food: 3,
+                markers: Markers::default(),
+                anthill: None,
+            };
+
+            let mut world = World::new(grid);
+            let id = world.add_ant(Color::Red, food).unwrap();
+            assert_eq!(world.food_in_anthill(Color::Red), 0);
+
+            world.ant_mut(id).unwrap().pickup_food().unwrap();
+            world.ant_mut(id).unwrap().rotate(Direction::Right);
+            world.ant_mut(id).unwrap().move_forward().unwrap();
+            world.ant_mut(id).unwrap().drop_food().unwrap();
+            assert_eq!(world.cell_of(id).unwrap().anthill(), Some(Color::Red));
+            assert_eq!(world.food_in_anthill(Color::Red), 1);
+            assert_eq!(world.food_in_anthill(Color::Black), 0);
+
+            world.ant_mut(id).unwrap().pickup_food().unwrap();
+            world.ant_mut(id).unwrap().move_forward().unwrap();
+            world.ant_mut(id).unwrap().drop_food().unwrap();
+            assert_eq!(world.food_in_anthill(Color::Red), 1);
         }
 
         #[test]
-        fn add_ant() {
+        fn set_and_clear_marker() {
             let mut world = World::new(Grid::new(10, 15));
-
-            let pos = Position { x: 5, y: 5 };
-
-            let add_result = world.add_ant(Color::Red, pos);
-            assert!(add_result.is_ok());
-            let id = add_result.unwrap();
-
-            assert_eq!(world.ant(id).id(), id);
-            assert_eq!(world.swarm(Color::Red).next().map(|ant| ant.id()), Some(id));
-            assert_eq!(world.swarm(Color::Black).next(), None);
-            assert_eq!(world.grid().ant_at(pos), Some(id));
+            let pos = Position { x: 6, y: 7 };
+            let id = world.add_ant(Color::Black, pos).unwrap();
+
+            assert_eq!(world.ant_mut(id).unwrap().set_marker(5), Ok(()));
+            assert!(world
+                .grid()
+                .cell_at(pos)
+                .unwrap()
+                .has_marker(Color::Black, 5));
+            assert_eq!(world.ant_mut(id).unwrap().clear_marker(5), Ok(()));
+            assert!(!world
+                .grid()
+                .cell_at(pos)
+                .unwrap()
+                .has_any_marker(Color::Black));
+            assert_eq!(
+                world.ant_mut(id).unwrap().set_marker(6),
+                Err(WorldError::InvalidMarker)
+            );
         }
 
         #[test]
-        fn add_ant_into_wall() {
-            let blocked_pos = Position { x: 6, y: 7 };
-            let mut grid = Grid::new(10, 15);
-            *grid.cell_at_mut(blocked_pos).unwrap() = Cell::Wall;
+        fn snapshot() {
+            let mut grid = Grid::new(4, 3);
+            *grid.cell_at_mut(Position { x: 3, y: 2 }).unwrap() = Cell::Wall;
+            let mut world = World::new(grid);
+            let dead = world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+            let alive = world
+                .add_ant(Color::Black, Position { x: 1, y: 2 })
+                .unwrap();
+            world.remove_ant(dead).unwrap();
+
+            let snapshot = world.snapshot();
+            assert_eq!((snapshot.width(), snapshot.height()), (4, 3));
+            assert_eq!(snapshot.rows().count(), 3);
+            assert_eq!(snapshot.row(2)[3], Cell::Wall);
+            assert_eq!(snapshot.row(2)[1].ant(), Some(alive));
+            assert_eq!(snapshot.cell_at(Position { x: 3, y: 2 }), Some(&Cell::Wall));
+            assert!(snapshot.ant(dead).is_none());
+            assert_eq!(snapshot.dead_ant(dead).map(|ant| ant.id()), Some(dead));
+            assert!(snapshot.dead_ant(alive).is_none());
+            assert_eq!(snapshot.cells().count(), 12);
+            assert_eq!(
+                snapshot.ant(alive).map(|ant| ant.color()),
+                Some(Color::Black)
+            );
+            assert_eq!(
+                snapshot.ants().map(|ant| ant.id()).collect::<Vec<_>>(),
+                vec![alive]
+            );
+        }
 
+        #[test]
+        fn more_than_two_teams() {
+            let green = Color::team(2);
+            let mut grid = Grid::new(6, 4);
+            *grid.cell_at_mut(Position { x: 5, y: 3 }).unwrap() = Cell::new_anthill(green);
             let mut world = World::new(grid);
+            let red = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+            let other = world.add_ant(green, Position { x: 2, y: 1 }).unwrap();
+            world.ant_mut(other).unwrap().set_marker(4).unwrap();
+
+            assert_eq!(world.teams(), [Color::Black, Color::Red, green]);
             assert_eq!(
-                world.add_ant(Color::Red, blocked_pos),
-                Err(WorldError::Wall)
+                world.foes(Color::Red).collect::<Vec<_>>(),
+                [Color::Black, green]
             );
+            assert_eq!(world.swarm_size(green), 1);
+            let cell = world.grid().cell_at(Position { x: 2, y: 1 }).unwrap();
+            assert!(cell.has_foreign_marker(Color::Red));
+            assert!(!cell.has_foreign_marker(green));
+            assert!(cell.has_marker(green, 4));
+            assert_eq!(world.ant(red).unwrap().color(), Color::Red);
+
+            let mut out = Encoder::bare();
+            world.encode(&mut out);
+            let bytes = out.into_bytes();
+            let loaded = World::decode(&mut Decoder::bare(&bytes)).unwrap();
+            assert!(loaded == world);
+
+            assert_eq!(format!("{:?}", green), "Team2");
+            assert_eq!("Team2".parse(), Ok(green));
+            assert_eq!("Red".parse(), Ok(Color::Red));
+            assert!("Team8".parse::<Color>().is_err());
         }
 
         #[test]
-        fn add_ant_into_occupied() {
+        fn remap_instr_pointers() {
             let mut world = World::new(Grid::new(10, 15));
+            let red = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+            let black = world
+                .add_ant(Color::Black, Position { x: 2, y: 2 })
+                .unwrap();
+            world.ant_mut(red).unwrap().update_instr_pointer(4);
+            world.ant_mut(black).unwrap().update_instr_pointer(7);
+
+            world.remap_instr_pointers(|ant| match ant.color() {
+                Color::Red => ant.instr_pointer() * 10,
+                _ => 1,
+            });
+            assert_eq!(world.ant(red).unwrap().instr_pointer(), 40);
+            assert_eq!(world.ant(black).unwrap().instr_pointer(), 1);
+
+            world.reset_instr_pointers();
+            assert!(world.ants().all(|ant| ant.instr_pointer() == 0));
+        }
 
+        #[test]
+        fn sensed_cell() {
+            let mut grid = Grid::new(10, 15);
             let pos = Position { x: 6, y: 7 };
-            assert!(world.add_ant(Color::Red, pos).is_ok());
-            assert_eq!(world.add_ant(Color::Red, pos), Err(WorldError::Occupied));
+            *grid.cell_at_mut(pos.translate(Direction::UpLeft)).unwrap() = Cell::Wall;
+
+            let mut world = World::new(grid);
+            let id = world.add_ant(Color::Red, pos).unwrap();
+            world.ant_mut(id).unwrap().rotate(Direction::UpRight);
+
+            assert_eq!(
+                world.sensed_cell(id, SenseDirection::Here),
+                Some(world.cell_of(id).unwrap())
+            );
+            assert_eq!(
+                world
+                    .ant(id)
+                    .unwrap()
+                    .sensed_position(SenseDirection::Ahead),
+                pos.translate(Direction::UpRight)
+            );
+            assert_eq!(
+                world
+                    .ant(id)
+                    .unwrap()
+                    .sensed_position(SenseDirection::RightAhead),
+                pos.translate(Direction::Right)
+            );
+            assert_eq!(
+                world.sensed_cell(id, SenseDirection::LeftAhead),
+                Some(&Cell::Wall)
+            );
         }
 
         #[test]
-        fn rotate_ant() {
+        fn sensed_cell_out_of_bounds() {
             let mut world = World::new(Grid::new(10, 15));
+            let id = world.add_ant(Color::Red, Position { x: 9, y: 0 }).unwrap();
 
-            let id = world.add_ant(Color::Red, Position { x: 6, y: 7 }).unwrap();
-            world.ant_mut(id).rotate(Direction::DownRight);
-
-            assert_eq!(world.ant(id).direction(), Direction::DownRight);
+            assert_eq!(world.sensed_cell(id, SenseDirection::Ahead), None);
+            assert_eq!(
+                world
+                    .ant_mut(id)
+                    .unwrap()
+                    .sensed_cell(SenseDirection::LeftAhead),
+                None
+            );
         }
 
         #[test]
@@ -709,10 +3426,10 @@ mod tests {
 
             let id = world.add_ant(Color::Red, pos).unwrap();
 
-            assert!(world.ant_mut(id).move_forward().is_ok());
+            assert!(world.ant_mut(id).unwrap().move_forward().is_ok());
             assert_eq!(world.grid().ant_at(pos), None);
             assert_eq!(world.grid().ant_at(new_pos), Some(id));
-            assert_eq!(world.ant(id).position(), new_pos);
+            assert_eq!(world.ant(id).unwrap().position(), new_pos);
         }
 
         #[test]
@@ -722,11 +3439,55 @@ mod tests {
             let id = world.add_ant(Color::Red, Position { x: 9, y: 7 }).unwrap();
 
             assert_eq!(
-                world.ant_mut(id).move_forward(),
+                world.ant_mut(id).unwrap().move_forward(),
                 Err(WorldError::OutOfBounds)
             );
         }
 
+        #[test]
+        fn move_ant_around_torus() {
+            let mut world = World::new(Grid::new_torus(10, 15));
+
+            let id = world.add_ant(Color::Red, Position { x: 9, y: 7 }).unwrap();
+            assert_eq!(world.ant_mut(id).unwrap().move_forward(), Ok(()));
+            assert_eq!(world.ant(id).unwrap().position(), Position { x: 0, y: 7 });
+            assert_eq!(world.grid().ant_at(Position { x: 0, y: 7 }), Some(id));
+
+            world.ant_mut(id).unwrap().rotate(Direction::UpLeft);
+            world.ant_mut(id).unwrap().move_forward().unwrap();
+            assert_eq!(world.ant(id).unwrap().position(), Position { x: 0, y: 6 });
+        }
+
+        #[test]
+        fn sense_across_torus_edge() {
+            let mut world = World::new(Grid::new_torus(10, 15));
+            let id = world.add_ant(Color::Red, Position { x: 9, y: 0 }).unwrap();
+            let other = world
+                .add_ant(Color::Black, Position { x: 0, y: 0 })
+                .unwrap();
+
+            assert_eq!(
+                world.sensed_cell(id, SenseDirection::Ahead).unwrap().ant(),
+                Some(other)
+            );
+            assert_eq!(
+                world
+                    .ant_mut(id)
+                    .unwrap()
+                    .sensed_position(SenseDirection::LeftAhead),
+                Position { x: 0, y: 14 }
+            );
+        }
+
+        #[test]
+        fn add_ant_on_torus_wraps() {
+            let mut world = World::new(Grid::new_torus(10, 15));
+            let id = world
+                .add_ant(Color::Red, Position { x: -1, y: 15 })
+                .unwrap();
+            assert_eq!(world.ant(id).unwrap().position(), Position { x: 9, y: 0 });
+        }
+
         #[test]
         fn move_ant_into_occupied() {
             let mut world = World::new(Grid::new(10, 15));
@@ -737,7 +3498,10 @@ mod tests {
             let id = world.add_ant(Color::Red, pos).unwrap();
             world.add_ant(Color::Red, new_pos).unwrap();
 
-            assert_eq!(world.ant_mut(id).move_forward(), Err(WorldError::Occupied));
+            assert_eq!(
+                world.ant_mut(id).unwrap().move_forward(),
+                Err(WorldError::Occupied)
+            );
         }
 
         #[test]
@@ -750,7 +3514,10 @@ mod tests {
             let mut world = World::new(grid);
             let id = world.add_ant(Color::Red, pos).unwrap();
 
-            assert_eq!(world.ant_mut(id).move_forward(), Err(WorldError::Wall));
+            assert_eq!(
+                world.ant_mut(id).unwrap().move_forward(),
+                Err(WorldError::Wall)
+            );
         }
 
         #[test]
@@ -760,17 +3527,19 @@ mod tests {
             *grid.cell_at_mut(pos).unwrap() = Cell::FreeCell {
                 ant_id: None,
                 food: 5,
+                markers: Markers::default(),
+                anthill: None,
             };
 
             let mut world = World::new(grid);
             let id = world.add_ant(Color::Red, pos).unwrap();
 
-            assert_eq!(world.ant_mut(id).pickup_food(), Ok(()));
-            assert!(world.ant(id).carries_food());
+            assert_eq!(world.ant_mut(id).unwrap().pickup_food(), Ok(1));
+            assert!(world.ant(id).unwrap().carries_food());
             assert_eq!(world.grid().cell_at(pos).unwrap().food(), 4);
             assert_eq!(
-                world.ant_mut(id).pickup_food(),
-                Err(WorldError::AntCarriesFood)
+                world.ant_mut(id).unwrap().pickup_food(),
+                Err(WorldError::AntIsFull)
             );
         }
 
@@ -781,13 +3550,15 @@ mod tests {
             *grid.cell_at_mut(pos).unwrap() = Cell::FreeCell {
                 ant_id: None,
                 food: 0,
+                markers: Markers::default(),
+                anthill: None,
             };
 
             let mut world = World::new(grid);
             let id = world.add_ant(Color::Red, pos).unwrap();
 
             assert_eq!(
-                world.ant_mut(id).pickup_food(),
+                world.ant_mut(id).unwrap().pickup_food(),
                 Err(WorldError::CellHasNoFood)
             );
         }
@@ -799,16 +3570,322 @@ mod tests {
             *grid.cell_at_mut(pos).unwrap() = Cell::FreeCell {
                 ant_id: None,
                 food: 5,
+                markers: Markers::default(),
+                anthill: None,
             };
 
             let mut world = World::new(grid);
             let id = world.add_ant(Color::Red, pos).unwrap();
-            world.ant_mut(id).pickup_food().unwrap();
+            world.ant_mut(id).unwrap().pickup_food().unwrap();
+
+            assert_eq!(world.ant_mut(id).unwrap().drop_food(), Ok(1));
+            assert!(!world.ant(id).unwrap().carries_food());
+            assert_eq!(world.grid().cell_at(pos).unwrap().food(), 5);
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food(),
+                Err(WorldError::AntHasNoFood)
+            );
+        }
+
+        fn world_with_full_cell(pos: Position, limit: u32) -> (World, AntId) {
+            let mut grid = Grid::new(10, 15);
+            *grid.cell_at_mut(pos).unwrap() = Cell::FreeCell {
+                ant_id: None,
+                food: limit + 1,
+                markers: Markers::default(),
+                anthill: None,
+            };
+            let mut world = World::new(grid);
+            let id = world.add_ant(Color::Red, pos).unwrap();
+            world.ant_mut(id).unwrap().pickup_food().unwrap();
+            (world, id)
+        }
+
+        #[test]
+        fn drop_food_capped_below_limit() {
+            let pos = Position { x: 6, y: 7 };
+            let (mut world, id) = world_with_full_cell(pos, 5);
+            let cap = FoodCap {
+                limit: 6,
+                overflow: FoodOverflow::Fail,
+            };
+
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food_capped(cap),
+                Ok(vec![pos])
+            );
+            assert_eq!(world.grid().cell_at(pos).unwrap().food(), 6);
+        }
+
+        #[test]
+        fn drop_food_capped_fail() {
+            let pos = Position { x: 6, y: 7 };
+            let (mut world, id) = world_with_full_cell(pos, 5);
+            let cap = FoodCap {
+                limit: 5,
+                overflow: FoodOverflow::Fail,
+            };
+
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food_capped(cap),
+                Err(WorldError::CellFull)
+            );
+            assert!(world.ant(id).unwrap().carries_food());
+            assert_eq!(world.grid().cell_at(pos).unwrap().food(), 5);
+        }
 
-            assert_eq!(world.ant_mut(id).drop_food(), Ok(()));
-            assert!(!world.ant(id).carries_food());
+        #[test]
+        fn drop_food_capped_spills_ahead_first() {
+            let pos = Position { x: 6, y: 7 };
+            let (mut world, id) = world_with_full_cell(pos, 5);
+            world.ant_mut(id).unwrap().rotate(Direction::Left);
+            let cap = FoodCap {
+                limit: 5,
+                overflow: FoodOverflow::Spill,
+            };
+
+            let ahead = pos.translate(Direction::Left);
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food_capped(cap),
+                Ok(vec![ahead])
+            );
+            assert!(!world.ant(id).unwrap().carries_food());
             assert_eq!(world.grid().cell_at(pos).unwrap().food(), 5);
-            assert_eq!(world.ant_mut(id).drop_food(), Err(WorldError::AntHasNoFood));
+            assert_eq!(world.grid().cell_at(ahead).unwrap().food(), 1);
+        }
+
+        #[test]
+        fn drop_food_capped_spills_clockwise() {
+            let pos = Position { x: 6, y: 7 };
+            let mut grid = Grid::new(10, 15);
+            *grid.cell_at_mut(pos).unwrap() = Cell::FreeCell {
+                ant_id: None,
+                food: 3,
+                markers: Markers::default(),
+                anthill: None,
+            };
+            *grid.cell_at_mut(pos.translate(Direction::Right)).unwrap() = Cell::Wall;
+            *grid
+                .cell_at_mut(pos.translate(Direction::DownRight))
+                .unwrap() = Cell::FreeCell {
+                ant_id: None,
+                food: 2,
+                markers: Markers::default(),
+                anthill: None,
+            };
+            let mut world = World::new(grid);
+            let id = world.add_ant(Color::Red, pos).unwrap();
+            world.ant_mut(id).unwrap().pickup_food().unwrap();
+            let cap = FoodCap {
+                limit: 2,
+                overflow: FoodOverflow::Spill,
+            };
+
+            let down_left = pos.translate(Direction::DownLeft);
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food_capped(cap),
+                Ok(vec![down_left])
+            );
+            assert_eq!(world.grid().cell_at(down_left).unwrap().food(), 1);
+        }
+
+        #[test]
+        fn drop_food_capped_nearest() {
+            let pos = Position { x: 6, y: 7 };
+            let mut grid = Grid::new(10, 15);
+            let neighbours: Vec<_> = grid.ring(pos, 1).collect();
+            for position in neighbours.into_iter().chain([pos, pos]) {
+                grid.cell_at_mut(position).unwrap().try_drop_food().unwrap();
+            }
+            let mut world = World::new(grid);
+            let id = world.add_ant(Color::Red, pos).unwrap();
+            world.ant_mut(id).unwrap().pickup_food().unwrap();
+
+            let spill = FoodCap {
+                limit: 1,
+                overflow: FoodOverflow::Spill,
+            };
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food_capped(spill),
+                Err(WorldError::CellFull)
+            );
+            let nearest = FoodCap {
+                limit: 1,
+                overflow: FoodOverflow::Nearest,
+            };
+            let first = world.grid().ring(pos, 2).next().unwrap();
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food_capped(nearest),
+                Ok(vec![first])
+            );
+            assert_eq!(world.grid().cell_at(first).unwrap().food(), 1);
+        }
+
+        #[test]
+        fn drop_food_capped_nowhere_to_spill() {
+            let pos = Position { x: 0, y: 0 };
+            let mut grid = Grid::new(2, 1);
+            *grid.cell_at_mut(Position { x: 1, y: 0 }).unwrap() = Cell::Wall;
+            *grid.cell_at_mut(pos).unwrap() = Cell::FreeCell {
+                ant_id: None,
+                food: 2,
+                markers: Markers::default(),
+                anthill: None,
+            };
+            let mut world = World::new(grid);
+            let id = world.add_ant(Color::Red, pos).unwrap();
+            world.ant_mut(id).unwrap().pickup_food().unwrap();
+            let cap = FoodCap {
+                limit: 1,
+                overflow: FoodOverflow::Spill,
+            };
+
+            assert_eq!(
+                world.ant_mut(id).unwrap().drop_food_capped(cap),
+                Err(WorldError::CellFull)
+            );
+            assert!(world.ant(id).unwrap().carries_food());
+        }
+    }
+
+    #[test]
+    fn queries() {
+        let mut grid = Grid::new(6, 6);
+        for (x, y, food) in [(1, 1, 3), (4, 2, 1)] {
+            *grid.cell_at_mut(Position { x, y }).unwrap() = Cell::FreeCell {
+                ant_id: None,
+                food,
+                markers: Markers::default(),
+                anthill: None,
+            };
+        }
+        let mut world = World::new(grid);
+        let near = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+        let close = world
+            .add_ant(Color::Black, Position { x: 2, y: 2 })
+            .unwrap();
+        world.add_ant(Color::Red, Position { x: 5, y: 5 }).unwrap();
+        world.ant_mut(near).unwrap().pickup_food().unwrap();
+
+        assert_eq!(world.total_food(), 4);
+        assert_eq!(world.ant_count(Color::Red), 2);
+        assert_eq!(world.ant_count(Color::Black), 1);
+        assert_eq!(
+            world.food_positions().collect::<Vec<_>>(),
+            [Position { x: 1, y: 1 }, Position { x: 4, y: 2 }]
+        );
+        let ids = |radius| {
+            (world.ants_in_radius(Position { x: 1, y: 1 }, radius))
+                .map(|ant| ant.id())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(ids(0), [near]);
+        assert_eq!(ids(2), [near, close]);
+    }
+
+    #[test]
+    fn ants_in_radius_on_a_small_torus() {
+        let mut world = World::new(Grid::new_torus(2, 2));
+        world.add_ant(Color::Red, Position { x: 0, y: 0 }).unwrap();
+        world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+        assert_eq!(world.ants_in_radius(Position { x: 0, y: 0 }, 3).count(), 2);
+    }
+
+    #[test]
+    fn regrow() {
+        let mut grid = Grid::new(4, 1);
+        *grid.cell_at_mut(Position { x: 1, y: 0 }).unwrap() = Cell::Wall;
+        *grid.cell_at_mut(Position { x: 2, y: 0 }).unwrap() = Cell::new_anthill(Color::Red);
+        let mut world = World::new(grid);
+        let mut rule = RegrowthRule {
+            spawners: (0..5).map(|x| Position { x, y: 0 }).collect(),
+            interval: 1,
+            amount: 3,
+            cap: 4,
+            chance: 100,
+        };
+        let mut rng = Rng::new(0);
+        // Walls, anthills and positions off the grid are skipped.
+        assert_eq!(
+            world.regrow(&rule, &mut rng),
+            [(Position { x: 0, y: 0 }, 3), (Position { x: 3, y: 0 }, 3)]
+        );
+        assert_eq!(
+            world.regrow(&rule, &mut rng),
+            [(Position { x: 0, y: 0 }, 1), (Position { x: 3, y: 0 }, 1)]
+        );
+        assert!(world.regrow(&rule, &mut rng).is_empty());
+
+        rule.chance = 0;
+        rule.cap = 10;
+        assert!(world.regrow(&rule, &mut rng).is_empty());
+        assert!(!RegrowthRule {
+            interval: 0,
+            ..rule
+        }
+        .is_due(0));
+    }
+
+    #[test]
+    fn state_hash() {
+        let mut world = World::new(Grid::new(4, 3));
+        let id = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+        world.ant_mut(id).unwrap().set_marker(2).unwrap();
+        let before = world.state_hash();
+        // Pinned, as hashes are kept around to compare against.
+        assert_eq!(before, 0x2eef_8f4d_2e36_7328);
+        assert_eq!(world.clone().state_hash(), before);
+
+        world.ant_mut(id).unwrap().update_instr_pointer(3);
+        let jumped = world.state_hash();
+        assert_ne!(jumped, before);
+        world.ant_mut(id).unwrap().rotate(Direction::Left);
+        assert_ne!(world.state_hash(), jumped);
+    }
+
+    #[cfg(feature = "serde")]
+    mod serde {
+        use super::*;
+
+        #[test]
+        fn world_round_trip() {
+            let mut grid = Grid::new(6, 4);
+            *grid.cell_at_mut(Position { x: 0, y: 0 }).unwrap() = Cell::Wall;
+            *grid.cell_at_mut(Position { x: 5, y: 3 }).unwrap() = Cell::new_anthill(Color::Black);
+            grid.cell_at_mut(Position { x: 2, y: 2 })
+                .unwrap()
+                .try_drop_food()
+                .unwrap();
+            let mut world = World::new(grid);
+            let red = world.add_ant(Color::Red, Position { x: 2, y: 2 }).unwrap();
+            let black = world
+                .add_ant(Color::Black, Position { x: 4, y: 1 })
+                .unwrap();
+            world.ant_mut(red).unwrap().pickup_food().unwrap();
+            world.ant_mut(red).unwrap().set_marker(3).unwrap();
+            world.ant_mut(black).unwrap().rotate(Direction::UpLeft);
+            world.ant_mut(black).unwrap().update_instr_pointer(7);
+            let dead = world.add_ant(Color::Red, Position { x: 1, y: 1 }).unwrap();
+            world.remove_ant(dead).unwrap();
+
+            let json = serde_json::to_string(&world).unwrap();
+            let loaded: World = serde_json::from_str(&json).unwrap();
+            crate::testing::assert_world_eq(&loaded, &world);
+        }
+
+        #[test]
+        fn grid_is_saved_as_rows() {
+            let mut grid = Grid::new(2, 3);
+            *grid.cell_at_mut(Position { x: 1, y: 2 }).unwrap() = Cell::Wall;
+            let json = serde_json::to_value(&grid).unwrap();
+            assert_eq!(json["cells"].as_array().unwrap().len(), 3);
+            assert_eq!(json["cells"][2][1], serde_json::json!("Wall"));
+            let loaded: Grid = serde_json::from_value(json.clone()).unwrap();
+            assert!(loaded == grid);
+
+            let mut ragged = json;
+            ragged["cells"][1].as_array_mut().unwrap().pop();
+            assert!(serde_json::from_value::<Grid>(ragged).is_err());
         }
     }
 }